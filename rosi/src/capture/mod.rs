@@ -0,0 +1,158 @@
+//! Packet captures in the pcap and pcapng formats, for opening traffic in Wireshark and
+//! replaying it in tests.
+
+mod pcap;
+mod pcapng;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::common::Serialise;
+use crate::protocols::ethernet::Frame;
+use crate::util::serialise_enum;
+
+pub use pcap::{PcapReader, PcapWriter};
+pub use pcapng::{PcapngReader, PcapngWriter};
+
+/// Largest packet a capture keeps by default, which is larger than any frame the stack sees.
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+
+// The link-layer header type of captured packets, from the tcpdump.org list of LINKTYPE values.
+serialise_enum! {
+    pub LinkType(u16, 2) {
+        Ethernet:   1,
+        Raw:        101,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// One captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    /// Length of the packet on the wire, which is more than `data` holds if it was truncated.
+    pub original_length: u32,
+    /// Only known for pcapng captures that recorded it.
+    pub direction: Option<Direction>,
+    pub data: Vec<u8>,
+}
+
+/// Something packets can be written to as they are sent and received.
+pub trait CaptureWriter: Send {
+    fn write_packet(&mut self, timestamp: SystemTime, direction: Direction, data: &[u8]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Creates a capture file, in pcapng format if its name ends in `.pcapng` and pcap otherwise.
+pub fn create(path: impl AsRef<Path>, link_type: LinkType) -> io::Result<Box<dyn CaptureWriter>> {
+    let path = path.as_ref();
+    let file = BufWriter::new(File::create(path)?);
+
+    if path.extension().is_some_and(|extension| extension == "pcapng") {
+        Ok(Box::new(PcapngWriter::new(file, link_type, DEFAULT_SNAPLEN)?))
+    } else {
+        Ok(Box::new(PcapWriter::new(file, link_type, DEFAULT_SNAPLEN)?))
+    }
+}
+
+/// Reads either format, telling them apart by the magic number at the start.
+pub enum Reader<R: Read> {
+    Pcap(PcapReader<R>),
+    Pcapng(PcapngReader<R>),
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        if magic == pcapng::SECTION_HEADER {
+            Ok(Self::Pcapng(PcapngReader::after_magic(reader)?))
+        } else {
+            Ok(Self::Pcap(PcapReader::after_magic(reader, magic)?))
+        }
+    }
+
+    /// The link type of the packets. For pcapng, this is the type of the first interface.
+    pub fn link_type(&self) -> LinkType {
+        match self {
+            Self::Pcap(reader) => reader.link_type(),
+            Self::Pcapng(reader) => reader.link_type(),
+        }
+    }
+
+    /// The packets that decode as Ethernet frames. Reading stops at the first I/O error.
+    pub fn frames(self) -> impl Iterator<Item = Frame> {
+        self.map_while(Result::ok).filter_map(|record| Frame::deserialise(&record.data).ok())
+    }
+}
+
+impl Reader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Pcap(reader) => reader.next(),
+            Self::Pcapng(reader) => reader.next(),
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads exactly `buf.len()` bytes, or returns `Ok(false)` if the reader was already at its end.
+fn read_or_end(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Integers in the byte order a capture was written in.
+#[derive(Debug, Clone, Copy)]
+struct ByteOrder {
+    big_endian: bool,
+}
+
+impl ByteOrder {
+    fn u16(&self, buf: &[u8]) -> u16 {
+        let bytes = [buf[0], buf[1]];
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn u32(&self, buf: &[u8]) -> u32 {
+        let bytes = [buf[0], buf[1], buf[2], buf[3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+fn since_epoch(timestamp: SystemTime) -> Duration {
+    timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+}
+
+fn write_all(writer: &mut impl Write, parts: &[&[u8]]) -> io::Result<()> {
+    parts.iter().try_for_each(|part| writer.write_all(part))
+}
@@ -0,0 +1,182 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime};
+
+use super::{read_or_end, since_epoch, write_all, invalid_data, ByteOrder, CaptureWriter, Direction, LinkType, Record};
+
+const MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+const VERSION: (u16, u16) = (2, 4);
+const HEADER_LENGTH: usize = 24;
+const RECORD_HEADER_LENGTH: usize = 16;
+
+/// Largest packet the reader accepts, to stop a corrupt length from allocating without bound.
+const MAX_RECORD_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Writes the classic libpcap format, with microsecond timestamps in little-endian byte order.
+///
+/// The format has nowhere to record whether a packet was sent or received.
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    snaplen: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header. Packets longer than `snaplen` are truncated.
+    pub fn new(mut writer: W, link_type: LinkType, snaplen: u32) -> io::Result<Self> {
+        write_all(&mut writer, &[
+            &MAGIC_MICROSECONDS.to_le_bytes(),
+            &VERSION.0.to_le_bytes(),
+            &VERSION.1.to_le_bytes(),
+            &0i32.to_le_bytes(),    // Time zone offset, always UTC
+            &0u32.to_le_bytes(),    // Timestamp accuracy, always 0
+            &snaplen.to_le_bytes(),
+            &u32::from(u16::from(link_type)).to_le_bytes(),
+        ])?;
+
+        Ok(Self { writer, snaplen })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> CaptureWriter for PcapWriter<W> {
+    fn write_packet(&mut self, timestamp: SystemTime, _direction: Direction, data: &[u8]) -> io::Result<()> {
+        let since_epoch = since_epoch(timestamp);
+        let captured = &data[..data.len().min(self.snaplen as usize)];
+
+        write_all(&mut self.writer, &[
+            &(since_epoch.as_secs() as u32).to_le_bytes(),
+            &since_epoch.subsec_micros().to_le_bytes(),
+            &(captured.len() as u32).to_le_bytes(),
+            &(data.len() as u32).to_le_bytes(),
+            captured,
+        ])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads the classic libpcap format in either byte order, with microsecond or nanosecond timestamps.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    order: ByteOrder,
+    nanoseconds: bool,
+    link_type: LinkType,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        Self::after_magic(reader, magic)
+    }
+
+    pub(super) fn after_magic(mut reader: R, magic: [u8; 4]) -> io::Result<Self> {
+        let (order, nanoseconds) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (MAGIC_MICROSECONDS, _) => (ByteOrder { big_endian: false }, false),
+            (MAGIC_NANOSECONDS, _) => (ByteOrder { big_endian: false }, true),
+            (_, MAGIC_MICROSECONDS) => (ByteOrder { big_endian: true }, false),
+            (_, MAGIC_NANOSECONDS) => (ByteOrder { big_endian: true }, true),
+            _ => return Err(invalid_data(format!("not a capture file: magic number {magic:02x?}"))),
+        };
+
+        let mut header = [0u8; HEADER_LENGTH - 4];
+        reader.read_exact(&mut header)?;
+
+        let version = (order.u16(&header[0..]), order.u16(&header[2..]));
+        if version.0 != VERSION.0 {
+            return Err(invalid_data(format!("unsupported pcap version {}.{}", version.0, version.1)));
+        }
+
+        // The link type shares its field with the FCS length in some writers.
+        let link_type = LinkType::from(order.u32(&header[16..]) as u16);
+
+        Ok(Self { reader, order, nanoseconds, link_type })
+    }
+
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0u8; RECORD_HEADER_LENGTH];
+        if !read_or_end(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = self.order.u32(&header[0..]) as u64;
+        let fraction = self.order.u32(&header[4..]);
+        let captured = self.order.u32(&header[8..]) as usize;
+        let original_length = self.order.u32(&header[12..]);
+
+        let fraction = if self.nanoseconds {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+
+        if captured > MAX_RECORD_LENGTH {
+            return Err(invalid_data(format!("pcap record of {captured} bytes is too long")));
+        }
+
+        let mut data = vec![0u8; captured];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(Record {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds) + fraction,
+            original_length,
+            direction: None,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[test]
+fn test_pcap_round_trip() {
+    use crate::capture::Reader;
+
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+
+    let mut writer = PcapWriter::new(vec![], LinkType::Ethernet, 4).unwrap();
+    writer.write_packet(timestamp, Direction::Inbound, &[1, 2, 3]).unwrap();
+    writer.write_packet(timestamp, Direction::Outbound, &[1, 2, 3, 4, 5, 6]).unwrap();
+    let file = writer.into_inner();
+
+    assert_eq!(file.len(), HEADER_LENGTH + 2 * RECORD_HEADER_LENGTH + 3 + 4);
+
+    let reader = Reader::new(file.as_slice()).unwrap();
+    assert_eq!(reader.link_type(), LinkType::Ethernet);
+
+    let records = reader.collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(records[0], Record { timestamp, original_length: 3, direction: None, data: vec![1, 2, 3] });
+    assert_eq!(records[1].original_length, 6);
+    assert_eq!(records[1].data, vec![1, 2, 3, 4]);
+
+    // The same header written big-endian with nanosecond timestamps.
+    let mut file = vec![];
+    file.extend(MAGIC_NANOSECONDS.to_be_bytes());
+    file.extend([0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+    file.extend([0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0xaa]);
+
+    let record = PcapReader::new(file.as_slice()).unwrap().next().unwrap().unwrap();
+    assert_eq!(record.timestamp, SystemTime::UNIX_EPOCH + Duration::new(1, 2));
+    assert_eq!(record.data, vec![0xaa]);
+
+    // A corrupt length is refused rather than allocated.
+    file.extend([0, 0, 0, 1, 0, 0, 0, 2, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1]);
+    assert!(PcapReader::new(file.as_slice()).unwrap().nth(1).unwrap().is_err());
+
+    assert!(PcapReader::new([0u8; 24].as_slice()).is_err());
+}
@@ -0,0 +1,384 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime};
+
+use super::{read_or_end, since_epoch, write_all, invalid_data, ByteOrder, CaptureWriter, Direction, LinkType, Record};
+
+/// Block type of the section header, which is the same in either byte order.
+pub(super) const SECTION_HEADER: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const SIMPLE_PACKET: u32 = 0x0000_0003;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const VERSION: (u16, u16) = (1, 0);
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Largest block the reader accepts, to stop a corrupt length from allocating without bound.
+const MAX_BLOCK_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Writes pcapng with a single interface, microsecond timestamps, and the direction of each
+/// packet in its flags.
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    snaplen: u32,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and the description of an unnamed interface.
+    pub fn new(writer: W, link_type: LinkType, snaplen: u32) -> io::Result<Self> {
+        Self::with_interface_name(writer, link_type, snaplen, None)
+    }
+
+    pub fn with_interface_name(mut writer: W, link_type: LinkType, snaplen: u32, name: Option<&str>) -> io::Result<Self> {
+        let mut section = vec![];
+        section.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend(VERSION.0.to_le_bytes());
+        section.extend(VERSION.1.to_le_bytes());
+        section.extend((-1i64).to_le_bytes());  // Section length not given
+        write_block(&mut writer, u32::from_le_bytes(SECTION_HEADER), &section)?;
+
+        let mut interface = vec![];
+        interface.extend(u16::from(link_type).to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        interface.extend(snaplen.to_le_bytes());
+        if let Some(name) = name {
+            push_option(&mut interface, OPT_IF_NAME, name.as_bytes());
+            push_option(&mut interface, OPT_END, &[]);
+        }
+        write_block(&mut writer, INTERFACE_DESCRIPTION, &interface)?;
+
+        Ok(Self { writer, snaplen })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> CaptureWriter for PcapngWriter<W> {
+    fn write_packet(&mut self, timestamp: SystemTime, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let microseconds = since_epoch(timestamp).as_micros() as u64;
+        let captured = &data[..data.len().min(self.snaplen as usize)];
+
+        let mut body = vec![];
+        body.extend(0u32.to_le_bytes());    // Interface ID
+        body.extend(((microseconds >> 32) as u32).to_le_bytes());
+        body.extend((microseconds as u32).to_le_bytes());
+        body.extend((captured.len() as u32).to_le_bytes());
+        body.extend((data.len() as u32).to_le_bytes());
+        body.extend(captured);
+        pad(&mut body);
+
+        let flags: u32 = match direction {
+            Direction::Inbound => 0b01,
+            Direction::Outbound => 0b10,
+        };
+        push_option(&mut body, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        push_option(&mut body, OPT_END, &[]);
+
+        write_block(&mut self.writer, ENHANCED_PACKET, &body)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let length = (12 + body.len()) as u32;
+    write_all(writer, &[&block_type.to_le_bytes(), &length.to_le_bytes(), body, &length.to_le_bytes()])
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend(code.to_le_bytes());
+    buf.extend((value.len() as u16).to_le_bytes());
+    buf.extend(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// An interface described in the current section.
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    /// Timestamp units per second.
+    resolution: u64,
+}
+
+/// Reads pcapng in either byte order. Packets are taken from enhanced and simple packet blocks;
+/// other blocks are skipped.
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    order: ByteOrder,
+    interfaces: Vec<Interface>,
+    /// The link type of the first interface, which is kept across sections.
+    link_type: Option<LinkType>,
+    /// The first packet, read on opening to get to the interfaces described before it.
+    first: Option<Record>,
+}
+
+impl<R: Read> PcapngReader<R> {
+    /// Reads the section header at the start of the file.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut block_type = [0u8; 4];
+        reader.read_exact(&mut block_type)?;
+        if block_type != SECTION_HEADER {
+            return Err(invalid_data("not a pcapng file: missing section header"));
+        }
+
+        Self::after_magic(reader)
+    }
+
+    pub(super) fn after_magic(reader: R) -> io::Result<Self> {
+        let mut this = Self {
+            reader,
+            order: ByteOrder { big_endian: false },
+            interfaces: vec![],
+            link_type: None,
+            first: None,
+        };
+
+        let mut start = [0u8; 8];
+        this.reader.read_exact(&mut start)?;
+        this.read_section_header(start)?;
+        this.first = this.read_record()?;
+        Ok(this)
+    }
+
+    /// The link type of the first interface, or Ethernet if the capture describes none.
+    pub fn link_type(&self) -> LinkType {
+        self.link_type.unwrap_or(LinkType::Ethernet)
+    }
+
+    /// Reads the rest of a block after its type and length, without the trailing length.
+    fn read_body(&mut self, length: usize, already_read: usize) -> io::Result<Vec<u8>> {
+        if length < 12 || length < already_read + 4 || length > MAX_BLOCK_LENGTH || !length.is_multiple_of(4) {
+            return Err(invalid_data(format!("invalid pcapng block length {length}")));
+        }
+
+        let mut body = vec![0u8; length - already_read];
+        self.reader.read_exact(&mut body)?;
+        body.truncate(body.len() - 4);
+        Ok(body)
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let mut start = [0u8; 8];
+            if !read_or_end(&mut self.reader, &mut start)? {
+                return Ok(None);
+            }
+
+            if start[..4] == SECTION_HEADER {
+                let mut header = [0u8; 8];
+                header[..4].copy_from_slice(&start[4..]);
+                self.reader.read_exact(&mut header[4..])?;
+                self.read_section_header(header)?;
+                continue;
+            }
+
+            let block_type = self.order.u32(&start);
+            let length = self.order.u32(&start[4..]) as usize;
+            let body = self.read_body(length, 8)?;
+
+            match block_type {
+                INTERFACE_DESCRIPTION => self.read_interface(&body)?,
+                ENHANCED_PACKET => return self.read_enhanced_packet(&body).map(Some),
+                SIMPLE_PACKET => return self.read_simple_packet(&body).map(Some),
+                _ => (),
+            }
+        }
+    }
+
+    /// Reads the rest of a section header, given its length and byte-order magic. Interfaces
+    /// are numbered from zero again in each section.
+    fn read_section_header(&mut self, start: [u8; 8]) -> io::Result<()> {
+        self.order = match (u32::from_le_bytes([start[4], start[5], start[6], start[7]]), u32::from_be_bytes([start[4], start[5], start[6], start[7]])) {
+            (BYTE_ORDER_MAGIC, _) => ByteOrder { big_endian: false },
+            (_, BYTE_ORDER_MAGIC) => ByteOrder { big_endian: true },
+            _ => return Err(invalid_data("invalid pcapng byte-order magic")),
+        };
+
+        let length = self.order.u32(&start) as usize;
+        self.read_body(length, 12)?;
+        self.interfaces.clear();
+        Ok(())
+    }
+
+    fn read_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid_data("truncated pcapng interface description"));
+        }
+
+        let link_type = LinkType::from(self.order.u16(body));
+        let mut resolution = 1_000_000;
+
+        for (code, value) in self.options(&body[8..]) {
+            if code == OPT_IF_TSRESOL && !value.is_empty() {
+                // The top bit picks a power of two rather than of ten.
+                let exponent = (value[0] & 0x7f) as u32;
+                resolution = if value[0] & 0x80 == 0 { 10u64.checked_pow(exponent) } else { 1u64.checked_shl(exponent) }
+                    .ok_or_else(|| invalid_data("pcapng timestamp resolution out of range"))?;
+            }
+        }
+
+        self.link_type.get_or_insert(link_type);
+        self.interfaces.push(Interface { resolution });
+        Ok(())
+    }
+
+    fn read_enhanced_packet(&self, body: &[u8]) -> io::Result<Record> {
+        if body.len() < 20 {
+            return Err(invalid_data("truncated pcapng packet block"));
+        }
+
+        let interface = self.interface(self.order.u32(body))?;
+        let ticks = (self.order.u32(&body[4..]) as u64) << 32 | self.order.u32(&body[8..]) as u64;
+        let captured = self.order.u32(&body[12..]) as usize;
+        let original_length = self.order.u32(&body[16..]);
+
+        let data = body.get(20..20 + captured).ok_or_else(|| invalid_data("pcapng packet longer than its block"))?;
+        let options_start = 20 + captured.next_multiple_of(4);
+
+        let direction = self.options(body.get(options_start..).unwrap_or_default())
+            .find(|(code, value)| *code == OPT_EPB_FLAGS && value.len() == 4)
+            .and_then(|(_, value)| match self.order.u32(value) & 0b11 {
+                0b01 => Some(Direction::Inbound),
+                0b10 => Some(Direction::Outbound),
+                _ => None,
+            });
+
+        Ok(Record {
+            timestamp: SystemTime::UNIX_EPOCH + ticks_to_duration(ticks, interface.resolution),
+            original_length,
+            direction,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Simple packet blocks have no timestamp, so they are given the Unix epoch.
+    fn read_simple_packet(&self, body: &[u8]) -> io::Result<Record> {
+        if body.len() < 4 {
+            return Err(invalid_data("truncated pcapng simple packet block"));
+        }
+
+        let original_length = self.order.u32(body);
+        let captured = (original_length as usize).min(body.len() - 4);
+
+        Ok(Record {
+            timestamp: SystemTime::UNIX_EPOCH,
+            original_length,
+            direction: None,
+            data: body[4..4 + captured].to_vec(),
+        })
+    }
+
+    fn interface(&self, id: u32) -> io::Result<Interface> {
+        self.interfaces.get(id as usize).copied().ok_or_else(|| invalid_data(format!("pcapng packet for undescribed interface {id}")))
+    }
+
+    /// The options in `buf` as code and value pairs, up to the end marker.
+    fn options<'a>(&self, mut buf: &'a [u8]) -> impl Iterator<Item = (u16, &'a [u8])> + 'a {
+        let order = self.order;
+        std::iter::from_fn(move || {
+            if buf.len() < 4 {
+                return None;
+            }
+
+            let (code, length) = (order.u16(buf), order.u16(&buf[2..]) as usize);
+            let value = buf.get(4..4 + length)?;
+            if code == OPT_END {
+                return None;
+            }
+
+            buf = buf.get(4 + length.next_multiple_of(4)..).unwrap_or_default();
+            Some((code, value))
+        })
+    }
+}
+
+/// `ticks` of `resolution` a second. The remainder is scaled in 128 bits, which resolutions
+/// finer than nanoseconds need.
+fn ticks_to_duration(ticks: u64, resolution: u64) -> Duration {
+    let nanoseconds = (ticks % resolution) as u128 * 1_000_000_000 / resolution as u128;
+    Duration::from_secs(ticks / resolution) + Duration::from_nanos(nanoseconds as u64)
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.first.take() {
+            Some(record) => Some(Ok(record)),
+            None => self.read_record().transpose(),
+        }
+    }
+}
+
+#[test]
+fn test_pcapng_round_trip() {
+    use crate::capture::Reader;
+    use crate::common::address::MacAddress;
+    use crate::common::Serialise;
+    use crate::protocols::ethernet::{EtherType, Frame};
+
+    let frame = Frame::new(MacAddress::from([0x02, 0, 0, 0, 0, 2]), MacAddress::from([0x02, 0, 0, 0, 0, 1]), EtherType::Ipv4, vec![0x45; 46]);
+    let mut bytes = vec![0u8; frame.byte_length()];
+    frame.serialise(&mut bytes);
+
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_654_321);
+
+    let mut writer = PcapngWriter::with_interface_name(vec![], LinkType::Ethernet, 65535, Some("tap0")).unwrap();
+    writer.write_packet(timestamp, Direction::Outbound, &bytes).unwrap();
+    writer.write_packet(timestamp, Direction::Inbound, &[0xff; 3]).unwrap();
+    let file = writer.into_inner();
+    assert_eq!(file.len() % 4, 0);
+
+    let records = Reader::new(file.as_slice()).unwrap().collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(records[0], Record { timestamp, original_length: bytes.len() as u32, direction: Some(Direction::Outbound), data: bytes });
+    assert_eq!(records[1].direction, Some(Direction::Inbound));
+
+    // The link type is known before any packet has been read, even if there are none.
+    let raw = PcapngWriter::new(vec![], LinkType::Raw, 65535).unwrap().into_inner();
+    assert_eq!(Reader::new(raw.as_slice()).unwrap().link_type(), LinkType::Raw);
+
+    // Only the first packet is a whole frame.
+    let frames = Reader::new(file.as_slice()).unwrap().frames().collect::<Vec<_>>();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].source(), frame.source());
+    assert_eq!(frames[0].data(), frame.data());
+
+    // A second section may use the other byte order and its own resolution.
+    let mut section = vec![];
+    section.extend(SECTION_HEADER);
+    section.extend(28u32.to_be_bytes());
+    section.extend(BYTE_ORDER_MAGIC.to_be_bytes());
+    section.extend([0, 1, 0, 0]);
+    section.extend((-1i64).to_be_bytes());
+    section.extend(28u32.to_be_bytes());
+    section.extend(INTERFACE_DESCRIPTION.to_be_bytes());
+    section.extend(28u32.to_be_bytes());
+    section.extend([0, 1, 0, 0, 0, 0, 0xff, 0xff]);
+    section.extend([0, 9, 0, 1, 3, 0, 0, 0]);   // Milliseconds
+    section.extend(28u32.to_be_bytes());
+    section.extend(ENHANCED_PACKET.to_be_bytes());
+    section.extend(36u32.to_be_bytes());
+    section.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x04, 0xd2, 0, 0, 0, 1, 0, 0, 0, 1, 0xaa, 0, 0, 0]);
+    section.extend(36u32.to_be_bytes());
+
+    let mut file = file;
+    file.extend(section);
+    let record = Reader::new(file.as_slice()).unwrap().nth(2).unwrap().unwrap();
+    assert_eq!(record.timestamp, SystemTime::UNIX_EPOCH + Duration::from_millis(1234));
+    assert_eq!(record.data, vec![0xaa]);
+
+    // Picoseconds, with a remainder that overflows 64 bits once scaled to nanoseconds.
+    assert_eq!(ticks_to_duration(5_999_999_999_999, 1_000_000_000_000), Duration::new(5, 999_999_999));
+}
@@ -11,6 +11,29 @@ impl core::fmt::Display for Ipv4Address {
     }
 }
 
+impl Ipv4Address {
+    pub const UNSPECIFIED: Self = Self { bytes: [0; 4] };
+    /// The limited broadcast address, which reaches every host on the link and no further.
+    pub const BROADCAST: Self = Self { bytes: [255; 4] };
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] & 0xf0 == 0xe0
+    }
+
+    /// Whether the address is in `169.254.0.0/16`, which routers do not forward (RFC 3927).
+    pub fn is_link_local(&self) -> bool {
+        self.bytes[..2] == [169, 254]
+    }
+}
+
 addr_type! {
     pub Ipv6Address(16, u128)
 }
@@ -25,4 +48,30 @@ impl core::fmt::Display for Ipv6Address {
             self.bytes[12], self.bytes[13], self.bytes[14], self.bytes[15],
         )
     }
-}
\ No newline at end of file
+}
+
+impl Ipv6Address {
+    pub const UNSPECIFIED: Self = Self { bytes: [0; 16] };
+    pub const ALL_NODES: Self = Self { bytes: [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01] };
+    pub const ALL_ROUTERS: Self = Self { bytes: [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02] };
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_multicast(&self) -> bool {
+        self.bytes[0] == 0xff
+    }
+
+    /// Whether the address is in `fe80::/10`, which routers do not forward (RFC 4291).
+    pub fn is_link_local(&self) -> bool {
+        self.bytes[0] == 0xfe && self.bytes[1] & 0xc0 == 0x80
+    }
+
+    /// The solicited-node multicast group that Neighbor Solicitations for this address are sent to (RFC 4291).
+    pub fn solicited_node(&self) -> Self {
+        let mut bytes = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, 0, 0, 0];
+        bytes[13..].copy_from_slice(&self.bytes[13..]);
+        Self { bytes }
+    }
+}
@@ -4,8 +4,13 @@ addr_type! {
 
 #[allow(dead_code)]
 impl MacAddress {
+    pub const BROADCAST: Self = Self { bytes: [0xff; 6] };
+
     pub fn from_hex(s: &str) -> Option<Self> {
         let chunks = s.split(':');
+        if chunks.clone().count() != 6 {
+            return None;
+        }
 
         let mut bytes = [0u8; 6];
         for (i, b) in chunks.enumerate() {
@@ -47,6 +52,12 @@ impl MacAddress {
 
         Some(Self::from(bytes))
     }
+
+    /// The Ethernet multicast address that IPv6 multicast group `address` maps to (RFC 2464).
+    pub fn ipv6_multicast(address: super::Ipv6Address) -> Self {
+        let address: [u8; 16] = address.into();
+        Self::from([0x33, 0x33, address[12], address[13], address[14], address[15]])
+    }
 }
 
 impl core::fmt::Display for MacAddress {
@@ -79,4 +90,5 @@ fn test_macaddress() {
 
     let mac = MacAddress::from_hex("fe:71:4d:96:e5:95").unwrap();
     assert_eq!(mac.to_string(), "fe:71:4d:96:e5:95");
+    assert!(MacAddress::from_hex("fe:71").is_none());
 }
\ No newline at end of file
@@ -12,7 +12,7 @@ macro_rules! addr_type {
         $addr_type:ident
         ($byte_len:literal $(,$num_type:ty)?)
     ) => {
-        #[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+        #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
         #[repr(transparent)]
         $vis struct $addr_type {
             bytes: [u8; $byte_len]
@@ -0,0 +1,164 @@
+use super::address::{Address, Ipv4Address, Ipv6Address};
+
+/// Ones' complement accumulator for the Internet checksum (RFC 1071).
+///
+/// Data may be fed in any number of pieces; an odd trailing byte is held
+/// back and paired with the first byte of the next piece.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Checksum {
+    sum: u32,
+    odd: Option<u8>,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a checksum with the IPv4 pseudo-header used by UDP and TCP.
+    pub fn ipv4_pseudo_header(source: Ipv4Address, destination: Ipv4Address, proto: u8, length: u16) -> Self {
+        let mut checksum = Self::new();
+        checksum.add_bytes(source.bytes());
+        checksum.add_bytes(destination.bytes());
+        checksum.add_u16(proto as u16);
+        checksum.add_u16(length);
+        checksum
+    }
+
+    /// Starts a checksum with the IPv6 pseudo-header used by UDP, TCP and ICMPv6.
+    pub fn ipv6_pseudo_header(source: Ipv6Address, destination: Ipv6Address, next_header: u8, length: u32) -> Self {
+        let mut checksum = Self::new();
+        checksum.add_bytes(source.bytes());
+        checksum.add_bytes(destination.bytes());
+        checksum.add_u32(length);
+        checksum.add_u32(next_header as u32);
+        checksum
+    }
+
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        let bytes = match self.odd.take() {
+            Some(msb) if !bytes.is_empty() => {
+                self.add_u16(u16::from_be_bytes([msb, bytes[0]]));
+                &bytes[1..]
+            },
+            odd => {
+                self.odd = odd;
+                bytes
+            },
+        };
+
+        let mut chunks = bytes.chunks_exact(2);
+        for pair in &mut chunks {
+            self.sum += u16::from_be_bytes([pair[0], pair[1]]) as u32;
+        }
+
+        if let [last] = chunks.remainder() {
+            self.odd = Some(*last);
+        }
+
+        self.fold();
+    }
+
+    pub fn add_u16(&mut self, value: u16) {
+        self.sum += value as u32;
+        self.fold();
+    }
+
+    pub fn add_u32(&mut self, value: u32) {
+        self.add_u16((value >> 16) as u16);
+        self.add_u16(value as u16);
+    }
+
+    /// The ones' complement sum accumulated so far, without the final complement.
+    pub fn sum(&self) -> u16 {
+        let mut copy = *self;
+        if let Some(msb) = copy.odd.take() {
+            copy.add_u16(u16::from_be_bytes([msb, 0]));
+        }
+
+        copy.sum as u16
+    }
+
+    /// The value to place in a checksum field.
+    pub fn finish(&self) -> u16 {
+        !self.sum()
+    }
+
+    /// Whether data that already contains its checksum field sums to zero.
+    pub fn is_valid(&self) -> bool {
+        self.sum() == 0xffff
+    }
+
+    fn fold(&mut self) {
+        while self.sum > 0xffff {
+            self.sum = (self.sum & 0xffff) + (self.sum >> 16);
+        }
+    }
+}
+
+/// The addresses of the IP pseudo-header that UDP, TCP and ICMPv6 checksums cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoHeader {
+    Ipv4 { source: Ipv4Address, destination: Ipv4Address },
+    Ipv6 { source: Ipv6Address, destination: Ipv6Address },
+}
+
+impl PseudoHeader {
+    /// Starts a checksum over the pseudo-header for an upper-layer message of `length` bytes.
+    pub fn checksum(&self, proto: u8, length: u32) -> Checksum {
+        match *self {
+            Self::Ipv4 { source, destination } => Checksum::ipv4_pseudo_header(source, destination, proto, length as u16),
+            Self::Ipv6 { source, destination } => Checksum::ipv6_pseudo_header(source, destination, proto, length),
+        }
+    }
+}
+
+/// Computes the Internet checksum of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u16 {
+    let mut checksum = Checksum::new();
+    checksum.add_bytes(bytes);
+    checksum.finish()
+}
+
+/// Verifies `bytes`, which must include their own checksum field.
+pub fn verify(bytes: &[u8]) -> bool {
+    let mut checksum = Checksum::new();
+    checksum.add_bytes(bytes);
+    checksum.is_valid()
+}
+
+/// Incrementally updates `checksum` after a 16-bit field changed from `old` to `new` (RFC 1624, eqn. 3).
+pub fn update(checksum: u16, old: u16, new: u16) -> u16 {
+    let mut sum = Checksum::new();
+    sum.add_u16(!checksum);
+    sum.add_u16(!old);
+    sum.add_u16(new);
+    sum.finish()
+}
+
+/// Incrementally updates `checksum` after a 32-bit field (such as an address) changed.
+pub fn update_u32(checksum: u16, old: u32, new: u32) -> u16 {
+    let checksum = update(checksum, (old >> 16) as u16, (new >> 16) as u16);
+    update(checksum, old as u16, new as u16)
+}
+
+#[test]
+fn test_checksum() {
+    // Example from RFC 1071 section 3.
+    let bytes = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+    assert_eq!(checksum(&bytes), !0xddf2);
+
+    let mut split = Checksum::new();
+    split.add_bytes(&bytes[..3]);
+    split.add_bytes(&bytes[3..]);
+    assert_eq!(split.finish(), checksum(&bytes));
+
+    let mut with_checksum = bytes.to_vec();
+    with_checksum.extend_from_slice(&checksum(&bytes).to_be_bytes());
+    assert!(verify(&with_checksum));
+
+    // Changing a word and patching incrementally must match a full recomputation.
+    let mut changed = bytes;
+    changed[2..4].copy_from_slice(&0x1234u16.to_be_bytes());
+    assert_eq!(update(checksum(&bytes), 0xf203, 0x1234), checksum(&changed));
+}
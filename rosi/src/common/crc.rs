@@ -0,0 +1,68 @@
+/// Reflected form of the IEEE 802.3 polynomial 0x04C11DB7.
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Table-driven CRC-32 as used by the Ethernet frame check sequence.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self { crc: 0xffff_ffff }
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.crc = (self.crc >> 8) ^ TABLE[((self.crc ^ *b as u32) & 0xff) as usize];
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+/// Computes the CRC-32 of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.add_bytes(bytes);
+    crc.finish()
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    assert_eq!(crc32(&[]), 0);
+
+    let mut split = Crc32::new();
+    split.add_bytes(b"1234");
+    split.add_bytes(b"56789");
+    assert_eq!(split.finish(), 0xcbf4_3926);
+}
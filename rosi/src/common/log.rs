@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::Pdu;
+
+/// The value of one field in a structured log line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+}
+
+macro_rules! value_from_int {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(value: $t) -> Self {
+                    Self::Int(value as u64)
+                }
+            }
+        )*
+    };
+}
+
+value_from_int!(u8, u16, u32, u64, usize);
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::Str(value.into())
+    }
+}
+
+impl Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Self::Str(s) => write_json_string(s, out),
+            Self::Int(n) => out.push_str(&n.to_string()),
+            Self::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        }
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Where log lines go.
+pub trait Sink: Send {
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+}
+
+/// Writes lines to standard error.
+pub struct Stderr;
+
+impl Sink for Stderr {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stderr().lock(), "{line}")
+    }
+}
+
+/// Appends lines to a file, flushing after each one.
+pub struct FileSink {
+    file: LineWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self { file: LineWriter::new(file) })
+    }
+}
+
+impl Sink for FileSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{line}")
+    }
+}
+
+/// Keeps lines in memory. Clones share the same buffer, so one can be handed to a logger
+/// and the other used to read back what was logged.
+#[derive(Clone, Default)]
+pub struct Memory {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().clone()
+    }
+}
+
+impl Sink for Memory {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.lines.lock().unwrap().push(line.into());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The action followed by the PDU's one-line summary.
+    Text,
+    /// One JSON object per line, with the action, protocol, summary and header fields.
+    Json,
+}
+
+/// Formats PDUs and writes them to a sink.
+pub struct Logger {
+    sink: Box<dyn Sink>,
+    format: Format,
+}
+
+impl Logger {
+    pub fn new(sink: impl Sink + 'static, format: Format) -> Self {
+        Self {
+            sink: Box::new(sink),
+            format,
+        }
+    }
+
+    /// Formats one line for `pdu`, tagged with what is being done with it.
+    pub fn format<P: Pdu + ?Sized>(&self, action: &str, pdu: &P) -> String {
+        match self.format {
+            Format::Text => format!("{action} {}", pdu.summary()),
+            Format::Json => {
+                let mut out = String::from("{");
+                let fields = [("action", Value::from(action)), ("protocol", pdu.protocol().into()), ("summary", pdu.summary().into())];
+
+                for (i, (name, value)) in fields.into_iter().chain(pdu.fields()).enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(name, &mut out);
+                    out.push(':');
+                    value.write_json(&mut out);
+                }
+
+                out.push('}');
+                out
+            },
+        }
+    }
+
+    /// Writes a line for `pdu`. Failing to log is not worth stopping the stack for, so errors
+    /// are reported on standard error and otherwise ignored.
+    pub fn log<P: Pdu + ?Sized>(&mut self, action: &str, pdu: &P) {
+        let line = self.format(action, pdu);
+        if let Err(e) = self.sink.write_line(&line) {
+            eprintln!("log: {e}");
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new(Stderr, Format::Text)
+    }
+}
+
+/// The logger `Pdu::log` writes to. Until one is set, PDUs are logged as text to standard error.
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+pub fn set_logger(logger: Logger) {
+    *LOGGER.lock().unwrap() = Some(logger);
+}
+
+/// Writes a line for `pdu` to the global logger.
+pub fn log<P: Pdu + ?Sized>(action: &str, pdu: &P) {
+    LOGGER.lock().unwrap().get_or_insert_with(Logger::default).log(action, pdu);
+}
+
+#[test]
+fn test_json_lines() {
+    use crate::common::address::{Ipv4Address, MacAddress};
+    use crate::protocols::arp;
+
+    let request = arp::Packet::request(
+        MacAddress::from([0x02, 0, 0, 0, 0, 1]).into(), Ipv4Address::from([10, 0, 0, 1]).into(),
+        MacAddress::default().into(), Ipv4Address::from([10, 0, 0, 2]).into(),
+    ).unwrap();
+
+    let memory = Memory::new();
+    let mut logger = Logger::new(memory.clone(), Format::Json);
+    logger.log("DROP", &request);
+    logger.log("PROCESS \"quoted\"", &Arc::<[u8]>::from([0u8; 3]));
+
+    assert_eq!(memory.lines(), vec![
+        concat!(
+            r#"{"action":"DROP","protocol":"arp","summary":"ARP, Request who-has 10.0.0.2 tell 10.0.0.1","#,
+            r#""operation":"Request","sha":"02:00:00:00:00:01","spa":"10.0.0.1","tha":"00:00:00:00:00:00","tpa":"10.0.0.2"}"#,
+        ).to_string(),
+        r#"{"action":"PROCESS \"quoted\"","protocol":"raw","summary":"3 bytes","length":3}"#.to_string(),
+    ]);
+
+    let mut logger = Logger::new(memory.clone(), Format::Text);
+    logger.log("DROP", &request);
+    assert_eq!(memory.lines()[2], "DROP ARP, Request who-has 10.0.0.2 tell 10.0.0.1");
+}
@@ -3,11 +3,19 @@
 pub mod address;
 pub use address::Address;
 
+pub mod checksum;
+pub use checksum::{Checksum, PseudoHeader};
+
+pub mod crc;
+pub use crc::Crc32;
+
+pub mod log;
+
 mod layer;
 pub use layer::Layer;
 
 pub mod pdu;
-pub use pdu::Pdu;
+pub use pdu::{Pdu, Wrapper};
 
 #[macro_use]
 mod serialise;
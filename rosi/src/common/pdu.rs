@@ -1,17 +1,45 @@
 use std::sync::Arc;
 
 use super::Serialise;
+use super::log::{self, Value};
 
 pub trait Pdu: Serialise {
-    fn log(&self, action: &str);
+    /// The protocol's name in structured logs, such as `ipv4`.
+    fn protocol(&self) -> &'static str;
+
+    /// A one-line summary in the style of tcpdump.
+    fn summary(&self) -> String;
+
+    /// The header fields by name, for structured logs.
+    fn fields(&self) -> Vec<(&'static str, Value)>;
+
+    /// Writes the PDU to the log, tagged with what is being done with it.
+    fn log(&self, action: &str) {
+        log::log(action, self)
+    }
 }
 
 impl Pdu for Arc<[u8]> {
-    fn log(&self, _: &str) {
-        unimplemented!()
+    fn protocol(&self) -> &'static str {
+        "raw"
+    }
+
+    fn summary(&self) -> String {
+        format!("{} bytes", self.len())
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![("length", self.len().into())]
     }
 }
 
+/// A PDU that encapsulates the PDU of the layer above it.
 pub trait Wrapper {
     fn unwrap_data(&self) -> Arc<[u8]>;
+}
+
+impl Wrapper for Arc<[u8]> {
+    fn unwrap_data(&self) -> Arc<[u8]> {
+        self.clone()
+    }
 }
\ No newline at end of file
@@ -5,6 +5,8 @@ pub enum DeserialiseError {
     Static(&'static str),
     Heap(String),
     BufferTooSmall(&'static str, u32, u32, usize, usize),
+    ChecksumMismatch(&'static str, u16, u16),
+    FcsMismatch(u32, u32),
 }
 
 impl From<&'static str> for DeserialiseError {
@@ -26,7 +28,9 @@ impl core::fmt::Display for DeserialiseError {
         match self {
             Self::Static(s) => write!(f, "{s}."),
             Self::Heap(s) => write!(f, "{s}."),
-            Self::BufferTooSmall(file, l, c, required, bufsize) => write!(f, "{file}:{l}:{c} buffer too small (expected {required}, actual {bufsize}).")
+            Self::BufferTooSmall(file, l, c, required, bufsize) => write!(f, "{file}:{l}:{c} buffer too small (expected {required}, actual {bufsize})."),
+            Self::ChecksumMismatch(proto, expected, actual) => write!(f, "{proto} checksum mismatch (expected {expected:04x}, actual {actual:04x})."),
+            Self::FcsMismatch(expected, actual) => write!(f, "frame check sequence mismatch (expected {expected:08x}, actual {actual:08x}).")
         }
     }
 }
@@ -94,8 +98,8 @@ impl Serialise for Arc<[u8]> {
         len
     }
 
-    fn deserialise(_: &[u8]) -> Result<Self, DeserialiseError> {
-        unimplemented!()
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        Ok(Arc::from(buf))
     }
 }
 
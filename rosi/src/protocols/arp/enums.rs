@@ -38,7 +38,7 @@ macro_rules! address_type {
             $(,)?
         }
     ) => {
-        #[derive(Debug, Copy, Clone)]
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
         $vis enum $enum_name {
             $($addr_type($addr_type)),*
         }
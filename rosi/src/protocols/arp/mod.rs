@@ -1,3 +1,4 @@
 mod enums;
 mod packet;
+pub use enums::{Htype, Operation, HardwareAddress, ProtocolAddress};
 pub use packet::Packet;
\ No newline at end of file
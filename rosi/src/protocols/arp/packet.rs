@@ -1,4 +1,5 @@
-use crate::common::{DeserialiseError, Serialise, serialise_fields};
+use crate::common::{DeserialiseError, Pdu, Serialise, serialise_fields};
+use crate::common::log::Value;
 
 use crate::protocols::ethernet;
 use super::enums::{
@@ -6,7 +7,7 @@ use super::enums::{
     HardwareAddress, ProtocolAddress,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Packet {
     htype: Htype,
     ptype: ethernet::EtherType,
@@ -130,6 +131,30 @@ impl core::fmt::Display for Packet {
     }
 }
 
+impl Pdu for Packet {
+    fn protocol(&self) -> &'static str {
+        "arp"
+    }
+
+    fn summary(&self) -> String {
+        match self.operation {
+            Operation::Request => format!("ARP, Request who-has {} tell {}", self.tpa, self.spa),
+            Operation::Response => format!("ARP, Reply {} is-at {}", self.spa, self.sha),
+            operation => format!("ARP, {} {} > {}", operation, self.spa, self.tpa),
+        }
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("operation", self.operation.to_string().into()),
+            ("sha", self.sha.to_string().into()),
+            ("spa", self.spa.to_string().into()),
+            ("tha", self.tha.to_string().into()),
+            ("tpa", self.tpa.to_string().into()),
+        ]
+    }
+}
+
 #[test]
 #[allow(unused)]
 fn test_arp_multiple_new() {
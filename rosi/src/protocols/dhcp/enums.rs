@@ -0,0 +1,21 @@
+use crate::util::serialise_enum;
+
+serialise_enum! {
+    pub Op(u8, 1) {
+        BootRequest: 1,
+        BootReply: 2,
+    }
+}
+
+serialise_enum! {
+    pub MessageType(u8, 1) {
+        Discover: 1,
+        Offer: 2,
+        Request: 3,
+        Decline: 4,
+        Ack: 5,
+        Nak: 6,
+        Release: 7,
+        Inform: 8,
+    }
+}
@@ -0,0 +1,403 @@
+use crate::common::{DeserialiseError, Pdu, Serialise, serialise_fields};
+use crate::common::address::{Ipv4Address, MacAddress};
+use crate::common::log::Value;
+use crate::protocols::arp::Htype;
+
+use super::{DhcpOption, MessageType, Op};
+
+/// The fixed BOOTP header, up to and including the file field.
+const HEADER_LENGTH: usize = 236;
+
+/// Marks the start of the options (RFC 2131, section 3).
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// BOOTP relay agents may drop messages shorter than this (RFC 1542, section 2.1).
+const MIN_LENGTH: usize = 300;
+
+const CHADDR_LENGTH: usize = 16;
+const SNAME_LENGTH: usize = 64;
+const FILE_LENGTH: usize = 128;
+
+/// Asks the server to broadcast its replies, for clients that cannot receive unicast
+/// datagrams before they have an address.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// A DHCP message (RFC 2131). The server host name and boot file fields are not used by
+/// this stack and are always written empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    op: Op,
+    htype: Htype,
+    hlen: u8,
+    hops: u8,
+    xid: u32,
+    secs: u16,
+    flags: u16,
+    ciaddr: Ipv4Address,
+    yiaddr: Ipv4Address,
+    siaddr: Ipv4Address,
+    giaddr: Ipv4Address,
+    chaddr: MacAddress,
+    /// Options without the pad and end markers, which are added when writing.
+    options: Vec<DhcpOption>,
+}
+
+impl Message {
+    fn new(op: Op, message_type: MessageType, xid: u32, chaddr: MacAddress) -> Self {
+        Self {
+            op,
+            htype: Htype::Ethernet,
+            hlen: MacAddress::default().byte_length() as u8,
+            hops: 0,
+            xid,
+            secs: 0,
+            flags: 0,
+            ciaddr: Ipv4Address::UNSPECIFIED,
+            yiaddr: Ipv4Address::UNSPECIFIED,
+            siaddr: Ipv4Address::UNSPECIFIED,
+            giaddr: Ipv4Address::UNSPECIFIED,
+            chaddr,
+            options: vec![DhcpOption::MessageType(message_type)],
+        }
+    }
+
+    /// A message from a client, identified by `xid` and its hardware address.
+    pub fn request(message_type: MessageType, xid: u32, chaddr: MacAddress) -> Self {
+        Self::new(Op::BootRequest, message_type, xid, chaddr)
+    }
+
+    /// A message from a server answering `request`, keeping its transaction, flags and relay agent.
+    pub fn reply(request: &Self, message_type: MessageType) -> Self {
+        Self {
+            flags: request.flags,
+            giaddr: request.giaddr,
+            ..Self::new(Op::BootReply, message_type, request.xid, request.chaddr)
+        }
+    }
+
+    crate::util::getter!(op: Op);
+    crate::util::getter!(hops: u8);
+    crate::util::getter!(xid: u32);
+    crate::util::getter!(secs: u16);
+    crate::util::getter!(ciaddr: Ipv4Address);
+    crate::util::getter!(yiaddr: Ipv4Address);
+    crate::util::getter!(siaddr: Ipv4Address);
+    crate::util::getter!(giaddr: Ipv4Address);
+    crate::util::getter!(chaddr: MacAddress);
+
+    /// Seconds since the client began acquiring or renewing its address.
+    pub fn set_secs(&mut self, secs: u16) {
+        self.secs = secs;
+    }
+
+    /// The client's address, set only when it is bound, renewing or rebinding.
+    pub fn set_ciaddr(&mut self, ciaddr: Ipv4Address) {
+        self.ciaddr = ciaddr;
+    }
+
+    /// The address offered or assigned to the client.
+    pub fn set_yiaddr(&mut self, yiaddr: Ipv4Address) {
+        self.yiaddr = yiaddr;
+    }
+
+    pub fn set_siaddr(&mut self, siaddr: Ipv4Address) {
+        self.siaddr = siaddr;
+    }
+
+    pub fn broadcast(&self) -> bool {
+        self.flags & FLAG_BROADCAST != 0
+    }
+
+    pub fn set_broadcast(&mut self, broadcast: bool) {
+        if broadcast {
+            self.flags |= FLAG_BROADCAST;
+        } else {
+            self.flags &= !FLAG_BROADCAST;
+        }
+    }
+
+    pub fn options(&self) -> &[DhcpOption] {
+        &self.options
+    }
+
+    /// Adds `option`, replacing any with the same code.
+    pub fn add_option(&mut self, option: DhcpOption) {
+        self.options.retain(|o| o.code() != option.code());
+        self.options.push(option);
+    }
+
+    pub fn option(&self, code: u8) -> Option<&DhcpOption> {
+        self.options.iter().find(|o| o.code() == code)
+    }
+
+    fn find<'a, T>(&'a self, f: impl Fn(&'a DhcpOption) -> Option<T>) -> Option<T> {
+        self.options.iter().find_map(f)
+    }
+
+    /// The type of the message. A message without one is a plain BOOTP message.
+    pub fn message_type(&self) -> Option<MessageType> {
+        self.find(|o| match o {
+            DhcpOption::MessageType(message_type) => Some(*message_type),
+            _ => None,
+        })
+    }
+
+    pub fn subnet_mask(&self) -> Option<Ipv4Address> {
+        self.find(|o| match o {
+            DhcpOption::SubnetMask(mask) => Some(*mask),
+            _ => None,
+        })
+    }
+
+    pub fn routers(&self) -> &[Ipv4Address] {
+        self.find(|o| match o {
+            DhcpOption::Router(routers) => Some(routers.as_slice()),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    pub fn dns_servers(&self) -> &[Ipv4Address] {
+        self.find(|o| match o {
+            DhcpOption::DomainNameServer(servers) => Some(servers.as_slice()),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    pub fn domain_name(&self) -> Option<&str> {
+        self.find(|o| match o {
+            DhcpOption::DomainName(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn requested_address(&self) -> Option<Ipv4Address> {
+        self.find(|o| match o {
+            DhcpOption::RequestedAddress(address) => Some(*address),
+            _ => None,
+        })
+    }
+
+    pub fn server_identifier(&self) -> Option<Ipv4Address> {
+        self.find(|o| match o {
+            DhcpOption::ServerIdentifier(address) => Some(*address),
+            _ => None,
+        })
+    }
+
+    /// The lease time in seconds, where `u32::MAX` means the lease never expires.
+    pub fn lease_time(&self) -> Option<u32> {
+        self.find(|o| match o {
+            DhcpOption::LeaseTime(seconds) => Some(*seconds),
+            _ => None,
+        })
+    }
+
+    pub fn renewal_time(&self) -> Option<u32> {
+        self.find(|o| match o {
+            DhcpOption::RenewalTime(seconds) => Some(*seconds),
+            _ => None,
+        })
+    }
+
+    pub fn rebinding_time(&self) -> Option<u32> {
+        self.find(|o| match o {
+            DhcpOption::RebindingTime(seconds) => Some(*seconds),
+            _ => None,
+        })
+    }
+
+    pub fn client_identifier(&self) -> Option<&[u8]> {
+        self.find(|o| match o {
+            DhcpOption::ClientIdentifier(id) => Some(id.as_slice()),
+            _ => None,
+        })
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        self.find(|o| match o {
+            DhcpOption::Message(message) => Some(message.as_str()),
+            _ => None,
+        })
+    }
+
+    fn options_length(&self) -> usize {
+        self.options.iter().map(Serialise::byte_length).sum::<usize>() + DhcpOption::End.byte_length()
+    }
+}
+
+impl Serialise for Message {
+    fn byte_length(&self) -> usize {
+        (HEADER_LENGTH + MAGIC_COOKIE.len() + self.options_length()).max(MIN_LENGTH)
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let length = self.byte_length();
+        buf[..length].fill(0);
+
+        let mut chaddr = [0u8; CHADDR_LENGTH];
+        self.chaddr.serialise(&mut chaddr);
+
+        let index = serialise_fields!(
+            buf=buf,
+            self.op,
+            u16::from(self.htype) as u8,
+            self.hlen,
+            self.hops,
+            self.xid,
+            self.secs,
+            self.flags,
+            self.ciaddr,
+            self.yiaddr,
+            self.siaddr,
+            self.giaddr,
+            chaddr.as_slice(),
+        );
+        let index = index + SNAME_LENGTH + FILE_LENGTH;
+        let index = index + MAGIC_COOKIE.as_slice().serialise(&mut buf[index..]);
+
+        let index = self.options.iter().fold(index, |index, option| index + option.serialise(&mut buf[index..]));
+        DhcpOption::End.serialise(&mut buf[index..]);
+
+        length
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let options_start = HEADER_LENGTH + MAGIC_COOKIE.len();
+        if buf.len() < options_start {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), options_start, buf.len()));
+        }
+
+        if buf[HEADER_LENGTH..options_start] != MAGIC_COOKIE {
+            return Err(DeserialiseError::Static("missing dhcp magic cookie"));
+        }
+
+        // The hardware type takes one byte here, against two in ARP.
+        let htype = Htype::from(buf[1] as u16);
+        let hlen = buf[2];
+        if htype != Htype::Ethernet || hlen as usize != MacAddress::default().byte_length() {
+            return Err(DeserialiseError::Heap(format!("unsupported dhcp hardware type {htype} length {hlen}")));
+        }
+
+        let address = |index: usize| Ipv4Address::deserialise(&buf[index..]);
+        let options = DhcpOption::deserialise_list(&buf[options_start..])?
+            .into_iter()
+            .filter(|o| !matches!(o, DhcpOption::Pad | DhcpOption::End))
+            .collect();
+
+        Ok(Self {
+            op: Op::deserialise(buf)?,
+            htype,
+            hlen,
+            hops: buf[3],
+            xid: u32::deserialise(&buf[4..])?,
+            secs: u16::deserialise(&buf[8..])?,
+            flags: u16::deserialise(&buf[10..])?,
+            ciaddr: address(12)?,
+            yiaddr: address(16)?,
+            siaddr: address(20)?,
+            giaddr: address(24)?,
+            chaddr: MacAddress::deserialise(&buf[28..])?,
+            options,
+        })
+    }
+}
+
+impl core::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.message_type() {
+            Some(message_type) => write!(f, "DHCP {message_type}")?,
+            None => write!(f, "BOOTP {}", self.op)?,
+        }
+        write!(f, ", xid 0x{:08x}, chaddr {}", self.xid, self.chaddr)?;
+
+        let addresses = [("ciaddr", self.ciaddr), ("yiaddr", self.yiaddr), ("siaddr", self.siaddr), ("giaddr", self.giaddr)];
+        for (name, address) in addresses.iter().filter(|(_, address)| !address.is_unspecified()) {
+            write!(f, ", {name} {address}")?;
+        }
+        if self.broadcast() {
+            write!(f, ", broadcast")?;
+        }
+
+        self.options.iter()
+            .filter(|o| !matches!(o, DhcpOption::MessageType(..)))
+            .try_for_each(|o| write!(f, ", {o}"))
+    }
+}
+
+impl Pdu for Message {
+    fn protocol(&self) -> &'static str {
+        "dhcp"
+    }
+
+    fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("op", self.op.to_string().into()),
+            ("type", self.message_type().map_or_else(|| "bootp".to_owned(), |t| t.to_string()).into()),
+            ("xid", self.xid.into()),
+            ("chaddr", self.chaddr.to_string().into()),
+            ("yiaddr", self.yiaddr.to_string().into()),
+        ]
+    }
+}
+
+#[test]
+fn test_dhcp_message() {
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 0x0a]);
+    let mut discover = Message::request(MessageType::Discover, 0x3903f326, mac);
+    discover.set_broadcast(true);
+    discover.add_option(DhcpOption::ParameterRequestList(vec![1, 3, 6]));
+    discover.add_option(DhcpOption::RequestedAddress(Ipv4Address::from([192, 168, 0, 10])));
+
+    let mut bytes = vec![0u8; discover.byte_length()];
+    assert_eq!(discover.serialise(&mut bytes), MIN_LENGTH);
+    assert_eq!(&bytes[..4], &[1, 1, 6, 0]);
+    assert_eq!(&bytes[4..8], &[0x39, 0x03, 0xf3, 0x26]);
+    assert_eq!(&bytes[10..12], &[0x80, 0]);
+    assert_eq!(&bytes[28..34], &[0x02, 0, 0, 0, 0, 0x0a]);
+    assert_eq!(&bytes[236..243], &[99, 130, 83, 99, 53, 1, 1]);
+    assert_eq!(bytes[254], 255);
+
+    let parsed = Message::deserialise(&bytes).unwrap();
+    assert_eq!(parsed, discover);
+    assert_eq!(parsed.requested_address(), Some(Ipv4Address::from([192, 168, 0, 10])));
+
+    let mut ack = Message::reply(&parsed, MessageType::Ack);
+    ack.set_yiaddr(Ipv4Address::from([192, 168, 0, 10]));
+    ack.add_option(DhcpOption::ServerIdentifier(Ipv4Address::from([192, 168, 0, 1])));
+    ack.add_option(DhcpOption::SubnetMask(Ipv4Address::from([255, 255, 255, 0])));
+    ack.add_option(DhcpOption::Router(vec![Ipv4Address::from([192, 168, 0, 1])]));
+    ack.add_option(DhcpOption::DomainNameServer(vec![Ipv4Address::from([1, 1, 1, 1]), Ipv4Address::from([8, 8, 8, 8])]));
+    ack.add_option(DhcpOption::LeaseTime(3600));
+    ack.add_option(DhcpOption::ClientIdentifier(vec![1, 2, 0, 0, 0, 0, 0x0a]));
+    ack.add_option(DhcpOption::Unknown { code: 119, data: vec![3, b'l', b'a', b'n', 0] });
+
+    let mut bytes = vec![0u8; ack.byte_length()];
+    ack.serialise(&mut bytes);
+    let parsed = Message::deserialise(&bytes).unwrap();
+    assert_eq!(parsed.op(), Op::BootReply);
+    assert!(parsed.broadcast());
+    assert_eq!(parsed.message_type(), Some(MessageType::Ack));
+    assert_eq!(parsed.dns_servers().len(), 2);
+    assert_eq!(parsed.routers(), &[Ipv4Address::from([192, 168, 0, 1])]);
+    assert_eq!(parsed.lease_time(), Some(3600));
+    assert_eq!(parsed.renewal_time(), None);
+    assert_eq!(parsed.client_identifier(), Some([1, 2, 0, 0, 0, 0, 0x0a].as_slice()));
+    assert_eq!(parsed.option(119), Some(&DhcpOption::Unknown { code: 119, data: vec![3, b'l', b'a', b'n', 0] }));
+    assert_eq!(
+        parsed.to_string(),
+        "DHCP Ack, xid 0x3903f326, chaddr 02:00:00:00:00:0a, yiaddr 192.168.0.10, broadcast, server 192.168.0.1, \
+         subnet-mask 255.255.255.0, router 192.168.0.1, dns 1.1.1.1 8.8.8.8, lease 3600s, client-id 01:02:00:00:00:00:0a, unknown-119 len 5"
+    );
+
+    // Pad options are skipped, and a message with a bad length option is rejected.
+    bytes[240..243].copy_from_slice(&[0, 0, 0]);
+    assert!(Message::deserialise(&bytes).unwrap().message_type().is_none());
+    bytes[240..242].copy_from_slice(&[51, 3]);
+    assert!(Message::deserialise(&bytes).is_err());
+    bytes[236] = 0;
+    assert!(Message::deserialise(&bytes).is_err());
+}
@@ -0,0 +1,12 @@
+mod enums;
+mod message;
+mod options;
+
+pub use enums::{MessageType, Op};
+pub use message::Message;
+pub use options::DhcpOption;
+
+/// The UDP port DHCP servers and relay agents listen on.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port DHCP clients listen on.
+pub const CLIENT_PORT: u16 = 68;
@@ -0,0 +1,277 @@
+use crate::common::{DeserialiseError, Serialise};
+use crate::common::address::Ipv4Address;
+
+use super::MessageType;
+
+const CODE_PAD: u8 = 0;
+const CODE_SUBNET_MASK: u8 = 1;
+const CODE_ROUTER: u8 = 3;
+const CODE_DOMAIN_NAME_SERVER: u8 = 6;
+const CODE_HOST_NAME: u8 = 12;
+const CODE_DOMAIN_NAME: u8 = 15;
+const CODE_INTERFACE_MTU: u8 = 26;
+const CODE_BROADCAST_ADDRESS: u8 = 28;
+const CODE_REQUESTED_ADDRESS: u8 = 50;
+const CODE_LEASE_TIME: u8 = 51;
+const CODE_MESSAGE_TYPE: u8 = 53;
+const CODE_SERVER_IDENTIFIER: u8 = 54;
+const CODE_PARAMETER_REQUEST_LIST: u8 = 55;
+const CODE_MESSAGE: u8 = 56;
+const CODE_MAXIMUM_MESSAGE_SIZE: u8 = 57;
+const CODE_RENEWAL_TIME: u8 = 58;
+const CODE_REBINDING_TIME: u8 = 59;
+const CODE_CLIENT_IDENTIFIER: u8 = 61;
+const CODE_END: u8 = 255;
+
+/// A DHCP option (RFC 2132). Times are in seconds.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum DhcpOption {
+    Pad,
+    End,
+    SubnetMask(Ipv4Address),
+    /// Routers on the client's subnet, in order of preference.
+    Router(Vec<Ipv4Address>),
+    DomainNameServer(Vec<Ipv4Address>),
+    HostName(String),
+    DomainName(String),
+    InterfaceMtu(u16),
+    BroadcastAddress(Ipv4Address),
+    RequestedAddress(Ipv4Address),
+    LeaseTime(u32),
+    MessageType(MessageType),
+    ServerIdentifier(Ipv4Address),
+    /// The codes of the options the client wants in the reply.
+    ParameterRequestList(Vec<u8>),
+    /// Why a server refused a request or a client declined an address.
+    Message(String),
+    MaximumMessageSize(u16),
+    /// T1, after which the client renews its lease with the server that granted it.
+    RenewalTime(u32),
+    /// T2, after which the client asks any server to extend its lease.
+    RebindingTime(u32),
+    /// A hardware type followed by an address, or a type of 0 followed by any unique name.
+    ClientIdentifier(Vec<u8>),
+    /// An option this codec does not understand, kept so it can be written back unchanged.
+    Unknown { code: u8, data: Vec<u8> },
+}
+
+impl DhcpOption {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Pad => CODE_PAD,
+            Self::End => CODE_END,
+            Self::SubnetMask(..) => CODE_SUBNET_MASK,
+            Self::Router(..) => CODE_ROUTER,
+            Self::DomainNameServer(..) => CODE_DOMAIN_NAME_SERVER,
+            Self::HostName(..) => CODE_HOST_NAME,
+            Self::DomainName(..) => CODE_DOMAIN_NAME,
+            Self::InterfaceMtu(..) => CODE_INTERFACE_MTU,
+            Self::BroadcastAddress(..) => CODE_BROADCAST_ADDRESS,
+            Self::RequestedAddress(..) => CODE_REQUESTED_ADDRESS,
+            Self::LeaseTime(..) => CODE_LEASE_TIME,
+            Self::MessageType(..) => CODE_MESSAGE_TYPE,
+            Self::ServerIdentifier(..) => CODE_SERVER_IDENTIFIER,
+            Self::ParameterRequestList(..) => CODE_PARAMETER_REQUEST_LIST,
+            Self::Message(..) => CODE_MESSAGE,
+            Self::MaximumMessageSize(..) => CODE_MAXIMUM_MESSAGE_SIZE,
+            Self::RenewalTime(..) => CODE_RENEWAL_TIME,
+            Self::RebindingTime(..) => CODE_REBINDING_TIME,
+            Self::ClientIdentifier(..) => CODE_CLIENT_IDENTIFIER,
+            Self::Unknown { code, .. } => *code,
+        }
+    }
+
+    /// Parses options until the buffer or an End option is reached.
+    pub fn deserialise_list(buf: &[u8]) -> Result<Vec<Self>, DeserialiseError> {
+        let mut options = vec![];
+        let mut index = 0;
+
+        while index < buf.len() {
+            let option = Self::deserialise(&buf[index..])?;
+            // What was read, which a text option decoded with replacement characters is not.
+            index += match option {
+                Self::Pad | Self::End => 1,
+                _ => 2 + buf[index + 1] as usize,
+            };
+
+            let end = option == Self::End;
+            options.push(option);
+            if end {
+                break;
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// The value of the option, without its code and length.
+    fn data(&self) -> Vec<u8> {
+        let addresses = |addresses: &[Ipv4Address]| addresses.iter().flat_map(|a| <[u8; 4]>::from(*a)).collect();
+
+        match self {
+            Self::Pad | Self::End => vec![],
+            Self::SubnetMask(address)
+            | Self::BroadcastAddress(address)
+            | Self::RequestedAddress(address)
+            | Self::ServerIdentifier(address) => <[u8; 4]>::from(*address).to_vec(),
+            Self::Router(list) | Self::DomainNameServer(list) => addresses(list),
+            Self::HostName(s) | Self::DomainName(s) | Self::Message(s) => s.as_bytes().to_vec(),
+            Self::InterfaceMtu(n) | Self::MaximumMessageSize(n) => n.to_be_bytes().to_vec(),
+            Self::LeaseTime(n) | Self::RenewalTime(n) | Self::RebindingTime(n) => n.to_be_bytes().to_vec(),
+            Self::MessageType(message_type) => vec![u8::from(*message_type)],
+            Self::ParameterRequestList(data) | Self::ClientIdentifier(data) | Self::Unknown { data, .. } => data.clone(),
+        }
+    }
+
+    fn expect_length(code: u8, length: usize, valid: bool) -> Result<(), DeserialiseError> {
+        if valid {
+            Ok(())
+        } else {
+            Err(DeserialiseError::Heap(format!("invalid length {length} for dhcp option {code}")))
+        }
+    }
+}
+
+impl Serialise for DhcpOption {
+    fn byte_length(&self) -> usize {
+        match self {
+            Self::Pad | Self::End => 1,
+            option => 2 + option.data().len().min(u8::MAX as usize),
+        }
+    }
+
+    /// Writes the option. Values longer than 255 bytes do not fit in one option, and are cut
+    /// short rather than split across several (RFC 3396).
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.code();
+        if matches!(self, Self::Pad | Self::End) {
+            return 1;
+        }
+
+        let data = self.data();
+        let length = data.len().min(u8::MAX as usize);
+        buf[1] = length as u8;
+        buf[2..2 + length].copy_from_slice(&data[..length]);
+        2 + length
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let code = match buf.first() {
+            Some(code) => *code,
+            None => return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 1, 0)),
+        };
+
+        match code {
+            CODE_PAD => return Ok(Self::Pad),
+            CODE_END => return Ok(Self::End),
+            _ => (),
+        }
+
+        if buf.len() < 2 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2, buf.len()));
+        }
+
+        let length = buf[1] as usize;
+        if buf.len() < 2 + length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2 + length, buf.len()));
+        }
+
+        let data = &buf[2..2 + length];
+        let address = || -> Result<Ipv4Address, DeserialiseError> {
+            Self::expect_length(code, length, length == 4)?;
+            Ipv4Address::deserialise(data)
+        };
+        let addresses = || -> Result<Vec<Ipv4Address>, DeserialiseError> {
+            Self::expect_length(code, length, length > 0 && length.is_multiple_of(4))?;
+            data.chunks(4).map(Ipv4Address::deserialise).collect()
+        };
+        let string = || String::from_utf8_lossy(data).into_owned();
+        let u16 = || -> Result<u16, DeserialiseError> {
+            Self::expect_length(code, length, length == 2)?;
+            u16::deserialise(data)
+        };
+        let u32 = || -> Result<u32, DeserialiseError> {
+            Self::expect_length(code, length, length == 4)?;
+            u32::deserialise(data)
+        };
+
+        Ok(match code {
+            CODE_SUBNET_MASK => Self::SubnetMask(address()?),
+            CODE_ROUTER => Self::Router(addresses()?),
+            CODE_DOMAIN_NAME_SERVER => Self::DomainNameServer(addresses()?),
+            CODE_HOST_NAME => Self::HostName(string()),
+            CODE_DOMAIN_NAME => Self::DomainName(string()),
+            CODE_INTERFACE_MTU => Self::InterfaceMtu(u16()?),
+            CODE_BROADCAST_ADDRESS => Self::BroadcastAddress(address()?),
+            CODE_REQUESTED_ADDRESS => Self::RequestedAddress(address()?),
+            CODE_LEASE_TIME => Self::LeaseTime(u32()?),
+            CODE_MESSAGE_TYPE => {
+                Self::expect_length(code, length, length == 1)?;
+                Self::MessageType(data[0].into())
+            },
+            CODE_SERVER_IDENTIFIER => Self::ServerIdentifier(address()?),
+            CODE_PARAMETER_REQUEST_LIST => Self::ParameterRequestList(data.to_vec()),
+            CODE_MESSAGE => Self::Message(string()),
+            CODE_MAXIMUM_MESSAGE_SIZE => Self::MaximumMessageSize(u16()?),
+            CODE_RENEWAL_TIME => Self::RenewalTime(u32()?),
+            CODE_REBINDING_TIME => Self::RebindingTime(u32()?),
+            CODE_CLIENT_IDENTIFIER => {
+                Self::expect_length(code, length, length >= 2)?;
+                Self::ClientIdentifier(data.to_vec())
+            },
+            code => Self::Unknown { code, data: data.to_vec() },
+        })
+    }
+}
+
+impl core::fmt::Display for DhcpOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |f: &mut std::fmt::Formatter<'_>, name: &str, addresses: &[Ipv4Address]| {
+            write!(f, "{name}")?;
+            addresses.iter().try_for_each(|address| write!(f, " {address}"))
+        };
+
+        match self {
+            Self::Pad => write!(f, "pad"),
+            Self::End => write!(f, "end"),
+            Self::SubnetMask(mask) => write!(f, "subnet-mask {mask}"),
+            Self::Router(routers) => list(f, "router", routers),
+            Self::DomainNameServer(servers) => list(f, "dns", servers),
+            Self::HostName(name) => write!(f, "hostname {name:?}"),
+            Self::DomainName(name) => write!(f, "domain {name:?}"),
+            Self::InterfaceMtu(mtu) => write!(f, "mtu {mtu}"),
+            Self::BroadcastAddress(address) => write!(f, "broadcast {address}"),
+            Self::RequestedAddress(address) => write!(f, "requested {address}"),
+            Self::LeaseTime(seconds) => write!(f, "lease {seconds}s"),
+            Self::MessageType(message_type) => write!(f, "type {message_type}"),
+            Self::ServerIdentifier(address) => write!(f, "server {address}"),
+            Self::ParameterRequestList(codes) => {
+                write!(f, "request")?;
+                codes.iter().try_for_each(|code| write!(f, " {code}"))
+            },
+            Self::Message(message) => write!(f, "message {message:?}"),
+            Self::MaximumMessageSize(size) => write!(f, "max-size {size}"),
+            Self::RenewalTime(seconds) => write!(f, "t1 {seconds}s"),
+            Self::RebindingTime(seconds) => write!(f, "t2 {seconds}s"),
+            Self::ClientIdentifier(id) => {
+                write!(f, "client-id ")?;
+                id.iter().enumerate().try_for_each(|(i, b)| write!(f, "{}{b:02x}", if i == 0 { "" } else { ":" }))
+            },
+            Self::Unknown { code, data } => write!(f, "unknown-{code} len {}", data.len()),
+        }
+    }
+}
+
+#[test]
+fn test_dhcp_options() {
+    // A host name that is not UTF-8 takes up what it says, whatever it decodes to.
+    let options = DhcpOption::deserialise_list(&[12, 1, 0xe9, 53, 1, 1, 255]).unwrap();
+    assert_eq!(options, vec![DhcpOption::HostName("\u{fffd}".into()), DhcpOption::MessageType(MessageType::Discover), DhcpOption::End]);
+
+    // Values too long for one option are cut short, and the length says so.
+    let option = DhcpOption::DomainName("a".repeat(300));
+    let mut buf = vec![0u8; option.byte_length()];
+    assert_eq!((buf.len(), option.serialise(&mut buf)), (257, 257));
+    assert_eq!(buf[1], 255);
+    assert_eq!(DhcpOption::deserialise(&buf).unwrap(), DhcpOption::DomainName("a".repeat(255)));
+}
@@ -1,14 +1,17 @@
-use crate::common::{address::MacAddress, DeserialiseError, Serialise, Layer, Pdu};
+use std::sync::Arc;
+
+use crate::common::{address::MacAddress, crc, DeserialiseError, Serialise, Layer, Pdu, Wrapper};
+use crate::common::log::Value;
 use super::ethertype::EtherType;
+use super::vlan::{Tci, VlanTag};
 
 #[derive(Debug)]
 struct FrameHeader {
     mac_destination: MacAddress,
     mac_source: MacAddress,
 
+    tags: Vec<VlanTag>,     // Outermost first
     ethertype: EtherType,
-    tpid: Option<EtherType>,
-    tci: u16,   // Presence dependent on tpid
 }
 
 impl Serialise for FrameHeader {
@@ -17,10 +20,7 @@ impl Serialise for FrameHeader {
         self.mac_destination.byte_length() +
         self.mac_source.byte_length() +
         self.ethertype.byte_length() +
-        match self.tpid {
-            Some(tpid) => tpid.byte_length() + 2,
-            None => 0,
-        }
+        self.tags.iter().map(|tag| tag.byte_length()).sum::<usize>()
     }
 
     fn serialise(&self, buf: &mut [u8]) -> usize {
@@ -29,10 +29,9 @@ impl Serialise for FrameHeader {
         index += self.mac_destination.serialise(&mut buf[index..]);
         index += self.mac_source.serialise(&mut buf[index..]);
 
-        if let Some(tpid) = self.tpid {
-            index += tpid.serialise(&mut buf[index..]);
-            index += self.tci.serialise(&mut buf[index..]);
-        };
+        for tag in &self.tags {
+            index += tag.serialise(&mut buf[index..]);
+        }
 
         index += self.ethertype.serialise(&mut buf[index..]);
         index
@@ -46,23 +45,21 @@ impl Serialise for FrameHeader {
         let mac_source = MacAddress::deserialise(&buf[index..])?;
         index += mac_source.byte_length();
 
-        let ethertype = EtherType::deserialise(&buf[index..])?;
-        let (ethertype, tpid, tci) = match ethertype {
-            EtherType::ServiceVlanTag | EtherType::VlanTaggedFrame => {
-                let tci = u16::deserialise(&buf[index..])?;
-                let new_ethertype = EtherType::deserialise(&buf[index..])?;
+        let mut tags = vec![];
+        let mut ethertype = EtherType::deserialise(&buf[index..])?;
+        while VlanTag::is_tpid(ethertype) {
+            let tag = VlanTag::deserialise(&buf[index..])?;
+            index += tag.byte_length();
+            tags.push(tag);
 
-                (new_ethertype, Some(ethertype), tci)
-            }
-            _ => (ethertype, None, 0),
-        };
+            ethertype = EtherType::deserialise(&buf[index..])?;
+        }
 
         Ok(Self {
             mac_destination,
             mac_source,
+            tags,
             ethertype,
-            tpid,
-            tci,
         })
     }
 }
@@ -71,7 +68,11 @@ impl Serialise for FrameHeader {
 pub struct Frame {
     header: FrameHeader,
     data: Vec<u8>,
+    /// Bytes after an 802.3 payload shorter than the minimum frame, kept so that the frame
+    /// check sequence and `serialise` cover the frame as it was received.
+    padding: Vec<u8>,
     fcs: u32,
+    include_fcs: bool,
 }
 
 #[allow(dead_code)]
@@ -81,58 +82,115 @@ impl Frame {
         ethertype: EtherType,
         data: Vec<u8>,
     ) -> Self {
-        Self {
+        let mut frame = Self {
             header: FrameHeader {
                 mac_destination: destination,
                 mac_source: source,
+                tags: vec![],
                 ethertype,
-                tpid: None,
-                tci: 0,
             },
             data,
+            padding: vec![],
             fcs: 0,
-        }
+            include_fcs: false,
+        };
+
+        frame.fcs = frame.compute_fcs();
+        frame
     }
 
     pub fn new_vlan_tagged(
         destination: MacAddress, source: MacAddress,
-        tpid: EtherType, tci: u16,
+        tags: Vec<VlanTag>,
         ethertype: EtherType,
         data: Vec<u8>,
     ) -> Self {
-        Self {
+        let mut frame = Self {
             header: FrameHeader {
                 mac_destination: destination,
                 mac_source: source,
+                tags,
                 ethertype,
-                tpid: Some(tpid),
-                tci,
             },
             data,
+            padding: vec![],
             fcs: 0,
-        }
+            include_fcs: false,
+        };
+
+        frame.fcs = frame.compute_fcs();
+        frame
     }
 
     crate::util::getter!(destination(header.mac_destination): MacAddress);
     crate::util::getter!(source(header.mac_source): MacAddress);
     crate::util::getter!(ethertype(header.ethertype): EtherType);
-    crate::util::getter!(tpid(header.tpid): Option<EtherType>);
     crate::util::getter!(fcs: u32);
+    crate::util::getter!(include_fcs: bool);
+
+    /// The VLAN tags on the frame, outermost (S-tag) first.
+    pub fn tags(&self) -> &[VlanTag] {
+        &self.header.tags
+    }
+
+    pub fn tpid(&self) -> Option<EtherType> {
+        self.header.tags.first().map(|tag| tag.tpid())
+    }
+
+    pub fn tci(&self) -> Option<Tci> {
+        self.header.tags.first().map(|tag| tag.tci())
+    }
 
-    pub fn tci(&self) -> Option<u16> {
-        self.header.tpid.and(Some(self.header.tci))
+    /// The VLAN ID of the innermost tag, which identifies the customer VLAN.
+    pub fn vlan_id(&self) -> Option<u16> {
+        self.header.tags.last().map(|tag| tag.tci().vid())
     }
 
     pub fn data(&self) -> &[u8] {
         &self.data
     }
 
-    fn get_fcs(&self) -> u32 {
-        todo!()
-        /*
-        polynomial = 0x4C11DB7
-        initial_value = 0xFFFFFFFF
-        */
+    /// Chooses whether `serialise` appends the frame check sequence.
+    pub fn set_include_fcs(&mut self, include_fcs: bool) {
+        self.include_fcs = include_fcs;
+    }
+
+    /// Computes the CRC-32 frame check sequence over the header, data and any padding.
+    pub fn compute_fcs(&self) -> u32 {
+        let mut crc = crc::Crc32::new();
+        let mut header = vec![0u8; self.header.byte_length()];
+        self.header.serialise(&mut header);
+        crc.add_bytes(&header);
+        crc.add_bytes(&self.data);
+        crc.add_bytes(&self.padding);
+        crc.finish()
+    }
+
+    /// Checks the stored frame check sequence against the header, data and any padding.
+    pub fn verify_fcs(&self) -> Result<(), DeserialiseError> {
+        let expected = self.compute_fcs();
+        if expected == self.fcs {
+            Ok(())
+        } else {
+            Err(DeserialiseError::FcsMismatch(expected, self.fcs))
+        }
+    }
+
+    /// Parses a frame whose last four bytes are the frame check sequence, rejecting it if the
+    /// sequence does not match.
+    pub fn deserialise_with_fcs(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < 4 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 4, buf.len()));
+        }
+
+        let (frame_bytes, fcs_bytes) = buf.split_at(buf.len() - 4);
+        let fcs = u32::from_le_bytes([fcs_bytes[0], fcs_bytes[1], fcs_bytes[2], fcs_bytes[3]]);
+
+        let mut frame = Self::deserialise(frame_bytes)?;
+        frame.fcs = fcs;
+        frame.include_fcs = true;
+        frame.verify_fcs()?;
+        Ok(frame)
     }
 }
 
@@ -145,11 +203,13 @@ impl core::fmt::Display for Frame {
         writeln!(f, "EtherType:   {}", self.header.ethertype)?;
         writeln!(f, "FCS:         {:08x}", self.fcs)?;
         writeln!(f)?;
-        if let Some(tpid) = self.header.tpid {
+        if !self.header.tags.is_empty() {
             writeln!(f, "VLAN Tagging:")?;
             writeln!(f, "-----------------")?;
-            writeln!(f, "TPID:        {}", tpid)?;
-            writeln!(f, "TCI:         {}", self.header.tci)?;
+            self.header.tags.iter().try_for_each(|tag| {
+                writeln!(f, "TPID:        {}", tag.tpid())?;
+                writeln!(f, "TCI:         {}", tag.tci())
+            })?;
             writeln!(f)?;
         };
 
@@ -169,26 +229,67 @@ impl core::fmt::Display for Frame {
 }
 
 impl Pdu for Frame {
-    fn log(&self, action: &str) {
-        println!(
-            "{} ",
-            action
+    fn protocol(&self) -> &'static str {
+        "ethernet"
+    }
+
+    fn summary(&self) -> String {
+        let vlan = self.vlan_id().map(|id| format!("vlan {id}, ")).unwrap_or_default();
+        format!(
+            "{} > {}, {}ethertype {} (0x{:04x}), length {}",
+            self.header.mac_source,
+            self.header.mac_destination,
+            vlan,
+            self.header.ethertype,
+            u16::from(&self.header.ethertype),
+            self.byte_length(),
         )
     }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let mut fields = vec![
+            ("source", self.header.mac_source.to_string().into()),
+            ("destination", self.header.mac_destination.to_string().into()),
+            ("ethertype", u16::from(&self.header.ethertype).into()),
+            ("length", self.byte_length().into()),
+        ];
+
+        if let Some(id) = self.vlan_id() {
+            fields.push(("vlan", id.into()));
+        }
+
+        fields
+    }
+}
+
+impl Wrapper for Frame {
+    fn unwrap_data(&self) -> Arc<[u8]> {
+        Arc::from(self.data.as_slice())
+    }
 }
 
 impl Serialise for Frame {
     fn byte_length(&self) -> usize {
         self.header.byte_length()
         + self.data.len()
-        // + self.fcs.byte_length()
+        + self.padding.len()
+        + if self.include_fcs { self.fcs.byte_length() } else { 0 }
     }
 
     fn serialise(&self, buf: &mut [u8]) -> usize {
         let index = 0;
         let index = index + self.header.serialise(&mut buf[index..]);
         let index = index + self.data.as_slice().serialise(&mut buf[index..]);
-        index // + self.fcs.serialise(&mut buf[index..])
+        let index = index + self.padding.as_slice().serialise(&mut buf[index..]);
+
+        if self.include_fcs {
+            // The FCS is transmitted least significant byte first.
+            let fcs = crc::crc32(&buf[..index]);
+            buf[index..index + 4].copy_from_slice(&fcs.to_le_bytes());
+            index + 4
+        } else {
+            index
+        }
     }
 
     fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
@@ -197,17 +298,22 @@ impl Serialise for Frame {
         let end_index = if let EtherType::PayloadLength(len) = header.ethertype {
             header.byte_length() + len as usize
         } else {
-            // buf.len() - 4
             buf.len()
         };
 
+        if buf.len() < end_index {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), end_index, buf.len()));
+        }
+
         let data = buf[header.byte_length()..end_index].to_owned();
-        // let fcs = u32::deserialise(&buf[end_index..])?;
+        let padding = buf[end_index..].to_owned();
 
         Ok(Self {
             header,
             data,
-            fcs: 0 // fcs
+            padding,
+            fcs: 0,
+            include_fcs: false,
         })
     }
 }
@@ -216,6 +322,8 @@ impl Layer for Frame {
     fn wrap(&mut self, data: &dyn Serialise) {
         self.data = vec![0u8; data.byte_length()];
         data.serialise(self.data.as_mut_slice());
+        self.padding.clear();
+        self.fcs = self.compute_fcs();
     }
 }
 
@@ -224,11 +332,103 @@ fn test_display_frame() {
     let frame = Frame::new_vlan_tagged(
         MacAddress::from_hex("fe:77:4d:96:d5:95").unwrap(),
         MacAddress::from_hex("33:33:00:00:00:02").unwrap(),
-        EtherType::VlanTaggedFrame,
-        0xdead,
+        vec![VlanTag::customer(Tci::from(0xdead))],
         EtherType::Ipv4,
         vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17],
     );
 
     print!("{frame}");
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_frame_fcs() {
+    let mut frame = Frame::new(
+        MacAddress::from_hex("ff:ff:ff:ff:ff:ff").unwrap(),
+        MacAddress::from_hex("fe:77:4d:96:d5:95").unwrap(),
+        EtherType::Arp,
+        vec![],
+    );
+    frame.wrap(&[0u8; 46].as_slice());
+    frame.set_include_fcs(true);
+
+    let mut bytes = vec![0u8; frame.byte_length()];
+    assert_eq!(frame.serialise(&mut bytes), 64);
+    assert_eq!(crc::crc32(&bytes[..60]).to_le_bytes(), bytes[60..]);
+
+    let parsed = Frame::deserialise_with_fcs(&bytes).unwrap();
+    assert_eq!(parsed.fcs(), frame.fcs());
+    assert_eq!(parsed.data(), frame.data());
+    assert!(parsed.verify_fcs().is_ok());
+
+    bytes[20] ^= 0xff;
+    assert!(matches!(
+        Frame::deserialise_with_fcs(&bytes),
+        Err(DeserialiseError::FcsMismatch(..))
+    ));
+
+    // An 802.3 frame with a three byte LLC payload, padded to the minimum of 60 bytes.
+    let mut bytes = vec![0u8; 64];
+    bytes[..6].copy_from_slice(&[0x01, 0x80, 0xc2, 0, 0, 0]);
+    bytes[6..12].copy_from_slice(&[0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    bytes[12..17].copy_from_slice(&[0, 3, 0x42, 0x42, 0x03]);
+    bytes[40] = 0xaa;
+    let fcs = crc::crc32(&bytes[..60]).to_le_bytes();
+    bytes[60..].copy_from_slice(&fcs);
+
+    let parsed = Frame::deserialise_with_fcs(&bytes).unwrap();
+    assert_eq!(parsed.ethertype(), EtherType::PayloadLength(3));
+    assert_eq!(parsed.data(), [0x42, 0x42, 0x03]);
+    assert!(parsed.verify_fcs().is_ok());
+
+    let mut serialised = vec![0u8; parsed.byte_length()];
+    assert_eq!(parsed.serialise(&mut serialised), 64);
+    assert_eq!(serialised, bytes);
+}
+
+#[test]
+fn test_vlan_tagged_frame() {
+    // ARP request tagged with 802.1Q VLAN 10, priority 0.
+    let bytes = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x52, 0x54, 0x00, 0x12, 0x34, 0x56,
+        0x81, 0x00, 0x00, 0x0a, 0x08, 0x06,
+        0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, 0x52, 0x54, 0x00, 0x12,
+        0x34, 0x56, 0xc0, 0xa8, 0x0a, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xc0, 0xa8, 0x0a, 0x02,
+    ];
+
+    let frame = Frame::deserialise(&bytes).unwrap();
+    assert_eq!(frame.tags(), &[VlanTag::customer(Tci::new(0, false, 10))]);
+    assert_eq!(frame.ethertype(), EtherType::Arp);
+    assert_eq!(frame.data(), &bytes[18..]);
+
+    let mut new_bytes = vec![0u8; frame.byte_length()];
+    frame.serialise(&mut new_bytes);
+    assert_eq!(new_bytes, bytes);
+}
+
+#[test]
+fn test_qinq_frame() {
+    // IPv4 frame with an 802.1ad S-tag (VLAN 100, PCP 3) over an 802.1Q C-tag (VLAN 200, DEI set).
+    let bytes = [
+        0x00, 0x1b, 0x21, 0x3c, 0x9d, 0xf8, 0x00, 0x0c, 0x29, 0xa1, 0xb2, 0xc3,
+        0x88, 0xa8, 0x60, 0x64, 0x81, 0x00, 0x10, 0xc8, 0x08, 0x00,
+        0xde, 0xad, 0xbe, 0xef,
+    ];
+
+    let frame = Frame::deserialise(&bytes).unwrap();
+    assert_eq!(
+        frame.tags(),
+        &[
+            VlanTag::service(Tci::new(3, false, 100)),
+            VlanTag::customer(Tci::new(0, true, 200)),
+        ]
+    );
+    assert_eq!(frame.tpid(), Some(EtherType::ServiceVlanTag));
+    assert_eq!(frame.vlan_id(), Some(200));
+    assert_eq!(frame.ethertype(), EtherType::Ipv4);
+    assert_eq!(frame.data(), &[0xde, 0xad, 0xbe, 0xef]);
+
+    let mut new_bytes = vec![0u8; frame.byte_length()];
+    frame.serialise(&mut new_bytes);
+    assert_eq!(new_bytes, bytes);
+}
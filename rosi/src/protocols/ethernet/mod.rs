@@ -2,6 +2,8 @@
 
 mod ethertype;
 mod frame;
+mod vlan;
 
 pub use ethertype::EtherType;
-pub use frame::Frame;
\ No newline at end of file
+pub use frame::Frame;
+pub use vlan::{Tci, VlanTag};
\ No newline at end of file
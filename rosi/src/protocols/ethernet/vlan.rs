@@ -0,0 +1,131 @@
+use crate::common::{DeserialiseError, Serialise};
+use super::ethertype::EtherType;
+
+/// 802.1Q Tag Control Information.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Default)]
+pub struct Tci {
+    pcp: u8,    // 3 bits
+    dei: bool,
+    vid: u16,   // 12 bits
+}
+
+impl Tci {
+    pub fn new(pcp: u8, dei: bool, vid: u16) -> Self {
+        Self {
+            pcp: pcp & 0b111,
+            dei,
+            vid: vid & 0x0fff,
+        }
+    }
+
+    crate::util::getter!(pcp: u8);
+    crate::util::getter!(dei: bool);
+    crate::util::getter!(vid: u16);
+}
+
+impl From<u16> for Tci {
+    fn from(value: u16) -> Self {
+        Self {
+            pcp: (value >> 13) as u8,
+            dei: value & 0x1000 > 0,
+            vid: value & 0x0fff,
+        }
+    }
+}
+
+impl From<Tci> for u16 {
+    fn from(value: Tci) -> Self {
+        (value.pcp as u16) << 13 | (value.dei as u16) << 12 | value.vid
+    }
+}
+
+impl Serialise for Tci {
+    #[inline]
+    fn byte_length(&self) -> usize {
+        2
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        u16::from(*self).serialise(buf)
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < 2 {
+            Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2, buf.len()))
+        } else {
+            Ok(Self::from(u16::from_be_bytes([buf[0], buf[1]])))
+        }
+    }
+}
+
+impl core::fmt::Display for Tci {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PCP {}, DEI {}, VID {}", self.pcp, self.dei as u8, self.vid)
+    }
+}
+
+/// A single 802.1Q (C-tag) or 802.1ad (S-tag) header.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub struct VlanTag {
+    tpid: EtherType,
+    tci: Tci,
+}
+
+impl VlanTag {
+    pub fn new(tpid: EtherType, tci: Tci) -> Self {
+        Self { tpid, tci }
+    }
+
+    /// An 802.1Q customer tag.
+    pub fn customer(tci: Tci) -> Self {
+        Self::new(EtherType::VlanTaggedFrame, tci)
+    }
+
+    /// An 802.1ad service tag.
+    pub fn service(tci: Tci) -> Self {
+        Self::new(EtherType::ServiceVlanTag, tci)
+    }
+
+    crate::util::getter!(tpid: EtherType);
+    crate::util::getter!(tci: Tci);
+
+    pub(super) fn is_tpid(ethertype: EtherType) -> bool {
+        matches!(ethertype, EtherType::VlanTaggedFrame | EtherType::ServiceVlanTag)
+    }
+}
+
+impl Serialise for VlanTag {
+    #[inline]
+    fn byte_length(&self) -> usize {
+        self.tpid.byte_length() + self.tci.byte_length()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let index = self.tpid.serialise(buf);
+        index + self.tci.serialise(&mut buf[index..])
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let tpid = EtherType::deserialise(buf)?;
+        if !Self::is_tpid(tpid) {
+            return Err(DeserialiseError::Heap(format!("{tpid} is not a vlan tpid")));
+        }
+
+        let tci = Tci::deserialise(&buf[tpid.byte_length()..])?;
+        Ok(Self { tpid, tci })
+    }
+}
+
+impl core::fmt::Display for VlanTag {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} ({})", self.tpid, self.tci)
+    }
+}
+
+#[test]
+fn test_tci() {
+    let tci = Tci::from(0xa00a);
+    assert_eq!(tci, Tci::new(5, false, 10));
+    assert_eq!(u16::from(Tci::new(7, true, 4095)), 0xffff);
+    assert_eq!(tci.to_string(), "PCP 5, DEI 0, VID 10");
+}
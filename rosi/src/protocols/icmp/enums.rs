@@ -0,0 +1,44 @@
+use crate::util::serialise_enum;
+
+serialise_enum! {
+    pub IcmpType(u8, 1) {
+        EchoReply: 0,
+        DestinationUnreachable: 3,
+        Redirect: 5,
+        EchoRequest: 8,
+        TimeExceeded: 11,
+        ParameterProblem: 12,
+    }
+}
+
+serialise_enum! {
+    pub UnreachableCode(u8, 1) {
+        Network: 0,
+        Host: 1,
+        Protocol: 2,
+        Port: 3,
+        FragmentationNeeded: 4,
+        SourceRouteFailed: 5,
+        NetworkUnknown: 6,
+        HostUnknown: 7,
+        NetworkProhibited: 9,
+        HostProhibited: 10,
+        AdministrativelyProhibited: 13,
+    }
+}
+
+serialise_enum! {
+    pub RedirectCode(u8, 1) {
+        Network: 0,
+        Host: 1,
+        TosNetwork: 2,
+        TosHost: 3,
+    }
+}
+
+serialise_enum! {
+    pub TimeExceededCode(u8, 1) {
+        TtlExceeded: 0,
+        FragmentReassembly: 1,
+    }
+}
@@ -0,0 +1,5 @@
+mod enums;
+mod packet;
+
+pub use enums::{IcmpType, UnreachableCode, RedirectCode, TimeExceededCode};
+pub use packet::{Message, Packet};
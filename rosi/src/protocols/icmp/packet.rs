@@ -0,0 +1,374 @@
+use crate::common::{checksum, DeserialiseError, Layer, Pdu, Serialise, serialise_fields};
+use crate::common::address::Ipv4Address;
+use crate::common::log::Value;
+use crate::protocols::ipv4::{Ipv4Header, Ipv4Packet};
+
+use super::enums::{IcmpType, RedirectCode, TimeExceededCode, UnreachableCode};
+
+const HEADER_LENGTH: usize = 8;
+
+/// Number of bytes of the offending datagram's payload quoted in error messages (RFC 792).
+const QUOTED_DATA_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    EchoRequest { identifier: u16, sequence: u16, data: Vec<u8> },
+    EchoReply { identifier: u16, sequence: u16, data: Vec<u8> },
+    DestinationUnreachable { code: UnreachableCode, next_hop_mtu: u16, original: Vec<u8> },
+    Redirect { code: RedirectCode, gateway: Ipv4Address, original: Vec<u8> },
+    TimeExceeded { code: TimeExceededCode, original: Vec<u8> },
+    ParameterProblem { pointer: u8, original: Vec<u8> },
+    Unknown { icmp_type: u8, code: u8, rest: [u8; 4], data: Vec<u8> },
+}
+
+impl Message {
+    pub fn icmp_type(&self) -> IcmpType {
+        match self {
+            Self::EchoRequest { .. } => IcmpType::EchoRequest,
+            Self::EchoReply { .. } => IcmpType::EchoReply,
+            Self::DestinationUnreachable { .. } => IcmpType::DestinationUnreachable,
+            Self::Redirect { .. } => IcmpType::Redirect,
+            Self::TimeExceeded { .. } => IcmpType::TimeExceeded,
+            Self::ParameterProblem { .. } => IcmpType::ParameterProblem,
+            Self::Unknown { icmp_type, .. } => IcmpType::from(*icmp_type),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::EchoRequest { .. } | Self::EchoReply { .. } | Self::ParameterProblem { .. } => 0,
+            Self::DestinationUnreachable { code, .. } => (*code).into(),
+            Self::Redirect { code, .. } => (*code).into(),
+            Self::TimeExceeded { code, .. } => (*code).into(),
+            Self::Unknown { code, .. } => *code,
+        }
+    }
+
+    /// The four bytes following the checksum, whose meaning depends on the message type.
+    fn rest_of_header(&self) -> [u8; 4] {
+        match self {
+            Self::EchoRequest { identifier, sequence, .. } | Self::EchoReply { identifier, sequence, .. } => {
+                let [i0, i1] = identifier.to_be_bytes();
+                let [s0, s1] = sequence.to_be_bytes();
+                [i0, i1, s0, s1]
+            },
+            Self::DestinationUnreachable { next_hop_mtu, .. } => {
+                let [m0, m1] = next_hop_mtu.to_be_bytes();
+                [0, 0, m0, m1]
+            },
+            Self::Redirect { gateway, .. } => (*gateway).into(),
+            Self::TimeExceeded { .. } => [0; 4],
+            Self::ParameterProblem { pointer, .. } => [*pointer, 0, 0, 0],
+            Self::Unknown { rest, .. } => *rest,
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            Self::EchoRequest { data, .. } | Self::EchoReply { data, .. } | Self::Unknown { data, .. } => data,
+            Self::DestinationUnreachable { original, .. } |
+            Self::Redirect { original, .. } |
+            Self::TimeExceeded { original, .. } |
+            Self::ParameterProblem { original, .. } => original,
+        }
+    }
+
+    fn payload_mut(&mut self) -> &mut Vec<u8> {
+        match self {
+            Self::EchoRequest { data, .. } | Self::EchoReply { data, .. } | Self::Unknown { data, .. } => data,
+            Self::DestinationUnreachable { original, .. } |
+            Self::Redirect { original, .. } |
+            Self::TimeExceeded { original, .. } |
+            Self::ParameterProblem { original, .. } => original,
+        }
+    }
+
+    fn from_parts(icmp_type: u8, code: u8, rest: [u8; 4], payload: Vec<u8>) -> Self {
+        let [r0, r1, r2, r3] = rest;
+        match (IcmpType::from(icmp_type), code) {
+            (IcmpType::EchoRequest, 0) => Self::EchoRequest {
+                identifier: u16::from_be_bytes([r0, r1]),
+                sequence: u16::from_be_bytes([r2, r3]),
+                data: payload,
+            },
+            (IcmpType::EchoReply, 0) => Self::EchoReply {
+                identifier: u16::from_be_bytes([r0, r1]),
+                sequence: u16::from_be_bytes([r2, r3]),
+                data: payload,
+            },
+            (IcmpType::DestinationUnreachable, code) => Self::DestinationUnreachable {
+                code: code.into(),
+                next_hop_mtu: u16::from_be_bytes([r2, r3]),
+                original: payload,
+            },
+            (IcmpType::Redirect, code) => Self::Redirect {
+                code: code.into(),
+                gateway: rest.into(),
+                original: payload,
+            },
+            (IcmpType::TimeExceeded, code) => Self::TimeExceeded {
+                code: code.into(),
+                original: payload,
+            },
+            (IcmpType::ParameterProblem, 0) => Self::ParameterProblem {
+                pointer: r0,
+                original: payload,
+            },
+            _ => Self::Unknown { icmp_type, code, rest, data: payload },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Packet {
+    message: Message,
+    checksum: u16,
+}
+
+impl Packet {
+    pub fn new(message: Message) -> Self {
+        let mut packet = Self {
+            message,
+            checksum: 0,
+        };
+
+        packet.checksum = packet.compute_checksum();
+        packet
+    }
+
+    pub fn echo_request(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        Self::new(Message::EchoRequest { identifier, sequence, data })
+    }
+
+    pub fn echo_reply(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        Self::new(Message::EchoReply { identifier, sequence, data })
+    }
+
+    pub fn destination_unreachable(code: UnreachableCode, next_hop_mtu: u16, original: &Ipv4Packet) -> Self {
+        Self::new(Message::DestinationUnreachable { code, next_hop_mtu, original: Self::quote(original) })
+    }
+
+    pub fn redirect(code: RedirectCode, gateway: Ipv4Address, original: &Ipv4Packet) -> Self {
+        Self::new(Message::Redirect { code, gateway, original: Self::quote(original) })
+    }
+
+    pub fn time_exceeded(code: TimeExceededCode, original: &Ipv4Packet) -> Self {
+        Self::new(Message::TimeExceeded { code, original: Self::quote(original) })
+    }
+
+    pub fn parameter_problem(pointer: u8, original: &Ipv4Packet) -> Self {
+        Self::new(Message::ParameterProblem { pointer, original: Self::quote(original) })
+    }
+
+    /// Builds the echo reply for an echo request, or `None` for any other message.
+    pub fn reply(&self) -> Option<Self> {
+        match &self.message {
+            Message::EchoRequest { identifier, sequence, data } => Some(Self::echo_reply(*identifier, *sequence, data.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn icmp_type(&self) -> IcmpType {
+        self.message.icmp_type()
+    }
+
+    crate::util::getter!(checksum: u16);
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.message,
+            Message::DestinationUnreachable { .. } |
+            Message::Redirect { .. } |
+            Message::TimeExceeded { .. } |
+            Message::ParameterProblem { .. }
+        )
+    }
+
+    /// The IPv4 header of the datagram that caused an error message.
+    pub fn original_header(&self) -> Option<Result<Ipv4Header, DeserialiseError>> {
+        if self.is_error() {
+            Some(Ipv4Header::deserialise(self.message.payload()))
+        } else {
+            None
+        }
+    }
+
+    pub fn compute_checksum(&self) -> u16 {
+        let mut sum = checksum::Checksum::new();
+        sum.add_u16(u16::from_be_bytes([self.message.icmp_type().into(), self.message.code()]));
+        sum.add_bytes(&self.message.rest_of_header());
+        sum.add_bytes(self.message.payload());
+        sum.finish()
+    }
+
+    /// The offending datagram's header and the first 8 bytes of its payload.
+    fn quote(original: &Ipv4Packet) -> Vec<u8> {
+        let header = original.header();
+        let data = &original.data()[..original.data().len().min(QUOTED_DATA_LENGTH)];
+
+        let mut quoted = vec![0u8; header.byte_length() + data.len()];
+        let index = header.serialise(&mut quoted);
+        quoted[index..].copy_from_slice(data);
+        quoted
+    }
+}
+
+impl Serialise for Packet {
+    fn byte_length(&self) -> usize {
+        HEADER_LENGTH + self.message.payload().len()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        serialise_fields!(
+            buf=buf,
+            u8::from(self.message.icmp_type()),
+            self.message.code(),
+            self.compute_checksum(),
+            self.message.rest_of_header().as_slice(),
+            self.message.payload(),
+        )
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < HEADER_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), HEADER_LENGTH, buf.len()));
+        }
+
+        let checksum = u16::from_be_bytes([buf[2], buf[3]]);
+        let message = Message::from_parts(
+            buf[0],
+            buf[1],
+            [buf[4], buf[5], buf[6], buf[7]],
+            buf[HEADER_LENGTH..].to_vec(),
+        );
+
+        if !checksum::verify(buf) {
+            let packet = Self::new(message);
+            return Err(DeserialiseError::ChecksumMismatch("icmp", packet.checksum, checksum));
+        }
+
+        Ok(Self {
+            message,
+            checksum,
+        })
+    }
+}
+
+impl core::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Message::EchoRequest { identifier, sequence, data } | Message::EchoReply { identifier, sequence, data } => write!(
+                f,
+                "ICMP {} - ID: {}, Sequence: {}, Length: {}",
+                self.icmp_type(), identifier, sequence, data.len(),
+            ),
+            Message::DestinationUnreachable { code, next_hop_mtu, .. } => write!(
+                f,
+                "ICMP {} - Code: {}, Next-Hop MTU: {}",
+                self.icmp_type(), code, next_hop_mtu,
+            ),
+            Message::Redirect { code, gateway, .. } => write!(
+                f,
+                "ICMP {} - Code: {}, Gateway: {}",
+                self.icmp_type(), code, gateway,
+            ),
+            Message::TimeExceeded { code, .. } => write!(
+                f,
+                "ICMP {} - Code: {}",
+                self.icmp_type(), code,
+            ),
+            Message::ParameterProblem { pointer, .. } => write!(
+                f,
+                "ICMP {} - Pointer: {}",
+                self.icmp_type(), pointer,
+            ),
+            Message::Unknown { icmp_type, code, .. } => write!(
+                f,
+                "ICMP Type {} - Code: {}",
+                icmp_type, code,
+            ),
+        }
+    }
+}
+
+impl Pdu for Packet {
+    fn protocol(&self) -> &'static str {
+        "icmp"
+    }
+
+    fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("type", u8::from(self.icmp_type()).into()),
+            ("code", self.message.code().into()),
+            ("length", self.byte_length().into()),
+        ]
+    }
+}
+
+impl Layer for Packet {
+    fn wrap(&mut self, data: &dyn Serialise) {
+        let payload = self.message.payload_mut();
+        *payload = vec![0u8; data.byte_length()];
+        data.serialise(payload.as_mut_slice());
+        self.checksum = self.compute_checksum();
+    }
+}
+
+#[test]
+fn test_icmp_echo() {
+    let mut bytes = vec![
+        0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x01,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ];
+    let checksum = checksum::checksum(&bytes);
+    bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let request = Packet::deserialise(&bytes).unwrap();
+    assert_eq!(
+        request.message(),
+        &Message::EchoRequest { identifier: 0x1234, sequence: 1, data: vec![0xff; 8] }
+    );
+
+    let reply = request.reply().unwrap();
+    let mut reply_bytes = vec![0u8; reply.byte_length()];
+    reply.serialise(&mut reply_bytes);
+    assert_eq!(reply_bytes[0], 0);
+    assert!(checksum::verify(&reply_bytes));
+
+    bytes[8] = 0;
+    assert!(matches!(Packet::deserialise(&bytes), Err(DeserialiseError::ChecksumMismatch(..))));
+}
+
+#[test]
+fn test_icmp_error_quotes_original() {
+    use crate::protocols::ipv4::IpProtocol;
+
+    let original = Ipv4Packet::new(
+        Ipv4Address::from([10, 0, 0, 2]),
+        Ipv4Address::from([10, 0, 1, 7]),
+        IpProtocol::Udp,
+        (0..32).collect(),
+    );
+
+    let error = Packet::time_exceeded(TimeExceededCode::TtlExceeded, &original);
+    assert_eq!(error.byte_length(), HEADER_LENGTH + 20 + QUOTED_DATA_LENGTH);
+
+    let mut bytes = vec![0u8; error.byte_length()];
+    error.serialise(&mut bytes);
+
+    let parsed = Packet::deserialise(&bytes).unwrap();
+    assert!(parsed.is_error());
+
+    let header = parsed.original_header().unwrap().unwrap();
+    assert_eq!(header.source(), original.source());
+    assert_eq!(header.destination(), original.destination());
+    assert_eq!(header.proto(), IpProtocol::Udp);
+}
@@ -0,0 +1,44 @@
+use crate::util::serialise_enum;
+
+serialise_enum! {
+    pub Icmpv6Type(u8, 1) {
+        DestinationUnreachable: 1,
+        PacketTooBig: 2,
+        TimeExceeded: 3,
+        ParameterProblem: 4,
+        EchoRequest: 128,
+        EchoReply: 129,
+        RouterSolicitation: 133,
+        RouterAdvertisement: 134,
+        NeighborSolicitation: 135,
+        NeighborAdvertisement: 136,
+        Redirect: 137,
+    }
+}
+
+serialise_enum! {
+    pub UnreachableCode(u8, 1) {
+        NoRoute: 0,
+        AdministrativelyProhibited: 1,
+        BeyondScope: 2,
+        Address: 3,
+        Port: 4,
+        SourcePolicyFailed: 5,
+        RejectRoute: 6,
+    }
+}
+
+serialise_enum! {
+    pub TimeExceededCode(u8, 1) {
+        HopLimitExceeded: 0,
+        FragmentReassembly: 1,
+    }
+}
+
+serialise_enum! {
+    pub ParameterProblemCode(u8, 1) {
+        ErroneousHeaderField: 0,
+        UnrecognisedNextHeader: 1,
+        UnrecognisedOption: 2,
+    }
+}
@@ -0,0 +1,7 @@
+mod enums;
+mod ndp;
+mod packet;
+
+pub use enums::{Icmpv6Type, UnreachableCode, TimeExceededCode, ParameterProblemCode};
+pub use ndp::NdpOption;
+pub use packet::{Message, Packet};
@@ -0,0 +1,144 @@
+use crate::common::{DeserialiseError, Serialise, serialise_fields};
+use crate::common::address::{Ipv6Address, MacAddress};
+
+const OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_REDIRECTED_HEADER: u8 = 4;
+const OPTION_MTU: u8 = 5;
+
+/// A Neighbor Discovery option (RFC 4861 section 4.6), whose length is a multiple of 8 bytes.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum NdpOption {
+    SourceLinkLayerAddress(MacAddress),
+    TargetLinkLayerAddress(MacAddress),
+    PrefixInformation {
+        prefix_length: u8,
+        on_link: bool,
+        autonomous: bool,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: Ipv6Address,
+    },
+    /// As much of the redirected packet as fits, starting with its IPv6 header.
+    RedirectedHeader(Vec<u8>),
+    Mtu(u32),
+    Unknown { option_type: u8, data: Vec<u8> },
+}
+
+impl NdpOption {
+    pub fn option_type(&self) -> u8 {
+        match self {
+            Self::SourceLinkLayerAddress(..) => OPTION_SOURCE_LINK_LAYER_ADDRESS,
+            Self::TargetLinkLayerAddress(..) => OPTION_TARGET_LINK_LAYER_ADDRESS,
+            Self::PrefixInformation { .. } => OPTION_PREFIX_INFORMATION,
+            Self::RedirectedHeader(..) => OPTION_REDIRECTED_HEADER,
+            Self::Mtu(..) => OPTION_MTU,
+            Self::Unknown { option_type, .. } => *option_type,
+        }
+    }
+
+    /// Parses options until the end of the buffer.
+    pub fn deserialise_list(buf: &[u8]) -> Result<Vec<Self>, DeserialiseError> {
+        let mut options = vec![];
+        let mut index = 0;
+
+        while index < buf.len() {
+            let option = Self::deserialise(&buf[index..])?;
+            index += option.byte_length();
+            options.push(option);
+        }
+
+        Ok(options)
+    }
+}
+
+impl Serialise for NdpOption {
+    fn byte_length(&self) -> usize {
+        match self {
+            Self::SourceLinkLayerAddress(..) | Self::TargetLinkLayerAddress(..) | Self::Mtu(..) => 8,
+            Self::PrefixInformation { .. } => 32,
+            Self::RedirectedHeader(data) => (8 + data.len()).next_multiple_of(8),
+            Self::Unknown { data, .. } => (2 + data.len()).next_multiple_of(8),
+        }
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.byte_length();
+        buf[0] = self.option_type();
+        buf[1] = (len / 8) as u8;
+        buf[2..len].fill(0);
+
+        match self {
+            Self::SourceLinkLayerAddress(mac) | Self::TargetLinkLayerAddress(mac) => { mac.serialise(&mut buf[2..]); },
+            Self::PrefixInformation { prefix_length, on_link, autonomous, valid_lifetime, preferred_lifetime, prefix } => {
+                serialise_fields!(
+                    start=2,
+                    buf=buf,
+                    *prefix_length,
+                    (*on_link as u8) << 7 | (*autonomous as u8) << 6,
+                    *valid_lifetime,
+                    *preferred_lifetime,
+                    0u32,
+                    *prefix,
+                );
+            },
+            Self::RedirectedHeader(data) => buf[8..8 + data.len()].copy_from_slice(data),
+            Self::Mtu(mtu) => { mtu.serialise(&mut buf[4..]); },
+            Self::Unknown { data, .. } => buf[2..2 + data.len()].copy_from_slice(data),
+        }
+
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < 2 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2, buf.len()));
+        }
+
+        let option_type = buf[0];
+        let length = buf[1] as usize * 8;
+        if length == 0 {
+            return Err(DeserialiseError::Heap(format!("invalid zero length for ndp option {option_type}")));
+        }
+
+        if buf.len() < length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), length, buf.len()));
+        }
+
+        let data = &buf[2..length];
+        Ok(match (option_type, length) {
+            (OPTION_SOURCE_LINK_LAYER_ADDRESS, 8) => Self::SourceLinkLayerAddress(MacAddress::deserialise(data)?),
+            (OPTION_TARGET_LINK_LAYER_ADDRESS, 8) => Self::TargetLinkLayerAddress(MacAddress::deserialise(data)?),
+            (OPTION_PREFIX_INFORMATION, 32) => Self::PrefixInformation {
+                prefix_length: data[0],
+                on_link: data[1] & 0b1000_0000 > 0,
+                autonomous: data[1] & 0b0100_0000 > 0,
+                valid_lifetime: u32::deserialise(&data[2..])?,
+                preferred_lifetime: u32::deserialise(&data[6..])?,
+                prefix: Ipv6Address::deserialise(&data[14..])?,
+            },
+            (OPTION_REDIRECTED_HEADER, _) => Self::RedirectedHeader(data[6..].to_vec()),
+            (OPTION_MTU, 8) => Self::Mtu(u32::deserialise(&data[2..])?),
+            _ => Self::Unknown { option_type, data: data.to_vec() },
+        })
+    }
+}
+
+impl core::fmt::Display for NdpOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SourceLinkLayerAddress(mac) => write!(f, "source link-address {mac}"),
+            Self::TargetLinkLayerAddress(mac) => write!(f, "target link-address {mac}"),
+            Self::PrefixInformation { prefix_length, on_link, autonomous, valid_lifetime, preferred_lifetime, prefix } => write!(
+                f,
+                "prefix info {prefix}/{prefix_length}{}{} valid {valid_lifetime}s preferred {preferred_lifetime}s",
+                if *on_link { " L" } else { "" },
+                if *autonomous { " A" } else { "" },
+            ),
+            Self::RedirectedHeader(data) => write!(f, "redirected header len {}", data.len()),
+            Self::Mtu(mtu) => write!(f, "mtu {mtu}"),
+            Self::Unknown { option_type, data } => write!(f, "unknown-{option_type} len {}", data.len()),
+        }
+    }
+}
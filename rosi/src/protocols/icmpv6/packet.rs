@@ -0,0 +1,571 @@
+use crate::common::{DeserialiseError, Layer, Pdu, PseudoHeader, Serialise, serialise_fields};
+use crate::common::address::{Ipv6Address, MacAddress};
+use crate::common::log::Value;
+use crate::protocols::ipv4::IpProtocol;
+use crate::protocols::ipv6::{Ipv6Header, Ipv6Packet};
+
+use super::enums::{Icmpv6Type, ParameterProblemCode, TimeExceededCode, UnreachableCode};
+use super::ndp::NdpOption;
+
+const HEADER_LENGTH: usize = 4;
+const CHECKSUM_OFFSET: usize = 2;
+
+/// Error messages quote as much of the invoking packet as fits in the IPv6 minimum MTU (RFC 4443).
+const MAX_QUOTED_LENGTH: usize = 1280 - 40 - 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    DestinationUnreachable { code: UnreachableCode, original: Vec<u8> },
+    PacketTooBig { mtu: u32, original: Vec<u8> },
+    TimeExceeded { code: TimeExceededCode, original: Vec<u8> },
+    ParameterProblem { code: ParameterProblemCode, pointer: u32, original: Vec<u8> },
+    EchoRequest { identifier: u16, sequence: u16, data: Vec<u8> },
+    EchoReply { identifier: u16, sequence: u16, data: Vec<u8> },
+    RouterSolicitation { options: Vec<NdpOption> },
+    RouterAdvertisement {
+        hop_limit: u8,
+        managed: bool,
+        other: bool,
+        router_lifetime: u16,
+        reachable_time: u32,
+        retrans_timer: u32,
+        options: Vec<NdpOption>,
+    },
+    NeighborSolicitation { target: Ipv6Address, options: Vec<NdpOption> },
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_entry: bool,
+        target: Ipv6Address,
+        options: Vec<NdpOption>,
+    },
+    Redirect { target: Ipv6Address, destination: Ipv6Address, options: Vec<NdpOption> },
+    Unknown { icmp_type: u8, code: u8, body: Vec<u8> },
+}
+
+impl Message {
+    pub fn icmp_type(&self) -> Icmpv6Type {
+        match self {
+            Self::DestinationUnreachable { .. } => Icmpv6Type::DestinationUnreachable,
+            Self::PacketTooBig { .. } => Icmpv6Type::PacketTooBig,
+            Self::TimeExceeded { .. } => Icmpv6Type::TimeExceeded,
+            Self::ParameterProblem { .. } => Icmpv6Type::ParameterProblem,
+            Self::EchoRequest { .. } => Icmpv6Type::EchoRequest,
+            Self::EchoReply { .. } => Icmpv6Type::EchoReply,
+            Self::RouterSolicitation { .. } => Icmpv6Type::RouterSolicitation,
+            Self::RouterAdvertisement { .. } => Icmpv6Type::RouterAdvertisement,
+            Self::NeighborSolicitation { .. } => Icmpv6Type::NeighborSolicitation,
+            Self::NeighborAdvertisement { .. } => Icmpv6Type::NeighborAdvertisement,
+            Self::Redirect { .. } => Icmpv6Type::Redirect,
+            Self::Unknown { icmp_type, .. } => Icmpv6Type::from(*icmp_type),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::DestinationUnreachable { code, .. } => (*code).into(),
+            Self::TimeExceeded { code, .. } => (*code).into(),
+            Self::ParameterProblem { code, .. } => (*code).into(),
+            Self::Unknown { code, .. } => *code,
+            _ => 0,
+        }
+    }
+
+    /// The Neighbor Discovery options carried by the message, empty for non-NDP messages.
+    pub fn options(&self) -> &[NdpOption] {
+        match self {
+            Self::RouterSolicitation { options } |
+            Self::RouterAdvertisement { options, .. } |
+            Self::NeighborSolicitation { options, .. } |
+            Self::NeighborAdvertisement { options, .. } |
+            Self::Redirect { options, .. } => options,
+            _ => &[],
+        }
+    }
+
+    fn options_length(&self) -> usize {
+        self.options().iter().map(|o| o.byte_length()).sum()
+    }
+
+    fn body_length(&self) -> usize {
+        match self {
+            Self::DestinationUnreachable { original, .. } |
+            Self::PacketTooBig { original, .. } |
+            Self::TimeExceeded { original, .. } |
+            Self::ParameterProblem { original, .. } => 4 + original.len(),
+            Self::EchoRequest { data, .. } | Self::EchoReply { data, .. } => 4 + data.len(),
+            Self::RouterSolicitation { .. } => 4 + self.options_length(),
+            Self::RouterAdvertisement { .. } => 12 + self.options_length(),
+            Self::NeighborSolicitation { .. } | Self::NeighborAdvertisement { .. } => 20 + self.options_length(),
+            Self::Redirect { .. } => 36 + self.options_length(),
+            Self::Unknown { body, .. } => body.len(),
+        }
+    }
+
+    /// Writes everything after the checksum field.
+    fn serialise_body(&self, buf: &mut [u8]) -> usize {
+        let index = match self {
+            Self::DestinationUnreachable { original, .. } | Self::TimeExceeded { original, .. } => {
+                serialise_fields!(buf=buf, 0u32, original.as_slice())
+            },
+            Self::PacketTooBig { mtu, original } => serialise_fields!(buf=buf, *mtu, original.as_slice()),
+            Self::ParameterProblem { pointer, original, .. } => serialise_fields!(buf=buf, *pointer, original.as_slice()),
+            Self::EchoRequest { identifier, sequence, data } | Self::EchoReply { identifier, sequence, data } => {
+                serialise_fields!(buf=buf, *identifier, *sequence, data.as_slice())
+            },
+            Self::RouterSolicitation { .. } => serialise_fields!(buf=buf, 0u32),
+            Self::RouterAdvertisement { hop_limit, managed, other, router_lifetime, reachable_time, retrans_timer, .. } => {
+                serialise_fields!(
+                    buf=buf,
+                    *hop_limit,
+                    (*managed as u8) << 7 | (*other as u8) << 6,
+                    *router_lifetime,
+                    *reachable_time,
+                    *retrans_timer,
+                )
+            },
+            Self::NeighborSolicitation { target, .. } => serialise_fields!(buf=buf, 0u32, *target),
+            Self::NeighborAdvertisement { router, solicited, override_entry, target, .. } => {
+                serialise_fields!(
+                    buf=buf,
+                    (*router as u32) << 31 | (*solicited as u32) << 30 | (*override_entry as u32) << 29,
+                    *target,
+                )
+            },
+            Self::Redirect { target, destination, .. } => serialise_fields!(buf=buf, 0u32, *target, *destination),
+            Self::Unknown { body, .. } => serialise_fields!(buf=buf, body.as_slice()),
+        };
+
+        self.options().iter().fold(index, |index, option| index + option.serialise(&mut buf[index..]))
+    }
+
+    fn from_parts(icmp_type: u8, code: u8, body: &[u8]) -> Result<Self, DeserialiseError> {
+        let required = match Icmpv6Type::from(icmp_type) {
+            Icmpv6Type::Unknown(..) => 0,
+            Icmpv6Type::RouterAdvertisement => 12,
+            Icmpv6Type::NeighborSolicitation | Icmpv6Type::NeighborAdvertisement => 20,
+            Icmpv6Type::Redirect => 36,
+            _ => 4,
+        };
+
+        if body.len() < required {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), HEADER_LENGTH + required, HEADER_LENGTH + body.len()));
+        }
+
+        let word = || u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+        let options = || NdpOption::deserialise_list(&body[required..]);
+
+        Ok(match Icmpv6Type::from(icmp_type) {
+            Icmpv6Type::DestinationUnreachable => Self::DestinationUnreachable {
+                code: code.into(),
+                original: body[4..].to_vec(),
+            },
+            Icmpv6Type::PacketTooBig => Self::PacketTooBig {
+                mtu: word(),
+                original: body[4..].to_vec(),
+            },
+            Icmpv6Type::TimeExceeded => Self::TimeExceeded {
+                code: code.into(),
+                original: body[4..].to_vec(),
+            },
+            Icmpv6Type::ParameterProblem => Self::ParameterProblem {
+                code: code.into(),
+                pointer: word(),
+                original: body[4..].to_vec(),
+            },
+            Icmpv6Type::EchoRequest => Self::EchoRequest {
+                identifier: u16::from_be_bytes([body[0], body[1]]),
+                sequence: u16::from_be_bytes([body[2], body[3]]),
+                data: body[4..].to_vec(),
+            },
+            Icmpv6Type::EchoReply => Self::EchoReply {
+                identifier: u16::from_be_bytes([body[0], body[1]]),
+                sequence: u16::from_be_bytes([body[2], body[3]]),
+                data: body[4..].to_vec(),
+            },
+            Icmpv6Type::RouterSolicitation => Self::RouterSolicitation {
+                options: options()?,
+            },
+            Icmpv6Type::RouterAdvertisement => Self::RouterAdvertisement {
+                hop_limit: body[0],
+                managed: body[1] & 0b1000_0000 > 0,
+                other: body[1] & 0b0100_0000 > 0,
+                router_lifetime: u16::from_be_bytes([body[2], body[3]]),
+                reachable_time: u32::deserialise(&body[4..])?,
+                retrans_timer: u32::deserialise(&body[8..])?,
+                options: options()?,
+            },
+            Icmpv6Type::NeighborSolicitation => Self::NeighborSolicitation {
+                target: Ipv6Address::deserialise(&body[4..])?,
+                options: options()?,
+            },
+            Icmpv6Type::NeighborAdvertisement => Self::NeighborAdvertisement {
+                router: body[0] & 0b1000_0000 > 0,
+                solicited: body[0] & 0b0100_0000 > 0,
+                override_entry: body[0] & 0b0010_0000 > 0,
+                target: Ipv6Address::deserialise(&body[4..])?,
+                options: options()?,
+            },
+            Icmpv6Type::Redirect => Self::Redirect {
+                target: Ipv6Address::deserialise(&body[4..])?,
+                destination: Ipv6Address::deserialise(&body[20..])?,
+                options: options()?,
+            },
+            Icmpv6Type::Unknown(..) => Self::Unknown { icmp_type, code, body: body.to_vec() },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Packet {
+    message: Message,
+    checksum: u16,
+}
+
+impl Packet {
+    /// Creates a packet without a checksum; call `fill_checksum` once the pseudo-header is known.
+    pub fn new(message: Message) -> Self {
+        Self {
+            message,
+            checksum: 0,
+        }
+    }
+
+    pub fn echo_request(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        Self::new(Message::EchoRequest { identifier, sequence, data })
+    }
+
+    pub fn echo_reply(identifier: u16, sequence: u16, data: Vec<u8>) -> Self {
+        Self::new(Message::EchoReply { identifier, sequence, data })
+    }
+
+    pub fn destination_unreachable(code: UnreachableCode, original: &Ipv6Packet) -> Self {
+        Self::new(Message::DestinationUnreachable { code, original: Self::quote(original) })
+    }
+
+    pub fn packet_too_big(mtu: u32, original: &Ipv6Packet) -> Self {
+        Self::new(Message::PacketTooBig { mtu, original: Self::quote(original) })
+    }
+
+    pub fn time_exceeded(code: TimeExceededCode, original: &Ipv6Packet) -> Self {
+        Self::new(Message::TimeExceeded { code, original: Self::quote(original) })
+    }
+
+    pub fn parameter_problem(code: ParameterProblemCode, pointer: u32, original: &Ipv6Packet) -> Self {
+        Self::new(Message::ParameterProblem { code, pointer, original: Self::quote(original) })
+    }
+
+    pub fn router_solicitation(source: Option<MacAddress>) -> Self {
+        let options = source.into_iter().map(NdpOption::SourceLinkLayerAddress).collect();
+        Self::new(Message::RouterSolicitation { options })
+    }
+
+    /// Asks for the link-layer address of `target`. `source` must be `None` during duplicate address detection.
+    pub fn neighbor_solicitation(target: Ipv6Address, source: Option<MacAddress>) -> Self {
+        let options = source.into_iter().map(NdpOption::SourceLinkLayerAddress).collect();
+        Self::new(Message::NeighborSolicitation { target, options })
+    }
+
+    pub fn neighbor_advertisement(target: Ipv6Address, mac: MacAddress, router: bool, solicited: bool) -> Self {
+        Self::new(Message::NeighborAdvertisement {
+            router,
+            solicited,
+            override_entry: true,
+            target,
+            options: vec![NdpOption::TargetLinkLayerAddress(mac)],
+        })
+    }
+
+    /// Builds the echo reply for an echo request, or `None` for any other message.
+    pub fn reply(&self) -> Option<Self> {
+        match &self.message {
+            Message::EchoRequest { identifier, sequence, data } => Some(Self::echo_reply(*identifier, *sequence, data.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn icmp_type(&self) -> Icmpv6Type {
+        self.message.icmp_type()
+    }
+
+    crate::util::getter!(checksum: u16);
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self.message,
+            Message::DestinationUnreachable { .. } |
+            Message::PacketTooBig { .. } |
+            Message::TimeExceeded { .. } |
+            Message::ParameterProblem { .. }
+        )
+    }
+
+    /// Whether this is one of the Neighbor Discovery messages of RFC 4861.
+    ///
+    /// These must arrive with a hop limit of 255, which callers should check on the IPv6 packet.
+    pub fn is_ndp(&self) -> bool {
+        matches!(
+            self.message,
+            Message::RouterSolicitation { .. } |
+            Message::RouterAdvertisement { .. } |
+            Message::NeighborSolicitation { .. } |
+            Message::NeighborAdvertisement { .. } |
+            Message::Redirect { .. }
+        )
+    }
+
+    pub fn source_link_layer_address(&self) -> Option<MacAddress> {
+        self.message.options().iter().find_map(|o| match o {
+            NdpOption::SourceLinkLayerAddress(mac) => Some(*mac),
+            _ => None,
+        })
+    }
+
+    pub fn target_link_layer_address(&self) -> Option<MacAddress> {
+        self.message.options().iter().find_map(|o| match o {
+            NdpOption::TargetLinkLayerAddress(mac) => Some(*mac),
+            _ => None,
+        })
+    }
+
+    /// The IPv6 header of the packet that caused an error message.
+    pub fn original_header(&self) -> Option<Result<Ipv6Header, DeserialiseError>> {
+        match &self.message {
+            Message::DestinationUnreachable { original, .. } |
+            Message::PacketTooBig { original, .. } |
+            Message::TimeExceeded { original, .. } |
+            Message::ParameterProblem { original, .. } => Some(Ipv6Header::deserialise(original)),
+            _ => None,
+        }
+    }
+
+    /// Computes the checksum over `pseudo_header` and the message, ignoring the stored checksum.
+    pub fn compute_checksum(&self, pseudo_header: &PseudoHeader) -> u16 {
+        let mut body = vec![0u8; self.message.body_length()];
+        self.message.serialise_body(&mut body);
+
+        let mut sum = pseudo_header.checksum(IpProtocol::Ipv6Icmp.into(), self.byte_length() as u32);
+        sum.add_u16(u16::from_be_bytes([self.message.icmp_type().into(), self.message.code()]));
+        sum.add_bytes(&body);
+        sum.finish()
+    }
+
+    pub fn fill_checksum(&mut self, pseudo_header: &PseudoHeader) {
+        self.checksum = self.compute_checksum(pseudo_header);
+    }
+
+    pub fn verify_checksum(&self, pseudo_header: &PseudoHeader) -> Result<(), DeserialiseError> {
+        let expected = self.compute_checksum(pseudo_header);
+        if expected == self.checksum {
+            Ok(())
+        } else {
+            Err(DeserialiseError::ChecksumMismatch("icmpv6", expected, self.checksum))
+        }
+    }
+
+    fn quote(original: &Ipv6Packet) -> Vec<u8> {
+        let mut quoted = vec![0u8; original.byte_length()];
+        original.serialise(&mut quoted);
+        quoted.truncate(MAX_QUOTED_LENGTH);
+        quoted
+    }
+}
+
+impl Serialise for Packet {
+    fn byte_length(&self) -> usize {
+        HEADER_LENGTH + self.message.body_length()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let index = serialise_fields!(
+            buf=buf,
+            self.message.icmp_type(),
+            self.message.code(),
+            self.checksum,
+        );
+
+        index + self.message.serialise_body(&mut buf[index..])
+    }
+
+    /// Parses a packet without checking its checksum, which needs the enclosing IPv6 addresses.
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < HEADER_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), HEADER_LENGTH, buf.len()));
+        }
+
+        Ok(Self {
+            message: Message::from_parts(buf[0], buf[1], &buf[HEADER_LENGTH..])?,
+            checksum: u16::from_be_bytes([buf[CHECKSUM_OFFSET], buf[CHECKSUM_OFFSET + 1]]),
+        })
+    }
+}
+
+impl core::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ICMPv6 {}", self.icmp_type())?;
+
+        match &self.message {
+            Message::EchoRequest { identifier, sequence, data } | Message::EchoReply { identifier, sequence, data } => write!(
+                f,
+                " - ID: {}, Sequence: {}, Length: {}",
+                identifier, sequence, data.len(),
+            )?,
+            Message::DestinationUnreachable { code, .. } => write!(f, " - Code: {code}")?,
+            Message::PacketTooBig { mtu, .. } => write!(f, " - MTU: {mtu}")?,
+            Message::TimeExceeded { code, .. } => write!(f, " - Code: {code}")?,
+            Message::ParameterProblem { code, pointer, .. } => write!(f, " - Code: {code}, Pointer: {pointer}")?,
+            Message::RouterAdvertisement { hop_limit, router_lifetime, .. } => write!(
+                f,
+                " - Hop Limit: {}, Lifetime: {}s",
+                hop_limit, router_lifetime,
+            )?,
+            Message::NeighborSolicitation { target, .. } => write!(f, " - Target: {target}")?,
+            Message::NeighborAdvertisement { router, solicited, override_entry, target, .. } => write!(
+                f,
+                " - Target: {}, Flags: {}{}{}",
+                target,
+                if *router { "R" } else { "" },
+                if *solicited { "S" } else { "" },
+                if *override_entry { "O" } else { "" },
+            )?,
+            Message::Redirect { target, destination, .. } => write!(f, " - Target: {target}, Destination: {destination}")?,
+            Message::RouterSolicitation { .. } => (),
+            Message::Unknown { code, .. } => write!(f, " - Code: {code}")?,
+        }
+
+        self.message.options().iter().try_for_each(|o| write!(f, ", {o}"))
+    }
+}
+
+impl Pdu for Packet {
+    fn protocol(&self) -> &'static str {
+        "icmpv6"
+    }
+
+    fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("type", u8::from(self.icmp_type()).into()),
+            ("code", self.message.code().into()),
+            ("length", self.byte_length().into()),
+        ]
+    }
+}
+
+impl Layer for Packet {
+    /// Replaces the echo data or quoted packet; other messages carry no payload and are left as they are.
+    fn wrap(&mut self, data: &dyn Serialise) {
+        let payload = match &mut self.message {
+            Message::EchoRequest { data, .. } | Message::EchoReply { data, .. } => data,
+            Message::DestinationUnreachable { original, .. } |
+            Message::PacketTooBig { original, .. } |
+            Message::TimeExceeded { original, .. } |
+            Message::ParameterProblem { original, .. } => original,
+            _ => return,
+        };
+
+        *payload = vec![0u8; data.byte_length()];
+        data.serialise(payload.as_mut_slice());
+        self.checksum = 0;
+    }
+}
+
+#[test]
+fn test_icmpv6_neighbor_discovery() {
+    let mac = MacAddress::from([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    let source = Ipv6Address::from([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+    let target = Ipv6Address::from([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xab, 0xcd, 0xef]);
+
+    let pseudo_header = PseudoHeader::Ipv6 { source, destination: target.solicited_node() };
+    let mut solicitation = Packet::neighbor_solicitation(target, Some(mac));
+    solicitation.fill_checksum(&pseudo_header);
+
+    let mut bytes = vec![0u8; solicitation.byte_length()];
+    assert_eq!(solicitation.serialise(&mut bytes), 32);
+    assert_eq!(bytes[0], 135);
+    assert_eq!(&bytes[24..26], &[1, 1]);
+
+    let parsed = Packet::deserialise(&bytes).unwrap();
+    parsed.verify_checksum(&pseudo_header).unwrap();
+    assert!(parsed.is_ndp());
+    assert_eq!(parsed.source_link_layer_address(), Some(mac));
+    assert_eq!(parsed.message(), solicitation.message());
+    assert!(parsed.verify_checksum(&PseudoHeader::Ipv6 { source, destination: target }).is_err());
+
+    assert_eq!(target.solicited_node().to_string(), "ff02:0000:0000:0000:0000:0001:ffab:cdef");
+    assert_eq!(MacAddress::ipv6_multicast(target.solicited_node()).to_string(), "33:33:ff:ab:cd:ef");
+
+    let advertisement = Packet::neighbor_advertisement(target, mac, false, true);
+    let mut bytes = vec![0u8; advertisement.byte_length()];
+    advertisement.serialise(&mut bytes);
+    assert_eq!(bytes[4], 0b0110_0000);
+
+    let parsed = Packet::deserialise(&bytes).unwrap();
+    assert_eq!(parsed.target_link_layer_address(), Some(mac));
+
+    let prefix = NdpOption::PrefixInformation {
+        prefix_length: 64,
+        on_link: true,
+        autonomous: true,
+        valid_lifetime: 86400,
+        preferred_lifetime: 14400,
+        prefix: Ipv6Address::from([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+    };
+    let advertisement = Packet::new(Message::RouterAdvertisement {
+        hop_limit: 64,
+        managed: false,
+        other: true,
+        router_lifetime: 1800,
+        reachable_time: 0,
+        retrans_timer: 0,
+        options: vec![NdpOption::SourceLinkLayerAddress(mac), NdpOption::Mtu(1500), prefix],
+    });
+    assert_eq!(advertisement.byte_length(), 16 + 8 + 8 + 32);
+
+    let mut bytes = vec![0u8; advertisement.byte_length()];
+    advertisement.serialise(&mut bytes);
+    let parsed = Packet::deserialise(&bytes).unwrap();
+    assert_eq!(parsed.message(), advertisement.message());
+
+    bytes[17] = 0;
+    assert!(Packet::deserialise(&bytes).is_err());
+
+    print!("{parsed}");
+}
+
+#[test]
+fn test_icmpv6_error_quotes_original() {
+    let original = Ipv6Packet::new(
+        Ipv6Address::from([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]),
+        Ipv6Address::from([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x07]),
+        IpProtocol::Udp,
+        vec![0; 1400],
+    );
+
+    let mut error = Packet::packet_too_big(1280, &original);
+    assert_eq!(error.byte_length(), 1280 - 40);
+
+    let pseudo_header = PseudoHeader::Ipv6 { source: original.destination(), destination: original.source() };
+    error.fill_checksum(&pseudo_header);
+
+    let mut bytes = vec![0u8; error.byte_length()];
+    error.serialise(&mut bytes);
+
+    let parsed = Packet::deserialise(&bytes).unwrap();
+    parsed.verify_checksum(&pseudo_header).unwrap();
+    assert!(parsed.is_error());
+
+    let header = parsed.original_header().unwrap().unwrap();
+    assert_eq!(header.source(), original.source());
+    assert_eq!(header.next_header(), IpProtocol::Udp);
+
+    let request = Packet::echo_request(7, 1, vec![1, 2, 3]);
+    assert_eq!(request.reply().unwrap().message(), &Message::EchoReply { identifier: 7, sequence: 1, data: vec![1, 2, 3] });
+}
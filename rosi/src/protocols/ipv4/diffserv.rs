@@ -0,0 +1,208 @@
+use std::str::FromStr;
+
+/// A differentiated services codepoint, the upper six bits of the IPv4 type of service and
+/// the IPv6 traffic class (RFC 2474).
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone, Copy, Default)]
+pub struct Dscp(u8);
+
+impl Dscp {
+    /// Default forwarding, which is also class selector 0.
+    pub const DF: Self = Self(0);
+    /// Expedited forwarding (RFC 3246).
+    pub const EF: Self = Self(46);
+    /// Voice admit (RFC 5865).
+    pub const VOICE_ADMIT: Self = Self(44);
+    /// Lower effort (RFC 8622).
+    pub const LE: Self = Self(1);
+
+    pub const CS0: Self = Self::cs(0);
+    pub const CS1: Self = Self::cs(1);
+    pub const CS2: Self = Self::cs(2);
+    pub const CS3: Self = Self::cs(3);
+    pub const CS4: Self = Self::cs(4);
+    pub const CS5: Self = Self::cs(5);
+    pub const CS6: Self = Self::cs(6);
+    pub const CS7: Self = Self::cs(7);
+
+    pub const AF11: Self = Self::af(1, 1);
+    pub const AF12: Self = Self::af(1, 2);
+    pub const AF13: Self = Self::af(1, 3);
+    pub const AF21: Self = Self::af(2, 1);
+    pub const AF22: Self = Self::af(2, 2);
+    pub const AF23: Self = Self::af(2, 3);
+    pub const AF31: Self = Self::af(3, 1);
+    pub const AF32: Self = Self::af(3, 2);
+    pub const AF33: Self = Self::af(3, 3);
+    pub const AF41: Self = Self::af(4, 1);
+    pub const AF42: Self = Self::af(4, 2);
+    pub const AF43: Self = Self::af(4, 3);
+
+    /// Class selector `class`, 0 to 7, which keeps the meaning of the old IP precedence.
+    pub const fn cs(class: u8) -> Self {
+        Self((class & 0b111) << 3)
+    }
+
+    /// Assured forwarding class `class`, 1 to 4, with drop precedence `drop`, 1 to 3 (RFC 2597).
+    pub const fn af(class: u8, drop: u8) -> Self {
+        Self((class & 0b111) << 3 | (drop & 0b11) << 1)
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+
+    /// The class selector or assured forwarding class, which is the old IP precedence.
+    pub fn class(&self) -> u8 {
+        self.0 >> 3
+    }
+
+    /// The drop precedence of an assured forwarding codepoint.
+    pub fn drop_precedence(&self) -> Option<u8> {
+        self.is_af().then_some((self.0 >> 1) & 0b11)
+    }
+
+    pub fn is_cs(&self) -> bool {
+        self.0 & 0b111 == 0
+    }
+
+    pub fn is_af(&self) -> bool {
+        matches!(self.class(), 1..=4) && matches!(self.0 & 0b111, 0b010 | 0b100 | 0b110)
+    }
+}
+
+impl From<u8> for Dscp {
+    /// Takes the lower six bits.
+    fn from(value: u8) -> Self {
+        Self(value & 0b11_1111)
+    }
+}
+
+impl From<Dscp> for u8 {
+    fn from(value: Dscp) -> Self {
+        value.0
+    }
+}
+
+impl core::fmt::Display for Dscp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            Self::EF => write!(f, "ef"),
+            Self::VOICE_ADMIT => write!(f, "va"),
+            Self::LE => write!(f, "le"),
+            dscp if dscp.is_cs() => write!(f, "cs{}", dscp.class()),
+            dscp if dscp.is_af() => write!(f, "af{}{}", dscp.class(), (dscp.0 >> 1) & 0b11),
+            Self(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl FromStr for Dscp {
+    type Err = String;
+
+    /// Parses a name as written by `Display`, `df`, or a number from 0 to 63.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid dscp {s}");
+        let name = s.to_ascii_lowercase();
+        let digit = |i: usize| name.as_bytes().get(i).filter(|c| c.is_ascii_digit()).map(|c| c - b'0').ok_or_else(invalid);
+
+        match name.as_str() {
+            "df" | "be" => Ok(Self::DF),
+            "ef" => Ok(Self::EF),
+            "va" => Ok(Self::VOICE_ADMIT),
+            "le" => Ok(Self::LE),
+            name if name.len() == 3 && name.starts_with("cs") && digit(2)? <= 7 => Ok(Self::cs(digit(2)?)),
+            name if name.len() == 4 && name.starts_with("af") && (1..=4).contains(&digit(2)?) && (1..=3).contains(&digit(3)?) => {
+                Ok(Self::af(digit(2)?, digit(3)?))
+            },
+            name => match name.parse::<u8>() {
+                Ok(value) if value < 64 => Ok(Self(value)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// An explicit congestion notification codepoint, the lower two bits of the IPv4 type of
+/// service and the IPv6 traffic class (RFC 3168).
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Ecn {
+    /// The sender does not support ECN.
+    #[default]
+    NotEct,
+    Ect1,
+    Ect0,
+    /// Congestion was experienced on the way.
+    Ce,
+}
+
+impl Ecn {
+    /// Whether the sender supports ECN, so that a router may mark the packet instead of dropping it.
+    pub fn is_capable(&self) -> bool {
+        *self != Self::NotEct
+    }
+}
+
+impl From<u8> for Ecn {
+    /// Takes the lower two bits.
+    fn from(value: u8) -> Self {
+        match value & 0b11 {
+            0b00 => Self::NotEct,
+            0b01 => Self::Ect1,
+            0b10 => Self::Ect0,
+            _ => Self::Ce,
+        }
+    }
+}
+
+impl From<Ecn> for u8 {
+    fn from(value: Ecn) -> Self {
+        match value {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+impl core::fmt::Display for Ecn {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotEct => write!(f, "not-ect"),
+            Self::Ect1 => write!(f, "ect1"),
+            Self::Ect0 => write!(f, "ect0"),
+            Self::Ce => write!(f, "ce"),
+        }
+    }
+}
+
+/// Splits a type of service or traffic class byte into its codepoints.
+pub(crate) fn split_tos(tos: u8) -> (Dscp, Ecn) {
+    (Dscp::from(tos >> 2), Ecn::from(tos))
+}
+
+/// Joins the codepoints into a type of service or traffic class byte.
+pub(crate) fn join_tos(dscp: Dscp, ecn: Ecn) -> u8 {
+    dscp.value() << 2 | u8::from(ecn)
+}
+
+#[test]
+fn test_dscp() {
+    assert_eq!(Dscp::AF41.value(), 34);
+    assert_eq!(Dscp::CS6.value(), 48);
+    assert_eq!(Dscp::AF23.drop_precedence(), Some(3));
+    assert_eq!(Dscp::EF.drop_precedence(), None);
+
+    let names = [Dscp::DF, Dscp::CS1, Dscp::AF11, Dscp::AF43, Dscp::EF, Dscp::LE, Dscp::from(63)].map(|dscp| dscp.to_string());
+    assert_eq!(names, ["cs0", "cs1", "af11", "af43", "ef", "le", "63"]);
+    assert!(names.iter().all(|name| name.parse::<Dscp>().unwrap().to_string() == *name));
+    assert_eq!("AF31".parse(), Ok(Dscp::AF31));
+    assert_eq!("df".parse(), Ok(Dscp::CS0));
+    assert!("af44".parse::<Dscp>().is_err());
+    assert!("cs8".parse::<Dscp>().is_err());
+    assert!("64".parse::<Dscp>().is_err());
+
+    assert_eq!(split_tos(0xb8), (Dscp::EF, Ecn::NotEct));
+    assert_eq!(split_tos(0x8b), (Dscp::AF41, Ecn::Ce));
+    assert_eq!(join_tos(Dscp::AF11, Ecn::Ect0), 0x2a);
+}
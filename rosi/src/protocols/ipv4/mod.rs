@@ -0,0 +1,9 @@
+mod diffserv;
+mod options;
+mod packet;
+mod proto;
+
+pub use diffserv::{Dscp, Ecn};
+pub use options::{Ipv4Option, TimestampFlag};
+pub use packet::{Ipv4Header, Ipv4Packet};
+pub use proto::IpProtocol;
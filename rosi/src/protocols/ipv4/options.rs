@@ -0,0 +1,335 @@
+use crate::common::{DeserialiseError, Serialise};
+use crate::common::address::Ipv4Address;
+
+const KIND_END_OF_LIST: u8 = 0;
+const KIND_NO_OPERATION: u8 = 1;
+const KIND_RECORD_ROUTE: u8 = 7;
+const KIND_TIMESTAMP: u8 = 68;
+const KIND_SECURITY: u8 = 130;
+const KIND_LOOSE_SOURCE_ROUTE: u8 = 131;
+const KIND_STRICT_SOURCE_ROUTE: u8 = 137;
+const KIND_ROUTER_ALERT: u8 = 148;
+
+/// Set in the kind of an option that is copied into every fragment, not only the first.
+const COPIED: u8 = 0x80;
+
+/// The smallest pointer of a route option, pointing at its first address.
+const MIN_ROUTE_POINTER: u8 = 4;
+/// The smallest pointer of a timestamp option, pointing at its first entry.
+const MIN_TIMESTAMP_POINTER: u8 = 5;
+
+/// What the entries of a timestamp option hold (RFC 791).
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TimestampFlag {
+    /// Timestamps only.
+    TimestampsOnly,
+    /// Each router's address, followed by its timestamp.
+    WithAddresses,
+    /// Timestamps from the routers whose addresses the sender filled in beforehand.
+    Prespecified,
+}
+
+impl TimestampFlag {
+    fn entry_length(&self) -> usize {
+        match self {
+            Self::TimestampsOnly => 4,
+            Self::WithAddresses | Self::Prespecified => 8,
+        }
+    }
+}
+
+impl From<TimestampFlag> for u8 {
+    fn from(value: TimestampFlag) -> Self {
+        match value {
+            TimestampFlag::TimestampsOnly => 0,
+            TimestampFlag::WithAddresses => 1,
+            TimestampFlag::Prespecified => 3,
+        }
+    }
+}
+
+impl TryFrom<u8> for TimestampFlag {
+    type Error = DeserialiseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::TimestampsOnly),
+            1 => Ok(Self::WithAddresses),
+            3 => Ok(Self::Prespecified),
+            _ => Err(DeserialiseError::Heap(format!("invalid ipv4 timestamp flag {value}"))),
+        }
+    }
+}
+
+/// An IPv4 header option.
+///
+/// The route and timestamp options hold every slot, including those not filled in yet, and a
+/// pointer to the first free one counted in bytes from the start of the option.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Ipv4Option {
+    EndOfList,
+    NoOperation,
+    RecordRoute { pointer: u8, route: Vec<Ipv4Address> },
+    /// Timestamps in milliseconds since midnight UT, with the number of routers that had no
+    /// room left for theirs.
+    Timestamp { pointer: u8, overflow: u8, flag: TimestampFlag, entries: Vec<(Option<Ipv4Address>, u32)> },
+    /// Basic security option: a classification level and the protection authorities (RFC 1108).
+    Security { classification: u8, authorities: Vec<u8> },
+    LooseSourceRoute { pointer: u8, route: Vec<Ipv4Address> },
+    StrictSourceRoute { pointer: u8, route: Vec<Ipv4Address> },
+    /// Asks every router on the path to look at the datagram; 0 means it carries IGMP (RFC 2113).
+    RouterAlert(u16),
+    /// An option this codec does not understand, kept so it can be written back unchanged.
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl Ipv4Option {
+    /// A record route option with room for `slots` addresses.
+    pub fn record_route(slots: usize) -> Self {
+        Self::RecordRoute { pointer: MIN_ROUTE_POINTER, route: vec![Ipv4Address::default(); slots] }
+    }
+
+    pub fn kind(&self) -> u8 {
+        match self {
+            Self::EndOfList => KIND_END_OF_LIST,
+            Self::NoOperation => KIND_NO_OPERATION,
+            Self::RecordRoute { .. } => KIND_RECORD_ROUTE,
+            Self::Timestamp { .. } => KIND_TIMESTAMP,
+            Self::Security { .. } => KIND_SECURITY,
+            Self::LooseSourceRoute { .. } => KIND_LOOSE_SOURCE_ROUTE,
+            Self::StrictSourceRoute { .. } => KIND_STRICT_SOURCE_ROUTE,
+            Self::RouterAlert(..) => KIND_ROUTER_ALERT,
+            Self::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Whether the option is repeated in every fragment of a datagram.
+    pub fn is_copied(&self) -> bool {
+        self.kind() & COPIED != 0
+    }
+
+    /// The addresses filled into a route option so far.
+    pub fn recorded(&self) -> Option<&[Ipv4Address]> {
+        match self {
+            Self::RecordRoute { pointer, route } | Self::LooseSourceRoute { pointer, route } | Self::StrictSourceRoute { pointer, route } => {
+                let filled = (pointer.saturating_sub(MIN_ROUTE_POINTER) / 4) as usize;
+                Some(&route[..filled.min(route.len())])
+            },
+            _ => None,
+        }
+    }
+
+    /// Adds `address` to a record route option, returning false if it is full.
+    pub fn record(&mut self, address: Ipv4Address) -> bool {
+        let Self::RecordRoute { pointer, route } = self else {
+            return false;
+        };
+
+        let slot = (pointer.saturating_sub(MIN_ROUTE_POINTER) / 4) as usize;
+        match route.get_mut(slot) {
+            Some(free) => {
+                *free = address;
+                *pointer += 4;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Parses options until the buffer or an End of Option List is reached.
+    pub fn deserialise_list(buf: &[u8]) -> Result<Vec<Self>, DeserialiseError> {
+        let mut options = vec![];
+        let mut index = 0;
+
+        while index < buf.len() {
+            let option = Self::deserialise(&buf[index..])?;
+            index += option.byte_length();
+
+            let end = option == Self::EndOfList;
+            options.push(option);
+            if end {
+                break;
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn expect_length(kind: u8, length: usize, expected: usize) -> Result<(), DeserialiseError> {
+        if length == expected {
+            Ok(())
+        } else {
+            Err(DeserialiseError::Heap(format!("invalid length {length} for ipv4 option {kind} (expected {expected})")))
+        }
+    }
+
+    fn deserialise_route(kind: u8, data: &[u8]) -> Result<(u8, Vec<Ipv4Address>), DeserialiseError> {
+        let Some((&pointer, addresses)) = data.split_first() else {
+            return Err(DeserialiseError::Heap(format!("missing pointer in ipv4 option {kind}")));
+        };
+        if pointer < MIN_ROUTE_POINTER || !addresses.len().is_multiple_of(4) {
+            return Err(DeserialiseError::Heap(format!("invalid ipv4 route option {kind} with pointer {pointer} and length {}", data.len() + 2)));
+        }
+
+        let route = addresses.chunks_exact(4).map(Ipv4Address::deserialise).collect::<Result<_, _>>()?;
+        Ok((pointer, route))
+    }
+
+    fn deserialise_timestamp(data: &[u8]) -> Result<Self, DeserialiseError> {
+        let [pointer, flags, entries @ ..] = data else {
+            return Err(DeserialiseError::Heap(format!("invalid length {} for ipv4 timestamp option", data.len() + 2)));
+        };
+
+        let flag = TimestampFlag::try_from(flags & 0xf)?;
+        if *pointer < MIN_TIMESTAMP_POINTER || !entries.len().is_multiple_of(flag.entry_length()) {
+            return Err(DeserialiseError::Heap(format!("invalid ipv4 timestamp option with pointer {pointer} and length {}", data.len() + 2)));
+        }
+
+        let entries = entries.chunks_exact(flag.entry_length()).map(|entry| Ok(match flag {
+            TimestampFlag::TimestampsOnly => (None, u32::deserialise(entry)?),
+            _ => (Some(Ipv4Address::deserialise(entry)?), u32::deserialise(&entry[4..])?),
+        })).collect::<Result<_, DeserialiseError>>()?;
+
+        Ok(Self::Timestamp { pointer: *pointer, overflow: flags >> 4, flag, entries })
+    }
+
+    fn serialise_route(pointer: u8, route: &[Ipv4Address], buf: &mut [u8]) {
+        buf[0] = pointer;
+        route.iter().enumerate().for_each(|(i, address)| { address.serialise(&mut buf[1 + i * 4..]); });
+    }
+}
+
+impl Serialise for Ipv4Option {
+    fn byte_length(&self) -> usize {
+        match self {
+            Self::EndOfList | Self::NoOperation => 1,
+            Self::RecordRoute { route, .. } | Self::LooseSourceRoute { route, .. } | Self::StrictSourceRoute { route, .. } => 3 + route.len() * 4,
+            Self::Timestamp { flag, entries, .. } => 4 + entries.len() * flag.entry_length(),
+            Self::Security { authorities, .. } => 3 + authorities.len(),
+            Self::RouterAlert(..) => 4,
+            Self::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.byte_length();
+        buf[0] = self.kind();
+        if len == 1 {
+            return len;
+        }
+
+        buf[1] = len as u8;
+        let data = &mut buf[2..len];
+        match self {
+            Self::RecordRoute { pointer, route } | Self::LooseSourceRoute { pointer, route } | Self::StrictSourceRoute { pointer, route } => {
+                Self::serialise_route(*pointer, route, data);
+            },
+            Self::Timestamp { pointer, overflow, flag, entries } => {
+                data[0] = *pointer;
+                data[1] = overflow << 4 | u8::from(*flag);
+                for (i, (address, timestamp)) in entries.iter().enumerate() {
+                    let entry = &mut data[2 + i * flag.entry_length()..];
+                    match address {
+                        Some(address) => {
+                            address.serialise(entry);
+                            timestamp.serialise(&mut entry[4..]);
+                        },
+                        None => { timestamp.serialise(entry); },
+                    }
+                }
+            },
+            Self::Security { classification, authorities } => {
+                data[0] = *classification;
+                data[1..].copy_from_slice(authorities);
+            },
+            Self::RouterAlert(value) => { value.serialise(data); },
+            Self::Unknown { data: raw, .. } => data.copy_from_slice(raw),
+            Self::EndOfList | Self::NoOperation => (),
+        }
+
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let kind = match buf.first() {
+            Some(kind) => *kind,
+            None => return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 1, 0)),
+        };
+
+        match kind {
+            KIND_END_OF_LIST => return Ok(Self::EndOfList),
+            KIND_NO_OPERATION => return Ok(Self::NoOperation),
+            _ => (),
+        }
+
+        if buf.len() < 2 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2, buf.len()));
+        }
+
+        let length = buf[1] as usize;
+        if length < 2 {
+            return Err(DeserialiseError::Heap(format!("invalid length {length} for ipv4 option {kind}")));
+        }
+
+        if buf.len() < length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), length, buf.len()));
+        }
+
+        let data = &buf[2..length];
+        Ok(match kind {
+            KIND_RECORD_ROUTE => {
+                let (pointer, route) = Self::deserialise_route(kind, data)?;
+                Self::RecordRoute { pointer, route }
+            },
+            KIND_LOOSE_SOURCE_ROUTE => {
+                let (pointer, route) = Self::deserialise_route(kind, data)?;
+                Self::LooseSourceRoute { pointer, route }
+            },
+            KIND_STRICT_SOURCE_ROUTE => {
+                let (pointer, route) = Self::deserialise_route(kind, data)?;
+                Self::StrictSourceRoute { pointer, route }
+            },
+            KIND_TIMESTAMP => Self::deserialise_timestamp(data)?,
+            KIND_SECURITY => match data.split_first() {
+                Some((classification, authorities)) => Self::Security { classification: *classification, authorities: authorities.to_vec() },
+                None => return Err(DeserialiseError::Heap(format!("invalid length {length} for ipv4 security option"))),
+            },
+            KIND_ROUTER_ALERT => {
+                Self::expect_length(kind, length, 4)?;
+                Self::RouterAlert(u16::deserialise(data)?)
+            },
+            _ => Self::Unknown { kind, data: data.to_vec() },
+        })
+    }
+}
+
+impl core::fmt::Display for Ipv4Option {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let route = |f: &mut std::fmt::Formatter<'_>, name: &str, route: &[Ipv4Address]| {
+            write!(f, "{name}")?;
+            route.iter().try_for_each(|address| write!(f, " {address}"))
+        };
+
+        match self {
+            Self::EndOfList => write!(f, "eol"),
+            Self::NoOperation => write!(f, "nop"),
+            Self::RecordRoute { route: addresses, .. } => route(f, "rr", addresses),
+            Self::LooseSourceRoute { route: addresses, .. } => route(f, "lsrr", addresses),
+            Self::StrictSourceRoute { route: addresses, .. } => route(f, "ssrr", addresses),
+            Self::Timestamp { overflow, entries, .. } => {
+                write!(f, "ts")?;
+                entries.iter().try_for_each(|entry| match entry {
+                    (Some(address), timestamp) => write!(f, " {address}@{timestamp}"),
+                    (None, timestamp) => write!(f, " {timestamp}"),
+                })?;
+                match overflow {
+                    0 => Ok(()),
+                    overflow => write!(f, " ({overflow} hops not recorded)"),
+                }
+            },
+            Self::Security { classification, .. } => write!(f, "security {classification:#04x}"),
+            Self::RouterAlert(value) => write!(f, "ra {value}"),
+            Self::Unknown { kind, data } => write!(f, "unknown-{kind} len {}", data.len()),
+        }
+    }
+}
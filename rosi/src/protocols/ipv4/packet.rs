@@ -0,0 +1,602 @@
+use std::sync::Arc;
+
+use crate::common::{checksum, DeserialiseError, PseudoHeader, Layer, Pdu, Serialise, Wrapper, serialise_fields};
+use crate::common::address::Ipv4Address;
+use crate::common::log::Value;
+
+use super::diffserv::{self, Dscp, Ecn};
+use super::options::Ipv4Option;
+use super::proto::IpProtocol;
+
+const MIN_HEADER_LENGTH: usize = 20;
+const MAX_HEADER_LENGTH: usize = 60;
+const CHECKSUM_OFFSET: usize = 10;
+const DEFAULT_TTL: u8 = 64;
+
+#[derive(Debug, Clone)]
+pub struct Ipv4Header {
+    version: u8,        // 4 bits
+    ihl: u8,            // 4 bits
+
+    // Type of Service (8 bits)
+    dscp: Dscp,         // 6 bits
+    ecn: Ecn,           // 2 bits
+
+    total_length: u16,
+    identification: u16,
+
+    // Flags (3 bits)
+    reserved_1: bool,
+    dont_fragment: bool,
+    more_fragments: bool,
+
+    fragment_offset: u16,    // 13 bits
+
+    ttl: u8,
+    proto: IpProtocol,
+    checksum: u16,
+
+    source_addr: Ipv4Address,
+    dest_addr: Ipv4Address,
+
+    options: Vec<Ipv4Option>,
+}
+
+macro_rules! bool_to_bit {
+    ($b:expr, $bit:expr) => {
+        if $b {
+            1 << ($bit)
+        } else {
+            0
+        }
+    };
+}
+
+impl Ipv4Header {
+    crate::util::getter!(version: u8);
+    crate::util::getter!(ihl: u8);
+    crate::util::getter!(dscp: Dscp);
+    crate::util::getter!(ecn: Ecn);
+    crate::util::getter!(total_length: u16);
+    crate::util::getter!(identification: u16);
+    crate::util::getter!(dont_fragment: bool);
+    crate::util::getter!(more_fragments: bool);
+    crate::util::getter!(fragment_offset: u16);
+    crate::util::getter!(ttl: u8);
+    crate::util::getter!(proto: IpProtocol);
+    crate::util::getter!(checksum: u16);
+    crate::util::getter!(source(source_addr): Ipv4Address);
+    crate::util::getter!(destination(dest_addr): Ipv4Address);
+
+    pub fn options(&self) -> &[Ipv4Option] {
+        &self.options
+    }
+
+    /// The raw type of service byte holding the DSCP and ECN codepoints.
+    pub fn tos(&self) -> u8 {
+        diffserv::join_tos(self.dscp, self.ecn)
+    }
+
+    /// Computes the header checksum, ignoring the value currently stored in the header.
+    pub fn compute_checksum(&self) -> u16 {
+        let mut buf = [0u8; MAX_HEADER_LENGTH];
+        let len = self.serialise_without_checksum(&mut buf);
+        checksum::checksum(&buf[..len])
+    }
+
+    pub fn validate_checksum(&self) -> bool {
+        self.checksum == self.compute_checksum()
+    }
+
+    fn serialise_without_checksum(&self, buf: &mut [u8]) -> usize {
+        let ihl = self.ihl & 0xf;
+
+        let index = serialise_fields!(
+            buf=buf,
+            (self.version & 0xf) << 4 | ihl,
+            self.tos(),
+            self.total_length,
+            self.identification,
+            (
+                bool_to_bit!(self.reserved_1, 15) |
+                bool_to_bit!(self.dont_fragment, 14) |
+                bool_to_bit!(self.more_fragments, 13) |
+                self.fragment_offset & 0b0001_1111_1111_1111
+            ),
+            self.ttl,
+            self.proto,
+            0u16,
+            self.source_addr,
+            self.dest_addr,
+        );
+
+        let index = self.options.iter().fold(index, |index, option| index + option.serialise(&mut buf[index..]));
+
+        // Pad with End of Option List bytes up to the header length.
+        buf[index..self.byte_length()].fill(0);
+
+        self.byte_length()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ipv4Packet {
+    header: Ipv4Header,
+    data: Vec<u8>,
+}
+
+impl Ipv4Packet {
+    pub fn new(
+        source: Ipv4Address, destination: Ipv4Address,
+        proto: IpProtocol,
+        data: Vec<u8>,
+    ) -> Self {
+        let mut packet = Self {
+            header: Ipv4Header {
+                version: 4,
+                ihl: (MIN_HEADER_LENGTH / 4) as u8,
+                dscp: Dscp::DF,
+                ecn: Ecn::NotEct,
+                total_length: 0,
+                identification: 0,
+                reserved_1: false,
+                dont_fragment: false,
+                more_fragments: false,
+                fragment_offset: 0,
+                ttl: DEFAULT_TTL,
+                proto,
+                checksum: 0,
+                source_addr: source,
+                dest_addr: destination,
+                options: vec![],
+            },
+            data,
+        };
+
+        packet.update_header();
+        packet
+    }
+
+    crate::util::getter!(version(header.version): u8);
+    crate::util::getter!(ihl(header.ihl): u8);
+    crate::util::getter!(dscp(header.dscp): Dscp);
+    crate::util::getter!(ecn(header.ecn): Ecn);
+    crate::util::getter!(total_length(header.total_length): u16);
+    crate::util::getter!(identification(header.identification): u16);
+    crate::util::getter!(dont_fragment(header.dont_fragment): bool);
+    crate::util::getter!(more_fragments(header.more_fragments): bool);
+    crate::util::getter!(fragment_offset(header.fragment_offset): u16);
+    crate::util::getter!(ttl(header.ttl): u8);
+    crate::util::getter!(proto(header.proto): IpProtocol);
+    crate::util::getter!(checksum(header.checksum): u16);
+    crate::util::getter!(source(header.source_addr): Ipv4Address);
+    crate::util::getter!(destination(header.dest_addr): Ipv4Address);
+
+    pub fn header(&self) -> &Ipv4Header {
+        &self.header
+    }
+
+    pub fn options(&self) -> &[Ipv4Option] {
+        &self.header.options
+    }
+
+    pub fn router_alert(&self) -> Option<u16> {
+        self.header.options.iter().find_map(|o| match o {
+            Ipv4Option::RouterAlert(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    /// The addresses recorded so far by a record route option.
+    pub fn recorded_route(&self) -> Option<&[Ipv4Address]> {
+        self.header.options.iter().find(|o| matches!(o, Ipv4Option::RecordRoute { .. })).and_then(Ipv4Option::recorded)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Largest payload the total length field can describe under the current header.
+    pub fn max_data_length(&self) -> usize {
+        u16::MAX as usize - self.header.byte_length()
+    }
+
+    /// The pseudo-header covered by the checksum of the carried UDP or TCP payload.
+    pub fn pseudo_header(&self) -> PseudoHeader {
+        PseudoHeader::Ipv4 {
+            source: self.header.source_addr,
+            destination: self.header.dest_addr,
+        }
+    }
+
+    pub fn tos(&self) -> u8 {
+        self.header.tos()
+    }
+
+    pub fn set_tos(&mut self, tos: u8) {
+        (self.header.dscp, self.header.ecn) = diffserv::split_tos(tos);
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    pub fn set_dscp(&mut self, dscp: Dscp) {
+        self.header.dscp = dscp;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    pub fn set_ecn(&mut self, ecn: Ecn) {
+        self.header.ecn = ecn;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.header.ttl = ttl;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    pub fn set_identification(&mut self, identification: u16) {
+        self.header.identification = identification;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    pub fn set_dont_fragment(&mut self, dont_fragment: bool) {
+        self.header.dont_fragment = dont_fragment;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    /// Marks the packet as the fragment at `fragment_offset`, counted in 8 byte units, of a
+    /// larger datagram.
+    pub fn set_fragment(&mut self, more_fragments: bool, fragment_offset: u16) {
+        self.header.more_fragments = more_fragments;
+        self.header.fragment_offset = fragment_offset & 0b0001_1111_1111_1111;
+        self.header.checksum = self.header.compute_checksum();
+    }
+
+    /// Replaces the options and recomputes the header length. Fails if they do not fit in 40
+    /// bytes, or leave too little room for the data.
+    pub fn set_options(&mut self, options: Vec<Ipv4Option>) -> Result<(), Vec<Ipv4Option>> {
+        let length = options.iter().map(|o| o.byte_length()).sum::<usize>().next_multiple_of(4);
+        if MIN_HEADER_LENGTH + length > MAX_HEADER_LENGTH || MIN_HEADER_LENGTH + length + self.data.len() > u16::MAX as usize {
+            return Err(options);
+        }
+
+        self.header.options = options;
+        self.header.ihl = ((MIN_HEADER_LENGTH + length) / 4) as u8;
+        self.update_header();
+        Ok(())
+    }
+
+    /// Panics if the data does not fit in the total length, rather than sending a packet
+    /// whose length is wrong.
+    fn update_header(&mut self) {
+        assert!(
+            self.data.len() <= self.max_data_length(),
+            "ipv4 data of {} bytes is longer than the {} a packet can carry",
+            self.data.len(), self.max_data_length(),
+        );
+        self.header.total_length = (self.header.byte_length() + self.data.len()) as u16;
+        self.header.checksum = self.header.compute_checksum();
+    }
+}
+
+impl Serialise for Ipv4Header {
+    fn byte_length(&self) -> usize {
+        self.ihl as usize * 4
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.serialise_without_checksum(buf);
+        let checksum = checksum::checksum(&buf[..len]);
+        checksum.serialise(&mut buf[CHECKSUM_OFFSET..]);
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError>
+    where Self: Sized {
+        if buf.len() < MIN_HEADER_LENGTH {
+            return Err(DeserialiseError::Static("minimum size of ipv4 header is 20 bytes"));
+        }
+
+        let ihl = buf[0] & 0x0f;
+
+        let num_bytes = (ihl * 4) as usize;
+        if num_bytes < MIN_HEADER_LENGTH {
+            return Err(DeserialiseError::Heap(format!("invalid ipv4 header length: {ihl}")));
+        }
+
+        if buf.len() < num_bytes {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), num_bytes, buf.len()));
+        };
+
+        let header = Self {
+            version: (buf[0] & 0xf0) >> 4,
+            ihl,
+
+            dscp: Dscp::from(buf[1] >> 2),
+            ecn: Ecn::from(buf[1]),
+
+            total_length: u16::from_be_bytes([buf[2], buf[3]]),
+            identification: u16::from_be_bytes([buf[4], buf[5]]),
+
+            reserved_1:     (buf[6] & 0b1000_0000) > 0,
+            dont_fragment:  (buf[6] & 0b0100_0000) > 0,
+            more_fragments: (buf[6] & 0b0010_0000) > 0,
+
+            fragment_offset: u16::from_be_bytes([buf[6] & 0b0001_1111, buf[7]]),
+
+            ttl: buf[8],
+            proto: IpProtocol::deserialise(&buf[9..])?,
+            checksum: u16::from_be_bytes([buf[10], buf[11]]),
+
+            source_addr: Ipv4Address::deserialise(&buf[12..])?,
+            dest_addr: Ipv4Address::deserialise(&buf[16..])?,
+
+            options: Ipv4Option::deserialise_list(&buf[MIN_HEADER_LENGTH..num_bytes])?,
+        };
+
+        if !checksum::verify(&buf[..num_bytes]) {
+            return Err(DeserialiseError::ChecksumMismatch("ipv4", header.compute_checksum(), header.checksum));
+        }
+
+        Ok(header)
+    }
+}
+
+impl Serialise for Ipv4Packet {
+    fn byte_length(&self) -> usize {
+        self.header.byte_length() + self.data.len()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let index = 0;
+        let index = index + self.header.serialise(&mut buf[index..]);
+        index + self.data.as_slice().serialise(&mut buf[index..])
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let header = Ipv4Header::deserialise(buf)?;
+        if header.version != 4 {
+            return Err(DeserialiseError::Heap(format!("invalid ipv4 version: {}", header.version)));
+        }
+
+        let total_length = header.total_length as usize;
+        if total_length < header.byte_length() {
+            return Err(DeserialiseError::Heap(format!("ipv4 total length {total_length} is shorter than its header")));
+        }
+
+        if buf.len() < total_length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), total_length, buf.len()));
+        }
+
+        let data = buf[header.byte_length()..total_length].to_owned();
+
+        Ok(Self {
+            header,
+            data,
+        })
+    }
+}
+
+impl core::fmt::Display for Ipv4Packet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "IPv4 Packet: {} bytes", self.byte_length())?;
+        writeln!(f, "-----------------")?;
+        writeln!(f, "Source:      {}", self.header.source_addr)?;
+        writeln!(f, "Destination: {}", self.header.dest_addr)?;
+        writeln!(f, "Protocol:    {}", self.header.proto)?;
+        writeln!(f, "TTL:         {}", self.header.ttl)?;
+        writeln!(f, "DSCP:        {}", self.header.dscp)?;
+        writeln!(f, "ECN:         {}", self.header.ecn)?;
+        writeln!(f, "ID:          {}", self.header.identification)?;
+        writeln!(
+            f, "Flags:       {}{}",
+            if self.header.dont_fragment { "DF " } else { "" },
+            if self.header.more_fragments { "MF " } else { "" },
+        )?;
+        writeln!(f, "Offset:      {}", self.header.fragment_offset)?;
+        writeln!(f, "Checksum:    {:04x}", self.header.checksum)?;
+        if !self.header.options.is_empty() {
+            let options = self.header.options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+            writeln!(f, "Options:     [{}]", options.join(","))?;
+        }
+        writeln!(f)?;
+
+        if !self.data.is_empty() {
+            writeln!(f, "Data:")?;
+            writeln!(f, "-----------------")?;
+            self.data.chunks(16).try_for_each(|chunk| {
+                let mut line = String::with_capacity(16 * 3);
+                chunk.iter().for_each(|d| line.push_str(&format!("{:02x} ", d)));
+                writeln!(f, "{}", line)
+            })?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pdu for Ipv4Packet {
+    fn protocol(&self) -> &'static str {
+        "ipv4"
+    }
+
+    fn summary(&self) -> String {
+        let header = &self.header;
+        let flags = match (header.dont_fragment, header.more_fragments) {
+            (true, _) => " [DF]",
+            (false, true) => " [+]",
+            (false, false) => "",
+        };
+        let offset = match header.fragment_offset {
+            0 => String::new(),
+            offset => format!(" offset {}", offset as usize * 8),
+        };
+        let tos = match (header.dscp, header.ecn) {
+            (Dscp::DF, Ecn::NotEct) => String::new(),
+            (dscp, Ecn::NotEct) => format!(" dscp {dscp}"),
+            (dscp, ecn) => format!(" dscp {dscp} ecn {ecn}"),
+        };
+        let options = match header.options.is_empty() {
+            true => String::new(),
+            false => format!(" options [{}]", header.options.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(",")),
+        };
+
+        format!(
+            "IP {} > {}: {}{} ttl {} id {}{}{} length {}{}",
+            header.source_addr, header.dest_addr, header.proto, tos,
+            header.ttl, header.identification, flags, offset, header.total_length, options,
+        )
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        let header = &self.header;
+        vec![
+            ("source", header.source_addr.to_string().into()),
+            ("destination", header.dest_addr.to_string().into()),
+            ("proto", u8::from(header.proto).into()),
+            ("dscp", header.dscp.value().into()),
+            ("ecn", u8::from(header.ecn).into()),
+            ("ttl", header.ttl.into()),
+            ("id", header.identification.into()),
+            ("dont_fragment", header.dont_fragment.into()),
+            ("more_fragments", header.more_fragments.into()),
+            ("fragment_offset", header.fragment_offset.into()),
+            ("length", header.total_length.into()),
+        ]
+    }
+}
+
+impl Wrapper for Ipv4Packet {
+    fn unwrap_data(&self) -> Arc<[u8]> {
+        Arc::from(self.data.as_slice())
+    }
+}
+
+impl Layer for Ipv4Packet {
+    fn wrap(&mut self, data: &dyn Serialise) {
+        self.data = vec![0u8; data.byte_length()];
+        data.serialise(self.data.as_mut_slice());
+        self.update_header();
+    }
+}
+
+#[test]
+fn test_ipv4_packet() {
+    let mut packet = Ipv4Packet::new(
+        Ipv4Address::from([172, 16, 0, 1]),
+        Ipv4Address::from([127, 0, 0, 1]),
+        IpProtocol::Icmp,
+        vec![],
+    );
+
+    packet.wrap(&[8u8, 0, 0, 0, 0, 1, 0, 1].as_slice());
+    assert_eq!(packet.total_length(), 28);
+    assert_eq!(packet.ihl(), 5);
+
+    let mut bytes = vec![0u8; packet.byte_length()];
+    assert_eq!(packet.serialise(&mut bytes), 28);
+    assert_eq!(bytes[0], 0x45);
+
+    assert!(checksum::verify(&bytes[..20]));
+    assert_eq!(u16::from_be_bytes([bytes[10], bytes[11]]), packet.checksum());
+
+    let new_packet = Ipv4Packet::deserialise(&bytes).unwrap();
+    assert!(new_packet.header().validate_checksum());
+    assert_eq!(new_packet.source(), packet.source());
+    assert_eq!(new_packet.destination(), packet.destination());
+    assert_eq!(new_packet.proto(), IpProtocol::Icmp);
+    assert_eq!(new_packet.data(), packet.data());
+
+    print!("{new_packet}");
+
+    packet.set_tos(0xb9);
+    assert_eq!((packet.dscp(), packet.ecn()), (Dscp::EF, Ecn::Ect1));
+    packet.set_ecn(Ecn::Ce);
+    packet.serialise(&mut bytes);
+    assert_eq!(bytes[1], 0xbb);
+    assert!(checksum::verify(&bytes[..20]));
+
+    let new_packet = Ipv4Packet::deserialise(&bytes).unwrap();
+    assert_eq!((new_packet.dscp(), new_packet.ecn(), new_packet.tos()), (Dscp::EF, Ecn::Ce, 0xbb));
+    assert!(new_packet.summary().contains(" dscp ef ecn ce ttl"), "{}", new_packet.summary());
+
+    // The total length bounds the data, and options cannot take the room it needs.
+    packet.wrap(&vec![0u8; packet.max_data_length()].as_slice());
+    assert_eq!(packet.total_length(), u16::MAX);
+    assert!(packet.set_options(vec![Ipv4Option::RouterAlert(0)]).is_err());
+    assert!(std::panic::catch_unwind(move || packet.wrap(&vec![0u8; 65516].as_slice())).is_err());
+}
+
+#[test]
+fn test_ipv4_checksum() {
+    // ICMP echo request header from 172.16.10.99 to 172.16.10.12.
+    let mut bytes = [
+        0x45, 0x00, 0x00, 0x54, 0x94, 0x2b, 0x40, 0x00, 0x40, 0x01,
+        0x39, 0xee, 0xac, 0x10, 0x0a, 0x63, 0xac, 0x10, 0x0a, 0x0c,
+    ];
+
+    let header = Ipv4Header::deserialise(&bytes).unwrap();
+    assert_eq!(header.checksum(), 0x39ee);
+    assert_eq!(header.compute_checksum(), 0x39ee);
+
+    bytes[8] = 0x3f;
+    assert!(matches!(
+        Ipv4Header::deserialise(&bytes),
+        Err(DeserialiseError::ChecksumMismatch(..))
+    ));
+}
+
+#[test]
+fn test_ipv4_options() {
+    use super::options::TimestampFlag;
+
+    let mut packet = Ipv4Packet::new(
+        Ipv4Address::from([10, 0, 0, 1]),
+        Ipv4Address::from([10, 0, 0, 2]),
+        IpProtocol::Icmp,
+        vec![8, 0, 0, 0, 0, 1, 0, 1],
+    );
+
+    let mut record_route = Ipv4Option::record_route(2);
+    assert!(record_route.record(Ipv4Address::from([10, 0, 0, 1])));
+    packet.set_options(vec![
+        record_route,
+        Ipv4Option::RouterAlert(0),
+        Ipv4Option::Timestamp { pointer: 5, overflow: 1, flag: TimestampFlag::WithAddresses, entries: vec![(Some(Ipv4Address::default()), 0)] },
+        Ipv4Option::Unknown { kind: 25, data: vec![1] },
+    ]).unwrap();
+
+    // 11 + 4 + 12 + 3 bytes of options, padded to 32.
+    assert_eq!(packet.ihl(), 13);
+    assert_eq!(packet.total_length(), 60);
+
+    let mut bytes = vec![0u8; packet.byte_length()];
+    packet.serialise(&mut bytes);
+    assert_eq!(&bytes[20..26], &[7, 11, 8, 10, 0, 0]);
+    assert_eq!(&bytes[50..52], &[0, 0]);
+
+    let new_packet = Ipv4Packet::deserialise(&bytes).unwrap();
+    assert_eq!(new_packet.options()[..4], packet.options()[..]);
+    assert_eq!(new_packet.options()[4], Ipv4Option::EndOfList);
+    assert_eq!(new_packet.router_alert(), Some(0));
+    assert_eq!(new_packet.recorded_route(), Some([Ipv4Address::from([10, 0, 0, 1])].as_slice()));
+    assert!(new_packet.summary().ends_with("options [rr 10.0.0.1 0.0.0.0,ra 0,ts 0.0.0.0@0 (1 hops not recorded),unknown-25 len 1,eol]"));
+
+    let mut full = Ipv4Option::record_route(0);
+    assert!(!full.record(Ipv4Address::default()));
+    assert!(packet.set_options(vec![Ipv4Option::record_route(10)]).is_err());
+
+    let sample = Ipv4Option::LooseSourceRoute { pointer: 4, route: vec![Ipv4Address::from([192, 0, 2, 1])] };
+    let mut buf = [0u8; 7];
+    sample.serialise(&mut buf);
+    assert_eq!(Ipv4Option::deserialise(&buf).unwrap(), sample);
+    assert!(sample.is_copied());
+
+    // Lengths that do not fit the option or its contents.
+    assert!(Ipv4Option::deserialise(&[148, 2]).is_err());
+    assert!(Ipv4Option::deserialise(&[7, 6, 4, 0, 0, 0]).is_err());
+    assert!(Ipv4Option::deserialise(&[7, 7, 3, 0, 0, 0, 0]).is_err());
+    assert!(Ipv4Option::deserialise(&[68, 8, 5, 2, 0, 0, 0, 0]).is_err());
+    assert!(Ipv4Option::deserialise(&[130, 4, 0]).is_err());
+    assert!(Ipv4Option::deserialise_list(&[1, 131, 1]).is_err());
+}
@@ -2,11 +2,14 @@ use crate::util::serialise_enum;
 
 serialise_enum! {
     pub IpProtocol(u8, 1) {
+        HopOpt:     0x00,
         Icmp:       0x01,
         Tcp:        0x06,
         Udp:        0x11,
+        Ipv6Route:  0x2b,
+        Ipv6Frag:   0x2c,
         Ipv6Icmp:   0x3a,
         Ipv6NoNxt:  0x3b,
         Ipv6Opts:   0x3c,
     }
-}
\ No newline at end of file
+}
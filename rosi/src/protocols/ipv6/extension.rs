@@ -0,0 +1,262 @@
+use crate::common::{DeserialiseError, Serialise};
+use crate::protocols::ipv4::IpProtocol;
+
+const OPTION_PAD1: u8 = 0;
+const OPTION_PADN: u8 = 1;
+const OPTION_ROUTER_ALERT: u8 = 5;
+
+/// A TLV option carried in a Hop-by-Hop or Destination Options header (RFC 8200 section 4.2).
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Ipv6Option {
+    Pad1,
+    PadN(u8),
+    RouterAlert(u16),
+    Unknown { option_type: u8, data: Vec<u8> },
+}
+
+impl Ipv6Option {
+    pub fn option_type(&self) -> u8 {
+        match self {
+            Self::Pad1 => OPTION_PAD1,
+            Self::PadN(..) => OPTION_PADN,
+            Self::RouterAlert(..) => OPTION_ROUTER_ALERT,
+            Self::Unknown { option_type, .. } => *option_type,
+        }
+    }
+
+    /// Padding that brings `length` bytes of header up to a multiple of 8.
+    fn padding(length: usize) -> Option<Self> {
+        match (8 - length % 8) % 8 {
+            0 => None,
+            1 => Some(Self::Pad1),
+            n => Some(Self::PadN(n as u8 - 2)),
+        }
+    }
+}
+
+impl Serialise for Ipv6Option {
+    fn byte_length(&self) -> usize {
+        match self {
+            Self::Pad1 => 1,
+            Self::PadN(len) => 2 + *len as usize,
+            Self::RouterAlert(..) => 4,
+            Self::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.byte_length();
+        buf[0] = self.option_type();
+        if len == 1 {
+            return len;
+        }
+
+        buf[1] = (len - 2) as u8;
+        match self {
+            Self::PadN(..) => buf[2..len].fill(0),
+            Self::RouterAlert(value) => { value.serialise(&mut buf[2..]); },
+            Self::Unknown { data, .. } => buf[2..len].copy_from_slice(data),
+            Self::Pad1 => (),
+        }
+
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let option_type = match buf.first() {
+            Some(t) => *t,
+            None => return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 1, 0)),
+        };
+
+        if option_type == OPTION_PAD1 {
+            return Ok(Self::Pad1);
+        }
+
+        if buf.len() < 2 || buf.len() < 2 + buf[1] as usize {
+            let required = buf.get(1).map_or(2, |len| 2 + *len as usize);
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), required, buf.len()));
+        }
+
+        let data = &buf[2..2 + buf[1] as usize];
+        Ok(match option_type {
+            OPTION_PADN => Self::PadN(data.len() as u8),
+            OPTION_ROUTER_ALERT if data.len() == 2 => Self::RouterAlert(u16::from_be_bytes([data[0], data[1]])),
+            _ => Self::Unknown { option_type, data: data.to_vec() },
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ExtensionHeader {
+    HopByHop(Vec<Ipv6Option>),
+    Routing { routing_type: u8, segments_left: u8, data: Vec<u8> },
+    Fragment { fragment_offset: u16, more_fragments: bool, identification: u32 },
+    DestinationOptions(Vec<Ipv6Option>),
+}
+
+impl ExtensionHeader {
+    /// The Next Header value that identifies this extension header.
+    pub fn proto(&self) -> IpProtocol {
+        match self {
+            Self::HopByHop(..) => IpProtocol::HopOpt,
+            Self::Routing { .. } => IpProtocol::Ipv6Route,
+            Self::Fragment { .. } => IpProtocol::Ipv6Frag,
+            Self::DestinationOptions(..) => IpProtocol::Ipv6Opts,
+        }
+    }
+
+    pub(super) fn is_extension(proto: IpProtocol) -> bool {
+        matches!(proto, IpProtocol::HopOpt | IpProtocol::Ipv6Route | IpProtocol::Ipv6Frag | IpProtocol::Ipv6Opts)
+    }
+
+    pub fn byte_length(&self) -> usize {
+        match self {
+            Self::HopByHop(options) | Self::DestinationOptions(options) => Self::options_length(options).next_multiple_of(8),
+            Self::Routing { data, .. } => (4 + data.len()).next_multiple_of(8),
+            Self::Fragment { .. } => 8,
+        }
+    }
+
+    fn options_length(options: &[Ipv6Option]) -> usize {
+        2 + options.iter().map(|o| o.byte_length()).sum::<usize>()
+    }
+
+    /// Writes the header, which has to know the Next Header value that follows it.
+    pub fn serialise_with_next(&self, next_header: IpProtocol, buf: &mut [u8]) -> usize {
+        let len = self.byte_length();
+        buf[0] = next_header.into();
+        buf[1] = (len / 8 - 1) as u8;
+
+        match self {
+            Self::HopByHop(options) | Self::DestinationOptions(options) => {
+                let index = options.iter().fold(2, |index, option| index + option.serialise(&mut buf[index..]));
+                if let Some(padding) = Ipv6Option::padding(index) {
+                    padding.serialise(&mut buf[index..]);
+                }
+            },
+            Self::Routing { routing_type, segments_left, data } => {
+                buf[2] = *routing_type;
+                buf[3] = *segments_left;
+                buf[4..4 + data.len()].copy_from_slice(data);
+                buf[4 + data.len()..len].fill(0);
+            },
+            Self::Fragment { fragment_offset, more_fragments, identification } => {
+                // The length field of the fragment header is reserved.
+                buf[1] = 0;
+                (fragment_offset << 3 | *more_fragments as u16).serialise(&mut buf[2..]);
+                identification.serialise(&mut buf[4..]);
+            },
+        }
+
+        len
+    }
+
+    /// Parses one extension header of type `proto`, returning it with the Next Header value it names.
+    pub fn deserialise_with_next(proto: IpProtocol, buf: &[u8]) -> Result<(Self, IpProtocol), DeserialiseError> {
+        if buf.len() < 8 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 8, buf.len()));
+        }
+
+        let next_header = IpProtocol::from(buf[0]);
+        let len = if proto == IpProtocol::Ipv6Frag { 8 } else { (buf[1] as usize + 1) * 8 };
+        if buf.len() < len {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), len, buf.len()));
+        }
+
+        let parse_options = |buf: &[u8]| -> Result<Vec<Ipv6Option>, DeserialiseError> {
+            let mut options = vec![];
+            let mut index = 0;
+            while index < buf.len() {
+                let option = Ipv6Option::deserialise(&buf[index..])?;
+                index += option.byte_length();
+                options.push(option);
+            }
+            Ok(options)
+        };
+
+        let header = match proto {
+            IpProtocol::HopOpt => Self::HopByHop(parse_options(&buf[2..len])?),
+            IpProtocol::Ipv6Opts => Self::DestinationOptions(parse_options(&buf[2..len])?),
+            IpProtocol::Ipv6Route => Self::Routing {
+                routing_type: buf[2],
+                segments_left: buf[3],
+                data: buf[4..len].to_vec(),
+            },
+            IpProtocol::Ipv6Frag => {
+                let offset = u16::from_be_bytes([buf[2], buf[3]]);
+                Self::Fragment {
+                    fragment_offset: offset >> 3,
+                    more_fragments: offset & 1 == 1,
+                    identification: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+                }
+            },
+            proto => return Err(DeserialiseError::Heap(format!("{proto} is not an ipv6 extension header"))),
+        };
+
+        Ok((header, next_header))
+    }
+}
+
+impl core::fmt::Display for ExtensionHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HopByHop(options) => write!(f, "Hop-by-Hop ({} options)", options.len()),
+            Self::DestinationOptions(options) => write!(f, "Destination Options ({} options)", options.len()),
+            Self::Routing { routing_type, segments_left, .. } => write!(f, "Routing (type {routing_type}, {segments_left} segments left)"),
+            Self::Fragment { fragment_offset, more_fragments, identification } => write!(
+                f,
+                "Fragment (offset {}, id {:08x}{})",
+                fragment_offset * 8, identification,
+                if *more_fragments { ", MF" } else { "" },
+            ),
+        }
+    }
+}
+
+/// Walks the extension header chain that starts with `next_header`.
+///
+/// Iteration stops at the first header that is not an extension header; `upper_layer` then
+/// gives that header's protocol and the offset where it starts.
+pub struct ExtensionWalker<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    next_header: IpProtocol,
+    failed: bool,
+}
+
+impl<'a> ExtensionWalker<'a> {
+    pub fn new(next_header: IpProtocol, buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            offset: 0,
+            next_header,
+            failed: false,
+        }
+    }
+
+    pub fn upper_layer(&self) -> (IpProtocol, usize) {
+        (self.next_header, self.offset)
+    }
+}
+
+impl Iterator for ExtensionWalker<'_> {
+    type Item = Result<ExtensionHeader, DeserialiseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || !ExtensionHeader::is_extension(self.next_header) {
+            return None;
+        }
+
+        match ExtensionHeader::deserialise_with_next(self.next_header, &self.buf[self.offset..]) {
+            Ok((header, next_header)) => {
+                self.offset += header.byte_length();
+                self.next_header = next_header;
+                Some(Ok(header))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
@@ -0,0 +1,5 @@
+mod extension;
+mod packet;
+
+pub use extension::{ExtensionHeader, ExtensionWalker, Ipv6Option};
+pub use packet::{Ipv6Header, Ipv6Packet};
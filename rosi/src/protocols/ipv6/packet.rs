@@ -0,0 +1,380 @@
+use std::sync::Arc;
+
+use crate::common::{DeserialiseError, PseudoHeader, Layer, Pdu, Serialise, Wrapper, serialise_fields};
+use crate::common::address::Ipv6Address;
+use crate::common::log::Value;
+use crate::protocols::ipv4::{Dscp, Ecn, IpProtocol};
+
+use super::extension::{ExtensionHeader, ExtensionWalker};
+
+const HEADER_LENGTH: usize = 40;
+const DEFAULT_HOP_LIMIT: u8 = 64;
+
+#[derive(Debug, Clone)]
+pub struct Ipv6Header {
+    version: u8,        // 4 bits
+    traffic_class: u8,
+    flow_label: u32,    // 20 bits
+
+    payload_length: u16,
+    next_header: IpProtocol,
+    hop_limit: u8,
+
+    source_addr: Ipv6Address,
+    dest_addr: Ipv6Address,
+}
+
+impl Ipv6Header {
+    crate::util::getter!(version: u8);
+    crate::util::getter!(traffic_class: u8);
+    crate::util::getter!(flow_label: u32);
+    crate::util::getter!(payload_length: u16);
+    crate::util::getter!(next_header: IpProtocol);
+    crate::util::getter!(hop_limit: u8);
+    crate::util::getter!(source(source_addr): Ipv6Address);
+    crate::util::getter!(destination(dest_addr): Ipv6Address);
+}
+
+#[derive(Debug, Clone)]
+pub struct Ipv6Packet {
+    header: Ipv6Header,
+    extensions: Vec<ExtensionHeader>,
+    /// The protocol of `data`, named by the last header in the chain.
+    upper_proto: IpProtocol,
+    data: Vec<u8>,
+}
+
+impl Ipv6Packet {
+    pub fn new(
+        source: Ipv6Address, destination: Ipv6Address,
+        proto: IpProtocol,
+        data: Vec<u8>,
+    ) -> Self {
+        let mut packet = Self {
+            header: Ipv6Header {
+                version: 6,
+                traffic_class: 0,
+                flow_label: 0,
+                payload_length: 0,
+                next_header: proto,
+                hop_limit: DEFAULT_HOP_LIMIT,
+                source_addr: source,
+                dest_addr: destination,
+            },
+            extensions: vec![],
+            upper_proto: proto,
+            data,
+        };
+
+        packet.update_header();
+        packet
+    }
+
+    crate::util::getter!(version(header.version): u8);
+    crate::util::getter!(traffic_class(header.traffic_class): u8);
+    crate::util::getter!(flow_label(header.flow_label): u32);
+    crate::util::getter!(payload_length(header.payload_length): u16);
+    crate::util::getter!(next_header(header.next_header): IpProtocol);
+    crate::util::getter!(hop_limit(header.hop_limit): u8);
+    crate::util::getter!(source(header.source_addr): Ipv6Address);
+    crate::util::getter!(destination(header.dest_addr): Ipv6Address);
+    crate::util::getter!(proto(upper_proto): IpProtocol);
+
+    pub fn header(&self) -> &Ipv6Header {
+        &self.header
+    }
+
+    pub fn extensions(&self) -> &[ExtensionHeader] {
+        &self.extensions
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Largest payload the payload length field can describe after the extension headers.
+    pub fn max_data_length(&self) -> usize {
+        (u16::MAX as usize).saturating_sub(self.extensions_length())
+    }
+
+    /// The fragment header in the chain, if the packet is a fragment.
+    pub fn fragment(&self) -> Option<&ExtensionHeader> {
+        self.extensions.iter().find(|e| matches!(e, ExtensionHeader::Fragment { .. }))
+    }
+
+    /// The pseudo-header covered by the checksum of the carried UDP, TCP or ICMPv6 payload.
+    pub fn pseudo_header(&self) -> PseudoHeader {
+        PseudoHeader::Ipv6 {
+            source: self.header.source_addr,
+            destination: self.header.dest_addr,
+        }
+    }
+
+    pub fn set_hop_limit(&mut self, hop_limit: u8) {
+        self.header.hop_limit = hop_limit;
+    }
+
+    pub fn set_traffic_class(&mut self, traffic_class: u8) {
+        self.header.traffic_class = traffic_class;
+    }
+
+    /// The DSCP, the upper six bits of the traffic class.
+    pub fn dscp(&self) -> Dscp {
+        Dscp::from(self.header.traffic_class >> 2)
+    }
+
+    /// The ECN codepoint, the lower two bits of the traffic class.
+    pub fn ecn(&self) -> Ecn {
+        Ecn::from(self.header.traffic_class)
+    }
+
+    pub fn set_dscp(&mut self, dscp: Dscp) {
+        self.header.traffic_class = dscp.value() << 2 | self.header.traffic_class & 0b11;
+    }
+
+    pub fn set_ecn(&mut self, ecn: Ecn) {
+        self.header.traffic_class = self.header.traffic_class & !0b11 | u8::from(ecn);
+    }
+
+    pub fn set_flow_label(&mut self, flow_label: u32) {
+        self.header.flow_label = flow_label & 0xf_ffff;
+    }
+
+    /// Replaces the extension header chain, which is written in the order given.
+    pub fn set_extensions(&mut self, extensions: Vec<ExtensionHeader>) {
+        self.extensions = extensions;
+        self.update_header();
+    }
+
+    /// Panics if the extension headers and data do not fit in the payload length, rather than
+    /// sending a packet whose length is wrong.
+    fn update_header(&mut self) {
+        assert!(
+            self.extensions_length() + self.data.len() <= u16::MAX as usize,
+            "ipv6 payload of {} bytes is longer than the {} a packet can carry",
+            self.extensions_length() + self.data.len(), u16::MAX,
+        );
+        self.header.next_header = self.extensions.first().map_or(self.upper_proto, |e| e.proto());
+        self.header.payload_length = (self.extensions_length() + self.data.len()) as u16;
+    }
+
+    fn extensions_length(&self) -> usize {
+        self.extensions.iter().map(|e| e.byte_length()).sum()
+    }
+}
+
+impl Serialise for Ipv6Header {
+    fn byte_length(&self) -> usize {
+        HEADER_LENGTH
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        serialise_fields!(
+            buf=buf,
+            (self.version as u32 & 0xf) << 28 |
+            (self.traffic_class as u32) << 20 |
+            self.flow_label & 0xf_ffff,
+            self.payload_length,
+            self.next_header,
+            self.hop_limit,
+            self.source_addr,
+            self.dest_addr,
+        )
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError>
+    where Self: Sized {
+        if buf.len() < HEADER_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), HEADER_LENGTH, buf.len()));
+        }
+
+        let first = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+
+        Ok(Self {
+            version: (first >> 28) as u8,
+            traffic_class: (first >> 20) as u8,
+            flow_label: first & 0xf_ffff,
+
+            payload_length: u16::from_be_bytes([buf[4], buf[5]]),
+            next_header: IpProtocol::deserialise(&buf[6..])?,
+            hop_limit: buf[7],
+
+            source_addr: Ipv6Address::deserialise(&buf[8..])?,
+            dest_addr: Ipv6Address::deserialise(&buf[24..])?,
+        })
+    }
+}
+
+impl Serialise for Ipv6Packet {
+    fn byte_length(&self) -> usize {
+        HEADER_LENGTH + self.extensions_length() + self.data.len()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let mut index = self.header.serialise(buf);
+
+        for (i, extension) in self.extensions.iter().enumerate() {
+            let next_header = self.extensions.get(i + 1).map_or(self.upper_proto, |e| e.proto());
+            index += extension.serialise_with_next(next_header, &mut buf[index..]);
+        }
+
+        index + self.data.as_slice().serialise(&mut buf[index..])
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let header = Ipv6Header::deserialise(buf)?;
+        if header.version != 6 {
+            return Err(DeserialiseError::Heap(format!("invalid ipv6 version: {}", header.version)));
+        }
+
+        let total_length = HEADER_LENGTH + header.payload_length as usize;
+        if buf.len() < total_length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), total_length, buf.len()));
+        }
+
+        let payload = &buf[HEADER_LENGTH..total_length];
+        let mut walker = ExtensionWalker::new(header.next_header, payload);
+        let extensions = walker.by_ref().collect::<Result<Vec<_>, _>>()?;
+        let (upper_proto, offset) = walker.upper_layer();
+
+        Ok(Self {
+            header,
+            extensions,
+            upper_proto,
+            data: payload[offset..].to_vec(),
+        })
+    }
+}
+
+impl core::fmt::Display for Ipv6Packet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "IPv6 Packet: {} bytes", self.byte_length())?;
+        writeln!(f, "-----------------")?;
+        writeln!(f, "Source:        {}", self.header.source_addr)?;
+        writeln!(f, "Destination:   {}", self.header.dest_addr)?;
+        writeln!(f, "Next Header:   {}", self.header.next_header)?;
+        writeln!(f, "Hop Limit:     {}", self.header.hop_limit)?;
+        writeln!(f, "Traffic Class: {:02x}", self.header.traffic_class)?;
+        writeln!(f, "Flow Label:    {:05x}", self.header.flow_label)?;
+        self.extensions.iter().try_for_each(|e| writeln!(f, "Extension:     {e}"))?;
+        writeln!(f, "Protocol:      {}", self.upper_proto)?;
+        writeln!(f)?;
+
+        if !self.data.is_empty() {
+            writeln!(f, "Data:")?;
+            writeln!(f, "-----------------")?;
+            self.data.chunks(16).try_for_each(|chunk| {
+                let mut line = String::with_capacity(16 * 3);
+                chunk.iter().for_each(|d| line.push_str(&format!("{:02x} ", d)));
+                writeln!(f, "{}", line)
+            })?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pdu for Ipv6Packet {
+    fn protocol(&self) -> &'static str {
+        "ipv6"
+    }
+
+    fn summary(&self) -> String {
+        let fragment = match self.fragment() {
+            Some(ExtensionHeader::Fragment { fragment_offset, more_fragments, identification }) => format!(
+                " frag id {} offset {}{}",
+                identification, *fragment_offset as usize * 8, if *more_fragments { " [+]" } else { "" },
+            ),
+            _ => String::new(),
+        };
+
+        format!(
+            "IP6 {} > {}: {} hlim {}{} length {}",
+            self.header.source_addr, self.header.dest_addr, self.upper_proto,
+            self.header.hop_limit, fragment, self.header.payload_length,
+        )
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("source", self.header.source_addr.to_string().into()),
+            ("destination", self.header.dest_addr.to_string().into()),
+            ("next_header", u8::from(self.upper_proto).into()),
+            ("hop_limit", self.header.hop_limit.into()),
+            ("traffic_class", self.header.traffic_class.into()),
+            ("flow_label", self.header.flow_label.into()),
+            ("extensions", self.extensions.len().into()),
+            ("length", self.header.payload_length.into()),
+        ]
+    }
+}
+
+impl Wrapper for Ipv6Packet {
+    fn unwrap_data(&self) -> Arc<[u8]> {
+        Arc::from(self.data.as_slice())
+    }
+}
+
+impl Layer for Ipv6Packet {
+    fn wrap(&mut self, data: &dyn Serialise) {
+        self.data = vec![0u8; data.byte_length()];
+        data.serialise(self.data.as_mut_slice());
+        self.update_header();
+    }
+}
+
+#[test]
+fn test_ipv6_extension_chain() {
+    use super::extension::Ipv6Option;
+
+    let mut packet = Ipv6Packet::new(
+        Ipv6Address::from([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+        Ipv6Address::from([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16]),
+        IpProtocol::Udp,
+        vec![],
+    );
+
+    packet.set_extensions(vec![
+        ExtensionHeader::HopByHop(vec![Ipv6Option::RouterAlert(0)]),
+        ExtensionHeader::Fragment { fragment_offset: 0, more_fragments: true, identification: 0xdeadbeef },
+    ]);
+    packet.wrap(&[0u8, 53, 0, 53, 0, 8, 0, 0].as_slice());
+
+    assert_eq!(packet.next_header(), IpProtocol::HopOpt);
+    assert_eq!(packet.payload_length(), 8 + 8 + 8);
+
+    let mut bytes = vec![0u8; packet.byte_length()];
+    assert_eq!(packet.serialise(&mut bytes), 64);
+    assert_eq!(bytes[0], 0x60);
+    // Hop-by-hop header names the fragment header, which names UDP.
+    assert_eq!(bytes[40], 0x2c);
+    assert_eq!(&bytes[42..48], &[5, 2, 0, 0, 1, 0]);
+    assert_eq!(bytes[48], 0x11);
+
+    let new_packet = Ipv6Packet::deserialise(&bytes).unwrap();
+    assert_eq!(new_packet.source(), packet.source());
+    assert_eq!(new_packet.destination(), packet.destination());
+    assert_eq!(new_packet.proto(), IpProtocol::Udp);
+    assert_eq!(new_packet.data(), packet.data());
+    assert_eq!(new_packet.extensions()[0], ExtensionHeader::HopByHop(vec![Ipv6Option::RouterAlert(0), Ipv6Option::PadN(0)]));
+    assert_eq!(new_packet.fragment(), packet.extensions().get(1));
+
+    // No Next Header ends the chain with nothing after it.
+    let mut packet = Ipv6Packet::new(packet.source(), packet.destination(), IpProtocol::Ipv6NoNxt, vec![]);
+    packet.set_extensions(vec![ExtensionHeader::DestinationOptions(vec![])]);
+    let mut bytes = vec![0u8; packet.byte_length()];
+    packet.serialise(&mut bytes);
+
+    let new_packet = Ipv6Packet::deserialise(&bytes).unwrap();
+    assert_eq!(new_packet.extensions(), &[ExtensionHeader::DestinationOptions(vec![Ipv6Option::PadN(4)])]);
+    assert_eq!(new_packet.proto(), IpProtocol::Ipv6NoNxt);
+    assert!(new_packet.data().is_empty());
+
+    print!("{new_packet}");
+
+    // The payload length bounds the extension headers and data together.
+    packet.wrap(&vec![0u8; packet.max_data_length()].as_slice());
+    assert_eq!(packet.payload_length(), u16::MAX);
+    assert!(std::panic::catch_unwind(move || packet.set_extensions(vec![ExtensionHeader::DestinationOptions(vec![]); 2])).is_err());
+}
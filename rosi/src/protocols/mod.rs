@@ -1,3 +1,10 @@
 pub mod arp;
+pub mod dhcp;
 pub mod ethernet;
+pub mod icmp;
+pub mod icmpv6;
+pub mod ipv4;
+pub mod ipv6;
+pub mod tcp;
+pub mod udp;
 // pub mod ip;
\ No newline at end of file
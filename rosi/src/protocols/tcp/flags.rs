@@ -0,0 +1,80 @@
+/// The control bits of a TCP header, including the ECN bits from RFC 3168.
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Default)]
+pub struct Flags {
+    bits: u8,
+}
+
+impl Flags {
+    pub const FIN: Self = Self { bits: 0x01 };
+    pub const SYN: Self = Self { bits: 0x02 };
+    pub const RST: Self = Self { bits: 0x04 };
+    pub const PSH: Self = Self { bits: 0x08 };
+    pub const ACK: Self = Self { bits: 0x10 };
+    pub const URG: Self = Self { bits: 0x20 };
+    pub const ECE: Self = Self { bits: 0x40 };
+    pub const CWR: Self = Self { bits: 0x80 };
+
+    pub const fn empty() -> Self {
+        Self { bits: 0 }
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    pub fn intersects(&self, other: Self) -> bool {
+        self.bits & other.bits != 0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.bits |= other.bits;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.bits &= !other.bits;
+    }
+}
+
+impl core::ops::BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self { bits: self.bits | rhs.bits }
+    }
+}
+
+impl From<u8> for Flags {
+    fn from(value: u8) -> Self {
+        Self { bits: value }
+    }
+}
+
+impl From<Flags> for u8 {
+    fn from(value: Flags) -> Self {
+        value.bits
+    }
+}
+
+impl core::fmt::Display for Flags {
+    /// Formats the flags the way tcpdump does, e.g. `S.` for SYN+ACK.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const LETTERS: [(Flags, char); 8] = [
+            (Flags::FIN, 'F'),
+            (Flags::SYN, 'S'),
+            (Flags::RST, 'R'),
+            (Flags::PSH, 'P'),
+            (Flags::URG, 'U'),
+            (Flags::ECE, 'E'),
+            (Flags::CWR, 'W'),
+            (Flags::ACK, '.'),
+        ];
+
+        if self.bits == 0 {
+            return write!(f, "none");
+        }
+
+        LETTERS.iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .try_for_each(|(_, c)| write!(f, "{c}"))
+    }
+}
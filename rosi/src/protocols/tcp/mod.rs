@@ -0,0 +1,7 @@
+mod flags;
+mod options;
+mod segment;
+
+pub use flags::Flags;
+pub use options::TcpOption;
+pub use segment::Segment;
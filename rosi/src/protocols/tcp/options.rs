@@ -0,0 +1,186 @@
+use crate::common::{DeserialiseError, Serialise};
+
+const KIND_END_OF_LIST: u8 = 0;
+const KIND_NO_OPERATION: u8 = 1;
+const KIND_MAXIMUM_SEGMENT_SIZE: u8 = 2;
+const KIND_WINDOW_SCALE: u8 = 3;
+const KIND_SACK_PERMITTED: u8 = 4;
+const KIND_SACK: u8 = 5;
+const KIND_TIMESTAMPS: u8 = 8;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TcpOption {
+    EndOfList,
+    NoOperation,
+    MaximumSegmentSize(u16),
+    WindowScale(u8),
+    SackPermitted,
+    /// Left and right edges of each received block (RFC 2018).
+    Sack(Vec<(u32, u32)>),
+    Timestamps { value: u32, echo_reply: u32 },
+    /// An option this codec does not understand, kept so it can be written back unchanged.
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl TcpOption {
+    pub fn kind(&self) -> u8 {
+        match self {
+            Self::EndOfList => KIND_END_OF_LIST,
+            Self::NoOperation => KIND_NO_OPERATION,
+            Self::MaximumSegmentSize(..) => KIND_MAXIMUM_SEGMENT_SIZE,
+            Self::WindowScale(..) => KIND_WINDOW_SCALE,
+            Self::SackPermitted => KIND_SACK_PERMITTED,
+            Self::Sack(..) => KIND_SACK,
+            Self::Timestamps { .. } => KIND_TIMESTAMPS,
+            Self::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Parses options until the buffer or an End of Option List is reached.
+    pub fn deserialise_list(buf: &[u8]) -> Result<Vec<Self>, DeserialiseError> {
+        let mut options = vec![];
+        let mut index = 0;
+
+        while index < buf.len() {
+            let option = Self::deserialise(&buf[index..])?;
+            index += option.byte_length();
+
+            let end = option == Self::EndOfList;
+            options.push(option);
+            if end {
+                break;
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn expect_length(kind: u8, length: usize, expected: usize) -> Result<(), DeserialiseError> {
+        if length == expected {
+            Ok(())
+        } else {
+            Err(DeserialiseError::Heap(format!("invalid length {length} for tcp option {kind} (expected {expected})")))
+        }
+    }
+}
+
+impl Serialise for TcpOption {
+    fn byte_length(&self) -> usize {
+        match self {
+            Self::EndOfList | Self::NoOperation => 1,
+            Self::MaximumSegmentSize(..) => 4,
+            Self::WindowScale(..) => 3,
+            Self::SackPermitted => 2,
+            Self::Sack(blocks) => 2 + blocks.len() * 8,
+            Self::Timestamps { .. } => 10,
+            Self::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.byte_length();
+        buf[0] = self.kind();
+        if len == 1 {
+            return len;
+        }
+
+        buf[1] = len as u8;
+        match self {
+            Self::MaximumSegmentSize(mss) => { mss.serialise(&mut buf[2..]); },
+            Self::WindowScale(shift) => { shift.serialise(&mut buf[2..]); },
+            Self::Sack(blocks) => {
+                for (i, (left, right)) in blocks.iter().enumerate() {
+                    left.serialise(&mut buf[2 + i * 8..]);
+                    right.serialise(&mut buf[6 + i * 8..]);
+                }
+            },
+            Self::Timestamps { value, echo_reply } => {
+                value.serialise(&mut buf[2..]);
+                echo_reply.serialise(&mut buf[6..]);
+            },
+            Self::Unknown { data, .. } => buf[2..len].copy_from_slice(data),
+            Self::EndOfList | Self::NoOperation | Self::SackPermitted => (),
+        }
+
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        let kind = match buf.first() {
+            Some(kind) => *kind,
+            None => return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 1, 0)),
+        };
+
+        match kind {
+            KIND_END_OF_LIST => return Ok(Self::EndOfList),
+            KIND_NO_OPERATION => return Ok(Self::NoOperation),
+            _ => (),
+        }
+
+        if buf.len() < 2 {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), 2, buf.len()));
+        }
+
+        let length = buf[1] as usize;
+        if length < 2 {
+            return Err(DeserialiseError::Heap(format!("invalid length {length} for tcp option {kind}")));
+        }
+
+        if buf.len() < length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), length, buf.len()));
+        }
+
+        let data = &buf[2..length];
+        Ok(match kind {
+            KIND_MAXIMUM_SEGMENT_SIZE => {
+                Self::expect_length(kind, length, 4)?;
+                Self::MaximumSegmentSize(u16::deserialise(data)?)
+            },
+            KIND_WINDOW_SCALE => {
+                Self::expect_length(kind, length, 3)?;
+                Self::WindowScale(data[0])
+            },
+            KIND_SACK_PERMITTED => {
+                Self::expect_length(kind, length, 2)?;
+                Self::SackPermitted
+            },
+            KIND_SACK => {
+                if data.is_empty() || !data.len().is_multiple_of(8) {
+                    return Err(DeserialiseError::Heap(format!("invalid length {length} for tcp sack option")));
+                }
+
+                let blocks = data.chunks_exact(8)
+                    .map(|block| Ok((u32::deserialise(block)?, u32::deserialise(&block[4..])?)))
+                    .collect::<Result<_, DeserialiseError>>()?;
+
+                Self::Sack(blocks)
+            },
+            KIND_TIMESTAMPS => {
+                Self::expect_length(kind, length, 10)?;
+                Self::Timestamps {
+                    value: u32::deserialise(data)?,
+                    echo_reply: u32::deserialise(&data[4..])?,
+                }
+            },
+            _ => Self::Unknown { kind, data: data.to_vec() },
+        })
+    }
+}
+
+impl core::fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EndOfList => write!(f, "eol"),
+            Self::NoOperation => write!(f, "nop"),
+            Self::MaximumSegmentSize(mss) => write!(f, "mss {mss}"),
+            Self::WindowScale(shift) => write!(f, "wscale {shift}"),
+            Self::SackPermitted => write!(f, "sackOK"),
+            Self::Sack(blocks) => {
+                write!(f, "sack {}", blocks.len())?;
+                blocks.iter().try_for_each(|(left, right)| write!(f, " {{{left}:{right}}}"))
+            },
+            Self::Timestamps { value, echo_reply } => write!(f, "TS val {value} ecr {echo_reply}"),
+            Self::Unknown { kind, data } => write!(f, "unknown-{kind} len {}", data.len()),
+        }
+    }
+}
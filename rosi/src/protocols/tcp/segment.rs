@@ -0,0 +1,344 @@
+use crate::common::{DeserialiseError, Layer, Pdu, PseudoHeader, Serialise, serialise_fields};
+use crate::common::log::Value;
+use crate::protocols::ipv4::IpProtocol;
+
+use super::flags::Flags;
+use super::options::TcpOption;
+
+const MIN_HEADER_LENGTH: usize = 20;
+const MAX_HEADER_LENGTH: usize = 60;
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    source_port: u16,
+    destination_port: u16,
+    sequence: u32,
+    acknowledgement: u32,
+    data_offset: u8,    // 4 bits
+    reserved: u8,       // 4 bits
+    flags: Flags,
+    window: u16,
+    checksum: u16,
+    urgent_pointer: u16,
+    options: Vec<TcpOption>,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    /// Creates a segment without a checksum; call `fill_checksum` once the pseudo-header is known.
+    pub fn new(
+        source_port: u16, destination_port: u16,
+        sequence: u32, acknowledgement: u32,
+        flags: Flags,
+        window: u16,
+        data: Vec<u8>,
+    ) -> Self {
+        Self {
+            source_port,
+            destination_port,
+            sequence,
+            acknowledgement,
+            data_offset: (MIN_HEADER_LENGTH / 4) as u8,
+            reserved: 0,
+            flags,
+            window,
+            checksum: 0,
+            urgent_pointer: 0,
+            options: vec![],
+            data,
+        }
+    }
+
+    crate::util::getter!(source_port: u16);
+    crate::util::getter!(destination_port: u16);
+    crate::util::getter!(sequence: u32);
+    crate::util::getter!(acknowledgement: u32);
+    crate::util::getter!(data_offset: u8);
+    crate::util::getter!(flags: Flags);
+    crate::util::getter!(window: u16);
+    crate::util::getter!(checksum: u16);
+    crate::util::getter!(urgent_pointer: u16);
+
+    pub fn options(&self) -> &[TcpOption] {
+        &self.options
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replaces the options and recomputes the data offset. Fails if they do not fit in 40 bytes.
+    pub fn set_options(&mut self, options: Vec<TcpOption>) -> Result<(), Vec<TcpOption>> {
+        let length = options.iter().map(|o| o.byte_length()).sum::<usize>().next_multiple_of(4);
+        if MIN_HEADER_LENGTH + length > MAX_HEADER_LENGTH {
+            return Err(options);
+        }
+
+        self.options = options;
+        self.data_offset = ((MIN_HEADER_LENGTH + length) / 4) as u8;
+        self.checksum = 0;
+        Ok(())
+    }
+
+    pub fn set_urgent_pointer(&mut self, urgent_pointer: u16) {
+        self.urgent_pointer = urgent_pointer;
+        self.checksum = 0;
+    }
+
+    pub fn header_length(&self) -> usize {
+        self.data_offset as usize * 4
+    }
+
+    /// The amount of sequence space the segment occupies, counting SYN and FIN.
+    pub fn sequence_length(&self) -> u32 {
+        self.data.len() as u32
+            + self.flags.contains(Flags::SYN) as u32
+            + self.flags.contains(Flags::FIN) as u32
+    }
+
+    pub fn mss(&self) -> Option<u16> {
+        self.options.iter().find_map(|o| match o {
+            TcpOption::MaximumSegmentSize(mss) => Some(*mss),
+            _ => None,
+        })
+    }
+
+    pub fn window_scale(&self) -> Option<u8> {
+        self.options.iter().find_map(|o| match o {
+            TcpOption::WindowScale(shift) => Some(*shift),
+            _ => None,
+        })
+    }
+
+    pub fn sack_permitted(&self) -> bool {
+        self.options.contains(&TcpOption::SackPermitted)
+    }
+
+    pub fn timestamps(&self) -> Option<(u32, u32)> {
+        self.options.iter().find_map(|o| match o {
+            TcpOption::Timestamps { value, echo_reply } => Some((*value, *echo_reply)),
+            _ => None,
+        })
+    }
+
+    /// Computes the checksum over `pseudo_header` and the segment, ignoring the stored checksum.
+    pub fn compute_checksum(&self, pseudo_header: &PseudoHeader) -> u16 {
+        let mut buf = vec![0u8; self.byte_length()];
+        self.serialise_without_checksum(&mut buf);
+
+        let mut sum = pseudo_header.checksum(IpProtocol::Tcp.into(), buf.len() as u32);
+        sum.add_bytes(&buf);
+        sum.finish()
+    }
+
+    pub fn fill_checksum(&mut self, pseudo_header: &PseudoHeader) {
+        self.checksum = self.compute_checksum(pseudo_header);
+    }
+
+    pub fn verify_checksum(&self, pseudo_header: &PseudoHeader) -> Result<(), DeserialiseError> {
+        let expected = self.compute_checksum(pseudo_header);
+        if expected == self.checksum {
+            Ok(())
+        } else {
+            Err(DeserialiseError::ChecksumMismatch("tcp", expected, self.checksum))
+        }
+    }
+
+    fn serialise_without_checksum(&self, buf: &mut [u8]) -> usize {
+        let index = serialise_fields!(
+            buf=buf,
+            self.source_port,
+            self.destination_port,
+            self.sequence,
+            self.acknowledgement,
+            (self.data_offset & 0xf) << 4 | self.reserved & 0xf,
+            u8::from(self.flags),
+            self.window,
+            0u16,
+            self.urgent_pointer,
+        );
+
+        let index = self.options.iter().fold(index, |index, option| index + option.serialise(&mut buf[index..]));
+
+        // Pad with End of Option List bytes up to the data offset.
+        let header_length = self.header_length();
+        buf[index..header_length].fill(0);
+
+        header_length + self.data.as_slice().serialise(&mut buf[header_length..])
+    }
+}
+
+impl Serialise for Segment {
+    fn byte_length(&self) -> usize {
+        self.header_length() + self.data.len()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        let len = self.serialise_without_checksum(buf);
+        self.checksum.serialise(&mut buf[16..]);
+        len
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < MIN_HEADER_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), MIN_HEADER_LENGTH, buf.len()));
+        }
+
+        let data_offset = buf[12] >> 4;
+        let header_length = data_offset as usize * 4;
+        if header_length < MIN_HEADER_LENGTH {
+            return Err(DeserialiseError::Heap(format!("invalid tcp data offset: {data_offset}")));
+        }
+
+        if buf.len() < header_length {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), header_length, buf.len()));
+        }
+
+        Ok(Self {
+            source_port: u16::from_be_bytes([buf[0], buf[1]]),
+            destination_port: u16::from_be_bytes([buf[2], buf[3]]),
+            sequence: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+            acknowledgement: u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]),
+            data_offset,
+            reserved: buf[12] & 0xf,
+            flags: Flags::from(buf[13]),
+            window: u16::from_be_bytes([buf[14], buf[15]]),
+            checksum: u16::from_be_bytes([buf[16], buf[17]]),
+            urgent_pointer: u16::from_be_bytes([buf[18], buf[19]]),
+            options: TcpOption::deserialise_list(&buf[MIN_HEADER_LENGTH..header_length])?,
+            data: buf[header_length..].to_vec(),
+        })
+    }
+}
+
+impl core::fmt::Display for Segment {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "TCP Segment: {} bytes", self.byte_length())?;
+        writeln!(f, "-----------------")?;
+        writeln!(f, "Source Port: {}", self.source_port)?;
+        writeln!(f, "Dest Port:   {}", self.destination_port)?;
+        writeln!(f, "Sequence:    {}", self.sequence)?;
+        writeln!(f, "Ack:         {}", self.acknowledgement)?;
+        writeln!(f, "Flags:       [{}]", self.flags)?;
+        writeln!(f, "Window:      {}", self.window)?;
+        writeln!(f, "Checksum:    {:04x}", self.checksum)?;
+        if !self.options.is_empty() {
+            let options = self.options.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+            writeln!(f, "Options:     [{}]", options.join(","))?;
+        }
+        writeln!(f)?;
+
+        if !self.data.is_empty() {
+            writeln!(f, "Data:")?;
+            writeln!(f, "-----------------")?;
+            self.data.chunks(16).try_for_each(|chunk| {
+                let mut line = String::with_capacity(16 * 3);
+                chunk.iter().for_each(|d| line.push_str(&format!("{:02x} ", d)));
+                writeln!(f, "{}", line)
+            })?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pdu for Segment {
+    fn protocol(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "TCP {} > {} [{}] seq {} ack {} win {} length {}",
+            self.source_port,
+            self.destination_port,
+            self.flags,
+            self.sequence,
+            self.acknowledgement,
+            self.window,
+            self.data.len(),
+        )
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("source_port", self.source_port.into()),
+            ("destination_port", self.destination_port.into()),
+            ("flags", self.flags.to_string().into()),
+            ("seq", self.sequence.into()),
+            ("ack", self.acknowledgement.into()),
+            ("window", self.window.into()),
+            ("length", self.data.len().into()),
+        ]
+    }
+}
+
+impl Layer for Segment {
+    /// Replaces the payload. The checksum is cleared and must be refilled with `fill_checksum`.
+    fn wrap(&mut self, data: &dyn Serialise) {
+        self.data = vec![0u8; data.byte_length()];
+        data.serialise(self.data.as_mut_slice());
+        self.checksum = 0;
+    }
+}
+
+#[test]
+fn test_tcp_syn_options() {
+    use crate::common::address::Ipv4Address;
+
+    // SYN with the option layout Linux uses: mss 1460, sackOK, TS val 3467 ecr 0, nop, wscale 7.
+    let bytes = [
+        0xa2, 0x3c, 0x00, 0x50, 0x3b, 0x9a, 0xca, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xa0, 0x02, 0xfa, 0xf0, 0x00, 0x00, 0x00, 0x00,
+        0x02, 0x04, 0x05, 0xb4, 0x04, 0x02, 0x08, 0x0a, 0x00, 0x00, 0x0d, 0x8b,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x03, 0x03, 0x07,
+    ];
+
+    let segment = Segment::deserialise(&bytes).unwrap();
+    assert_eq!(segment.source_port(), 41532);
+    assert_eq!(segment.destination_port(), 80);
+    assert_eq!(segment.sequence(), 1_000_000_000);
+    assert_eq!(segment.flags(), Flags::SYN);
+    assert_eq!(segment.mss(), Some(1460));
+    assert!(segment.sack_permitted());
+    assert_eq!(segment.timestamps(), Some((3467, 0)));
+    assert_eq!(segment.window_scale(), Some(7));
+    assert_eq!(segment.sequence_length(), 1);
+
+    let mut new_bytes = vec![0u8; segment.byte_length()];
+    segment.serialise(&mut new_bytes);
+    assert_eq!(new_bytes, bytes);
+
+    let pseudo_header = PseudoHeader::Ipv4 {
+        source: Ipv4Address::from([10, 0, 0, 1]),
+        destination: Ipv4Address::from([10, 0, 0, 2]),
+    };
+
+    let mut reply = Segment::new(80, 41532, 7, segment.sequence() + 1, Flags::SYN | Flags::ACK, 65535, vec![]);
+    reply.set_options(vec![
+        TcpOption::MaximumSegmentSize(1460),
+        TcpOption::NoOperation,
+        TcpOption::WindowScale(7),
+        TcpOption::Sack(vec![(1, 2)]),
+        TcpOption::Unknown { kind: 254, data: vec![0xf9, 0x89] },
+    ]).unwrap();
+    reply.fill_checksum(&pseudo_header);
+    assert_eq!(reply.header_length(), 44);
+
+    let mut reply_bytes = vec![0u8; reply.byte_length()];
+    reply.serialise(&mut reply_bytes);
+
+    let parsed = Segment::deserialise(&reply_bytes).unwrap();
+    assert_eq!(parsed.options()[..5], reply.options()[..]);
+    assert_eq!(parsed.options()[5], TcpOption::EndOfList);
+    assert!(parsed.verify_checksum(&pseudo_header).is_ok());
+}
+
+#[test]
+fn test_tcp_option_errors() {
+    assert!(TcpOption::deserialise(&[2, 3, 5]).is_err());
+    assert!(TcpOption::deserialise(&[5, 6, 0, 0, 0, 0]).is_err());
+    assert!(TcpOption::deserialise(&[8, 10, 0, 0]).is_err());
+    assert!(TcpOption::deserialise(&[30, 1]).is_err());
+}
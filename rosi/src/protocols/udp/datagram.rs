@@ -0,0 +1,213 @@
+use crate::common::{DeserialiseError, Layer, Pdu, PseudoHeader, Serialise, serialise_fields};
+use crate::common::log::Value;
+use crate::protocols::ipv4::IpProtocol;
+
+const HEADER_LENGTH: usize = 8;
+
+/// Largest payload the length field can describe.
+const MAX_DATA_LENGTH: usize = u16::MAX as usize - HEADER_LENGTH;
+
+/// The length field for `data`. Panics if it is too long to describe, rather than sending a
+/// datagram whose length is wrong.
+fn length(data: &[u8]) -> u16 {
+    assert!(data.len() <= MAX_DATA_LENGTH, "udp data of {} bytes is longer than the {MAX_DATA_LENGTH} a datagram can carry", data.len());
+    (HEADER_LENGTH + data.len()) as u16
+}
+
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    source_port: u16,
+    destination_port: u16,
+    length: u16,
+    checksum: u16,
+    data: Vec<u8>,
+}
+
+impl Datagram {
+    /// Creates a datagram without a checksum; call `fill_checksum` once the pseudo-header is known.
+    pub fn new(source_port: u16, destination_port: u16, data: Vec<u8>) -> Self {
+        Self {
+            source_port,
+            destination_port,
+            length: length(&data),
+            checksum: 0,
+            data,
+        }
+    }
+
+    crate::util::getter!(source_port: u16);
+    crate::util::getter!(destination_port: u16);
+    crate::util::getter!(length: u16);
+    crate::util::getter!(checksum: u16);
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Computes the checksum over `pseudo_header` and the datagram, ignoring the stored checksum.
+    pub fn compute_checksum(&self, pseudo_header: &PseudoHeader) -> u16 {
+        let mut sum = pseudo_header.checksum(IpProtocol::Udp.into(), self.length as u32);
+        sum.add_u16(self.source_port);
+        sum.add_u16(self.destination_port);
+        sum.add_u16(self.length);
+        sum.add_bytes(&self.data);
+
+        // An all-zero checksum means "no checksum", so a computed zero is sent as all ones (RFC 768).
+        match sum.finish() {
+            0 => 0xffff,
+            checksum => checksum,
+        }
+    }
+
+    pub fn fill_checksum(&mut self, pseudo_header: &PseudoHeader) {
+        self.checksum = self.compute_checksum(pseudo_header);
+    }
+
+    /// Checks the stored checksum. A zero checksum is only accepted over IPv4, where it is optional.
+    pub fn verify_checksum(&self, pseudo_header: &PseudoHeader) -> Result<(), DeserialiseError> {
+        if self.checksum == 0 && matches!(pseudo_header, PseudoHeader::Ipv4 { .. }) {
+            return Ok(());
+        }
+
+        let expected = self.compute_checksum(pseudo_header);
+        if expected == self.checksum {
+            Ok(())
+        } else {
+            Err(DeserialiseError::ChecksumMismatch("udp", expected, self.checksum))
+        }
+    }
+}
+
+impl Serialise for Datagram {
+    fn byte_length(&self) -> usize {
+        HEADER_LENGTH + self.data.len()
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        serialise_fields!(
+            buf=buf,
+            self.source_port,
+            self.destination_port,
+            self.length,
+            self.checksum,
+            self.data.as_slice(),
+        )
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < HEADER_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), HEADER_LENGTH, buf.len()));
+        }
+
+        let length = u16::from_be_bytes([buf[4], buf[5]]);
+        if (length as usize) < HEADER_LENGTH {
+            return Err(DeserialiseError::Heap(format!("invalid udp length: {length}")));
+        }
+
+        if buf.len() < length as usize {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), length as usize, buf.len()));
+        }
+
+        Ok(Self {
+            source_port: u16::from_be_bytes([buf[0], buf[1]]),
+            destination_port: u16::from_be_bytes([buf[2], buf[3]]),
+            length,
+            checksum: u16::from_be_bytes([buf[6], buf[7]]),
+            data: buf[HEADER_LENGTH..length as usize].to_vec(),
+        })
+    }
+}
+
+impl core::fmt::Display for Datagram {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "UDP Datagram: {} bytes", self.byte_length())?;
+        writeln!(f, "-----------------")?;
+        writeln!(f, "Source Port: {}", self.source_port)?;
+        writeln!(f, "Dest Port:   {}", self.destination_port)?;
+        writeln!(f, "Length:      {}", self.length)?;
+        writeln!(f, "Checksum:    {:04x}", self.checksum)?;
+        writeln!(f)?;
+
+        if !self.data.is_empty() {
+            writeln!(f, "Data:")?;
+            writeln!(f, "-----------------")?;
+            self.data.chunks(16).try_for_each(|chunk| {
+                let mut line = String::with_capacity(16 * 3);
+                chunk.iter().for_each(|d| line.push_str(&format!("{:02x} ", d)));
+                writeln!(f, "{}", line)
+            })?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Pdu for Datagram {
+    fn protocol(&self) -> &'static str {
+        "udp"
+    }
+
+    fn summary(&self) -> String {
+        format!("UDP {} > {} length {}", self.source_port, self.destination_port, self.data.len())
+    }
+
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("source_port", self.source_port.into()),
+            ("destination_port", self.destination_port.into()),
+            ("length", self.data.len().into()),
+        ]
+    }
+}
+
+impl Layer for Datagram {
+    /// Replaces the payload. The checksum is cleared and must be refilled with `fill_checksum`.
+    fn wrap(&mut self, data: &dyn Serialise) {
+        self.data = vec![0u8; data.byte_length()];
+        data.serialise(self.data.as_mut_slice());
+        self.length = length(&self.data);
+        self.checksum = 0;
+    }
+}
+
+#[test]
+fn test_udp_datagram() {
+    use crate::common::address::Ipv4Address;
+    use crate::protocols::ipv4::Ipv4Packet;
+
+    let mut datagram = Datagram::new(68, 67, vec![]);
+    datagram.wrap(&[1u8, 2, 3, 4, 5].as_slice());
+    assert_eq!(datagram.length(), 13);
+
+    let mut packet = Ipv4Packet::new(
+        Ipv4Address::from([192, 168, 0, 2]),
+        Ipv4Address::from([192, 168, 0, 1]),
+        IpProtocol::Udp,
+        vec![],
+    );
+    datagram.fill_checksum(&packet.pseudo_header());
+    packet.wrap(&datagram);
+
+    let parsed = Datagram::deserialise(packet.data()).unwrap();
+    assert_eq!(parsed.source_port(), 68);
+    assert_eq!(parsed.destination_port(), 67);
+    assert_eq!(parsed.data(), &[1, 2, 3, 4, 5]);
+    assert!(parsed.verify_checksum(&packet.pseudo_header()).is_ok());
+
+    let mut corrupt = packet.data().to_vec();
+    corrupt[8] = 0xff;
+    let parsed = Datagram::deserialise(&corrupt).unwrap();
+    assert!(matches!(
+        parsed.verify_checksum(&packet.pseudo_header()),
+        Err(DeserialiseError::ChecksumMismatch(..))
+    ));
+
+    corrupt[4..6].copy_from_slice(&4u16.to_be_bytes());
+    assert!(Datagram::deserialise(&corrupt).is_err());
+
+    // The length field bounds the data.
+    datagram.wrap(&vec![0u8; MAX_DATA_LENGTH].as_slice());
+    assert_eq!(datagram.length(), u16::MAX);
+    assert!(std::panic::catch_unwind(|| Datagram::new(68, 67, vec![0; MAX_DATA_LENGTH + 1])).is_err());
+}
@@ -0,0 +1,2 @@
+mod datagram;
+pub use datagram::Datagram;
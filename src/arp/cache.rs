@@ -0,0 +1,431 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rosi::common::address::{Ipv4Address, Ipv6Address, MacAddress};
+use rosi::protocols::arp::{HardwareAddress, Operation, Packet, ProtocolAddress};
+use rosi::protocols::ethernet::EtherType;
+
+/// Interval between requests for an unresolved address, and the most we send before giving up.
+const RETRANS_TIMER: Duration = Duration::from_secs(1);
+const MAX_REQUESTS: u32 = 3;
+
+/// How long a binding is trusted after we last heard from its owner.
+const REACHABLE_TIME: Duration = Duration::from_secs(30);
+
+/// How long an unconfirmed stale binding is kept before it is forgotten.
+const GC_STALE_TIME: Duration = Duration::from_secs(60);
+
+/// Packets queued per unresolved address; the oldest are dropped first.
+const MAX_PENDING: usize = 16;
+
+// Address conflict detection timings (RFC 5227 section 1.1).
+const PROBE_NUM: u32 = 3;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const ANNOUNCE_WAIT: Duration = Duration::from_secs(2);
+const ANNOUNCE_NUM: u32 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(2);
+const DEFEND_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    Incomplete,
+    Reachable,
+    Stale,
+}
+
+/// An outbound packet waiting for its next hop to be resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub ethertype: EtherType,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Output {
+    /// An ARP packet to be framed and sent to `destination`.
+    Arp { destination: HardwareAddress, packet: Packet },
+    /// A packet whose next hop is known and can be framed now.
+    Send { destination: HardwareAddress, pending: Pending },
+    /// Resolution of `address` failed and its queued packets were dropped.
+    Unreachable { address: ProtocolAddress, dropped: Vec<Pending> },
+    /// Another host claimed one of our addresses, which we have given up.
+    Conflict { address: ProtocolAddress, hardware: HardwareAddress },
+}
+
+#[derive(Debug)]
+enum Entry {
+    Incomplete { requests: u32, next_request: Instant, pending: VecDeque<Pending> },
+    Reachable { hardware: HardwareAddress, expires: Instant },
+    Stale { hardware: HardwareAddress, since: Instant, refreshed: Option<Instant> },
+}
+
+impl Entry {
+    fn state(&self) -> EntryState {
+        match self {
+            Self::Incomplete { .. } => EntryState::Incomplete,
+            Self::Reachable { .. } => EntryState::Reachable,
+            Self::Stale { .. } => EntryState::Stale,
+        }
+    }
+
+    fn hardware(&self) -> Option<HardwareAddress> {
+        match self {
+            Self::Incomplete { .. } => None,
+            Self::Reachable { hardware, .. } | Self::Stale { hardware, .. } => Some(*hardware),
+        }
+    }
+}
+
+/// Progress of one of our own addresses through conflict detection (RFC 5227).
+#[derive(Debug, Clone, Copy)]
+enum Claim {
+    Probing { sent: u32, next: Instant },
+    Announcing { sent: u32, next: Instant },
+    Bound { last_defended: Option<Instant> },
+}
+
+/// The neighbour table for one interface, along with the addresses the interface claims.
+///
+/// Like the TCP control block, the cache never touches the network. Received ARP packets
+/// are fed in with `on_packet`, timers are driven by `on_tick`, and anything that has to
+/// be transmitted is collected with `take_output`.
+#[derive(Debug)]
+pub struct ArpCache {
+    hardware: HardwareAddress,
+    entries: HashMap<ProtocolAddress, Entry>,
+    claims: Vec<(ProtocolAddress, Claim)>,
+    outbox: Vec<Output>,
+}
+
+fn unspecified(like: ProtocolAddress) -> ProtocolAddress {
+    match like {
+        ProtocolAddress::Ipv4Address(..) => Ipv4Address::default().into(),
+        ProtocolAddress::Ipv6Address(..) => Ipv6Address::default().into(),
+    }
+}
+
+fn same_family(a: ProtocolAddress, b: ProtocolAddress) -> bool {
+    core::mem::discriminant(&a) == core::mem::discriminant(&b)
+}
+
+impl ArpCache {
+    pub fn new(hardware: HardwareAddress) -> Self {
+        Self {
+            hardware,
+            entries: HashMap::new(),
+            claims: vec![],
+            outbox: vec![],
+        }
+    }
+
+    pub fn take_output(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    #[allow(dead_code)]
+    pub fn lookup(&self, address: ProtocolAddress) -> Option<HardwareAddress> {
+        self.entries.get(&address).and_then(Entry::hardware)
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self, address: ProtocolAddress) -> Option<EntryState> {
+        self.entries.get(&address).map(Entry::state)
+    }
+
+    /// Every entry with its state and, once resolved, its hardware address.
+    pub fn entries(&self) -> impl Iterator<Item = (ProtocolAddress, EntryState, Option<HardwareAddress>)> + '_ {
+        self.entries.iter().map(|(address, entry)| (*address, entry.state(), entry.hardware()))
+    }
+
+    /// Whether `address` is ours and has passed probing, so that it may be used while it is
+    /// announced (RFC 5227 section 2.3).
+    pub fn is_bound(&self, address: ProtocolAddress) -> bool {
+        self.claims.iter().any(|(a, claim)| *a == address && !matches!(claim, Claim::Probing { .. }))
+    }
+
+    /// Sends `pending` to `next_hop`, queueing it and starting resolution if the hardware address is unknown.
+    pub fn send(&mut self, next_hop: ProtocolAddress, pending: Pending, now: Instant) {
+        let entry = self.entries.entry(next_hop).or_insert_with(|| Entry::Incomplete {
+            requests: 0,
+            next_request: now,
+            pending: VecDeque::new(),
+        });
+
+        match entry {
+            Entry::Incomplete { pending: queue, .. } => {
+                if queue.len() == MAX_PENDING {
+                    queue.pop_front();
+                }
+                queue.push_back(pending);
+            },
+            Entry::Reachable { hardware, .. } => {
+                self.outbox.push(Output::Send { destination: *hardware, pending });
+            },
+            Entry::Stale { hardware, refreshed, .. } => {
+                let hardware = *hardware;
+                self.outbox.push(Output::Send { destination: hardware, pending });
+
+                // Keep using the stale binding, but ask its owner directly to confirm it.
+                if refreshed.is_none_or(|at| now >= at + RETRANS_TIMER) {
+                    *refreshed = Some(now);
+                    self.request(next_hop, hardware);
+                }
+            },
+        }
+
+        self.on_tick(now);
+    }
+
+    /// Claims `address` for this interface, probing for other users before announcing it.
+    pub fn claim(&mut self, address: ProtocolAddress, now: Instant) {
+        self.claims.retain(|(a, _)| *a != address);
+        self.claims.push((address, Claim::Probing { sent: 0, next: now }));
+        self.on_tick(now);
+    }
+
+    /// Takes `address` without probing and announces it with gratuitous ARP.
+    pub fn announce(&mut self, address: ProtocolAddress, now: Instant) {
+        self.claims.retain(|(a, _)| *a != address);
+        self.claims.push((address, Claim::Announcing { sent: 0, next: now }));
+        self.on_tick(now);
+    }
+
+    pub fn release(&mut self, address: ProtocolAddress) {
+        self.claims.retain(|(a, _)| *a != address);
+    }
+
+    pub fn on_packet(&mut self, packet: &Packet, now: Instant) {
+        let (sha, spa, tpa) = (packet.sha(), packet.spa(), packet.tpa());
+        if sha == self.hardware {
+            return;
+        }
+
+        if self.detect_conflict(packet, now) {
+            return;
+        }
+
+        if spa == unspecified(spa) {
+            return;
+        }
+
+        // RFC 826: update a binding we already have, and only create one if the packet was meant for us.
+        let merge = self.entries.contains_key(&spa) || self.is_bound(tpa);
+        if merge {
+            let previous = self.entries.insert(spa, Entry::Reachable { hardware: sha, expires: now + REACHABLE_TIME });
+            if let Some(Entry::Incomplete { pending, .. }) = previous {
+                self.outbox.extend(pending.into_iter().map(|pending| Output::Send { destination: sha, pending }));
+            }
+        }
+    }
+
+    pub fn on_tick(&mut self, now: Instant) {
+        let mut requests = vec![];
+        self.entries.retain(|address, entry| match entry {
+            Entry::Incomplete { requests: sent, next_request, pending } if now >= *next_request => {
+                if *sent == MAX_REQUESTS {
+                    self.outbox.push(Output::Unreachable { address: *address, dropped: pending.drain(..).collect() });
+                    return false;
+                }
+
+                *sent += 1;
+                *next_request = now + RETRANS_TIMER;
+                requests.push(*address);
+                true
+            },
+            Entry::Reachable { hardware, expires } if now >= *expires => {
+                *entry = Entry::Stale { hardware: *hardware, since: now, refreshed: None };
+                true
+            },
+            Entry::Stale { since, .. } => now < *since + GC_STALE_TIME,
+            _ => true,
+        });
+
+        for address in requests {
+            self.request(address, MacAddress::BROADCAST.into());
+        }
+
+        for i in 0..self.claims.len() {
+            self.advance_claim(i, now);
+        }
+    }
+
+    fn advance_claim(&mut self, index: usize, now: Instant) {
+        let (address, claim) = self.claims[index];
+        self.claims[index].1 = match claim {
+            Claim::Probing { sent, next } if now >= next => {
+                if sent < PROBE_NUM {
+                    self.probe(address);
+                    let wait = if sent + 1 == PROBE_NUM { ANNOUNCE_WAIT } else { PROBE_INTERVAL };
+                    Claim::Probing { sent: sent + 1, next: now + wait }
+                } else {
+                    self.gratuitous(address);
+                    Claim::Announcing { sent: 1, next: now + ANNOUNCE_INTERVAL }
+                }
+            },
+            Claim::Announcing { sent, next } if now >= next => {
+                self.gratuitous(address);
+                if sent + 1 == ANNOUNCE_NUM {
+                    Claim::Bound { last_defended: None }
+                } else {
+                    Claim::Announcing { sent: sent + 1, next: now + ANNOUNCE_INTERVAL }
+                }
+            },
+            claim => claim,
+        };
+    }
+
+    /// Handles packets that show another host using one of our addresses. Returns whether
+    /// the packet was a conflict, in which case it must not update the table.
+    fn detect_conflict(&mut self, packet: &Packet, now: Instant) -> bool {
+        let (sha, spa, tpa) = (packet.sha(), packet.spa(), packet.tpa());
+        let is_probe = packet.operation() == Operation::Request && spa == unspecified(spa);
+
+        let Some(index) = self.claims.iter().position(|(address, claim)| {
+            *address == spa || (is_probe && *address == tpa && !matches!(claim, Claim::Bound { .. }))
+        }) else {
+            return false;
+        };
+
+        let (address, claim) = self.claims[index];
+        match claim {
+            // RFC 5227 section 2.4 (b): defend once, then give way if the other host persists.
+            Claim::Bound { last_defended } if last_defended.is_none_or(|at| now >= at + DEFEND_INTERVAL) => {
+                self.claims[index].1 = Claim::Bound { last_defended: Some(now) };
+                self.gratuitous(address);
+            },
+            _ => {
+                self.claims.remove(index);
+                self.outbox.push(Output::Conflict { address, hardware: sha });
+            },
+        }
+
+        true
+    }
+
+    fn source_for(&self, target: ProtocolAddress) -> ProtocolAddress {
+        self.claims.iter()
+            .find(|(address, claim)| same_family(*address, target) && matches!(claim, Claim::Bound { .. }))
+            .map_or(unspecified(target), |(address, _)| *address)
+    }
+
+    fn request(&mut self, target: ProtocolAddress, destination: HardwareAddress) {
+        let source = self.source_for(target);
+        self.push_request(source, target, destination);
+    }
+
+    /// ARP probe: a request for `address` with an all-zero sender protocol address.
+    fn probe(&mut self, address: ProtocolAddress) {
+        self.push_request(unspecified(address), address, MacAddress::BROADCAST.into());
+    }
+
+    /// Gratuitous ARP announcement: a request with our address as both sender and target.
+    fn gratuitous(&mut self, address: ProtocolAddress) {
+        self.push_request(address, address, MacAddress::BROADCAST.into());
+    }
+
+    fn push_request(&mut self, spa: ProtocolAddress, tpa: ProtocolAddress, destination: HardwareAddress) {
+        let packet = Packet::request(self.hardware, spa, MacAddress::default().into(), tpa)
+            .expect("sender and target addresses share a type");
+        self.outbox.push(Output::Arp { destination, packet });
+    }
+}
+
+#[test]
+fn test_arp_cache_resolution() {
+    let ours = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let theirs: HardwareAddress = MacAddress::from([0x02, 0, 0, 0, 0, 2]).into();
+    let local: ProtocolAddress = Ipv4Address::from([10, 0, 0, 1]).into();
+    let remote: ProtocolAddress = Ipv4Address::from([10, 0, 0, 2]).into();
+    let other: ProtocolAddress = Ipv4Address::from([10, 0, 0, 3]).into();
+
+    let start = Instant::now();
+    let mut cache = ArpCache::new(ours.into());
+    cache.announce(local, start);
+    cache.on_tick(start + ANNOUNCE_INTERVAL);
+    assert!(cache.is_bound(local));
+    assert_eq!(cache.take_output().len(), 2);
+
+    let pending = Pending { ethertype: EtherType::Ipv4, data: vec![1, 2, 3] };
+    cache.send(remote, pending.clone(), start);
+    assert_eq!(cache.state(remote), Some(EntryState::Incomplete));
+
+    let output = cache.take_output();
+    let [Output::Arp { packet, .. }] = output.as_slice() else { panic!("expected one request") };
+    assert_eq!(packet.spa(), local);
+    assert_eq!(packet.tpa(), remote);
+
+    cache.on_tick(start + RETRANS_TIMER);
+    assert_eq!(cache.take_output().len(), 1);
+
+    let reply = Packet::response(theirs, remote, ours.into(), local).unwrap();
+    cache.on_packet(&reply, start + RETRANS_TIMER);
+    assert_eq!(cache.take_output(), vec![Output::Send { destination: theirs, pending: pending.clone() }]);
+    assert_eq!(cache.lookup(remote), Some(theirs));
+
+    let stale_at = start + RETRANS_TIMER + REACHABLE_TIME;
+    cache.on_tick(stale_at);
+    assert_eq!(cache.state(remote), Some(EntryState::Stale));
+
+    // Sending through a stale entry goes ahead and asks the neighbour directly to confirm.
+    cache.send(remote, pending.clone(), stale_at);
+    let output = cache.take_output();
+    assert!(matches!(output[1], Output::Arp { destination, .. } if destination == theirs));
+
+    cache.on_tick(stale_at + GC_STALE_TIME);
+    assert_eq!(cache.state(remote), None);
+
+    // A gratuitous ARP from a host we have never talked to does not create an entry.
+    cache.on_packet(&Packet::request(theirs, other, MacAddress::default().into(), other).unwrap(), stale_at);
+    assert_eq!(cache.state(other), None);
+
+    let now = stale_at + GC_STALE_TIME;
+    cache.send(other, pending.clone(), now);
+    (1..=MAX_REQUESTS).for_each(|i| cache.on_tick(now + RETRANS_TIMER * i));
+    let output = cache.take_output();
+    assert_eq!(output.len(), MAX_REQUESTS as usize + 1);
+    assert_eq!(output.last(), Some(&Output::Unreachable { address: other, dropped: vec![pending] }));
+}
+
+#[test]
+fn test_arp_cache_conflict_detection() {
+    let ours = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let theirs: HardwareAddress = MacAddress::from([0x02, 0, 0, 0, 0, 2]).into();
+    let address: ProtocolAddress = Ipv4Address::from([10, 0, 0, 1]).into();
+
+    let start = Instant::now();
+    let mut cache = ArpCache::new(ours.into());
+    cache.claim(address, start);
+
+    let output = cache.take_output();
+    let [Output::Arp { packet, .. }] = output.as_slice() else { panic!("expected one probe") };
+    assert_eq!(packet.spa(), unspecified(address));
+    assert_eq!(packet.tpa(), address);
+
+    let mut now = start;
+    for _ in 0..PROBE_NUM + ANNOUNCE_NUM {
+        now += ANNOUNCE_WAIT;
+        cache.on_tick(now);
+    }
+    let output = cache.take_output();
+    assert_eq!(output.len(), (PROBE_NUM - 1 + ANNOUNCE_NUM) as usize);
+    assert!(cache.is_bound(address));
+
+    // The first conflict is defended with an announcement, a second one soon after is given up.
+    let conflicting = Packet::request(theirs, address, MacAddress::default().into(), address).unwrap();
+    cache.on_packet(&conflicting, now);
+    let output = cache.take_output();
+    let [Output::Arp { packet, .. }] = output.as_slice() else { panic!("expected a defence") };
+    assert_eq!(packet.sha(), ours.into());
+    assert_eq!(packet.spa(), address);
+
+    cache.on_packet(&conflicting, now + Duration::from_secs(1));
+    assert_eq!(cache.take_output(), vec![Output::Conflict { address, hardware: theirs }]);
+    assert!(!cache.is_bound(address));
+
+    // Another host probing for the address we are probing for is also a conflict.
+    cache.claim(address, now);
+    cache.take_output();
+    let probe = Packet::request(theirs, unspecified(address), MacAddress::default().into(), address).unwrap();
+    cache.on_packet(&probe, now);
+    assert_eq!(cache.take_output(), vec![Output::Conflict { address, hardware: theirs }]);
+}
@@ -0,0 +1,6 @@
+mod cache;
+mod responder;
+mod service;
+
+pub use cache::Pending;
+pub use service::{ArpService, Resolver};
@@ -0,0 +1,69 @@
+use rosi::protocols::arp::{Operation, Packet};
+
+use crate::interface::Interface;
+
+use super::cache::ArpCache;
+
+/// Builds the reply to an ARP request for one of `interface`'s addresses.
+///
+/// Requests for addresses we do not own, or are still probing for in `cache`, are left
+/// unanswered. Probes, whose sender address
+/// is all zeroes, are answered like any other request so that the prober learns the address
+/// is taken (RFC 5227 section 2.1.1).
+pub fn reply(interface: &Interface, cache: &ArpCache, request: &Packet) -> Option<Packet> {
+    if request.operation() != Operation::Request || !interface.owns(request.tpa()) || !cache.is_bound(request.tpa()) {
+        return None;
+    }
+
+    // A gratuitous announcement of an address we own is a conflict, not a question.
+    if request.spa() == request.tpa() {
+        return None;
+    }
+
+    Packet::response(
+        interface.mac().into(),
+        request.tpa(),
+        request.sha(),
+        request.spa(),
+    )
+}
+
+#[test]
+fn test_arp_reply() {
+    use rosi::common::address::{Ipv4Address, MacAddress};
+
+    let ours = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let theirs = MacAddress::from([0x02, 0, 0, 0, 0, 2]);
+    let mut interface = Interface::new("tap0", ours, 1500);
+    interface.add_address("10.0.0.2/24".parse().unwrap());
+
+    let request = Packet::request(
+        theirs.into(),
+        Ipv4Address::from([10, 0, 0, 1]).into(),
+        MacAddress::default().into(),
+        Ipv4Address::from([10, 0, 0, 2]).into(),
+    ).unwrap();
+
+    // An address being probed for is not answered for until it is bound.
+    let mut cache = ArpCache::new(ours.into());
+    let now = std::time::Instant::now();
+    cache.claim(Ipv4Address::from([10, 0, 0, 2]).into(), now);
+    assert_eq!(reply(&interface, &cache, &request), None);
+    cache.announce(Ipv4Address::from([10, 0, 0, 2]).into(), now);
+
+    let response = reply(&interface, &cache, &request).unwrap();
+    assert_eq!(response.operation(), Operation::Response);
+    assert_eq!(response.sha(), ours.into());
+    assert_eq!(response.spa(), Ipv4Address::from([10, 0, 0, 2]).into());
+    assert_eq!(response.tha(), theirs.into());
+    assert_eq!(response.tpa(), Ipv4Address::from([10, 0, 0, 1]).into());
+
+    let elsewhere = Packet::request(
+        theirs.into(),
+        Ipv4Address::from([10, 0, 0, 1]).into(),
+        MacAddress::default().into(),
+        Ipv4Address::from([10, 0, 0, 3]).into(),
+    ).unwrap();
+    assert_eq!(reply(&interface, &cache, &elsewhere), None);
+    assert_eq!(reply(&interface, &cache, &response), None);
+}
@@ -0,0 +1,239 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use rosi::common::{Layer, Serialise};
+use rosi::common::address::MacAddress;
+use rosi::protocols::arp::{HardwareAddress, Packet, ProtocolAddress};
+use rosi::protocols::ethernet::{EtherType, Frame};
+
+use crate::interface::Interface;
+use crate::netservice::{Action, ActionType, Channels, NetService, NetServiceError};
+use crate::route::{Origin, RoutingTable};
+
+use super::cache::{ArpCache, EntryState, Output, Pending};
+use super::responder;
+
+/// A handle to the ARP cache for services that need their next hop resolved.
+#[derive(Clone)]
+pub struct Resolver {
+    cache: Arc<Mutex<ArpCache>>,
+    mac: MacAddress,
+}
+
+impl Resolver {
+    /// Queues `pending` for `next_hop`, returning the frames that are ready to be sent: the
+    /// packet itself once its hardware address is known, and any ARP requests.
+    pub fn send(&self, next_hop: ProtocolAddress, pending: Pending) -> Vec<Arc<[u8]>> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.send(next_hop, pending, Instant::now());
+        self.frames(cache.take_output())
+    }
+
+    /// Takes `address` for the interface without probing, as it was already checked, and
+    /// returns the gratuitous ARP announcing it.
+    pub fn announce(&self, address: ProtocolAddress) -> Vec<Arc<[u8]>> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.announce(address, Instant::now());
+        self.frames(cache.take_output())
+    }
+
+    /// Stops defending `address`, once the interface no longer owns it.
+    pub fn release(&self, address: ProtocolAddress) {
+        self.cache.lock().unwrap().release(address);
+    }
+
+    /// A snapshot of the neighbour table, sorted by address.
+    pub fn entries(&self) -> Vec<(ProtocolAddress, EntryState, Option<HardwareAddress>)> {
+        let mut entries = self.cache.lock().unwrap().entries().collect::<Vec<_>>();
+        entries.sort_by_key(|(address, ..)| match address {
+            ProtocolAddress::Ipv4Address(address) => u128::from(u32::from(*address)),
+            ProtocolAddress::Ipv6Address(address) => u128::from(*address),
+        });
+        entries
+    }
+
+    fn frames(&self, output: Vec<Output>) -> Vec<Arc<[u8]>> {
+        let mut frames = vec![];
+        for output in output {
+            let (destination, ethertype, data) = match output {
+                Output::Arp { destination, packet } => {
+                    let mut data = vec![0u8; packet.byte_length()];
+                    packet.serialise(&mut data);
+                    (destination, EtherType::Arp, data)
+                },
+                Output::Send { destination, pending } => (destination, pending.ethertype, pending.data),
+                Output::Unreachable { address, dropped } => {
+                    eprintln!("arp: could not resolve {address}, dropped {} packets", dropped.len());
+                    continue;
+                },
+                // Given up by `ArpService::flush`, as only the service passes packets to the cache.
+                Output::Conflict { .. } => continue,
+            };
+
+            let HardwareAddress::MacAddress(destination) = destination;
+            let frame = Frame::new(destination, self.mac, ethertype, data);
+
+            let mut buf = vec![0u8; frame.byte_length()];
+            frame.serialise(&mut buf);
+            frames.push(Arc::from(buf));
+        }
+
+        frames
+    }
+}
+
+/// Resolves IPv4 addresses for the interface and answers requests for the addresses it owns.
+/// An address another host turns out to be using is taken off the interface.
+pub struct ArpService {
+    interface: Arc<RwLock<Interface>>,
+    routes: Arc<RwLock<RoutingTable>>,
+    resolver: Resolver,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl ArpService {
+    /// Creates the service and starts conflict detection for the interface's IPv4 addresses.
+    pub fn new(interface: Arc<RwLock<Interface>>, routes: Arc<RwLock<RoutingTable>>) -> Self {
+        let mac = interface.read().unwrap().mac();
+        let mut cache = ArpCache::new(mac.into());
+
+        let now = Instant::now();
+        interface.read().unwrap().ipv4_addresses().for_each(|address| cache.claim(address.into(), now));
+
+        Self {
+            interface,
+            routes,
+            resolver: Resolver { cache: Arc::new(Mutex::new(cache)), mac },
+            channels: Channels::new(),
+            actions: vec![Action::new(ActionType::Process, |_, _| true, false)],
+        }
+    }
+
+    pub fn resolver(&self) -> Resolver {
+        self.resolver.clone()
+    }
+
+    fn flush(&self, cache: &mut ArpCache) -> Result<(), NetServiceError> {
+        let output = cache.take_output();
+        for output in &output {
+            if let Output::Conflict { address, hardware } = output {
+                self.give_up(*address, *hardware);
+            }
+        }
+
+        self.resolver.frames(output).into_iter().try_for_each(|frame| self.send_down(frame))
+    }
+
+    /// Stops using an address another host has (RFC 5227 section 2.4): it comes off the
+    /// interface, along with its connected route unless another address is on that network.
+    fn give_up(&self, address: ProtocolAddress, hardware: HardwareAddress) {
+        let mut interface = self.interface.write().unwrap();
+        let Some(lost) = interface.addresses().iter().find(|a| a.address() == address).copied() else {
+            return;
+        };
+
+        interface.remove_address(address);
+        let network = lost.network();
+        if !interface.addresses().iter().any(|a| a.network() == network) {
+            self.routes.write().unwrap().retain(|route| {
+                route.interface != interface.name() || route.origin != Origin::Connected || route.destination != network
+            });
+        }
+        eprintln!("arp: {hardware} is also using {address}, {} gave it up", interface.name());
+    }
+}
+
+impl NetService for ArpService {
+    type Pdu = Packet;
+
+    fn name(&self) -> &'static str {
+        "arp"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(_: &Self::Pdu) -> Option<Arc<[u8]>> {
+        None
+    }
+
+    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        let mut cache = self.resolver.cache.lock().unwrap();
+        cache.on_packet(&pdu, Instant::now());
+
+        if let Some(reply) = responder::reply(&self.interface.read().unwrap(), &cache, &pdu) {
+            let HardwareAddress::MacAddress(destination) = pdu.sha();
+            let mut frame = Frame::new(destination, self.resolver.mac, EtherType::Arp, vec![]);
+            frame.wrap(&reply);
+
+            let mut buf = vec![0u8; frame.byte_length()];
+            frame.serialise(&mut buf);
+            self.send_down(Arc::from(buf))?;
+        }
+
+        self.flush(&mut cache)
+    }
+
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        let mut cache = self.resolver.cache.lock().unwrap();
+        cache.on_tick(now);
+        self.flush(&mut cache)
+    }
+}
+
+#[test]
+fn test_conflict() {
+    use std::sync::mpsc;
+
+    use rosi::common::address::Ipv4Address;
+    use rosi::protocols::arp::Operation;
+
+    let (ours, theirs) = (MacAddress::from([0x02, 0, 0, 0, 0, 1]), MacAddress::from([0x02, 0, 0, 0, 0, 2]));
+    let (address, peer) = (Ipv4Address::from([10, 0, 0, 2]), Ipv4Address::from([10, 0, 0, 1]));
+
+    let mut interface = Interface::new("tap0", ours, crate::interface::DEFAULT_MTU);
+    interface.add_address("10.0.0.2/24".parse().unwrap());
+    let interface = Arc::new(RwLock::new(interface));
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&interface.read().unwrap());
+
+    let mut arp = ArpService::new(interface.clone(), routes.clone());
+    let (send_down, sent) = mpsc::channel();
+    arp.set_send_down(send_down);
+    arp.resolver().announce(address.into());
+    arp.on_tick(Instant::now() + std::time::Duration::from_secs(2)).unwrap();
+
+    let request = Packet::request(theirs.into(), peer.into(), MacAddress::default().into(), address.into()).unwrap();
+    let replies = || sent.try_iter()
+        .filter_map(|bytes| Packet::deserialise(Frame::deserialise(&bytes).unwrap().data()).ok())
+        .filter(|packet| packet.operation() == Operation::Response)
+        .count();
+    arp.process_pdu(request.clone()).unwrap();
+    assert_eq!(replies(), 1);
+
+    // Another host announcing the address is defended against once, then given way to.
+    let announcement = Packet::request(theirs.into(), address.into(), MacAddress::default().into(), address.into()).unwrap();
+    arp.process_pdu(announcement.clone()).unwrap();
+    assert!(interface.read().unwrap().owns(address));
+    arp.process_pdu(announcement).unwrap();
+    assert!(!interface.read().unwrap().owns(address));
+    assert!(routes.read().unwrap().lookup(peer).is_none());
+
+    sent.try_iter().for_each(drop);
+    arp.process_pdu(request).unwrap();
+    assert_eq!(replies(), 0);
+}
@@ -0,0 +1,301 @@
+use rosi::common::address::{Ipv4Address, MacAddress};
+use rosi::common::log::Format;
+use rosi::protocols::arp::ProtocolAddress;
+
+use crate::config::{self, Config, DhcpServerConfig, InterfaceConfig};
+use crate::interface::{self, InterfaceAddress};
+use crate::tun_tap::Mode;
+
+pub const USAGE: &str = "\
+usage: rstack [options] [command]
+
+commands:
+    run                         run the stack with an echo server on TCP port 7 (the default)
+    capture <file>              run the stack and record every frame to a pcap or pcapng file
+    replay <input> <output>     feed a capture through the stack instead of a device, and
+                                record the frames it sends
+    arp-table [seconds]         run the stack for a while (5 seconds) and print its ARP table
+    ping <address> [count]      send echo requests (4) and print the replies
+
+options:
+    -c, --config <file>         read interfaces, addresses, routes and rules from a TOML file
+    -i, --interface <name>      run on this interface from the config, or on a new one
+    -m, --mode <tap|tun>        open the device in TAP or TUN mode
+        --no-packet-info        open the device without packet information (IFF_NO_PI)
+        --mac <address>         the MAC address of the interface
+    -a, --address <cidr>        an address for the interface, replacing those configured;
+                                may be repeated
+    -d, --dhcp                  lease the IPv4 address of the interface from a DHCP server,
+                                instead of those configured
+        --dhcp-server <first-last>
+                                hand out the addresses of this range to clients on the
+                                interface, with the rest of the [dhcp-server] config
+        --egress-rate <bits/s>  send no faster than this, queueing frames by priority
+    -f, --forward               run on every configured interface and route between them
+    -r, --rules <file>          read firewall rules from a file instead of the config
+        --log-format <text|json>
+        --log-file <file>       write logs to a file instead of standard error
+    -v, --verbose               log every frame sent and received
+    -q, --quiet                 print neither the configuration nor the firewall counters
+    -h, --help                  print this message
+";
+
+const DEFAULT_ARP_TABLE_SECONDS: u64 = 5;
+const DEFAULT_PING_COUNT: u16 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Capture { output: String },
+    Replay { input: String, output: String },
+    ArpTable { seconds: u64 },
+    Ping { destination: Ipv4Address, count: u16 },
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub config: Option<String>,
+    pub interface: Option<String>,
+    pub mode: Option<Mode>,
+    pub no_packet_info: bool,
+    pub mac: Option<MacAddress>,
+    pub addresses: Vec<InterfaceAddress>,
+    pub dhcp: bool,
+    pub dhcp_pool: Option<(Ipv4Address, Ipv4Address)>,
+    pub egress_rate: Option<u64>,
+    pub forward: bool,
+    pub rules: Option<String>,
+    pub log_format: Format,
+    pub log_file: Option<String>,
+    pub verbosity: Verbosity,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config: None,
+            interface: None,
+            mode: None,
+            no_packet_info: false,
+            mac: None,
+            addresses: vec![],
+            dhcp: false,
+            dhcp_pool: None,
+            egress_rate: None,
+            forward: false,
+            rules: None,
+            log_format: Format::Text,
+            log_file: None,
+            verbosity: Verbosity::Normal,
+        }
+    }
+}
+
+impl Options {
+    /// The configured interface to run on with the options applied to it. Without `--interface`
+    /// this is the first one in the config, and if the config has none by that name, it is the
+    /// first one renamed.
+    pub fn interface(&self, config: &Config) -> InterfaceConfig {
+        let named = self.interface.as_ref().and_then(|name| config.interfaces.iter().find(|interface| interface.name == *name));
+        let mut interface = named.or(config.interfaces.first()).cloned().unwrap_or_default();
+
+        if let Some(name) = &self.interface {
+            interface.name = name.clone();
+        }
+        if let Some(mode) = self.mode {
+            interface.mode = mode;
+        }
+        if self.no_packet_info {
+            interface.packet_info = false;
+        }
+        if let Some(mac) = self.mac {
+            interface.mac = mac;
+        }
+        if !self.addresses.is_empty() {
+            interface.addresses = self.addresses.clone();
+        }
+        if self.dhcp {
+            interface.dhcp = true;
+            interface.addresses.retain(|address| !matches!(address.address(), ProtocolAddress::Ipv4Address(..)));
+        }
+        if self.egress_rate.is_some() {
+            interface.egress_rate = self.egress_rate;
+        }
+
+        interface
+    }
+
+    /// The DHCP server to run, if the config or `--dhcp-server` asks for one. Its pool is
+    /// the one given on the command line, and its interface, unless configured, the one picked
+    /// by `interface`.
+    pub fn dhcp_server(&self, config: &Config) -> Option<DhcpServerConfig> {
+        let mut server = match (&config.dhcp_server, self.dhcp_pool) {
+            (Some(server), pool) => DhcpServerConfig { pool: pool.unwrap_or(server.pool), ..server.clone() },
+            (None, Some(pool)) => DhcpServerConfig::new(pool),
+            (None, None) => return None,
+        };
+
+        if server.interface.is_none() {
+            server.interface = Some(self.interface(config).name);
+        }
+        Some(server)
+    }
+
+    pub fn forwarding(&self, config: &Config) -> bool {
+        self.forward || config.forwarding
+    }
+
+    /// The interfaces to run on: the one picked by `interface`, followed, when forwarding, by
+    /// the other configured ones as they are.
+    pub fn interfaces(&self, config: &Config) -> Vec<InterfaceConfig> {
+        let first = self.interface(config);
+        let others = config.interfaces.iter()
+            .filter(|interface| self.forwarding(config) && interface.name != first.name)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        std::iter::once(first).chain(others).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    pub command: Command,
+    pub options: Options,
+}
+
+impl Cli {
+    /// Parses the arguments after the program name. Options may come before or after the command.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut positional = vec![];
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                positional.push(arg);
+                continue;
+            }
+
+            let mut value = || args.next().ok_or_else(|| format!("missing value for {arg}"));
+            match arg.as_str() {
+                "-c" | "--config" => options.config = Some(value()?),
+                "-i" | "--interface" => options.interface = Some(value()?),
+                "-m" | "--mode" => options.mode = Some(value()?.parse()?),
+                "--no-packet-info" => options.no_packet_info = true,
+                "--mac" => options.mac = Some(interface::parse_mac(&value()?)?),
+                "-a" | "--address" => options.addresses.push(value()?.parse()?),
+                "-d" | "--dhcp" => options.dhcp = true,
+                "--dhcp-server" => options.dhcp_pool = Some(config::parse_pool(&value()?)?),
+                "--egress-rate" => {
+                    let rate = value()?;
+                    options.egress_rate = Some(rate.parse().map_err(|_| format!("invalid rate {rate}"))?);
+                },
+                "-f" | "--forward" => options.forward = true,
+                "-r" | "--rules" => options.rules = Some(value()?),
+                "--log-format" => options.log_format = match value()?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    format => return Err(format!("invalid log format {format}, expected text or json")),
+                },
+                "--log-file" => options.log_file = Some(value()?),
+                "-v" | "--verbose" => options.verbosity = Verbosity::Verbose,
+                "-q" | "--quiet" => options.verbosity = Verbosity::Quiet,
+                "-h" | "--help" => return Ok(Self { command: Command::Help, options }),
+                _ => return Err(format!("unknown option {arg}")),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let name = positional.next();
+        let mut argument = |what: &str| positional.next().ok_or_else(|| format!("missing {what} for {}", name.as_deref().unwrap_or_default()));
+
+        let command = match name.as_deref() {
+            None | Some("run") => Command::Run,
+            Some("capture") => Command::Capture { output: argument("output file")? },
+            Some("replay") => Command::Replay { input: argument("input file")?, output: argument("output file")? },
+            Some("arp-table") => Command::ArpTable {
+                seconds: match argument("seconds") {
+                    Ok(seconds) => seconds.parse().map_err(|_| format!("invalid number of seconds {seconds}"))?,
+                    Err(_) => DEFAULT_ARP_TABLE_SECONDS,
+                },
+            },
+            Some("ping") => Command::Ping {
+                destination: match interface::parse_address(&argument("address")?)? {
+                    ProtocolAddress::Ipv4Address(address) => address,
+                    address => return Err(format!("cannot ping {address}, only IPv4 is supported")),
+                },
+                count: match argument("count") {
+                    Ok(count) => count.parse().map_err(|_| format!("invalid count {count}"))?,
+                    Err(_) => DEFAULT_PING_COUNT,
+                },
+            },
+            Some(name) => return Err(format!("unknown command {name}")),
+        };
+
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument {extra}"));
+        }
+
+        Ok(Self { command, options })
+    }
+}
+
+#[test]
+fn test_command_line() {
+    let parse = |args: &str| Cli::parse(args.split_whitespace().map(String::from));
+
+    assert_eq!(parse(""), Ok(Cli { command: Command::Run, options: Options::default() }));
+    assert_eq!(parse("arp-table").unwrap().command, Command::ArpTable { seconds: DEFAULT_ARP_TABLE_SECONDS });
+    assert_eq!(parse("-q ping 10.0.0.1 2").unwrap().command, Command::Ping { destination: Ipv4Address::from([10, 0, 0, 1]), count: 2 });
+
+    let cli = parse("replay in.pcap -v out.pcapng --mode tun --no-packet-info -a 10.0.1.1/30 -a fe80::1/64 --egress-rate 64000 --log-format json").unwrap();
+    assert_eq!(cli.command, Command::Replay { input: "in.pcap".into(), output: "out.pcapng".into() });
+    assert_eq!(cli.options.verbosity, Verbosity::Verbose);
+    assert_eq!(cli.options.log_format, Format::Json);
+
+    // Options override the interface picked from the config.
+    let interface = cli.options.interface(&Config::default());
+    assert_eq!(interface.name, "tap0");
+    assert_eq!(interface.mode, Mode::Tun);
+    assert!(!interface.packet_info);
+    assert_eq!(interface.addresses.len(), 2);
+    assert_eq!(interface.egress_rate, Some(64000));
+    assert_eq!(parse("-i tap1").unwrap().options.interface(&Config::default()).name, "tap1");
+
+    // With DHCP the configured IPv4 addresses give way to the leased one.
+    let interface = parse("--dhcp -a 10.0.1.1/30 -a fe80::1/64").unwrap().options.interface(&Config::default());
+    assert!(interface.dhcp);
+    assert_eq!(interface.addresses, vec!["fe80::1/64".parse().unwrap()]);
+
+    // The server runs on the picked interface unless the config says otherwise.
+    let pool = (Ipv4Address::from([10, 0, 0, 100]), Ipv4Address::from([10, 0, 0, 199]));
+    let server = parse("-i tap1 --dhcp-server 10.0.0.100-10.0.0.199").unwrap().options.dhcp_server(&Config::default()).unwrap();
+    assert_eq!(server, DhcpServerConfig { interface: Some("tap1".into()), ..DhcpServerConfig::new(pool) });
+    assert_eq!(parse("").unwrap().options.dhcp_server(&Config::default()), None);
+
+    // Forwarding runs on the other configured interfaces too.
+    let config = Config {
+        interfaces: vec![InterfaceConfig::default(), InterfaceConfig { name: "tap1".into(), ..Default::default() }],
+        ..Default::default()
+    };
+    let names = |args| parse(args).unwrap().options.interfaces(&config).into_iter().map(|interface| interface.name).collect::<Vec<_>>();
+    assert_eq!(names(""), ["tap0"]);
+    assert_eq!(names("--forward"), ["tap0", "tap1"]);
+    assert_eq!(names("-f -i tap1"), ["tap1", "tap0"]);
+
+    assert_eq!(parse("ping"), Err("missing address for ping".into()));
+    assert!(parse("ping fe80::1").unwrap_err().ends_with("only IPv4 is supported"));
+    assert_eq!(parse("run --mac 02:00"), Err("invalid MAC address 02:00".into()));
+    assert_eq!(parse("run now"), Err("unexpected argument now".into()));
+    assert_eq!(parse("--config"), Err("missing value for --config".into()));
+    assert_eq!(parse("run -h").unwrap().command, Command::Help);
+}
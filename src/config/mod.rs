@@ -0,0 +1,428 @@
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use rosi::common::address::{Ipv4Address, MacAddress};
+use rosi::protocols::arp::ProtocolAddress;
+
+use crate::firewall::Firewall;
+use crate::interface::{self, InterfaceAddress, DEFAULT_MTU};
+use crate::tun_tap::Mode;
+
+use toml::{Table, Value};
+
+/// A device for the stack to run on, and how the stack is configured on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceConfig {
+    pub name: String,
+    pub mode: Mode,
+    /// Whether the device is opened with a packet information header in front of every
+    /// packet, which is to say without `IFF_NO_PI`.
+    pub packet_info: bool,
+    pub mac: MacAddress,
+    pub mtu: u16,
+    pub addresses: Vec<InterfaceAddress>,
+    /// Whether an IPv4 address and default route are leased from a DHCP server, besides any
+    /// addresses configured.
+    pub dhcp: bool,
+    /// The most bits per second sent on the interface, which is unlimited by default.
+    pub egress_rate: Option<u64>,
+}
+
+impl Default for InterfaceConfig {
+    fn default() -> Self {
+        Self {
+            name: "tap0".into(),
+            mode: Mode::Tap,
+            packet_info: true,
+            mac: MacAddress::from([0x02, 0, 0, 0, 0, 1]),
+            mtu: DEFAULT_MTU,
+            addresses: vec!["10.0.0.2/24".parse().unwrap()],
+            dhcp: false,
+            egress_rate: None,
+        }
+    }
+}
+
+/// A static route. A destination of `0.0.0.0/0` or `::/0` makes it a default route.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConfig {
+    pub destination: InterfaceAddress,
+    pub gateway: Option<ProtocolAddress>,
+    pub interface: Option<String>,
+    pub metric: u32,
+}
+
+/// Seconds a lease from the DHCP server lasts unless configured otherwise.
+pub const DEFAULT_LEASE_TIME: u32 = 3600;
+
+/// A DHCP server handing out addresses from a pool on the network of one interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpServerConfig {
+    /// The interface to serve, which is the first one the stack runs on if left out.
+    pub interface: Option<String>,
+    /// The first and last addresses handed out, both included.
+    pub pool: (Ipv4Address, Ipv4Address),
+    /// Seconds a lease lasts.
+    pub lease_time: u32,
+    /// The routers given to clients, which are the server's own address if none are configured.
+    pub routers: Vec<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub domain_name: Option<String>,
+    /// Addresses always given to the same hardware address, which need not be in the pool.
+    pub reservations: Vec<(MacAddress, Ipv4Address)>,
+    /// The file the leases are kept in across restarts. Without one they are kept in memory.
+    pub leases: Option<String>,
+}
+
+impl DhcpServerConfig {
+    pub fn new(pool: (Ipv4Address, Ipv4Address)) -> Self {
+        Self {
+            interface: None,
+            pool,
+            lease_time: DEFAULT_LEASE_TIME,
+            routers: vec![],
+            dns_servers: vec![],
+            domain_name: None,
+            reservations: vec![],
+            leases: None,
+        }
+    }
+}
+
+pub struct Config {
+    pub interfaces: Vec<InterfaceConfig>,
+    pub routes: Vec<RouteConfig>,
+    /// Whether the stack runs on every interface and routes packets between them.
+    pub forwarding: bool,
+    pub dhcp_server: Option<DhcpServerConfig>,
+    pub firewall: Firewall,
+}
+
+impl Config {
+    /// Reads the configuration from a file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{e}", path.display())))
+    }
+}
+
+/// What the stack runs with when it is not given a config file: `tap0` with the address
+/// `10.0.0.2/24`, no routes, no forwarding, no DHCP server and no firewall rules.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interfaces: vec![InterfaceConfig::default()],
+            routes: vec![],
+            forwarding: false,
+            dhcp_server: None,
+            firewall: Firewall::new(),
+        }
+    }
+}
+
+/// Takes the keys out of a table one by one, so that any left over can be reported as unknown.
+struct Keys {
+    path: String,
+    table: Table,
+}
+
+impl Keys {
+    fn take(&mut self, key: &str) -> Option<Value> {
+        self.table.remove(key)
+    }
+
+    fn error(&self, key: &str, message: impl core::fmt::Display) -> String {
+        format!("{}{key}: {message}", self.path)
+    }
+
+    fn mismatch(&self, key: &str, expected: &str, value: &Value) -> String {
+        self.error(key, format!("expected {expected}, found {}", value.type_str()))
+    }
+
+    /// Parses a string value with `parse`.
+    fn parse<T>(&mut self, key: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => parse(&s).map(Some).map_err(|e| self.error(key, e)),
+            Some(value) => Err(self.mismatch(key, "a string", &value)),
+        }
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<String>, String> {
+        self.parse(key, |s| Ok(s.to_owned()))
+    }
+
+    fn integer<T: TryFrom<i64>>(&mut self, key: &str) -> Result<Option<T>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Integer(n)) => T::try_from(n).map(Some).map_err(|_| self.error(key, format!("{n} is out of range"))),
+            Some(value) => Err(self.mismatch(key, "an integer", &value)),
+        }
+    }
+
+    fn boolean(&mut self, key: &str) -> Result<Option<bool>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(b)),
+            Some(value) => Err(self.mismatch(key, "a boolean", &value)),
+        }
+    }
+
+    fn strings(&mut self, key: &str) -> Result<Vec<String>, String> {
+        match self.take(key) {
+            None => Ok(vec![]),
+            Some(Value::Array(values)) => values.into_iter().map(|value| match value {
+                Value::String(s) => Ok(s),
+                value => Err(self.mismatch(key, "an array of strings", &value)),
+            }).collect(),
+            Some(value) => Err(self.mismatch(key, "an array", &value)),
+        }
+    }
+
+    fn table(&mut self, key: &str) -> Result<Option<Keys>, String> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Keys { path: format!("{}{key}.", self.path), table })),
+            Some(value) => Err(self.mismatch(key, "a table", &value)),
+        }
+    }
+
+    /// The tables of an array written as `[[key]]`.
+    fn tables(&mut self, key: &str) -> Result<Vec<Keys>, String> {
+        match self.take(key) {
+            None => Ok(vec![]),
+            Some(Value::Array(values)) => values.into_iter().enumerate().map(|(i, value)| match value {
+                Value::Table(table) => Ok(Keys { path: format!("{}{key}[{i}].", self.path), table }),
+                value => Err(self.mismatch(key, "an array of tables", &value)),
+            }).collect(),
+            Some(value) => Err(self.mismatch(key, "an array of tables", &value)),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.table.keys().next() {
+            Some(key) => Err(self.error(key, "unknown key")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Config {
+    type Err = String;
+
+    /// Parses a TOML file like this one, in which every key is optional:
+    ///
+    /// ```text
+    /// forwarding = false
+    ///
+    /// [[interface]]
+    /// name = "tap0"
+    /// mode = "tap"
+    /// packet-info = true
+    /// mac = "02:00:00:00:00:01"
+    /// mtu = 1500
+    /// addresses = ["10.0.0.2/24", "fe80::2/64"]
+    /// dhcp = false
+    /// egress-rate = 10_000_000
+    ///
+    /// [[route]]
+    /// destination = "0.0.0.0/0"
+    /// gateway = "10.0.0.1"
+    /// metric = 10
+    ///
+    /// [dhcp-server]
+    /// interface = "tap0"
+    /// pool = "10.0.0.100-10.0.0.199"
+    /// lease-time = 3600
+    /// routers = ["10.0.0.2"]
+    /// dns-servers = ["10.0.0.2"]
+    /// domain-name = "lan"
+    /// reservations = ["52:54:00:12:34:56 10.0.0.10"]
+    /// leases = "rstack.leases"
+    ///
+    /// [firewall]
+    /// rules = [
+    ///     "ipv4 accept src 10.0.0.0/24 proto tcp dst-port 22",
+    ///     "ipv4 reject proto tcp dst-port 22",
+    /// ]
+    /// ```
+    ///
+    /// A route needs a gateway, an interface, or both; without an interface it goes out of the
+    /// one its gateway is on. A DHCP server needs a pool; its reservations pair a MAC address
+    /// with an address. The firewall rules are written as they are in a rules file. Keys left out of an
+    /// interface take the values of the default `tap0`, except that it has no addresses.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let table = s.parse::<Table>().map_err(|e| {
+            let line = e.span().map_or(1, |span| s[..span.start].lines().count().max(1));
+            format!("{line}: {}", e.message())
+        })?;
+        let mut root = Keys { path: String::new(), table };
+        let defaults = InterfaceConfig::default();
+        let forwarding = root.boolean("forwarding")?.unwrap_or(false);
+
+        let mut interfaces = root.tables("interface")?.into_iter().map(|mut keys| {
+            let interface = InterfaceConfig {
+                name: keys.string("name")?.unwrap_or_else(|| defaults.name.clone()),
+                mode: keys.parse("mode", str::parse)?.unwrap_or(defaults.mode),
+                packet_info: keys.boolean("packet-info")?.unwrap_or(defaults.packet_info),
+                mac: keys.parse("mac", interface::parse_mac)?.unwrap_or(defaults.mac),
+                mtu: keys.integer("mtu")?.unwrap_or(defaults.mtu),
+                addresses: keys.strings("addresses")?.iter()
+                    .map(|address| address.parse().map_err(|e| keys.error("addresses", e)))
+                    .collect::<Result<_, _>>()?,
+                dhcp: keys.boolean("dhcp")?.unwrap_or(defaults.dhcp),
+                egress_rate: keys.integer("egress-rate")?,
+            };
+
+            keys.finish()?;
+            Ok(interface)
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        if interfaces.is_empty() {
+            interfaces.push(defaults);
+        }
+
+        let routes = root.tables("route")?.into_iter().map(|mut keys| {
+            let route = RouteConfig {
+                destination: keys.parse("destination", str::parse)?.ok_or_else(|| keys.error("destination", "missing"))?,
+                gateway: keys.parse("gateway", interface::parse_address)?,
+                interface: keys.string("interface")?,
+                metric: keys.integer("metric")?.unwrap_or(0),
+            };
+
+            match route.gateway {
+                None if route.interface.is_none() => return Err(keys.error("gateway", "missing, and so is interface")),
+                Some(gateway) if core::mem::discriminant(&gateway) != core::mem::discriminant(&route.destination.address()) => {
+                    return Err(keys.error("gateway", format!("{gateway} is not of the same family as {}", route.destination.network())));
+                },
+                _ => (),
+            }
+
+            keys.finish()?;
+            Ok(route)
+        }).collect::<Result<Vec<_>, String>>()?;
+
+        let dhcp_server = match root.table("dhcp-server")? {
+            Some(mut keys) => {
+                let addresses = |keys: &mut Keys, key: &str| keys.strings(key)?.iter()
+                    .map(|address| interface::parse_ipv4_address(address).map_err(|e| keys.error(key, e)))
+                    .collect::<Result<Vec<_>, _>>();
+
+                let server = DhcpServerConfig {
+                    interface: keys.string("interface")?,
+                    pool: keys.parse("pool", parse_pool)?.ok_or_else(|| keys.error("pool", "missing"))?,
+                    lease_time: keys.integer("lease-time")?.unwrap_or(DEFAULT_LEASE_TIME),
+                    routers: addresses(&mut keys, "routers")?,
+                    dns_servers: addresses(&mut keys, "dns-servers")?,
+                    domain_name: keys.string("domain-name")?,
+                    reservations: keys.strings("reservations")?.iter()
+                        .map(|reservation| parse_reservation(reservation).map_err(|e| keys.error("reservations", e)))
+                        .collect::<Result<_, _>>()?,
+                    leases: keys.string("leases")?,
+                };
+
+                keys.finish()?;
+                Some(server)
+            },
+            None => None,
+        };
+
+        let firewall = match root.table("firewall")? {
+            Some(mut keys) => {
+                let firewall = keys.strings("rules")?.join("\n").parse().map_err(|e| keys.error("rules", e))?;
+                keys.finish()?;
+                firewall
+            },
+            None => Firewall::new(),
+        };
+
+        root.finish()?;
+        Ok(Self { interfaces, routes, forwarding, dhcp_server, firewall })
+    }
+}
+
+/// Parses a range of IPv4 addresses such as `10.0.0.100-10.0.0.199`.
+pub fn parse_pool(s: &str) -> Result<(Ipv4Address, Ipv4Address), String> {
+    let (first, last) = s.split_once('-').ok_or_else(|| format!("expected a range such as 10.0.0.100-10.0.0.199, found {s}"))?;
+    let (first, last) = (interface::parse_ipv4_address(first.trim())?, interface::parse_ipv4_address(last.trim())?);
+    if u32::from(first) > u32::from(last) {
+        return Err(format!("{first} comes after {last}"));
+    }
+    Ok((first, last))
+}
+
+/// Parses a MAC address and the IPv4 address reserved for it, separated by whitespace.
+fn parse_reservation(s: &str) -> Result<(MacAddress, Ipv4Address), String> {
+    match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [mac, address] => Ok((interface::parse_mac(mac)?, interface::parse_ipv4_address(address)?)),
+        _ => Err(format!("expected a MAC address and an IPv4 address, found {s:?}")),
+    }
+}
+
+#[test]
+fn test_config_file() {
+    use rosi::common::address::Ipv4Address;
+
+    use crate::firewall::Verdict;
+
+    let config: Config = concat!(
+        "forwarding = true\n",
+        "\n",
+        "[[interface]]\n",
+        "name = \"tun0\"\n",
+        "mode = \"tun\"\n",
+        "packet-info = false\n",
+        "addresses = [\"10.0.1.1/30\"]\n",
+        "egress-rate = 1_000_000\n",
+        "\n",
+        "[[interface]]\n",
+        "dhcp = true\n",
+        "\n",
+        "[[route]]\n",
+        "destination = \"0.0.0.0/0\"\n",
+        "gateway = \"10.0.1.2\"\n",
+        "\n",
+        "[dhcp-server]\n",
+        "pool = \"10.0.1.2 - 10.0.1.2\"\n",
+        "dns-servers = [\"10.0.1.1\"]\n",
+        "reservations = [\"52:54:00:12:34:56 10.0.1.3\"]\n",
+        "\n",
+        "[firewall]\n",
+        "rules = [\"ipv4 policy drop\", \"ipv4 accept proto icmp\"]\n",
+    ).parse().unwrap();
+
+    assert_eq!(config.interfaces, vec![
+        InterfaceConfig { name: "tun0".into(), mode: Mode::Tun, packet_info: false, addresses: vec!["10.0.1.1/30".parse().unwrap()], egress_rate: Some(1_000_000), ..Default::default() },
+        InterfaceConfig { addresses: vec![], dhcp: true, ..Default::default() },
+    ]);
+    assert_eq!(config.routes, vec![
+        RouteConfig { destination: "0.0.0.0/0".parse().unwrap(), gateway: Some(Ipv4Address::from([10, 0, 1, 2]).into()), interface: None, metric: 0 },
+    ]);
+    assert!(config.forwarding);
+    assert_eq!(config.dhcp_server, Some(DhcpServerConfig {
+        dns_servers: vec![Ipv4Address::from([10, 0, 1, 1])],
+        reservations: vec![(MacAddress::from([0x52, 0x54, 0, 0x12, 0x34, 0x56]), Ipv4Address::from([10, 0, 1, 3]))],
+        ..DhcpServerConfig::new((Ipv4Address::from([10, 0, 1, 2]), Ipv4Address::from([10, 0, 1, 2])))
+    }));
+    assert_eq!(config.firewall.ipv4().policy(), Verdict::Drop);
+    assert_eq!(config.firewall.ipv4().rules().len(), 1);
+
+    assert!("".parse::<Config>().unwrap().interfaces == vec![InterfaceConfig::default()]);
+    assert_eq!("[[interface]]\nmtu = 70000\n".parse::<Config>().err(), Some("interface[0].mtu: 70000 is out of range".into()));
+    assert_eq!("[[interface]]\nmac = \"02:00\"\n".parse::<Config>().err(), Some("interface[0].mac: invalid MAC address 02:00".into()));
+    assert_eq!("[[route]]\nmetric = 1\n".parse::<Config>().err(), Some("route[0].destination: missing".into()));
+    assert_eq!("[[route]]\ndestination = \"10.2.0.0/16\"\n".parse::<Config>().err(), Some("route[0].gateway: missing, and so is interface".into()));
+    assert_eq!(
+        "[[route]]\ndestination = \"::/0\"\ngateway = \"10.0.1.2\"\n".parse::<Config>().err(),
+        Some("route[0].gateway: 10.0.1.2 is not of the same family as 0000:0000:0000:0000:0000:0000:0000:0000/0".into()),
+    );
+    assert!("[[route]]\ndestination = \"10.2.0.0/16\"\ninterface = \"tun0\"\n".parse::<Config>().is_ok());
+    assert!("forwarding = true\n\n[firewall\n".parse::<Config>().err().unwrap().starts_with("3: "));
+    assert_eq!("[dhcp-server]\npool = \"10.0.0.9-10.0.0.1\"\n".parse::<Config>().err(), Some("dhcp-server.pool: 10.0.0.9 comes after 10.0.0.1".into()));
+    assert_eq!("[dhcp-server]\npool = \"10.0.0.1-10.0.0.9\"\nrouters = [\"fe80::1\"]\n".parse::<Config>().err(), Some("dhcp-server.routers: fe80::1 is not an IPv4 address".into()));
+    assert_eq!("[firewall]\nrule = []\n".parse::<Config>().err(), Some("firewall.rule: unknown key".into()));
+    assert_eq!("[firewall]\nrules = [\"ipv5 drop\"]\n".parse::<Config>().err(), Some("firewall.rules: 1: unknown chain ipv5".into()));
+}
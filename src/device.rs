@@ -0,0 +1,451 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use rosi::capture::{self, CaptureWriter, Direction, LinkType, Reader};
+use rosi::common::{Pdu, Serialise};
+use rosi::protocols::ethernet::Frame;
+use rosi::protocols::ipv4::Ipv4Packet;
+use rosi::protocols::ipv6::Ipv6Packet;
+
+use super::ethernet::EthernetService;
+use super::ipv4::Ipv4Service;
+use super::ipv6::Ipv6Service;
+use super::netservice::{Action, Channels, NetService, NetServiceError, Stack};
+use super::qos::EgressQueue;
+
+/// How long a replay keeps the stack running after the last frame, for its answers to be written.
+const REPLAY_LINGER: Duration = Duration::from_millis(500);
+
+/// Where frames enter and leave the stack.
+pub trait Device: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    /// Whether the device carries Ethernet frames or bare IP packets.
+    fn link_type(&self) -> LinkType;
+
+    /// Blocks until a frame arrives, or returns `None` once the device will never have another.
+    fn recv(&self) -> io::Result<Option<Vec<u8>>>;
+
+    fn send(&self, frame: &[u8]) -> io::Result<()>;
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Replays the frames of a capture file into the stack, and writes every frame the stack sends
+/// to another. Needs no privileges, so the whole stack can be run in tests.
+///
+/// Frames are replayed as far apart as they were captured, so that the stack has finished with
+/// one frame before the next arrives just as it would have on the wire.
+pub struct PcapDevice {
+    /// Taken once the input has run out or cannot be read any further.
+    input: Mutex<Option<Reader<BufReader<File>>>>,
+    link_type: LinkType,
+    /// When the first frame was captured, and when it was replayed.
+    started: Mutex<Option<(SystemTime, Instant)>>,
+    output: Mutex<Box<dyn CaptureWriter>>,
+}
+
+impl PcapDevice {
+    /// Opens a capture of Ethernet frames or raw IP packets, and creates the output with the same link type.
+    pub fn open(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<Self> {
+        let input = Reader::open(input)?;
+        let link_type = input.link_type();
+        if !matches!(link_type, LinkType::Ethernet | LinkType::Raw) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("cannot replay {link_type} packets")));
+        }
+
+        Ok(
+            Self {
+                input: Mutex::new(Some(input)),
+                link_type,
+                started: Mutex::new(None),
+                output: Mutex::new(capture::create(output, link_type)?),
+            }
+        )
+    }
+}
+
+impl Device for PcapDevice {
+    fn name(&self) -> &str {
+        "pcap"
+    }
+
+    fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    fn recv(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut input = self.input.lock().unwrap();
+        match input.as_mut().and_then(Iterator::next) {
+            Some(Ok(record)) => {
+                let (captured, replayed) = *self.started.lock().unwrap().get_or_insert((record.timestamp, Instant::now()));
+                let offset = record.timestamp.duration_since(captured).unwrap_or_default();
+                thread::sleep((replayed + offset).saturating_duration_since(Instant::now()));
+                Ok(Some(record.data))
+            },
+            Some(Err(e)) => {
+                *input = None;
+                Err(e)
+            },
+            None => {
+                *input = None;
+                Ok(None)
+            },
+        }
+    }
+
+    fn send(&self, frame: &[u8]) -> io::Result<()> {
+        self.output.lock().unwrap().write_packet(SystemTime::now(), Direction::Outbound, frame)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.output.lock().unwrap().flush()
+    }
+}
+
+/// The version field of an IP packet, which is all there is to tell IPv4 from IPv6 on a raw link.
+pub fn ip_version(packet: &[u8]) -> Option<u8> {
+    packet.first().map(|byte| byte >> 4)
+}
+
+/// Writes the frames passing through the link to a capture, and logs them when tracing.
+#[derive(Clone)]
+struct Recorder {
+    link_type: LinkType,
+    capture: Option<Arc<Mutex<Box<dyn CaptureWriter>>>>,
+    trace: bool,
+}
+
+impl Recorder {
+    fn record(&self, direction: Direction, frame: &[u8]) {
+        self.trace(match direction {
+            Direction::Inbound => "IN",
+            Direction::Outbound => "OUT",
+        }, frame);
+
+        if let Some(capture) = &self.capture {
+            if let Err(e) = capture.lock().unwrap().write_packet(SystemTime::now(), direction, frame) {
+                eprintln!("link: capture: {e}");
+            }
+        }
+    }
+
+    fn trace(&self, action: &str, frame: &[u8]) {
+        if !self.trace {
+            return;
+        }
+
+        let logged = match (self.link_type, ip_version(frame)) {
+            (LinkType::Ethernet, _) => Frame::deserialise(frame).map(|frame| frame.log(action)),
+            (_, Some(6)) => Ipv6Packet::deserialise(frame).map(|packet| packet.log(action)),
+            _ => Ipv4Packet::deserialise(frame).map(|packet| packet.log(action)),
+        };
+
+        if logged.is_err() {
+            Arc::<[u8]>::from(frame).log(action);
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match &self.capture {
+            Some(capture) => capture.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The bottom of the stack: passes frames from a device up, and writes frames sent down to it.
+///
+/// Ethernet services go above a link to a TAP device, and IP services above one to a TUN device.
+/// Frames sent down wait in an egress queue, which sends them in order of priority.
+pub struct Link {
+    device: Arc<dyn Device>,
+    recorder: Recorder,
+    queue: EgressQueue,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl Link {
+    pub fn new(device: impl Device) -> Self {
+        let link_type = device.link_type();
+
+        Self {
+            device: Arc::new(device),
+            recorder: Recorder { link_type, capture: None, trace: false },
+            queue: EgressQueue::new(link_type, None, Instant::now()),
+            channels: Channels::new(),
+            actions: vec![],
+        }
+    }
+
+    pub fn ifname(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn link_type(&self) -> LinkType {
+        self.device.link_type()
+    }
+
+    /// Records every frame read from or written to the device.
+    pub fn set_capture(&mut self, capture: Box<dyn CaptureWriter>) {
+        self.recorder.capture = Some(Arc::new(Mutex::new(capture)));
+    }
+
+    /// Logs every frame read from or written to the device.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.recorder.trace = trace;
+    }
+
+    /// Limits the rate frames are sent at, in bits per second, so that the egress queue fills
+    /// up when the stack sends faster.
+    pub fn set_egress_rate(&mut self, rate: u64) {
+        self.queue = EgressQueue::new(self.link_type(), Some(rate / 8), Instant::now());
+    }
+
+    fn transmit(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        while let Some(frame) = self.queue.dequeue(now) {
+            self.recorder.record(Direction::Outbound, &frame);
+            self.device.send(&frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl NetService for Link {
+    type Pdu = Arc<[u8]>;
+
+    fn name(&self) -> &'static str {
+        "link"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(pdu: &Self::Pdu) -> Option<Arc<[u8]>> {
+        Some(pdu.clone())
+    }
+
+    fn process_pdu(&mut self, _: Self::Pdu) -> Result<(), NetServiceError> {
+        Ok(())
+    }
+
+    /// Queues the frame, to be sent on the next tick along with any others sent down meanwhile.
+    fn send(&mut self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        if !self.queue.enqueue(data.clone()) {
+            self.recorder.trace("DROP", &data);
+        }
+
+        Ok(())
+    }
+
+    /// Sends the queued frames the rate allows, and flushes the device and capture, so that
+    /// their files can be followed while the stack runs.
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        self.transmit(now)?;
+        self.device.flush()?;
+        self.recorder.flush()?;
+        Ok(())
+    }
+
+    /// Starts the service thread and a reader thread that feeds frames from the device into the
+    /// service's own queue. The returned handle is the reader's, which finishes a moment after
+    /// the device runs out of frames.
+    fn start(self) -> thread::JoinHandle<()> {
+        let device = self.device.clone();
+        let send_up = self.get_send_up();
+        let recorder = self.recorder.clone();
+
+        thread::spawn(move || self.run());
+
+        thread::spawn(move || {
+            loop {
+                match device.recv() {
+                    Ok(Some(frame)) => {
+                        recorder.record(Direction::Inbound, &frame);
+                        if send_up.send(Arc::from(frame)).is_err() {
+                            return;
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(e) => eprintln!("link: {e}"),
+                }
+            }
+
+            thread::sleep(REPLAY_LINGER);
+            if let Err(e) = device.flush() {
+                eprintln!("link: {e}");
+            }
+            if let Err(e) = recorder.flush() {
+                eprintln!("link: capture: {e}");
+            }
+        })
+    }
+}
+
+impl Stack<EthernetService> for Link {}
+impl Stack<Ipv4Service> for Link {}
+impl Stack<Ipv6Service> for Link {}
+
+#[test]
+fn test_replay() {
+    use std::sync::RwLock;
+
+    use rosi::capture::PcapWriter;
+    use rosi::common::Layer;
+    use rosi::common::address::{Ipv4Address, MacAddress};
+    use rosi::protocols::ethernet::{EtherType, Frame};
+    use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+    use rosi::protocols::{arp, icmp};
+
+    use crate::arp::ArpService;
+    use crate::interface::{Interface, DEFAULT_MTU};
+    use crate::ipv4::Ipv4Service;
+    use crate::netservice::ActionType;
+    use crate::route::RoutingTable;
+
+    let (mac, peer) = (MacAddress::from([0x02, 0, 0, 0, 0, 1]), MacAddress::from([0x02, 0, 0, 0, 0, 2]));
+    let (address, peer_address) = (Ipv4Address::from([10, 0, 0, 2]), Ipv4Address::from([10, 0, 0, 1]));
+
+    let directory = std::env::temp_dir().join(format!("rstack-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (input, output) = (directory.join("input.pcap"), directory.join("output.pcap"));
+
+    let mut writer = PcapWriter::new(File::create(&input).unwrap(), LinkType::Ethernet, capture::DEFAULT_SNAPLEN).unwrap();
+    let start = SystemTime::now();
+    let mut write = |offset, destination, ethertype, payload: &dyn Serialise| {
+        let mut frame = Frame::new(destination, peer, ethertype, vec![]);
+        frame.wrap(payload);
+        let mut buf = vec![0u8; frame.byte_length()];
+        frame.serialise(&mut buf);
+        writer.write_packet(start + Duration::from_millis(offset), Direction::Inbound, &buf).unwrap();
+    };
+
+    let request = arp::Packet::request(peer.into(), peer_address.into(), MacAddress::default().into(), address.into()).unwrap();
+    write(0, MacAddress::BROADCAST, EtherType::Arp, &request);
+
+    let mut ping = Ipv4Packet::new(peer_address, address, IpProtocol::Icmp, vec![]);
+    ping.wrap(&icmp::Packet::echo_request(1, 1, b"ping".to_vec()));
+    write(50, mac, EtherType::Ipv4, &ping);
+
+    // The address is still being probed, so the stack only learns the peer once it answers the
+    // stack's own request, which it sends while holding the echo reply.
+    let response = arp::Packet::response(peer.into(), peer_address.into(), mac.into(), address.into()).unwrap();
+    write(100, mac, EtherType::Arp, &response);
+    drop(writer);
+
+    let mut interface = Interface::new("pcap", mac, DEFAULT_MTU);
+    interface.add_address("10.0.0.2/24".parse().unwrap());
+    let interface = Arc::new(RwLock::new(interface));
+
+    let mut link = Link::new(PcapDevice::open(&input, &output).unwrap());
+    let mut ethernet = EthernetService::new(mac);
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&interface.read().unwrap());
+    let mut arp = ArpService::new(interface.clone(), routes.clone());
+    arp.resolver().announce(address.into());
+    let mut ipv4 = Ipv4Service::new(interface, arp.resolver(), routes);
+
+    link.stack(&mut ethernet, |_, _| true);
+    ethernet.stack(&mut arp, |_, frame| frame.ethertype() == EtherType::Arp);
+    ethernet.stack(&mut ipv4, |_, frame| frame.ethertype() == EtherType::Ipv4);
+    ipv4.add_filter(ActionType::Process, |_, packet| packet.proto() == IpProtocol::Icmp, false);
+
+    let link = link.start();
+    ethernet.start();
+    arp.start();
+    ipv4.start();
+    link.join().unwrap();
+
+    let frames = Reader::open(&output).unwrap().frames().filter(|frame| frame.destination() == peer).collect::<Vec<_>>();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let reply = frames.iter().find(|frame| frame.ethertype() == EtherType::Arp).unwrap();
+    assert_eq!(arp::Packet::deserialise(reply.data()).unwrap().spa(), address.into());
+
+    let reply = frames.iter().find(|frame| frame.ethertype() == EtherType::Ipv4).unwrap();
+    let reply = Ipv4Packet::deserialise(reply.data()).unwrap();
+    assert_eq!(reply.destination(), peer_address);
+    assert_eq!(icmp::Packet::deserialise(reply.data()).unwrap().message(), &icmp::Message::EchoReply { identifier: 1, sequence: 1, data: b"ping".to_vec() });
+}
+
+#[test]
+fn test_replay_point_to_point() {
+    use std::sync::RwLock;
+
+    use rosi::capture::PcapWriter;
+    use rosi::common::Layer;
+    use rosi::common::address::Ipv4Address;
+    use rosi::protocols::icmp;
+    use rosi::protocols::ipv4::IpProtocol;
+
+    use crate::interface::{Interface, DEFAULT_MTU};
+    use crate::netservice::ActionType;
+    use crate::route::RoutingTable;
+
+    let (address, peer_address) = (Ipv4Address::from([10, 0, 1, 1]), Ipv4Address::from([10, 0, 1, 2]));
+
+    let directory = std::env::temp_dir().join(format!("rstack-replay-tun-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (input, output) = (directory.join("input.pcap"), directory.join("output.pcapng"));
+
+    let mut ping = Ipv4Packet::new(peer_address, address, IpProtocol::Icmp, vec![]);
+    ping.wrap(&icmp::Packet::echo_request(1, 1, b"ping".to_vec()));
+    let mut buf = vec![0u8; ping.byte_length()];
+    ping.serialise(&mut buf);
+
+    let mut writer = PcapWriter::new(File::create(&input).unwrap(), LinkType::Raw, capture::DEFAULT_SNAPLEN).unwrap();
+    writer.write_packet(SystemTime::now(), Direction::Inbound, &buf).unwrap();
+    drop(writer);
+
+    let mut interface = Interface::point_to_point("pcap", DEFAULT_MTU);
+    interface.add_address("10.0.1.1/30".parse().unwrap());
+    let interface = Arc::new(RwLock::new(interface));
+
+    let mut link = Link::new(PcapDevice::open(&input, &output).unwrap());
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&interface.read().unwrap());
+    let mut ipv4 = Ipv4Service::point_to_point(interface.clone(), routes.clone());
+    let mut ipv6 = Ipv6Service::new(interface, routes);
+    assert_eq!(link.link_type(), LinkType::Raw);
+
+    // No ARP on a point-to-point link: the reply goes straight back down.
+    link.stack(&mut ipv4, |_, packet| ip_version(packet) == Some(4));
+    link.stack(&mut ipv6, |_, packet| ip_version(packet) == Some(6));
+    ipv4.add_filter(ActionType::Process, |_, packet| packet.proto() == IpProtocol::Icmp, false);
+
+    let link = link.start();
+    ipv4.start();
+    ipv6.start();
+    link.join().unwrap();
+
+    let reader = Reader::open(&output).unwrap();
+    assert_eq!(reader.link_type(), LinkType::Raw);
+    let records = reader.collect::<io::Result<Vec<_>>>().unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(records.len(), 1);
+    let reply = Ipv4Packet::deserialise(&records[0].data).unwrap();
+    assert_eq!(reply.destination(), peer_address);
+    assert_eq!(icmp::Packet::deserialise(reply.data()).unwrap().message(), &icmp::Message::EchoReply { identifier: 1, sequence: 1, data: b"ping".to_vec() });
+}
@@ -0,0 +1,521 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use rosi::common::address::{Ipv4Address, MacAddress};
+use rosi::protocols::arp::{self, Operation};
+use rosi::protocols::dhcp::{DhcpOption, Message, MessageType, Op};
+
+use crate::interface::InterfaceAddress;
+
+/// The first and longest waits for an answer to a DISCOVER or REQUEST, which double on every
+/// retransmission (RFC 2131, section 4.1).
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+
+/// REQUESTs sent for an offer before going back to discovery.
+const MAX_REQUESTS: u32 = 4;
+
+/// The shortest wait between REQUESTs while renewing or rebinding (RFC 2131, section 4.4.5).
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+
+/// ARP probes sent for a leased address before using it, and the time between them.
+const PROBE_NUM: u32 = 2;
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before discovering again after declining an address.
+const DECLINE_WAIT: Duration = Duration::from_secs(10);
+
+/// A lease time of all ones means the lease never expires.
+const INFINITE: u32 = u32::MAX;
+
+/// The options asked for in every DISCOVER and REQUEST.
+const PARAMETERS: [u8; 8] = [1, 3, 6, 15, 26, 51, 58, 59];
+
+/// The states of RFC 2131, figure 5, leaving out INIT-REBOOT and REBOOTING.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+}
+
+/// An address leased from a server, with the configuration that came with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub address: InterfaceAddress,
+    pub server: Ipv4Address,
+    pub routers: Vec<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub domain_name: Option<String>,
+    /// `None` for a lease that never expires.
+    pub lease_time: Option<Duration>,
+    pub renewal_time: Option<Duration>,
+    pub rebinding_time: Option<Duration>,
+    pub obtained: Instant,
+}
+
+impl Lease {
+    /// Reads the lease an ACK grants. T1 and T2 default to half and seven eighths of the lease
+    /// time, and a missing subnet mask to the mask of the address's class.
+    fn from_ack(ack: &Message, now: Instant) -> Option<Self> {
+        let address = ack.yiaddr();
+        let server = ack.server_identifier()?;
+        let seconds = |seconds: u32| (seconds != INFINITE).then(|| Duration::from_secs(seconds as u64));
+
+        let lease_time = seconds(ack.lease_time()?);
+        let prefix_length = match ack.subnet_mask() {
+            Some(mask) => u32::from(mask).leading_ones() as u8,
+            None => match <[u8; 4]>::from(address)[0] {
+                0..=127 => 8,
+                128..=191 => 16,
+                _ => 24,
+            },
+        };
+
+        Some(Self {
+            address: InterfaceAddress::new(address, prefix_length)?,
+            server,
+            routers: ack.routers().to_vec(),
+            dns_servers: ack.dns_servers().to_vec(),
+            domain_name: ack.domain_name().map(str::to_owned),
+            lease_time,
+            renewal_time: ack.renewal_time().map_or(lease_time.map(|t| t / 2), seconds),
+            rebinding_time: ack.rebinding_time().map_or(lease_time.map(|t| t * 7 / 8), seconds),
+            obtained: now,
+        })
+    }
+
+    pub fn ipv4_address(&self) -> Ipv4Address {
+        match self.address.address() {
+            arp::ProtocolAddress::Ipv4Address(address) => address,
+            arp::ProtocolAddress::Ipv6Address(..) => unreachable!("a lease is for an IPv4 address"),
+        }
+    }
+
+    fn at(&self, time: Option<Duration>) -> Option<Instant> {
+        time.and_then(|time| self.obtained.checked_add(time))
+    }
+
+    pub fn renews(&self) -> Option<Instant> {
+        self.at(self.renewal_time)
+    }
+
+    pub fn rebinds(&self) -> Option<Instant> {
+        self.at(self.rebinding_time)
+    }
+
+    pub fn expires(&self) -> Option<Instant> {
+        self.at(self.lease_time)
+    }
+}
+
+impl core::fmt::Display for Lease {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} from {}", self.address, self.server)?;
+        match self.lease_time {
+            Some(time) => write!(f, " for {}s", time.as_secs())?,
+            None => write!(f, " forever")?,
+        }
+        self.routers.first().map_or(Ok(()), |router| write!(f, ", router {router}"))?;
+        self.dns_servers.iter().enumerate().try_for_each(|(i, server)| write!(f, "{}{server}", if i == 0 { ", dns " } else { " " }))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Output {
+    /// A message to broadcast to every server on the link, from `ciaddr`, which is unspecified
+    /// until the client is bound.
+    Broadcast(Message),
+    /// A message to the server that granted the lease, from the leased address.
+    Unicast { destination: Ipv4Address, message: Message },
+    /// An ARP probe, checking that nobody else uses a leased address.
+    Probe(arp::Packet),
+    /// A lease was granted or extended, and its address and routes can be used.
+    Bound(Lease),
+    /// The lease expired or was withdrawn, and its address must no longer be used.
+    Unbound(Lease),
+}
+
+#[derive(Debug)]
+enum Phase {
+    Init { next: Instant },
+    Selecting { timeout: Duration, next: Instant },
+    Requesting { offer: Message, sent: u32, timeout: Duration, next: Instant },
+    /// The address was acknowledged but is probed for with ARP before it is used.
+    Checking { lease: Lease, sent: u32, next: Instant },
+    Bound { lease: Lease },
+    Renewing { lease: Lease, next: Instant },
+    Rebinding { lease: Lease, next: Instant },
+}
+
+/// A DHCP client for one interface (RFC 2131).
+///
+/// Like the ARP cache, the client never touches the network. Replies from servers are fed in
+/// with `on_message` and ARP packets with `on_arp`, timers are driven by `on_tick`, and the
+/// messages to send and the leases gained and lost are collected with `take_output`.
+#[derive(Debug)]
+pub struct DhcpClient {
+    mac: MacAddress,
+    phase: Phase,
+    /// The transaction the client waits for answers to.
+    xid: u32,
+    /// When the current attempt to acquire or extend a lease started, for the `secs` field.
+    started: Instant,
+    secret: u64,
+    outbox: Vec<Output>,
+}
+
+impl DhcpClient {
+    /// Creates a client that starts discovering at the first tick.
+    pub fn new(mac: MacAddress, now: Instant) -> Self {
+        let mut hasher = DefaultHasher::new();
+        now.hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+        mac.hash(&mut hasher);
+
+        Self {
+            mac,
+            phase: Phase::Init { next: now },
+            xid: 0,
+            started: now,
+            secret: hasher.finish(),
+            outbox: vec![],
+        }
+    }
+
+    pub fn take_output(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    #[allow(dead_code)]
+    pub fn state(&self) -> State {
+        match self.phase {
+            Phase::Init { .. } => State::Init,
+            Phase::Selecting { .. } => State::Selecting,
+            Phase::Requesting { .. } | Phase::Checking { .. } => State::Requesting,
+            Phase::Bound { .. } => State::Bound,
+            Phase::Renewing { .. } => State::Renewing,
+            Phase::Rebinding { .. } => State::Rebinding,
+        }
+    }
+
+    /// The lease the client holds, once its address has been checked.
+    pub fn lease(&self) -> Option<&Lease> {
+        match &self.phase {
+            Phase::Bound { lease } | Phase::Renewing { lease, .. } | Phase::Rebinding { lease, .. } => Some(lease),
+            _ => None,
+        }
+    }
+
+    /// The address being probed for with ARP, if any.
+    pub fn probing(&self) -> Option<Ipv4Address> {
+        match &self.phase {
+            Phase::Checking { lease, .. } => Some(lease.ipv4_address()),
+            _ => None,
+        }
+    }
+
+    /// Whether `packet` shows another host using the address being probed for: a packet
+    /// from it, or a probe for it from anybody else (RFC 5227, section 2.1.1).
+    pub fn conflicts(&self, packet: &arp::Packet) -> bool {
+        let Some(address) = self.probing() else {
+            return false;
+        };
+        let address = arp::ProtocolAddress::from(address);
+        let probe = packet.operation() == Operation::Request && packet.spa() == Ipv4Address::UNSPECIFIED.into();
+
+        packet.sha() != arp::HardwareAddress::from(self.mac) && (packet.spa() == address || (probe && packet.tpa() == address))
+    }
+
+    pub fn on_message(&mut self, message: &Message, now: Instant) {
+        if message.op() != Op::BootReply || message.xid() != self.xid || message.chaddr() != self.mac {
+            return;
+        }
+
+        let granted = Lease::from_ack(message, now).filter(|_| message.message_type() == Some(MessageType::Ack));
+        let phase = std::mem::replace(&mut self.phase, Phase::Init { next: now });
+        self.phase = match (phase, message.message_type(), granted) {
+            // The first offer is as good as any.
+            (Phase::Selecting { .. }, Some(MessageType::Offer), _) if message.server_identifier().is_some() => {
+                self.request(message, now)
+            },
+            (Phase::Requesting { .. }, _, Some(lease)) => Phase::Checking { lease, sent: 0, next: now },
+            (Phase::Renewing { .. } | Phase::Rebinding { .. }, _, Some(lease)) => self.bind(lease),
+            (Phase::Requesting { .. }, Some(MessageType::Nak), _) => Phase::Init { next: now },
+            (Phase::Renewing { lease, .. } | Phase::Rebinding { lease, .. }, Some(MessageType::Nak), _) => {
+                self.outbox.push(Output::Unbound(lease));
+                Phase::Init { next: now }
+            },
+            (phase, ..) => phase,
+        };
+    }
+
+    /// Declines the address being probed for if `packet` shows it is in use.
+    pub fn on_arp(&mut self, packet: &arp::Packet, now: Instant) {
+        if !self.conflicts(packet) {
+            return;
+        }
+
+        let Phase::Checking { lease, .. } = std::mem::replace(&mut self.phase, Phase::Init { next: now + DECLINE_WAIT }) else {
+            unreachable!("only a probed address conflicts");
+        };
+
+        let mut decline = Message::request(MessageType::Decline, self.xid, self.mac);
+        decline.add_option(DhcpOption::RequestedAddress(lease.ipv4_address()));
+        decline.add_option(DhcpOption::ServerIdentifier(lease.server));
+        decline.add_option(DhcpOption::Message(format!("{} is in use by {}", lease.ipv4_address(), packet.sha())));
+        self.outbox.push(Output::Broadcast(decline));
+    }
+
+    pub fn on_tick(&mut self, now: Instant) {
+        let phase = std::mem::replace(&mut self.phase, Phase::Init { next: now });
+        self.phase = match phase {
+            Phase::Init { next } if now >= next => self.discover(now),
+            Phase::Selecting { timeout, next } if now >= next => {
+                self.send_discover(now);
+                let timeout = (timeout * 2).min(MAX_TIMEOUT);
+                Phase::Selecting { timeout, next: now + timeout }
+            },
+            Phase::Requesting { sent, next, .. } if sent == MAX_REQUESTS && now >= next => self.discover(now),
+            Phase::Requesting { offer, sent, timeout, next } if now >= next => {
+                self.send_request(&offer, now);
+                let timeout = (timeout * 2).min(MAX_TIMEOUT);
+                Phase::Requesting { offer, sent: sent + 1, timeout, next: now + timeout }
+            },
+            Phase::Checking { lease, sent, next } if now >= next => {
+                if sent < PROBE_NUM {
+                    let probe = arp::Packet::request(self.mac.into(), Ipv4Address::UNSPECIFIED.into(), MacAddress::default().into(), lease.ipv4_address().into())
+                        .expect("sender and target addresses share a type");
+                    self.outbox.push(Output::Probe(probe));
+                    Phase::Checking { lease, sent: sent + 1, next: now + PROBE_INTERVAL }
+                } else {
+                    self.bind(lease)
+                }
+            },
+            Phase::Bound { lease } if lease.renews().is_some_and(|at| now >= at) => {
+                self.xid = self.next_xid(now);
+                self.started = now;
+                self.renew(lease, now)
+            },
+            Phase::Renewing { lease, .. } | Phase::Rebinding { lease, .. } if lease.expires().is_some_and(|at| now >= at) => {
+                self.outbox.push(Output::Unbound(lease));
+                self.discover(now)
+            },
+            Phase::Renewing { lease, .. } if lease.rebinds().is_some_and(|at| now >= at) => self.rebind(lease, now),
+            Phase::Renewing { lease, next } if now >= next => self.renew(lease, now),
+            Phase::Rebinding { lease, next } if now >= next => self.rebind(lease, now),
+            phase => phase,
+        };
+    }
+
+    fn next_xid(&self, now: Instant) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        self.secret.hash(&mut hasher);
+        now.hash(&mut hasher);
+        self.xid.hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    /// Starts a new transaction by broadcasting a DISCOVER.
+    fn discover(&mut self, now: Instant) -> Phase {
+        self.xid = self.next_xid(now);
+        self.started = now;
+        self.send_discover(now);
+        Phase::Selecting { timeout: INITIAL_TIMEOUT, next: now + INITIAL_TIMEOUT }
+    }
+
+    /// Asks for the address in `offer` by broadcasting a REQUEST, which also tells the other
+    /// servers that their offers were turned down.
+    fn request(&mut self, offer: &Message, now: Instant) -> Phase {
+        self.send_request(offer, now);
+        Phase::Requesting { offer: offer.clone(), sent: 1, timeout: INITIAL_TIMEOUT, next: now + INITIAL_TIMEOUT }
+    }
+
+    fn bind(&mut self, lease: Lease) -> Phase {
+        self.outbox.push(Output::Bound(lease.clone()));
+        Phase::Bound { lease }
+    }
+
+    /// Asks the server that granted the lease to extend it, and again at half the time left
+    /// until T2.
+    fn renew(&mut self, lease: Lease, now: Instant) -> Phase {
+        let message = self.message(MessageType::Request, Some(&lease), now);
+        self.outbox.push(Output::Unicast { destination: lease.server, message });
+        let next = now + Self::retry_interval(lease.rebinds(), now);
+        Phase::Renewing { lease, next }
+    }
+
+    /// Asks any server to extend the lease, and again at half the time left until it expires.
+    fn rebind(&mut self, lease: Lease, now: Instant) -> Phase {
+        self.outbox.push(Output::Broadcast(self.message(MessageType::Request, Some(&lease), now)));
+        let next = now + Self::retry_interval(lease.expires(), now);
+        Phase::Rebinding { lease, next }
+    }
+
+    fn retry_interval(until: Option<Instant>, now: Instant) -> Duration {
+        until.map_or(MAX_TIMEOUT, |until| (until.saturating_duration_since(now) / 2).max(MIN_RENEW_INTERVAL))
+    }
+
+    fn send_discover(&mut self, now: Instant) {
+        self.outbox.push(Output::Broadcast(self.message(MessageType::Discover, None, now)));
+    }
+
+    fn send_request(&mut self, offer: &Message, now: Instant) {
+        let mut request = self.message(MessageType::Request, None, now);
+        request.add_option(DhcpOption::RequestedAddress(offer.yiaddr()));
+        if let Some(server) = offer.server_identifier() {
+            request.add_option(DhcpOption::ServerIdentifier(server));
+        }
+        self.outbox.push(Output::Broadcast(request));
+    }
+
+    /// A message in the current transaction. One sent while `bound` to a lease carries its
+    /// address in `ciaddr` and names neither the address nor the server in options.
+    fn message(&self, message_type: MessageType, bound: Option<&Lease>, now: Instant) -> Message {
+        let mut message = Message::request(message_type, self.xid, self.mac);
+        message.set_secs(now.saturating_duration_since(self.started).as_secs().min(u16::MAX as u64) as u16);
+        if let Some(lease) = bound {
+            message.set_ciaddr(lease.ipv4_address());
+        }
+
+        let mut client_id = vec![1];
+        client_id.extend(<[u8; 6]>::from(self.mac));
+        message.add_option(DhcpOption::ClientIdentifier(client_id));
+        message.add_option(DhcpOption::ParameterRequestList(PARAMETERS.to_vec()));
+        message
+    }
+}
+
+#[test]
+fn test_dhcp_client() {
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let server = Ipv4Address::from([10, 0, 0, 1]);
+    let address = Ipv4Address::from([10, 0, 0, 50]);
+
+    let now = Instant::now();
+    let mut client = DhcpClient::new(mac, now);
+    let broadcast = |output: Vec<Output>| match output.as_slice() {
+        [Output::Broadcast(message)] => message.clone(),
+        output => panic!("expected one broadcast, got {output:?}"),
+    };
+    let reply = |request: &Message, message_type, lease_time| {
+        let mut reply = Message::reply(request, message_type);
+        reply.set_yiaddr(address);
+        reply.add_option(DhcpOption::ServerIdentifier(server));
+        reply.add_option(DhcpOption::SubnetMask(Ipv4Address::from([255, 255, 255, 0])));
+        reply.add_option(DhcpOption::Router(vec![server]));
+        reply.add_option(DhcpOption::LeaseTime(lease_time));
+        reply
+    };
+
+    // INIT -> SELECTING, retransmitting the DISCOVER after 4 seconds.
+    client.on_tick(now);
+    let discover = broadcast(client.take_output());
+    assert_eq!(discover.message_type(), Some(MessageType::Discover));
+    assert_eq!(client.state(), State::Selecting);
+    client.on_tick(now + Duration::from_secs(3));
+    assert!(client.take_output().is_empty());
+    client.on_tick(now + Duration::from_secs(4));
+    assert_eq!(broadcast(client.take_output()).secs(), 4);
+
+    // Replies to other transactions are ignored, and the offer is requested.
+    client.on_message(&reply(&Message::request(MessageType::Discover, discover.xid() ^ 1, mac), MessageType::Offer, 3600), now);
+    assert_eq!(client.state(), State::Selecting);
+    client.on_message(&reply(&discover, MessageType::Offer, 3600), now);
+    let request = broadcast(client.take_output());
+    assert_eq!(request.message_type(), Some(MessageType::Request));
+    assert_eq!(request.requested_address(), Some(address));
+    assert_eq!(request.server_identifier(), Some(server));
+    assert_eq!(request.xid(), discover.xid());
+
+    // The acknowledged address is probed for before it is used.
+    client.on_message(&reply(&request, MessageType::Ack, 3600), now);
+    assert_eq!(client.state(), State::Requesting);
+    client.on_tick(now);
+    let probe = match client.take_output().as_slice() {
+        [Output::Probe(probe)] => probe.clone(),
+        output => panic!("expected a probe, got {output:?}"),
+    };
+    assert_eq!(probe.spa(), Ipv4Address::UNSPECIFIED.into());
+    assert_eq!(probe.tpa(), address.into());
+    assert!(!client.conflicts(&probe));
+
+    client.on_tick(now + PROBE_INTERVAL);
+    client.on_tick(now + PROBE_INTERVAL * 2);
+    let lease = match client.take_output().as_slice() {
+        [Output::Probe(..), Output::Bound(lease)] => lease.clone(),
+        output => panic!("expected a probe and a lease, got {output:?}"),
+    };
+    assert_eq!(lease.address, "10.0.0.50/24".parse().unwrap());
+    assert_eq!(lease.routers, vec![server]);
+    assert_eq!(lease.renewal_time, Some(Duration::from_secs(1800)));
+    assert_eq!(lease.rebinding_time, Some(Duration::from_secs(3150)));
+    assert_eq!(client.state(), State::Bound);
+
+    // At T1 the lease is renewed with its server, and at T2 with any server.
+    let t1 = lease.renews().unwrap();
+    client.on_tick(t1);
+    let renew = match client.take_output().as_slice() {
+        [Output::Unicast { destination, message }] if *destination == server => message.clone(),
+        output => panic!("expected a unicast request, got {output:?}"),
+    };
+    assert_eq!(renew.ciaddr(), address);
+    assert_eq!(renew.requested_address(), None);
+    assert_eq!(client.state(), State::Renewing);
+
+    client.on_tick(lease.rebinds().unwrap());
+    let rebind = broadcast(client.take_output());
+    assert_eq!(rebind.ciaddr(), address);
+    assert_eq!(client.state(), State::Rebinding);
+
+    client.on_message(&reply(&rebind, MessageType::Ack, INFINITE), lease.rebinds().unwrap());
+    assert!(matches!(client.take_output().as_slice(), [Output::Bound(Lease { lease_time: None, .. })]));
+    client.on_tick(now + Duration::from_secs(1 << 40));
+    assert!(client.take_output().is_empty());
+
+    // A conflict found while probing declines the address, and a NAK withdraws a lease.
+    let mut client = DhcpClient::new(mac, now);
+    client.on_tick(now);
+    let discover = broadcast(client.take_output());
+    client.on_message(&reply(&discover, MessageType::Offer, 60), now);
+    let request = broadcast(client.take_output());
+    client.on_message(&reply(&request, MessageType::Ack, 60), now);
+    assert_eq!(client.probing(), Some(address));
+
+    let other = MacAddress::from([0x02, 0, 0, 0, 0, 9]);
+    let answer = arp::Packet::response(other.into(), address.into(), mac.into(), Ipv4Address::UNSPECIFIED.into()).unwrap();
+    assert!(client.conflicts(&answer));
+    client.on_arp(&answer, now);
+    let decline = broadcast(client.take_output());
+    assert_eq!(decline.message_type(), Some(MessageType::Decline));
+    assert_eq!(decline.requested_address(), Some(address));
+    assert_eq!(client.state(), State::Init);
+    client.on_tick(now + DECLINE_WAIT);
+    assert_eq!(client.state(), State::Selecting);
+
+    let discover = broadcast(client.take_output());
+    client.on_message(&reply(&discover, MessageType::Offer, 60), now);
+    let request = broadcast(client.take_output());
+    client.on_message(&reply(&request, MessageType::Nak, 60), now);
+    assert_eq!(client.state(), State::Init);
+    assert!(client.take_output().is_empty());
+
+    // The last REQUEST is waited for as long as the others before discovery starts over.
+    let mut client = DhcpClient::new(mac, now);
+    client.on_tick(now);
+    let discover = broadcast(client.take_output());
+    client.on_message(&reply(&discover, MessageType::Offer, 60), now);
+    broadcast(client.take_output());
+    for seconds in [4, 12, 28] {
+        client.on_tick(now + Duration::from_secs(seconds));
+        assert_eq!(broadcast(client.take_output()).message_type(), Some(MessageType::Request));
+    }
+    client.on_tick(now + Duration::from_secs(59));
+    assert_eq!(client.state(), State::Requesting);
+    client.on_tick(now + Duration::from_secs(60));
+    assert_eq!(broadcast(client.take_output()).message_type(), Some(MessageType::Discover));
+}
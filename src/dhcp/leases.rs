@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rosi::common::address::{Ipv4Address, MacAddress};
+
+use crate::interface;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingState {
+    /// Leased to the client until the binding expires.
+    Bound,
+    /// Reported in use by the client, and kept from everyone until the binding expires.
+    Declined,
+}
+
+/// What the server knows about one address it has handed out. Expired bindings are kept so
+/// that a client coming back can be given its old address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub address: Ipv4Address,
+    pub mac: MacAddress,
+    pub expires: SystemTime,
+    pub state: BindingState,
+}
+
+impl Binding {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires <= now
+    }
+}
+
+/// The bindings of a DHCP server by address, kept in a file across restarts if it has one.
+///
+/// The file has a line per binding, with its address, client, expiry in seconds since the
+/// Unix epoch and state:
+///
+/// ```text
+/// 10.0.0.100 52:54:00:12:34:56 1760000000 bound
+/// 10.0.0.101 52:54:00:ab:cd:ef 1760000000 declined
+/// ```
+#[derive(Debug, Default)]
+pub struct LeaseDatabase {
+    bindings: BTreeMap<u32, Binding>,
+    path: Option<PathBuf>,
+    /// Whether the bindings differ from those in the file.
+    changed: bool,
+}
+
+impl LeaseDatabase {
+    /// A database that is kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the bindings in `path`, which is written back by `save`. A missing file is an
+    /// empty database.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut database = Self { path: Some(path.to_owned()), ..Self::default() };
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(database),
+            Err(e) => return Err(e),
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let binding = Self::parse_line(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {e}", path.display(), i + 1)))?;
+            database.bindings.insert(binding.address.into(), binding);
+        }
+
+        Ok(database)
+    }
+
+    fn parse_line(line: &str) -> Result<Binding, String> {
+        let [address, mac, expires, state] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(format!("expected an address, a MAC address, an expiry and a state, found {line:?}"));
+        };
+
+        Ok(Binding {
+            address: interface::parse_ipv4_address(address)?,
+            mac: interface::parse_mac(mac)?,
+            expires: UNIX_EPOCH + Duration::from_secs(expires.parse().map_err(|_| format!("invalid expiry {expires}"))?),
+            state: match state {
+                "bound" => BindingState::Bound,
+                "declined" => BindingState::Declined,
+                state => return Err(format!("invalid state {state}, expected bound or declined")),
+            },
+        })
+    }
+
+    /// Writes the bindings to the file, if there is one and they have changed. The file is
+    /// replaced whole, so that it is never left half written.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(path) = self.path.as_ref().filter(|_| self.changed) else {
+            return Ok(());
+        };
+
+        let mut text = String::new();
+        for binding in self.bindings.values() {
+            let expires = binding.expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let state = match binding.state {
+                BindingState::Bound => "bound",
+                BindingState::Declined => "declined",
+            };
+            text += &format!("{} {} {expires} {state}\n", binding.address, binding.mac);
+        }
+
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, text)?;
+        std::fs::rename(&temporary, path)?;
+
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn get(&self, address: Ipv4Address) -> Option<&Binding> {
+        self.bindings.get(&address.into())
+    }
+
+    /// The address most recently bound to `mac`, whether or not its binding has expired.
+    pub fn find(&self, mac: MacAddress) -> Option<&Binding> {
+        self.bindings.values()
+            .filter(|binding| binding.mac == mac && binding.state == BindingState::Bound)
+            .max_by_key(|binding| binding.expires)
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        self.bindings.values()
+    }
+
+    /// Adds `binding`, replacing any for the same address.
+    pub fn insert(&mut self, binding: Binding) {
+        self.bindings.insert(binding.address.into(), binding);
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, address: Ipv4Address) -> Option<Binding> {
+        let binding = self.bindings.remove(&address.into());
+        self.changed |= binding.is_some();
+        binding
+    }
+}
+
+#[test]
+fn test_lease_database() {
+    let path = std::env::temp_dir().join(format!("rstack-test-{}.leases", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mac = MacAddress::from([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    let expires = UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+    let bound = Binding { address: Ipv4Address::from([10, 0, 0, 101]), mac, expires, state: BindingState::Bound };
+    let declined = Binding { address: Ipv4Address::from([10, 0, 0, 100]), mac, expires, state: BindingState::Declined };
+
+    // A new file starts out empty, and the bindings written to it are read back in address order.
+    let mut database = LeaseDatabase::load(&path).unwrap();
+    assert_eq!(database.bindings().count(), 0);
+    database.insert(bound.clone());
+    database.insert(declined.clone());
+    database.save().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "10.0.0.100 52:54:00:12:34:56 1760000000 declined\n10.0.0.101 52:54:00:12:34:56 1760000000 bound\n",
+    );
+
+    let database = LeaseDatabase::load(&path).unwrap();
+    assert_eq!(database.bindings().cloned().collect::<Vec<_>>(), [declined, bound.clone()]);
+    assert_eq!(database.find(mac), Some(&bound));
+    assert!(database.get(Ipv4Address::from([10, 0, 0, 102])).is_none());
+
+    std::fs::write(&path, "# leases\n10.0.0.100 52:54:00:12:34:56 soon bound\n").unwrap();
+    assert_eq!(LeaseDatabase::load(&path).unwrap_err().to_string(), format!("{}:2: invalid expiry soon", path.display()));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,26 @@
+mod client;
+mod leases;
+mod server;
+mod service;
+
+use rosi::common::{Layer, Serialise};
+use rosi::common::address::Ipv4Address;
+use rosi::protocols::dhcp::Message;
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+use rosi::protocols::udp::Datagram;
+
+pub use leases::LeaseDatabase;
+pub use server::DhcpServer;
+pub use service::{to_server, DhcpHandle, DhcpServerService, DhcpService};
+
+/// `message` in a UDP datagram between two addresses and ports, in an IPv4 packet.
+fn udp_packet(source: (Ipv4Address, u16), destination: (Ipv4Address, u16), message: &Message) -> Ipv4Packet {
+    let mut data = vec![0u8; message.byte_length()];
+    message.serialise(&mut data);
+
+    let mut datagram = Datagram::new(source.1, destination.1, data);
+    let mut packet = Ipv4Packet::new(source.0, destination.0, IpProtocol::Udp, vec![]);
+    datagram.fill_checksum(&packet.pseudo_header());
+    packet.wrap(&datagram);
+    packet
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use rosi::common::address::{Ipv4Address, MacAddress};
+use rosi::protocols::arp::ProtocolAddress;
+use rosi::protocols::dhcp::{DhcpOption, Message, MessageType, Op};
+
+use crate::config::DhcpServerConfig;
+use crate::interface::InterfaceAddress;
+
+use super::leases::{Binding, BindingState, LeaseDatabase};
+
+/// How long an offered address is kept for the client it was offered to.
+const OFFER_TIME: Duration = Duration::from_secs(60);
+
+/// How long an address a client declined is kept from everyone (RFC 2131, section 4.3.3).
+const DECLINE_HOLD: Duration = Duration::from_secs(3600);
+
+/// The server side of DHCP (RFC 2131), handing out addresses on the network of one interface.
+///
+/// Like the client, it is a state machine that sends nothing itself: `handle` takes a message
+/// from a client and returns the reply, if any. Clients are told apart by hardware address,
+/// which is also what reservations go by; client identifiers are not looked at.
+pub struct DhcpServer {
+    /// The address of the server on the network it serves, which identifies it to clients.
+    address: InterfaceAddress,
+    server: Ipv4Address,
+    config: DhcpServerConfig,
+    leases: LeaseDatabase,
+    /// Addresses offered to clients, and when the offers lapse.
+    offers: HashMap<MacAddress, (Ipv4Address, SystemTime)>,
+}
+
+impl DhcpServer {
+    /// Returns `None` if `address` is not an IPv4 address.
+    pub fn new(address: InterfaceAddress, config: DhcpServerConfig, leases: LeaseDatabase) -> Option<Self> {
+        let ProtocolAddress::Ipv4Address(server) = address.address() else {
+            return None;
+        };
+
+        Some(Self {
+            address,
+            server,
+            config,
+            leases,
+            offers: HashMap::new(),
+        })
+    }
+
+    pub fn server_address(&self) -> Ipv4Address {
+        self.server
+    }
+
+    #[allow(dead_code)]
+    pub fn leases(&self) -> &LeaseDatabase {
+        &self.leases
+    }
+
+    /// Writes the lease database to its file.
+    pub fn save(&mut self) -> io::Result<()> {
+        self.leases.save()
+    }
+
+    /// Answers a message from a client.
+    pub fn handle(&mut self, request: &Message, now: SystemTime) -> Option<Message> {
+        if request.op() != Op::BootRequest {
+            return None;
+        }
+        self.offers.retain(|_, (_, lapses)| *lapses > now);
+
+        match request.message_type()? {
+            MessageType::Discover => self.discover(request, now),
+            MessageType::Request => self.request(request, now),
+            MessageType::Decline => {
+                self.decline(request, now);
+                None
+            },
+            MessageType::Release => {
+                self.release(request, now);
+                None
+            },
+            MessageType::Inform => Some(self.inform(request)),
+            _ => None,
+        }
+    }
+
+    fn discover(&mut self, request: &Message, now: SystemTime) -> Option<Message> {
+        let mac = request.chaddr();
+        let address = self.pick(mac, request.requested_address(), now)?;
+        self.offers.insert(mac, (address, now + OFFER_TIME));
+
+        let mut offer = Message::reply(request, MessageType::Offer);
+        offer.set_yiaddr(address);
+        self.add_options(&mut offer, true);
+        Some(offer)
+    }
+
+    /// Chooses the address to offer `mac`: its reservation, the address already offered to
+    /// it or last bound to it, the one it asks for, a pool address never handed out, and
+    /// failing those the pool address whose binding expired longest ago.
+    fn pick(&self, mac: MacAddress, requested: Option<Ipv4Address>, now: SystemTime) -> Option<Ipv4Address> {
+        let reserved = self.config.reservations.iter().find(|(m, _)| *m == mac).map(|(_, address)| *address);
+        let offered = self.offers.get(&mac).map(|(address, _)| *address);
+        let previous = self.leases.find(mac).map(|binding| binding.address);
+        let requested = requested.filter(|address| self.in_pool(*address));
+
+        if let Some(address) = [reserved, offered, previous, requested].into_iter().flatten().find(|a| self.available(*a, mac, now)) {
+            return Some(address);
+        }
+
+        let (first, last) = self.config.pool;
+        (u32::from(first)..=u32::from(last))
+            .map(Ipv4Address::from)
+            .find(|address| self.leases.get(*address).is_none() && self.available(*address, mac, now))
+            .or_else(|| self.leases.bindings()
+                .filter(|binding| self.in_pool(binding.address) && self.available(binding.address, mac, now))
+                .min_by_key(|binding| binding.expires)
+                .map(|binding| binding.address))
+    }
+
+    fn in_pool(&self, address: Ipv4Address) -> bool {
+        let (first, last) = self.config.pool;
+        (u32::from(first)..=u32::from(last)).contains(&u32::from(address))
+    }
+
+    /// Whether `address` can be leased to `mac`: it is in the pool or reserved for `mac`, and
+    /// neither reserved for, offered to nor held by another client.
+    fn available(&self, address: Ipv4Address, mac: MacAddress, now: SystemTime) -> bool {
+        let reserved = self.config.reservations.iter().find(|(_, a)| *a == address).map(|(m, _)| *m);
+        if reserved.is_some_and(|m| m != mac) || !(reserved.is_some() || self.in_pool(address)) {
+            return false;
+        }
+        if address == self.server_address() || !self.address.contains(address.into()) {
+            return false;
+        }
+        if self.offers.iter().any(|(m, (a, _))| *a == address && *m != mac) {
+            return false;
+        }
+
+        match self.leases.get(address) {
+            Some(binding) => binding.is_expired(now) || (binding.mac == mac && binding.state == BindingState::Bound),
+            None => true,
+        }
+    }
+
+    fn request(&mut self, request: &Message, now: SystemTime) -> Option<Message> {
+        let mac = request.chaddr();
+
+        match (request.server_identifier(), request.requested_address()) {
+            // SELECTING, either our offer or another server's.
+            (Some(server), requested) => {
+                if server != self.server_address() {
+                    self.offers.remove(&mac);
+                    return None;
+                }
+                match requested {
+                    Some(address) if self.available(address, mac, now) => Some(self.ack(request, address, now)),
+                    _ => Some(self.nak(request, "address not available")),
+                }
+            },
+            // INIT-REBOOT, checking an address the client remembers.
+            (None, Some(address)) => {
+                if !self.address.contains(address.into()) {
+                    return Some(self.nak(request, "wrong network"));
+                }
+                match self.leases.get(address) {
+                    Some(binding) if binding.mac == mac && binding.state == BindingState::Bound => Some(if self.available(address, mac, now) {
+                        self.ack(request, address, now)
+                    } else {
+                        self.nak(request, "address not available")
+                    }),
+                    Some(binding) if !binding.is_expired(now) => Some(self.nak(request, "address not available")),
+                    // The lease may be another server's, which is left to answer.
+                    _ => None,
+                }
+            },
+            // RENEWING or REBINDING, extending a lease from its address.
+            (None, None) if !request.ciaddr().is_unspecified() => {
+                let address = request.ciaddr();
+                match self.leases.get(address) {
+                    Some(binding) if binding.mac != mac && !binding.is_expired(now) => Some(self.nak(request, "address not available")),
+                    _ if self.available(address, mac, now) => Some(self.ack(request, address, now)),
+                    _ if self.address.contains(address.into()) => Some(self.nak(request, "address not available")),
+                    _ => None,
+                }
+            },
+            (None, None) => None,
+        }
+    }
+
+    /// Binds `address` to the client for a lease time, in place of any other address it had.
+    fn ack(&mut self, request: &Message, address: Ipv4Address, now: SystemTime) -> Message {
+        let mac = request.chaddr();
+        self.offers.remove(&mac);
+        if let Some(previous) = self.leases.find(mac).map(|binding| binding.address).filter(|a| *a != address) {
+            self.leases.remove(previous);
+        }
+        self.leases.insert(Binding {
+            address,
+            mac,
+            expires: now + Duration::from_secs(self.config.lease_time as u64),
+            state: BindingState::Bound,
+        });
+
+        let mut ack = Message::reply(request, MessageType::Ack);
+        ack.set_ciaddr(request.ciaddr());
+        ack.set_yiaddr(address);
+        self.add_options(&mut ack, true);
+        ack
+    }
+
+    fn nak(&self, request: &Message, reason: &str) -> Message {
+        let mut nak = Message::reply(request, MessageType::Nak);
+        nak.add_option(DhcpOption::ServerIdentifier(self.server_address()));
+        nak.add_option(DhcpOption::Message(reason.into()));
+        nak
+    }
+
+    /// Keeps an address the client found in use from everyone for a while.
+    fn decline(&mut self, request: &Message, now: SystemTime) {
+        let (mac, Some(address)) = (request.chaddr(), request.requested_address()) else {
+            return;
+        };
+        if request.server_identifier().is_some_and(|server| server != self.server_address()) {
+            return;
+        }
+
+        let ours = self.leases.get(address).is_some_and(|binding| binding.mac == mac && binding.state == BindingState::Bound);
+        if ours {
+            self.leases.insert(Binding { address, mac, expires: now + DECLINE_HOLD, state: BindingState::Declined });
+        }
+    }
+
+    /// Ends the client's lease early. The binding is kept, so that it gets the address back.
+    fn release(&mut self, request: &Message, now: SystemTime) {
+        let (mac, address) = (request.chaddr(), request.ciaddr());
+        let binding = self.leases.get(address)
+            .filter(|binding| binding.mac == mac && binding.state == BindingState::Bound && !binding.is_expired(now))
+            .cloned();
+
+        if let Some(binding) = binding {
+            self.leases.insert(Binding { expires: now, ..binding });
+        }
+    }
+
+    /// Gives a client configured by hand everything but an address (RFC 2131, section 3.4).
+    fn inform(&self, request: &Message) -> Message {
+        let mut ack = Message::reply(request, MessageType::Ack);
+        ack.set_ciaddr(request.ciaddr());
+        self.add_options(&mut ack, false);
+        ack
+    }
+
+    /// Adds the server's identifier and the configuration of the network, along with the
+    /// lease times if the reply grants a lease.
+    fn add_options(&self, reply: &mut Message, lease: bool) {
+        let server = self.server_address();
+        reply.add_option(DhcpOption::ServerIdentifier(server));
+
+        if lease {
+            let lease_time = self.config.lease_time;
+            reply.add_option(DhcpOption::LeaseTime(lease_time));
+            reply.add_option(DhcpOption::RenewalTime(lease_time / 2));
+            reply.add_option(DhcpOption::RebindingTime((lease_time as u64 * 7 / 8) as u32));
+        }
+
+        let mask = u32::MAX.checked_shl(32 - self.address.prefix_length() as u32).unwrap_or(0);
+        reply.add_option(DhcpOption::SubnetMask(Ipv4Address::from(mask)));
+        reply.add_option(DhcpOption::Router(if self.config.routers.is_empty() { vec![server] } else { self.config.routers.clone() }));
+        if !self.config.dns_servers.is_empty() {
+            reply.add_option(DhcpOption::DomainNameServer(self.config.dns_servers.clone()));
+        }
+        if let Some(domain_name) = &self.config.domain_name {
+            reply.add_option(DhcpOption::DomainName(domain_name.clone()));
+        }
+    }
+}
+
+#[test]
+fn test_dhcp_server() {
+    let ip = |last: u8| Ipv4Address::from([10, 0, 0, last]);
+    let mac = |last: u8| MacAddress::from([0x52, 0x54, 0, 0, 0, last]);
+
+    let config = DhcpServerConfig {
+        reservations: vec![(mac(9), ip(50))],
+        dns_servers: vec![ip(1)],
+        ..DhcpServerConfig::new((ip(100), ip(101)))
+    };
+    let mut server = DhcpServer::new("10.0.0.2/24".parse().unwrap(), config, LeaseDatabase::new()).unwrap();
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+
+    let message = |message_type, client: u8, options: Vec<DhcpOption>| {
+        let mut message = Message::request(message_type, client as u32, mac(client));
+        options.into_iter().for_each(|option| message.add_option(option));
+        message
+    };
+    let selecting = |client: u8, address: Ipv4Address| {
+        message(MessageType::Request, client, vec![DhcpOption::ServerIdentifier(ip(2)), DhcpOption::RequestedAddress(address)])
+    };
+    let renewing = |client: u8, address: Ipv4Address| {
+        let mut request = message(MessageType::Request, client, vec![]);
+        request.set_ciaddr(address);
+        request
+    };
+
+    // The first client is offered the first pool address, with the network's configuration.
+    let offer = server.handle(&message(MessageType::Discover, 1, vec![]), now).unwrap();
+    assert_eq!(offer.message_type(), Some(MessageType::Offer));
+    assert_eq!((offer.yiaddr(), offer.server_identifier()), (ip(100), Some(ip(2))));
+    assert_eq!(offer.subnet_mask(), Some(Ipv4Address::from([255, 255, 255, 0])));
+    assert_eq!((offer.routers(), offer.dns_servers()), (&[ip(2)][..], &[ip(1)][..]));
+    assert_eq!((offer.lease_time(), offer.renewal_time(), offer.rebinding_time()), (Some(3600), Some(1800), Some(3150)));
+
+    // While it is offered, another client gets the next address, and asking for the offered
+    // one is refused.
+    assert_eq!(server.handle(&message(MessageType::Discover, 2, vec![]), now).unwrap().yiaddr(), ip(101));
+    let nak = server.handle(&selecting(2, ip(100)), now).unwrap();
+    assert_eq!(nak.message_type(), Some(MessageType::Nak));
+    assert!(nak.text().is_some());
+
+    let ack = server.handle(&selecting(1, ip(100)), now).unwrap();
+    assert_eq!((ack.message_type(), ack.yiaddr()), (Some(MessageType::Ack), ip(100)));
+    assert_eq!(server.leases().get(ip(100)).map(|binding| binding.mac), Some(mac(1)));
+
+    // A client choosing another server's offer gives ours up, and the pool is then exhausted.
+    let mut other = selecting(2, ip(101));
+    other.add_option(DhcpOption::ServerIdentifier(ip(3)));
+    assert!(server.handle(&other, now).is_none());
+    assert_eq!(server.handle(&message(MessageType::Discover, 2, vec![]), now).unwrap().yiaddr(), ip(101));
+    server.handle(&selecting(2, ip(101)), now).unwrap();
+    assert!(server.handle(&message(MessageType::Discover, 3, vec![]), now).is_none());
+
+    // Reserved addresses go to their client only, even outside the pool.
+    assert_eq!(server.handle(&message(MessageType::Discover, 9, vec![]), now).unwrap().yiaddr(), ip(50));
+    assert_eq!(server.handle(&selecting(9, ip(50)), now).unwrap().yiaddr(), ip(50));
+
+    // Renewing extends a lease, and INIT-REBOOT is answered for the addresses the server knows.
+    let later = now + Duration::from_secs(1800);
+    assert_eq!(server.handle(&renewing(1, ip(100)), later).unwrap().message_type(), Some(MessageType::Ack));
+    assert_eq!(server.leases().get(ip(100)).unwrap().expires, later + Duration::from_secs(3600));
+    assert_eq!(server.handle(&renewing(3, ip(100)), later).unwrap().message_type(), Some(MessageType::Nak));
+    let reboot = |client, address| message(MessageType::Request, client, vec![DhcpOption::RequestedAddress(address)]);
+    assert_eq!(server.handle(&reboot(1, ip(100)), later).unwrap().message_type(), Some(MessageType::Ack));
+    assert_eq!(server.handle(&reboot(3, ip(100)), later).unwrap().message_type(), Some(MessageType::Nak));
+    assert_eq!(server.handle(&reboot(3, Ipv4Address::from([192, 168, 0, 5])), later).unwrap().message_type(), Some(MessageType::Nak));
+    assert!(server.handle(&reboot(3, ip(99)), later).is_none());
+
+    // A declined address is kept from everyone for a while.
+    assert!(server.handle(&message(MessageType::Decline, 2, vec![DhcpOption::RequestedAddress(ip(101))]), later).is_none());
+    assert_eq!(server.leases().get(ip(101)).unwrap().state, BindingState::Declined);
+    assert!(server.handle(&message(MessageType::Discover, 2, vec![]), later).is_none());
+
+    // A released address is free for others, but its client is offered it first.
+    let mut release = message(MessageType::Release, 1, vec![DhcpOption::ServerIdentifier(ip(2))]);
+    release.set_ciaddr(ip(100));
+    assert!(server.handle(&release, later).is_none());
+    assert!(server.leases().get(ip(100)).unwrap().is_expired(later));
+    let free = later + DECLINE_HOLD;
+    assert_eq!(server.handle(&message(MessageType::Discover, 1, vec![]), free).unwrap().yiaddr(), ip(100));
+    assert_eq!(server.handle(&message(MessageType::Discover, 3, vec![]), free).unwrap().yiaddr(), ip(101));
+
+    // INFORM is answered with the configuration and no lease.
+    let mut inform = message(MessageType::Inform, 4, vec![]);
+    inform.set_ciaddr(ip(20));
+    let ack = server.handle(&inform, later).unwrap();
+    assert_eq!((ack.message_type(), ack.ciaddr(), ack.yiaddr()), (Some(MessageType::Ack), ip(20), Ipv4Address::UNSPECIFIED));
+    assert_eq!((ack.lease_time(), ack.routers()), (None, &[ip(2)][..]));
+}
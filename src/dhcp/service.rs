@@ -0,0 +1,516 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use rosi::common::{Layer, Serialise};
+use rosi::common::address::{Ipv4Address, MacAddress};
+use rosi::protocols::arp;
+use rosi::protocols::dhcp::{self, Message, MessageType};
+use rosi::protocols::ethernet::{EtherType, Frame};
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+use rosi::protocols::udp::Datagram;
+
+use crate::arp::Resolver;
+use crate::interface::Interface;
+use crate::netservice::{Action, ActionType, ByteSender, Channels, NetService, NetServiceError};
+use crate::route::{Origin, Route, RoutingTable};
+
+use super::client::{DhcpClient, Lease, Output};
+use super::server::DhcpServer;
+use super::udp_packet;
+
+/// How often `wait_for_lease` looks at the client.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A handle to the DHCP client of an interface, for the services that need to know about it.
+#[derive(Clone)]
+pub struct DhcpHandle {
+    client: Arc<Mutex<DhcpClient>>,
+}
+
+impl DhcpHandle {
+    /// Whether `frame` is for the client: a UDP datagram to the client port, or an ARP packet
+    /// showing that the address being checked is in use.
+    pub fn wants(&self, frame: &Frame) -> bool {
+        match frame.ethertype() {
+            EtherType::Ipv4 => Ipv4Packet::deserialise(frame.data()).ok()
+                .filter(|packet| packet.proto() == IpProtocol::Udp)
+                .and_then(|packet| Datagram::deserialise(packet.data()).ok())
+                .is_some_and(|datagram| datagram.destination_port() == dhcp::CLIENT_PORT),
+            EtherType::Arp => arp::Packet::deserialise(frame.data()).is_ok_and(|packet| self.client.lock().unwrap().conflicts(&packet)),
+            _ => false,
+        }
+    }
+
+    pub fn lease(&self) -> Option<Lease> {
+        self.client.lock().unwrap().lease().cloned()
+    }
+
+    /// Blocks until the client holds a lease, for at most `timeout`.
+    pub fn wait_for_lease(&self, timeout: Duration) -> Option<Lease> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(lease) = self.lease() {
+                return Some(lease);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Configures an interface from a DHCP server.
+///
+/// The service sits beside IPv4 above Ethernet, and is sent whole frames: the replies from
+/// servers, and the ARP packets that show a leased address is taken. Until it has an address
+/// the interface cannot send through IPv4, so DISCOVERs, REQUESTs and the like are framed
+/// here, broadcast from `0.0.0.0`. Only REQUESTs renewing a lease are routed, through the
+/// IPv4 service of the interface.
+///
+/// Once a lease is checked its address is added to the interface, with a route to its
+/// network and a default route through its first router, and both go again when the lease
+/// does.
+pub struct DhcpService {
+    interface: Arc<RwLock<Interface>>,
+    resolver: Resolver,
+    routes: Arc<RwLock<RoutingTable>>,
+    send_ipv4: ByteSender,
+    client: Arc<Mutex<DhcpClient>>,
+    /// The lease the interface is configured with.
+    lease: Option<Lease>,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl DhcpService {
+    pub fn new(interface: Arc<RwLock<Interface>>, resolver: Resolver, routes: Arc<RwLock<RoutingTable>>, send_ipv4: ByteSender) -> Self {
+        let mac = interface.read().unwrap().mac();
+
+        Self {
+            interface,
+            resolver,
+            routes,
+            send_ipv4,
+            client: Arc::new(Mutex::new(DhcpClient::new(mac, Instant::now()))),
+            lease: None,
+            channels: Channels::new(),
+            actions: vec![Action::new(ActionType::Process, |_, _| true, false)],
+        }
+    }
+
+    pub fn handle(&self) -> DhcpHandle {
+        DhcpHandle { client: self.client.clone() }
+    }
+
+    fn mac(&self) -> MacAddress {
+        self.interface.read().unwrap().mac()
+    }
+
+    fn flush(&mut self, client: &mut DhcpClient) -> Result<(), NetServiceError> {
+        for output in client.take_output() {
+            match output {
+                Output::Broadcast(message) => {
+                    let packet = udp_packet((message.ciaddr(), dhcp::CLIENT_PORT), (Ipv4Address::BROADCAST, dhcp::SERVER_PORT), &message);
+                    self.send_frame(EtherType::Ipv4, &packet)?;
+                },
+                Output::Unicast { destination, message } => {
+                    let packet = udp_packet((message.ciaddr(), dhcp::CLIENT_PORT), (destination, dhcp::SERVER_PORT), &message);
+                    let mut bytes = vec![0u8; packet.byte_length()];
+                    packet.serialise(&mut bytes);
+                    self.send_ipv4.send(Arc::from(bytes))?;
+                },
+                Output::Probe(packet) => self.send_frame(EtherType::Arp, &packet)?,
+                Output::Bound(lease) => self.bind(lease)?,
+                Output::Unbound(lease) => self.unbind(&lease),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_frame(&self, ethertype: EtherType, data: &dyn Serialise) -> Result<(), NetServiceError> {
+        let mut frame = Frame::new(MacAddress::BROADCAST, self.mac(), ethertype, vec![]);
+        frame.wrap(data);
+
+        let mut bytes = vec![0u8; frame.byte_length()];
+        frame.serialise(&mut bytes);
+        self.send_down(Arc::from(bytes))
+    }
+
+    /// Configures the interface with `lease`, and announces its address if it is a new one.
+    fn bind(&mut self, lease: Lease) -> Result<(), NetServiceError> {
+        let renewed = match self.lease.take() {
+            Some(old) if old.address != lease.address => {
+                self.unbind(&old);
+                false
+            },
+            old => old.is_some(),
+        };
+
+        let name = {
+            let mut interface = self.interface.write().unwrap();
+            interface.add_address(lease.address);
+            interface.name().to_owned()
+        };
+
+        {
+            let mut routes = self.routes.write().unwrap();
+            routes.retain(|route| !(route.interface == name && route.origin == Origin::Dhcp));
+            routes.add(Route {
+                destination: lease.address.network(),
+                gateway: None,
+                interface: name.clone(),
+                metric: 0,
+                origin: Origin::Dhcp,
+            });
+            if let Some(router) = lease.routers.first() {
+                routes.add(Route {
+                    destination: "0.0.0.0/0".parse().unwrap(),
+                    gateway: Some((*router).into()),
+                    interface: name.clone(),
+                    metric: 0,
+                    origin: Origin::Dhcp,
+                });
+            }
+        }
+
+        if renewed {
+            eprintln!("dhcp: {name} renewed {lease}");
+        } else {
+            eprintln!("dhcp: {name} bound to {lease}");
+            self.resolver.announce(lease.address.address()).into_iter().try_for_each(|frame| self.send_down(frame))?;
+        }
+
+        self.lease = Some(lease);
+        Ok(())
+    }
+
+    /// Takes the address of `lease` off the interface, along with the routes that came with it.
+    fn unbind(&mut self, lease: &Lease) {
+        let address = lease.address.address();
+        let name = {
+            let mut interface = self.interface.write().unwrap();
+            interface.remove_address(address);
+            interface.name().to_owned()
+        };
+
+        // The connected route may have been added for the address before the lease was known.
+        let network = lease.address.network();
+        self.routes.write().unwrap().retain(|route| {
+            route.interface != name || !(route.origin == Origin::Dhcp || (route.destination == network && route.gateway.is_none()))
+        });
+        self.resolver.release(address);
+
+        if self.lease.as_ref().is_some_and(|l| l.address == lease.address) {
+            self.lease = None;
+        }
+        eprintln!("dhcp: {name} lost {}", lease.address);
+    }
+}
+
+impl NetService for DhcpService {
+    type Pdu = Frame;
+
+    fn name(&self) -> &'static str {
+        "dhcp"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(_: &Self::Pdu) -> Option<Arc<[u8]>> {
+        None
+    }
+
+    fn process_pdu(&mut self, frame: Self::Pdu) -> Result<(), NetServiceError> {
+        let client = self.client.clone();
+        let mut client = client.lock().unwrap();
+        let now = Instant::now();
+
+        match frame.ethertype() {
+            EtherType::Arp => client.on_arp(&arp::Packet::deserialise(frame.data())?, now),
+            EtherType::Ipv4 => {
+                let packet = Ipv4Packet::deserialise(frame.data())?;
+                let datagram = Datagram::deserialise(packet.data())?;
+                datagram.verify_checksum(&packet.pseudo_header())?;
+                client.on_message(&Message::deserialise(datagram.data())?, now);
+            },
+            _ => (),
+        }
+
+        self.flush(&mut client)
+    }
+
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        let client = self.client.clone();
+        let mut client = client.lock().unwrap();
+        client.on_tick(now);
+        self.flush(&mut client)
+    }
+}
+
+/// Whether `frame` is for a DHCP server: a UDP datagram to the server port.
+pub fn to_server(frame: &Frame) -> bool {
+    frame.ethertype() == EtherType::Ipv4 && Ipv4Packet::deserialise(frame.data()).ok()
+        .filter(|packet| packet.proto() == IpProtocol::Udp)
+        .and_then(|packet| Datagram::deserialise(packet.data()).ok())
+        .is_some_and(|datagram| datagram.destination_port() == dhcp::SERVER_PORT)
+}
+
+/// Runs a DHCP server on an interface.
+///
+/// The service sits beside IPv4 above Ethernet, and is sent the whole frames `to_server`
+/// picks out, as clients without an address cannot be reached through IPv4. Replies go out
+/// as RFC 2131, section 4.1 has it: to the relay agent or to the client's address through
+/// the IPv4 service, and otherwise framed here, broadcast when the client asks for it or is
+/// refused, or sent to its hardware address. The leases are saved after every message.
+pub struct DhcpServerService {
+    interface: Arc<RwLock<Interface>>,
+    send_ipv4: ByteSender,
+    server: DhcpServer,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl DhcpServerService {
+    pub fn new(interface: Arc<RwLock<Interface>>, server: DhcpServer, send_ipv4: ByteSender) -> Self {
+        Self {
+            interface,
+            send_ipv4,
+            server,
+            channels: Channels::new(),
+            actions: vec![Action::new(ActionType::Process, |_, _| true, false)],
+        }
+    }
+
+    fn reply(&self, reply: &Message) -> Result<(), NetServiceError> {
+        let server = self.server.server_address();
+
+        let (destination, port, mac) = if !reply.giaddr().is_unspecified() {
+            (reply.giaddr(), dhcp::SERVER_PORT, None)
+        } else if reply.message_type() == Some(MessageType::Nak) || reply.broadcast() {
+            (Ipv4Address::BROADCAST, dhcp::CLIENT_PORT, Some(MacAddress::BROADCAST))
+        } else if !reply.ciaddr().is_unspecified() {
+            (reply.ciaddr(), dhcp::CLIENT_PORT, None)
+        } else {
+            (reply.yiaddr(), dhcp::CLIENT_PORT, Some(reply.chaddr()))
+        };
+        let packet = udp_packet((server, dhcp::SERVER_PORT), (destination, port), reply);
+
+        match mac {
+            Some(mac) => {
+                let mut frame = Frame::new(mac, self.interface.read().unwrap().mac(), EtherType::Ipv4, vec![]);
+                frame.wrap(&packet);
+
+                let mut bytes = vec![0u8; frame.byte_length()];
+                frame.serialise(&mut bytes);
+                self.send_down(Arc::from(bytes))
+            },
+            None => {
+                let mut bytes = vec![0u8; packet.byte_length()];
+                packet.serialise(&mut bytes);
+                Ok(self.send_ipv4.send(Arc::from(bytes))?)
+            },
+        }
+    }
+}
+
+impl NetService for DhcpServerService {
+    type Pdu = Frame;
+
+    fn name(&self) -> &'static str {
+        "dhcp-server"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(_: &Self::Pdu) -> Option<Arc<[u8]>> {
+        None
+    }
+
+    fn process_pdu(&mut self, frame: Self::Pdu) -> Result<(), NetServiceError> {
+        let packet = Ipv4Packet::deserialise(frame.data())?;
+        let datagram = Datagram::deserialise(packet.data())?;
+        datagram.verify_checksum(&packet.pseudo_header())?;
+
+        let request = Message::deserialise(datagram.data())?;
+        let reply = self.server.handle(&request, SystemTime::now());
+        if let Err(e) = self.server.save() {
+            eprintln!("dhcp-server: cannot save the leases: {e}");
+        }
+
+        let Some(reply) = reply else {
+            return Ok(());
+        };
+        if reply.message_type() == Some(MessageType::Ack) && !reply.yiaddr().is_unspecified() {
+            eprintln!("dhcp-server: leased {} to {}", reply.yiaddr(), reply.chaddr());
+        }
+        self.reply(&reply)
+    }
+}
+
+#[test]
+fn test_dhcp_service() {
+    use std::sync::mpsc;
+
+    use rosi::protocols::dhcp::{DhcpOption, MessageType};
+
+    use crate::arp::ArpService;
+    use crate::interface::DEFAULT_MTU;
+
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let interface = Arc::new(RwLock::new(Interface::new("tap0", mac, DEFAULT_MTU)));
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    let (send_ipv4, _) = mpsc::channel();
+    let mut service = DhcpService::new(interface.clone(), ArpService::new(interface.clone(), routes.clone()).resolver(), routes.clone(), send_ipv4);
+
+    let (send_down, receive_down) = mpsc::channel();
+    service.set_send_down(send_down);
+
+    let sent = || {
+        let frame = Frame::deserialise(&receive_down.try_recv().unwrap()).unwrap();
+        assert_eq!(frame.destination(), MacAddress::BROADCAST);
+        frame
+    };
+    let message = |frame: &Frame| {
+        let packet = Ipv4Packet::deserialise(frame.data()).unwrap();
+        assert_eq!((packet.source(), packet.destination()), (Ipv4Address::UNSPECIFIED, Ipv4Address::BROADCAST));
+        let datagram = Datagram::deserialise(packet.data()).unwrap();
+        assert!(datagram.verify_checksum(&packet.pseudo_header()).is_ok());
+        Message::deserialise(datagram.data()).unwrap()
+    };
+    let server = Ipv4Address::from([10, 0, 0, 1]);
+    let reply = |request: &Message, message_type| {
+        let mut reply = Message::reply(request, message_type);
+        reply.set_yiaddr(Ipv4Address::from([10, 0, 0, 50]));
+        reply.add_option(DhcpOption::ServerIdentifier(server));
+        reply.add_option(DhcpOption::SubnetMask(Ipv4Address::from([255, 255, 255, 0])));
+        reply.add_option(DhcpOption::Router(vec![server]));
+        reply.add_option(DhcpOption::LeaseTime(3600));
+
+        let packet = udp_packet((server, dhcp::SERVER_PORT), (Ipv4Address::BROADCAST, dhcp::CLIENT_PORT), &reply);
+        let mut frame = Frame::new(MacAddress::BROADCAST, MacAddress::from([0x02, 0, 0, 0, 0, 2]), EtherType::Ipv4, vec![]);
+        frame.wrap(&packet);
+        frame
+    };
+
+    // The replies from the server are wanted, and lead to an ARP probe.
+    let now = Instant::now();
+    service.on_tick(now).unwrap();
+    let discover = message(&sent());
+    assert_eq!(discover.message_type(), Some(MessageType::Discover));
+
+    let handle = service.handle();
+    let offer = reply(&discover, MessageType::Offer);
+    assert!(handle.wants(&offer));
+    service.process_pdu(offer).unwrap();
+    let request = message(&sent());
+    service.process_pdu(reply(&request, MessageType::Ack)).unwrap();
+
+    let now = Instant::now();
+    service.on_tick(now).unwrap();
+    let probe = arp::Packet::deserialise(sent().data()).unwrap();
+    assert_eq!(probe.tpa(), Ipv4Address::from([10, 0, 0, 50]).into());
+    assert!(handle.lease().is_none());
+
+    // Once checked, the address is configured and announced.
+    service.on_tick(now + Duration::from_secs(1)).unwrap();
+    service.on_tick(now + Duration::from_secs(2)).unwrap();
+    sent();
+    assert!(handle.lease().is_some());
+    assert!(interface.read().unwrap().owns(Ipv4Address::from([10, 0, 0, 50])));
+    assert_eq!(routes.read().unwrap().to_string(), "10.0.0.0/24 dev tap0 proto dhcp\ndefault via 10.0.0.1 dev tap0 proto dhcp\n");
+    let announcement = arp::Packet::deserialise(sent().data()).unwrap();
+    assert_eq!(announcement.spa(), announcement.tpa());
+
+    // When the lease goes, so do the address and its routes.
+    let lease = handle.lease().unwrap();
+    service.unbind(&lease);
+    assert!(!interface.read().unwrap().owns(Ipv4Address::from([10, 0, 0, 50])));
+    assert!(routes.read().unwrap().lookup(server).is_none());
+}
+
+#[test]
+fn test_dhcp_server_service() {
+    use std::sync::mpsc;
+
+    use crate::config::DhcpServerConfig;
+    use crate::interface::DEFAULT_MTU;
+
+    use super::leases::LeaseDatabase;
+
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let interface = Arc::new(RwLock::new(Interface::new("tap0", mac, DEFAULT_MTU)));
+    let pool = (Ipv4Address::from([10, 0, 0, 100]), Ipv4Address::from([10, 0, 0, 199]));
+    let server = DhcpServer::new("10.0.0.2/24".parse().unwrap(), DhcpServerConfig::new(pool), LeaseDatabase::new()).unwrap();
+    let (send_ipv4, receive_ipv4) = mpsc::channel();
+    let mut service = DhcpServerService::new(interface, server, send_ipv4);
+
+    let (send_down, receive_down) = mpsc::channel();
+    service.set_send_down(send_down);
+
+    let client = MacAddress::from([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+    let request = |message: &Message| {
+        let packet = udp_packet((message.ciaddr(), dhcp::CLIENT_PORT), (Ipv4Address::BROADCAST, dhcp::SERVER_PORT), message);
+        let mut frame = Frame::new(MacAddress::BROADCAST, client, EtherType::Ipv4, vec![]);
+        frame.wrap(&packet);
+        frame
+    };
+    let sent = || {
+        let frame = Frame::deserialise(&receive_down.try_recv().unwrap()).unwrap();
+        let packet = Ipv4Packet::deserialise(frame.data()).unwrap();
+        let datagram = Datagram::deserialise(packet.data()).unwrap();
+        assert_eq!((packet.source(), datagram.destination_port()), (Ipv4Address::from([10, 0, 0, 2]), dhcp::CLIENT_PORT));
+        (frame.destination(), packet.destination(), Message::deserialise(datagram.data()).unwrap())
+    };
+
+    // An offer goes to the client's hardware address and the offered address, unless the
+    // client asks for it to be broadcast.
+    let mut discover = Message::request(MessageType::Discover, 1, client);
+    let frame = request(&discover);
+    assert!(to_server(&frame));
+    service.process_pdu(frame).unwrap();
+    let (mac, destination, offer) = sent();
+    assert_eq!((mac, destination, offer.yiaddr()), (client, pool.0, pool.0));
+
+    discover.set_broadcast(true);
+    service.process_pdu(request(&discover)).unwrap();
+    assert_eq!(sent().0, MacAddress::BROADCAST);
+
+    // A client with an address is answered through IPv4.
+    let mut inform = Message::request(MessageType::Inform, 2, client);
+    inform.set_ciaddr(Ipv4Address::from([10, 0, 0, 20]));
+    service.process_pdu(request(&inform)).unwrap();
+    let packet = Ipv4Packet::deserialise(&receive_ipv4.try_recv().unwrap()).unwrap();
+    assert_eq!(packet.destination(), Ipv4Address::from([10, 0, 0, 20]));
+    assert!(receive_down.try_recv().is_err());
+}
@@ -1,45 +1,72 @@
-use rosi::common::Serialise;
-use rosi::protocols::ethernet;
+use std::sync::Arc;
 
-use crate::netservice::ByteSender;
+use rosi::common::Wrapper;
+use rosi::common::address::MacAddress;
+use rosi::protocols::ethernet;
 
-use super::netservice::{
-    Action, ByteReceiver,
-    FilterAction,
-    NetService
-};
+use super::arp::ArpService;
+use super::ipv4::Ipv4Service;
+use super::ipv6::Ipv6Service;
+use super::netservice::{Action, Channels, NetService, NetServiceError, Stack};
 
+/// Ethernet II framing for one interface.
+///
+/// Upper layers address their own frames, since only they know how their next hop is
+/// resolved, so frames sent down to this service are passed on unchanged.
 pub struct EthernetService {
-    actions: Vec<Action<Self, ethernet::Frame>>,
-    send_to_receiver: ByteSender,
-    receiver: ByteReceiver,
-    sender: ByteSender,
+    mac: MacAddress,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl EthernetService {
+    pub fn new(mac: MacAddress) -> Self {
+        Self {
+            mac,
+            channels: Channels::new(),
+            actions: vec![],
+        }
+    }
+
+    /// Whether `frame` is addressed to us, to the broadcast address, or to a multicast group.
+    pub fn accepts(&self, frame: &ethernet::Frame) -> bool {
+        let destination = frame.destination();
+        destination == self.mac || <[u8; 6]>::from(destination)[0] & 1 == 1
+    }
 }
 
 impl NetService for EthernetService {
     type Pdu = ethernet::Frame;
 
-    fn actions(&self) -> &[Action<Self, Self::Pdu>] {
-        &self.actions
+    fn name(&self) -> &'static str {
+        "ethernet"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
     }
 
-    fn add_action(&mut self, filter: crate::netservice::PduFilter<Self, Self::Pdu>, action: FilterAction) {
-        self.actions.push((filter, action))
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
     }
 
-    fn recv(&self) -> Result<Self::Pdu, rosi::common::DeserialiseError> {
-        Self::Pdu::deserialise(&self.receiver.recv().unwrap())
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
     }
 
-    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), ()> {
-        unreachable!()
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
     }
 
-    fn get_send_up(&self) -> crate::netservice::ByteSender {
-        self.send_to_receiver.clone()
+    fn unwrap_data(pdu: &Self::Pdu) -> Option<Arc<[u8]>> {
+        Some(pdu.unwrap_data())
     }
 
-    fn set_send_down(&mut self, sender: crate::netservice::ByteSender) {
-        self.sender = sender
+    fn process_pdu(&mut self, _: Self::Pdu) -> Result<(), NetServiceError> {
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Stack<ArpService> for EthernetService {}
+impl Stack<Ipv4Service> for EthernetService {}
+impl Stack<Ipv6Service> for EthernetService {}
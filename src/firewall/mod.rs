@@ -0,0 +1,286 @@
+mod rule;
+
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rosi::common::Pdu;
+use rosi::protocols::ethernet::Frame;
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+use rosi::protocols::ipv6::{ExtensionHeader, Ipv6Packet};
+
+use crate::netservice::{Action, ActionType, NetService};
+
+pub use rule::{Fields, Rule, Verdict};
+
+/// A PDU that rules can be evaluated against.
+pub trait Inspect {
+    fn inspect(&self) -> Fields;
+}
+
+impl Inspect for Frame {
+    fn inspect(&self) -> Fields {
+        Fields {
+            source_mac: Some(self.source()),
+            destination_mac: Some(self.destination()),
+            ethertype: Some(self.ethertype()),
+            vlan: self.vlan_id(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Inspect for Ipv4Packet {
+    fn inspect(&self) -> Fields {
+        let first_fragment = self.header().fragment_offset() == 0;
+
+        Fields {
+            source: Some(self.source().into()),
+            destination: Some(self.destination().into()),
+            proto: Some(self.proto()),
+            ..ports(self.proto(), self.data(), first_fragment)
+        }
+    }
+}
+
+impl Inspect for Ipv6Packet {
+    fn inspect(&self) -> Fields {
+        let first_fragment = !matches!(self.fragment(), Some(ExtensionHeader::Fragment { fragment_offset, .. }) if *fragment_offset != 0);
+
+        Fields {
+            source: Some(self.source().into()),
+            destination: Some(self.destination().into()),
+            proto: Some(self.proto()),
+            ..ports(self.proto(), self.data(), first_fragment)
+        }
+    }
+}
+
+/// The ports of a TCP or UDP header, which only the first fragment of a datagram carries.
+fn ports(proto: IpProtocol, data: &[u8], first_fragment: bool) -> Fields {
+    if !matches!(proto, IpProtocol::Tcp | IpProtocol::Udp) || !first_fragment || data.len() < 4 {
+        return Fields::default();
+    }
+
+    Fields {
+        source_port: Some(u16::from_be_bytes([data[0], data[1]])),
+        destination_port: Some(u16::from_be_bytes([data[2], data[3]])),
+        ..Default::default()
+    }
+}
+
+/// The rules for one layer, tried in order until one decides what happens to a PDU.
+#[derive(Debug)]
+pub struct Chain {
+    name: &'static str,
+    policy: Verdict,
+    rules: Vec<Rule>,
+}
+
+impl Chain {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            policy: Verdict::Accept,
+            rules: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[allow(dead_code)]
+    pub fn policy(&self) -> Verdict {
+        self.policy
+    }
+
+    #[allow(dead_code)]
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Sets the verdict for PDUs that no rule decides on. It cannot be `Log`.
+    pub fn set_policy(&mut self, policy: Verdict) -> Result<(), String> {
+        if policy == Verdict::Log {
+            return Err("the policy must be accept, drop or reject".into());
+        }
+
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// The verdict of the first matching rule that is not `Log`, or the policy.
+    pub fn evaluate<P: Inspect + Pdu>(&self, pdu: &P) -> Verdict {
+        let fields = pdu.inspect();
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(&fields)) {
+            match rule.verdict() {
+                Verdict::Log => pdu.log(&format!("{} LOG", self.name)),
+                verdict => return verdict,
+            }
+        }
+
+        self.policy
+    }
+
+    /// An action that applies the chain before a service's other actions see the PDU.
+    /// Accepted PDUs are left to the actions after it.
+    pub fn action<S>(chain: Arc<Self>) -> Action<S>
+    where
+        S: NetService,
+        S::Pdu: Inspect,
+    {
+        let classify = move |_: &S, pdu: &S::Pdu| match chain.evaluate(pdu) {
+            Verdict::Accept | Verdict::Log => None,
+            Verdict::Drop => Some(ActionType::Drop),
+            Verdict::Reject => Some(ActionType::Reject),
+        };
+
+        Action::classifier(classify, false)
+    }
+}
+
+impl core::fmt::Display for Chain {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "chain {} (policy {})", self.name, self.policy)?;
+        self.rules.iter().try_for_each(|rule| writeln!(f, "{:>10} {rule}", rule.hits()))
+    }
+}
+
+/// A chain for each layer rstack filters at.
+#[derive(Debug, Clone)]
+pub struct Firewall {
+    ethernet: Arc<Chain>,
+    ipv4: Arc<Chain>,
+    ipv6: Arc<Chain>,
+}
+
+impl Firewall {
+    /// A firewall that accepts everything.
+    pub fn new() -> Self {
+        Self {
+            ethernet: Arc::new(Chain::new("ethernet")),
+            ipv4: Arc::new(Chain::new("ipv4")),
+            ipv6: Arc::new(Chain::new("ipv6")),
+        }
+    }
+
+    /// Reads the rules from a file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        std::fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{e}", path.display())))
+    }
+
+    pub fn ethernet(&self) -> Arc<Chain> {
+        self.ethernet.clone()
+    }
+
+    pub fn ipv4(&self) -> Arc<Chain> {
+        self.ipv4.clone()
+    }
+
+    pub fn ipv6(&self) -> Arc<Chain> {
+        self.ipv6.clone()
+    }
+}
+
+impl FromStr for Firewall {
+    type Err = String;
+
+    /// Parses one rule per line, each prefixed by the chain it belongs to:
+    ///
+    /// ```text
+    /// # Only let in SSH from the local network
+    /// ipv4 accept src 10.0.0.0/24 proto tcp dst-port 22
+    /// ipv4 reject proto tcp dst-port 22
+    /// ethernet policy accept
+    /// ```
+    ///
+    /// Errors are prefixed by the line number they were found on.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chains = [Chain::new("ethernet"), Chain::new("ipv4"), Chain::new("ipv6")];
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let parse = |chains: &mut [Chain]| -> Result<(), String> {
+                let (name, rule) = line.split_once(char::is_whitespace).ok_or("missing rule")?;
+                let chain = chains.iter_mut().find(|chain| chain.name() == name).ok_or_else(|| format!("unknown chain {name}"))?;
+
+                match rule.trim().strip_prefix("policy ") {
+                    Some(policy) => chain.set_policy(policy.trim().parse()?)?,
+                    None => chain.push(rule.parse()?),
+                }
+
+                Ok(())
+            };
+
+            parse(&mut chains).map_err(|e| format!("{}: {e}", number + 1))?;
+        }
+
+        let [ethernet, ipv4, ipv6] = chains.map(Arc::new);
+        Ok(Self { ethernet, ipv4, ipv6 })
+    }
+}
+
+impl core::fmt::Display for Firewall {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}{}{}", self.ethernet, self.ipv4, self.ipv6)
+    }
+}
+
+#[test]
+fn test_firewall_chains() {
+    use rosi::common::address::Ipv4Address;
+    use rosi::common::log::{self as logging, Format, Logger, Memory};
+
+    // Other tests log through the same global logger, so only the LOG lines are checked.
+    let memory = Memory::new();
+    logging::set_logger(Logger::new(memory.clone(), Format::Text));
+
+    let firewall: Firewall = "
+        # Comments and blank lines are skipped
+
+        ipv4 log proto tcp
+        ipv4 accept src 10.0.0.0/24 proto tcp dst-port 22
+        ipv4 reject proto tcp dst-port 22
+        ipv4 policy drop  # Nothing else gets in
+    ".parse().unwrap();
+
+    let chain = firewall.ipv4();
+    assert_eq!(chain.policy(), Verdict::Drop);
+    assert_eq!(firewall.ethernet().policy(), Verdict::Accept);
+
+    let ssh = |source: [u8; 4]| {
+        let mut segment = vec![0u8; 20];
+        segment[..4].copy_from_slice(&[0xc0, 0x00, 0x00, 0x16]);
+        Ipv4Packet::new(Ipv4Address::from(source), Ipv4Address::from([10, 0, 0, 2]), IpProtocol::Tcp, segment)
+    };
+
+    assert_eq!(chain.evaluate(&ssh([10, 0, 0, 1])), Verdict::Accept);
+    assert_eq!(chain.evaluate(&ssh([192, 168, 0, 1])), Verdict::Reject);
+    assert_eq!(chain.evaluate(&Ipv4Packet::new(Ipv4Address::from([10, 0, 0, 1]), Ipv4Address::from([10, 0, 0, 2]), IpProtocol::Udp, vec![0; 8])), Verdict::Drop);
+
+    let hits = chain.rules().iter().map(Rule::hits).collect::<Vec<_>>();
+    assert_eq!(hits, vec![2, 1, 1]);
+
+    let logged = memory.lines().into_iter().filter(|line| line.starts_with("ipv4 LOG")).collect::<Vec<_>>();
+    assert_eq!(logged, vec![
+        "ipv4 LOG IP 10.0.0.1 > 10.0.0.2: Tcp ttl 64 id 0 length 40",
+        "ipv4 LOG IP 192.168.0.1 > 10.0.0.2: Tcp ttl 64 id 0 length 40",
+    ]);
+
+    assert_eq!("ipv4 drop proto tcp\nipv5 drop".parse::<Firewall>().unwrap_err(), "2: unknown chain ipv5");
+    assert!("ethernet policy log".parse::<Firewall>().is_err());
+}
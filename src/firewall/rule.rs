@@ -0,0 +1,325 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rosi::common::address::MacAddress;
+use rosi::protocols::arp::ProtocolAddress;
+use rosi::protocols::ethernet::EtherType;
+use rosi::protocols::ipv4::IpProtocol;
+
+use crate::interface::{parse_mac, InterfaceAddress};
+
+/// What happens to a PDU that a rule matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    /// Drops the PDU and tells the sender it was refused.
+    Reject,
+    /// Logs the PDU and carries on with the next rule.
+    Log,
+}
+
+impl FromStr for Verdict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(Self::Accept),
+            "drop" => Ok(Self::Drop),
+            "reject" => Ok(Self::Reject),
+            "log" => Ok(Self::Log),
+            _ => Err(format!("unknown verdict {s}")),
+        }
+    }
+}
+
+impl core::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Accept => write!(f, "accept"),
+            Self::Drop => write!(f, "drop"),
+            Self::Reject => write!(f, "reject"),
+            Self::Log => write!(f, "log"),
+        }
+    }
+}
+
+/// The parts of a PDU that rules can match on. Fields that a layer does not see are `None`,
+/// and never match.
+#[derive(Debug, Clone, Default)]
+pub struct Fields {
+    pub source_mac: Option<MacAddress>,
+    pub destination_mac: Option<MacAddress>,
+    pub ethertype: Option<EtherType>,
+    pub vlan: Option<u16>,
+    pub source: Option<ProtocolAddress>,
+    pub destination: Option<ProtocolAddress>,
+    pub proto: Option<IpProtocol>,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+}
+
+/// An inclusive range of TCP or UDP ports, written `22` or `1024-65535`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    first: u16,
+    last: u16,
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s.split_once('-').unwrap_or((s, s));
+        let port = |p: &str| p.parse::<u16>().map_err(|e| format!("invalid port {p}: {e}"));
+
+        let (first, last) = (port(first)?, port(last)?);
+        if first > last {
+            return Err(format!("empty port range {s}"));
+        }
+
+        Ok(Self { first, last })
+    }
+}
+
+impl core::fmt::Display for PortRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// One test a rule makes, written as a keyword followed by a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    SourceMac(MacAddress),
+    DestinationMac(MacAddress),
+    EtherType(EtherType),
+    Vlan(u16),
+    Source(InterfaceAddress),
+    Destination(InterfaceAddress),
+    Proto(IpProtocol),
+    SourcePort(PortRange),
+    DestinationPort(PortRange),
+}
+
+impl Condition {
+    pub fn matches(&self, fields: &Fields) -> bool {
+        match self {
+            Self::SourceMac(mac) => fields.source_mac == Some(*mac),
+            Self::DestinationMac(mac) => fields.destination_mac == Some(*mac),
+            Self::EtherType(ethertype) => fields.ethertype == Some(*ethertype),
+            Self::Vlan(vlan) => fields.vlan == Some(*vlan),
+            Self::Source(network) => fields.source.is_some_and(|a| network.contains(a)),
+            Self::Destination(network) => fields.destination.is_some_and(|a| network.contains(a)),
+            Self::Proto(proto) => fields.proto == Some(*proto),
+            Self::SourcePort(range) => fields.source_port.is_some_and(|p| range.contains(p)),
+            Self::DestinationPort(range) => fields.destination_port.is_some_and(|p| range.contains(p)),
+        }
+    }
+
+    fn parse(key: &str, value: &str) -> Result<Self, String> {
+        match key {
+            "src-mac" => Ok(Self::SourceMac(parse_mac(value)?)),
+            "dst-mac" => Ok(Self::DestinationMac(parse_mac(value)?)),
+            "ethertype" => Ok(Self::EtherType(parse_ethertype(value)?)),
+            "vlan" => value.parse().map(Self::Vlan).map_err(|e| format!("invalid vlan {value}: {e}")),
+            "src" => Ok(Self::Source(parse_network(value)?)),
+            "dst" => Ok(Self::Destination(parse_network(value)?)),
+            "proto" => Ok(Self::Proto(parse_proto(value)?)),
+            "src-port" => Ok(Self::SourcePort(value.parse()?)),
+            "dst-port" => Ok(Self::DestinationPort(value.parse()?)),
+            _ => Err(format!("unknown match {key}")),
+        }
+    }
+}
+
+impl core::fmt::Display for Condition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SourceMac(mac) => write!(f, "src-mac {mac}"),
+            Self::DestinationMac(mac) => write!(f, "dst-mac {mac}"),
+            Self::EtherType(ethertype) => match ETHERTYPES.iter().find(|(_, e)| e == ethertype) {
+                Some((name, _)) => write!(f, "ethertype {name}"),
+                None => write!(f, "ethertype {:#06x}", u16::from(ethertype)),
+            },
+            Self::Vlan(vlan) => write!(f, "vlan {vlan}"),
+            Self::Source(network) => write!(f, "src {network}"),
+            Self::Destination(network) => write!(f, "dst {network}"),
+            Self::Proto(proto) => match PROTOCOLS.iter().find(|(_, p)| p == proto) {
+                Some((name, _)) => write!(f, "proto {name}"),
+                None => write!(f, "proto {}", u8::from(*proto)),
+            },
+            Self::SourcePort(range) => write!(f, "src-port {range}"),
+            Self::DestinationPort(range) => write!(f, "dst-port {range}"),
+        }
+    }
+}
+
+/// The EtherTypes and protocols that rules may name, which are written back the same way.
+const ETHERTYPES: [(&str, EtherType); 4] = [
+    ("arp", EtherType::Arp),
+    ("ipv4", EtherType::Ipv4),
+    ("ipv6", EtherType::Ipv6),
+    ("vlan", EtherType::VlanTaggedFrame),
+];
+const PROTOCOLS: [(&str, IpProtocol); 4] = [
+    ("icmp", IpProtocol::Icmp),
+    ("tcp", IpProtocol::Tcp),
+    ("udp", IpProtocol::Udp),
+    ("icmpv6", IpProtocol::Ipv6Icmp),
+];
+
+/// A name such as `arp`, or a number in hex or decimal.
+fn parse_ethertype(s: &str) -> Result<EtherType, String> {
+    match ETHERTYPES.iter().find(|(name, _)| *name == s) {
+        Some((_, ethertype)) => Ok(*ethertype),
+        None => parse_number(s).map(EtherType::from).ok_or_else(|| format!("invalid EtherType {s}")),
+    }
+}
+
+/// A name such as `tcp`, or a number in hex or decimal.
+fn parse_proto(s: &str) -> Result<IpProtocol, String> {
+    match PROTOCOLS.iter().find(|(name, _)| *name == s) {
+        Some((_, proto)) => Ok(*proto),
+        None => parse_number(s)
+            .and_then(|n| u8::try_from(n).ok())
+            .map(IpProtocol::from)
+            .ok_or_else(|| format!("invalid protocol {s}")),
+    }
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// CIDR notation, or a bare address for just that host.
+fn parse_network(s: &str) -> Result<InterfaceAddress, String> {
+    if s.contains('/') {
+        return s.parse();
+    }
+
+    match s.parse::<std::net::IpAddr>() {
+        Ok(address) => format!("{s}/{}", if address.is_ipv4() { 32 } else { 128 }).parse(),
+        Err(e) => Err(format!("invalid address {s}: {e}")),
+    }
+}
+
+/// A verdict for the PDUs that meet all of a list of conditions, with a count of how many
+/// PDUs it has matched.
+#[derive(Debug)]
+pub struct Rule {
+    verdict: Verdict,
+    conditions: Vec<Condition>,
+    hits: AtomicU64,
+}
+
+impl Rule {
+    pub fn new(verdict: Verdict, conditions: Vec<Condition>) -> Self {
+        Self {
+            verdict,
+            conditions,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        self.verdict
+    }
+
+    #[allow(dead_code)]
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Whether the rule matches `fields`, counting the hit if it does.
+    pub fn matches(&self, fields: &Fields) -> bool {
+        let matches = self.conditions.iter().all(|condition| condition.matches(fields));
+        if matches {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        matches
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    /// Parses a verdict followed by keyword and value pairs, such as `drop proto tcp dst-port 22`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let verdict = words.next().ok_or("missing verdict")?.parse()?;
+
+        let mut conditions = vec![];
+        while let Some(key) = words.next() {
+            let value = words.next().ok_or_else(|| format!("missing value for {key}"))?;
+            conditions.push(Condition::parse(key, value)?);
+        }
+
+        Ok(Self::new(verdict, conditions))
+    }
+}
+
+impl core::fmt::Display for Rule {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.verdict)?;
+        self.conditions.iter().try_for_each(|condition| write!(f, " {condition}"))
+    }
+}
+
+#[test]
+fn test_rule_matching() {
+    use rosi::common::address::Ipv4Address;
+
+    let rule: Rule = "reject src 10.0.0.0/24 proto tcp dst-port 20-22".parse().unwrap();
+    assert_eq!(rule.verdict(), Verdict::Reject);
+    assert_eq!(rule.to_string(), "reject src 10.0.0.0/24 proto tcp dst-port 20-22");
+
+    let mut fields = Fields {
+        source: Some(Ipv4Address::from([10, 0, 0, 7]).into()),
+        proto: Some(IpProtocol::Tcp),
+        destination_port: Some(22),
+        ..Default::default()
+    };
+    assert!(rule.matches(&fields));
+
+    fields.destination_port = Some(23);
+    assert!(!rule.matches(&fields));
+
+    fields.destination_port = None;
+    assert!(!rule.matches(&fields));
+    assert_eq!(rule.hits(), 1);
+
+    let rule: Rule = "drop ethertype 0x86dd src-mac 02:00:00:00:00:09 dst 10.0.0.2".parse().unwrap();
+    assert_eq!(rule.conditions()[0], Condition::EtherType(EtherType::Ipv6));
+    assert_eq!(rule.conditions()[2], Condition::Destination("10.0.0.2/32".parse().unwrap()));
+
+    // Rules are written back in a form they can be read from.
+    for text in ["drop ethertype ipv6 proto icmpv6", "accept ethertype 0x88cc", "reject proto 47 src-port 1024-65535"] {
+        let rule: Rule = text.parse().unwrap();
+        assert_eq!(rule.to_string(), text);
+        assert_eq!(rule.to_string().parse::<Rule>().unwrap().conditions(), rule.conditions());
+    }
+
+    assert!("allow proto tcp".parse::<Rule>().is_err());
+    assert!("drop proto".parse::<Rule>().is_err());
+    assert!("drop dst-port 22-20".parse::<Rule>().is_err());
+    assert!("drop colour blue".parse::<Rule>().is_err());
+}
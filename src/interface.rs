@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use rosi::common::address::{Ipv4Address, Ipv6Address, MacAddress};
+use rosi::protocols::arp::ProtocolAddress;
+
+pub const DEFAULT_MTU: u16 = 1500;
+
+/// An address assigned to an interface, together with the length of its network prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InterfaceAddress {
+    address: ProtocolAddress,
+    prefix_length: u8,
+}
+
+impl InterfaceAddress {
+    /// Returns `None` if the prefix is longer than the address.
+    pub fn new(address: impl Into<ProtocolAddress>, prefix_length: u8) -> Option<Self> {
+        let address = address.into();
+        if prefix_length as usize > Self::bits(address) {
+            return None;
+        }
+
+        Some(Self {
+            address,
+            prefix_length,
+        })
+    }
+
+    pub fn address(&self) -> ProtocolAddress {
+        self.address
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    /// Whether `other` is on the same network as this address.
+    pub fn contains(&self, other: ProtocolAddress) -> bool {
+        match (self.address, other) {
+            (ProtocolAddress::Ipv4Address(a), ProtocolAddress::Ipv4Address(b)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length as u32).unwrap_or(0);
+                u32::from(a) & mask == u32::from(b) & mask
+            },
+            (ProtocolAddress::Ipv6Address(a), ProtocolAddress::Ipv6Address(b)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length as u32).unwrap_or(0);
+                u128::from(a) & mask == u128::from(b) & mask
+            },
+            _ => false,
+        }
+    }
+
+    /// The network this address is on, with the bits after the prefix cleared.
+    pub fn network(&self) -> Self {
+        let address = match self.address {
+            ProtocolAddress::Ipv4Address(a) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length as u32).unwrap_or(0);
+                Ipv4Address::from(u32::from(a) & mask).into()
+            },
+            ProtocolAddress::Ipv6Address(a) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length as u32).unwrap_or(0);
+                Ipv6Address::from(u128::from(a) & mask).into()
+            },
+        };
+
+        Self { address, ..*self }
+    }
+
+    fn bits(address: ProtocolAddress) -> usize {
+        match address {
+            ProtocolAddress::Ipv4Address(..) => 32,
+            ProtocolAddress::Ipv6Address(..) => 128,
+        }
+    }
+}
+
+/// Parses a bare IPv4 or IPv6 address.
+pub fn parse_address(s: &str) -> Result<ProtocolAddress, String> {
+    match s.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(v4)) => Ok(Ipv4Address::from(v4.octets()).into()),
+        Ok(std::net::IpAddr::V6(v6)) => Ok(Ipv6Address::from(v6.octets()).into()),
+        Err(e) => Err(format!("invalid address {s}: {e}")),
+    }
+}
+
+/// Parses a bare IPv4 address.
+pub fn parse_ipv4_address(s: &str) -> Result<Ipv4Address, String> {
+    match parse_address(s)? {
+        ProtocolAddress::Ipv4Address(address) => Ok(address),
+        ProtocolAddress::Ipv6Address(..) => Err(format!("{s} is not an IPv4 address")),
+    }
+}
+
+/// Parses a MAC address written as six hex octets, such as `52:54:00:12:34:56`.
+pub fn parse_mac(s: &str) -> Result<MacAddress, String> {
+    MacAddress::from_hex(s).ok_or_else(|| format!("invalid MAC address {s}"))
+}
+
+impl FromStr for InterfaceAddress {
+    type Err = String;
+
+    /// Parses CIDR notation such as `10.0.0.2/24` or `fe80::2/64`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = s.split_once('/').ok_or_else(|| format!("missing prefix length in {s}"))?;
+        let prefix_length = prefix_length.parse::<u8>().map_err(|e| format!("invalid prefix length in {s}: {e}"))?;
+
+        let address = parse_address(address).map_err(|e| format!("{e} in {s}"))?;
+        Self::new(address, prefix_length).ok_or_else(|| format!("prefix length {prefix_length} is too long for {address}"))
+    }
+}
+
+impl core::fmt::Display for InterfaceAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+/// The identity of the stack on one link: its hardware address, the addresses it owns, and the MTU.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    name: String,
+    mac: MacAddress,
+    point_to_point: bool,
+    addresses: Vec<InterfaceAddress>,
+    mtu: u16,
+}
+
+impl Interface {
+    pub fn new(name: &str, mac: MacAddress, mtu: u16) -> Self {
+        Self {
+            name: name.to_owned(),
+            mac,
+            point_to_point: false,
+            addresses: vec![],
+            mtu,
+        }
+    }
+
+    /// An interface on a link without hardware addresses, such as a TUN device, where every
+    /// packet goes to the one peer at the other end.
+    pub fn point_to_point(name: &str, mtu: u16) -> Self {
+        Self {
+            point_to_point: true,
+            ..Self::new(name, MacAddress::default(), mtu)
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn mac(&self) -> MacAddress {
+        self.mac
+    }
+
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    pub fn is_point_to_point(&self) -> bool {
+        self.point_to_point
+    }
+
+    pub fn addresses(&self) -> &[InterfaceAddress] {
+        &self.addresses
+    }
+
+    pub fn ipv4_addresses(&self) -> impl Iterator<Item = Ipv4Address> + '_ {
+        self.addresses.iter().filter_map(|a| match a.address {
+            ProtocolAddress::Ipv4Address(address) => Some(address),
+            _ => None,
+        })
+    }
+
+    pub fn ipv6_addresses(&self) -> impl Iterator<Item = Ipv6Address> + '_ {
+        self.addresses.iter().filter_map(|a| match a.address {
+            ProtocolAddress::Ipv6Address(address) => Some(address),
+            _ => None,
+        })
+    }
+
+    /// Adds `address`, replacing any existing entry with a different prefix length.
+    pub fn add_address(&mut self, address: InterfaceAddress) {
+        self.remove_address(address.address);
+        self.addresses.push(address);
+    }
+
+    pub fn remove_address(&mut self, address: ProtocolAddress) -> bool {
+        let len = self.addresses.len();
+        self.addresses.retain(|a| a.address != address);
+        self.addresses.len() != len
+    }
+
+    pub fn owns(&self, address: impl Into<ProtocolAddress>) -> bool {
+        let address = address.into();
+        self.addresses.iter().any(|a| a.address == address)
+    }
+
+    /// Whether `address` is directly reachable on this link.
+    #[allow(dead_code)]
+    pub fn is_on_link(&self, address: impl Into<ProtocolAddress>) -> bool {
+        let address = address.into();
+        self.addresses.iter().any(|a| a.contains(address))
+    }
+}
+
+impl core::fmt::Display for Interface {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.point_to_point {
+            write!(f, "{}: point-to-point mtu {}", self.name, self.mtu)?;
+        } else {
+            write!(f, "{}: {} mtu {}", self.name, self.mac, self.mtu)?;
+        }
+        self.addresses.iter().try_for_each(|a| write!(f, " {a}"))
+    }
+}
+
+#[test]
+fn test_interface_addresses() {
+    let mut interface = Interface::new("tap0", MacAddress::from([0x02, 0, 0, 0, 0, 1]), DEFAULT_MTU);
+    interface.add_address("10.0.0.2/24".parse().unwrap());
+    interface.add_address("fe80::2/64".parse().unwrap());
+
+    assert!(interface.owns(Ipv4Address::from([10, 0, 0, 2])));
+    assert!(!interface.owns(Ipv4Address::from([10, 0, 0, 3])));
+    assert!(interface.is_on_link(Ipv4Address::from([10, 0, 0, 254])));
+    assert!(!interface.is_on_link(Ipv4Address::from([10, 0, 1, 1])));
+    assert!(interface.is_on_link(Ipv6Address::from([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x09])));
+
+    assert_eq!(interface.ipv4_addresses().collect::<Vec<_>>(), vec![Ipv4Address::from([10, 0, 0, 2])]);
+    assert_eq!(interface.ipv6_addresses().count(), 1);
+
+    assert!("10.0.0.2/33".parse::<InterfaceAddress>().is_err());
+    assert!("10.0.0.2".parse::<InterfaceAddress>().is_err());
+    assert_eq!(InterfaceAddress::new(Ipv4Address::default(), 0).map(|a| a.contains(Ipv4Address::from([1, 2, 3, 4]).into())), Some(true));
+
+    assert!(interface.remove_address(Ipv4Address::from([10, 0, 0, 2]).into()));
+    assert!(!interface.owns(Ipv4Address::from([10, 0, 0, 2])));
+}
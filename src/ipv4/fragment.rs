@@ -0,0 +1,76 @@
+use rosi::common::{Layer, Serialise};
+use rosi::protocols::ipv4::Ipv4Packet;
+
+/// Splits `packet` into fragments of at most `mtu` bytes, or returns it as it is if it fits.
+/// Returns `None` if it does not fit and may not be fragmented.
+///
+/// A fragment can itself be fragmented again: the pieces keep their place in the original
+/// datagram, and the last one keeps its more fragments flag.
+pub fn fragment(packet: &Ipv4Packet, mtu: u16) -> Option<Vec<Ipv4Packet>> {
+    let mtu = mtu as usize;
+    if packet.byte_length() <= mtu {
+        return Some(vec![packet.clone()]);
+    }
+
+    if packet.dont_fragment() {
+        return None;
+    }
+
+    // Only the options marked as copied are repeated after the first fragment (RFC 791).
+    let mut rest = packet.clone();
+    let copied = packet.options().iter().filter(|o| o.is_copied()).cloned().collect();
+    rest.set_options(copied).expect("some of the options fit where all of them did");
+
+    let data = packet.data();
+    let base = packet.fragment_offset() as usize * 8;
+
+    let mut fragments = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let mut fragment = if offset == 0 { packet.clone() } else { rest.clone() };
+
+        // Every fragment but the last carries a multiple of 8 bytes.
+        let room = (mtu.saturating_sub(fragment.header().byte_length()) & !7).max(8);
+        let end = data.len().min(offset + room);
+
+        fragment.wrap(&&data[offset..end]);
+        fragment.set_fragment(end < data.len() || packet.more_fragments(), ((base + offset) / 8) as u16);
+        fragments.push(fragment);
+
+        offset = end;
+    }
+
+    Some(fragments)
+}
+
+#[test]
+fn test_fragment() {
+    use rosi::common::address::Ipv4Address;
+    use rosi::protocols::ipv4::{IpProtocol, Ipv4Option};
+
+    let mut packet = Ipv4Packet::new(
+        Ipv4Address::from([10, 0, 0, 2]),
+        Ipv4Address::from([10, 0, 0, 1]),
+        IpProtocol::Udp,
+        (0..100).collect(),
+    );
+    // A record route option, which only the first fragment keeps, and a copied router alert.
+    packet.set_options(vec![Ipv4Option::record_route(1), Ipv4Option::NoOperation, Ipv4Option::RouterAlert(0)]).unwrap();
+    assert_eq!(packet.header().byte_length(), 32);
+
+    assert_eq!(fragment(&packet, 1500).unwrap().len(), 1);
+
+    let fragments = fragment(&packet, 60).unwrap();
+    let layout = fragments.iter()
+        .map(|f| (f.header().byte_length(), f.data().len(), f.fragment_offset(), f.more_fragments()))
+        .collect::<Vec<_>>();
+    assert_eq!(layout, vec![(32, 24, 0, true), (24, 32, 3, true), (24, 32, 7, true), (24, 12, 11, false)]);
+    assert_eq!(fragments[1].options(), &[Ipv4Option::RouterAlert(0)]);
+    assert!(fragments.iter().all(|f| f.byte_length() <= 60 && f.header().validate_checksum()));
+
+    let data = fragments.iter().flat_map(|f| f.data().iter().copied()).collect::<Vec<_>>();
+    assert_eq!(data, packet.data());
+
+    packet.set_dont_fragment(true);
+    assert!(fragment(&packet, 60).is_none());
+}
@@ -0,0 +1,430 @@
+mod fragment;
+mod reassembly;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use rosi::common::{Serialise, Wrapper};
+use rosi::common::address::Ipv4Address;
+use rosi::protocols::arp::ProtocolAddress;
+use rosi::protocols::ethernet::EtherType;
+use rosi::protocols::icmp::{self, TimeExceededCode, UnreachableCode};
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Header, Ipv4Packet};
+
+use super::arp::{Pending, Resolver};
+use super::interface::Interface;
+use super::netservice::{Action, ByteSender, Channels, NetService, NetServiceError};
+use super::route::RoutingTable;
+
+use reassembly::Reassembler;
+
+/// IPv4 for one interface: delivers packets addressed to us and answers pings.
+///
+/// Services above send whole IPv4 packets down, which are routed with the routing table
+/// shared by every interface. Packets for a route out of this interface are passed to the
+/// link once the hardware address of their next hop has been resolved; on a point-to-point
+/// link there is nothing to resolve, and they are passed down as they are. Packets for a
+/// route out of another interface are handed to the IPv4 service of that interface.
+///
+/// Packets too big for the interface MTU are fragmented on the way down, and fragments
+/// addressed to us are reassembled before any action sees them. With forwarding on, packets
+/// for other hosts that an action processes are routed on like a router would (RFC 1812).
+pub struct Ipv4Service {
+    interface: Arc<RwLock<Interface>>,
+    resolver: Option<Resolver>,
+    routes: Arc<RwLock<RoutingTable>>,
+    /// The IPv4 services of the other interfaces, by interface name.
+    others: HashMap<String, ByteSender>,
+    forwarding: bool,
+    reassembler: Reassembler,
+    /// The identification given to the next datagram we originate.
+    identification: u16,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl Ipv4Service {
+    pub fn new(interface: Arc<RwLock<Interface>>, resolver: Resolver, routes: Arc<RwLock<RoutingTable>>) -> Self {
+        Self {
+            resolver: Some(resolver),
+            ..Self::point_to_point(interface, routes)
+        }
+    }
+
+    pub fn point_to_point(interface: Arc<RwLock<Interface>>, routes: Arc<RwLock<RoutingTable>>) -> Self {
+        Self {
+            interface,
+            resolver: None,
+            routes,
+            others: HashMap::new(),
+            forwarding: false,
+            reassembler: Reassembler::new(),
+            identification: 0,
+            channels: Channels::new(),
+            actions: vec![],
+        }
+    }
+
+    /// Sends the packets routed out of interface `name` to `sender`, the IPv4 service on it.
+    pub fn add_interface(&mut self, name: &str, sender: ByteSender) {
+        self.others.insert(name.to_owned(), sender);
+    }
+
+    pub fn set_forwarding(&mut self, forwarding: bool) {
+        self.forwarding = forwarding;
+    }
+
+    /// Whether `packet` is addressed to one of our addresses or is a broadcast.
+    pub fn accepts(&self, packet: &Ipv4Packet) -> bool {
+        let destination = packet.destination();
+        destination.is_broadcast() || self.interface.read().unwrap().owns(destination)
+    }
+
+    /// Whether `packet` is for another host and forwarding is on. Packets to a group, or from
+    /// or to a link-local address, never leave the link they were sent on.
+    pub fn forwards(&self, packet: &Ipv4Packet) -> bool {
+        let (source, destination) = (packet.source(), packet.destination());
+        self.forwarding
+            && !self.accepts(packet)
+            && !destination.is_multicast()
+            && !source.is_unspecified()
+            && !source.is_link_local()
+            && !destination.is_link_local()
+    }
+
+    /// Routes a packet for another host on, taking one off its time to live, or tells its
+    /// sender why it cannot be.
+    fn forward(&mut self, mut packet: Ipv4Packet) -> Result<(), NetServiceError> {
+        if packet.ttl() <= 1 {
+            let error = icmp::Packet::time_exceeded(TimeExceededCode::TtlExceeded, &packet);
+            return self.report(&packet, error);
+        }
+
+        if self.routes.read().unwrap().lookup(packet.destination()).is_none() {
+            let error = icmp::Packet::destination_unreachable(UnreachableCode::Network, 0, &packet);
+            return self.report(&packet, error);
+        }
+
+        packet.set_ttl(packet.ttl() - 1);
+        self.send(serialise(&packet))
+    }
+
+    fn process_icmp(&mut self, packet: &Ipv4Packet) -> Result<(), NetServiceError> {
+        let message = icmp::Packet::deserialise(packet.data())?;
+        let Some(reply) = message.reply() else {
+            return Ok(());
+        };
+
+        self.send_icmp(packet.destination(), packet.source(), reply)
+    }
+
+    fn send_icmp(&mut self, source: Ipv4Address, destination: Ipv4Address, message: icmp::Packet) -> Result<(), NetServiceError> {
+        let mut bytes = vec![0u8; message.byte_length()];
+        message.serialise(&mut bytes);
+
+        let packet = Ipv4Packet::new(source, destination, IpProtocol::Icmp, bytes);
+        self.send(serialise(&packet))
+    }
+
+    /// Whether an ICMP error may be sent about `packet`: not if it was a broadcast, an ICMP
+    /// error itself, or a fragment other than the first (RFC 1122).
+    fn may_report(packet: &Ipv4Packet) -> bool {
+        let broadcast = packet.destination().is_broadcast();
+        let icmp_error = packet.proto() == IpProtocol::Icmp && icmp::Packet::deserialise(packet.data()).is_ok_and(|m| m.is_error());
+        !broadcast && !icmp_error && packet.fragment_offset() == 0
+    }
+
+    /// Sends an ICMP error about a packet that did not come from us to its sender, from our
+    /// first address on this interface.
+    fn report(&mut self, packet: &Ipv4Packet, error: icmp::Packet) -> Result<(), NetServiceError> {
+        let source = self.interface.read().unwrap().ipv4_addresses().next();
+        match source {
+            Some(source) if Self::may_report(packet) => self.send_icmp(source, packet.source(), error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gives a datagram from one of our addresses the next identification, so that its
+    /// fragments cannot be mixed up with those of another datagram wherever it is fragmented.
+    /// Forwarded datagrams and fragments keep theirs, which their pieces must share.
+    fn identify(&mut self, data: Arc<[u8]>) -> Result<Arc<[u8]>, NetServiceError> {
+        let header = Ipv4Header::deserialise(&data)?;
+        let fragment = header.more_fragments() || header.fragment_offset() != 0;
+        if header.dont_fragment() || fragment || !self.interface.read().unwrap().owns(header.source()) {
+            return Ok(data);
+        }
+
+        let mut packet = Ipv4Packet::deserialise(&data)?;
+        packet.set_identification(self.identification);
+        self.identification = self.identification.wrapping_add(1);
+        Ok(serialise(&packet))
+    }
+
+    /// Fragments a datagram too big for the MTU. If it may not be fragmented, a forwarded one
+    /// is answered with a fragmentation needed error, and one of ours fails back to its sender.
+    fn send_fragments(&mut self, packet: Ipv4Packet, mtu: u16, next_hop: ProtocolAddress) -> Result<(), NetServiceError> {
+        let Some(fragments) = fragment::fragment(&packet, mtu) else {
+            if self.interface.read().unwrap().owns(packet.source()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{} byte datagram to {} may not be fragmented for an MTU of {mtu}", packet.total_length(), packet.destination()),
+                ).into());
+            }
+
+            let error = icmp::Packet::destination_unreachable(UnreachableCode::FragmentationNeeded, mtu, &packet);
+            return self.report(&packet, error);
+        };
+
+        fragments.iter().try_for_each(|fragment| self.send_datagram(serialise(fragment), next_hop))
+    }
+
+    /// Passes a datagram to the link, addressed to the hardware address of `next_hop`.
+    fn send_datagram(&mut self, data: Arc<[u8]>, next_hop: ProtocolAddress) -> Result<(), NetServiceError> {
+        let Some(resolver) = &self.resolver else {
+            return self.send_down(data);
+        };
+
+        let pending = Pending { ethertype: EtherType::Ipv4, data: data.to_vec() };
+        resolver.send(next_hop, pending).into_iter().try_for_each(|frame| self.send_down(frame))
+    }
+}
+
+fn serialise(packet: &Ipv4Packet) -> Arc<[u8]> {
+    let mut bytes = vec![0u8; packet.byte_length()];
+    packet.serialise(&mut bytes);
+    Arc::from(bytes)
+}
+
+impl NetService for Ipv4Service {
+    type Pdu = Ipv4Packet;
+
+    fn name(&self) -> &'static str {
+        "ipv4"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(pdu: &Self::Pdu) -> Option<Arc<[u8]>> {
+        Some(pdu.unwrap_data())
+    }
+
+    /// Routes packets for other hosts on, and answers pings for us.
+    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        if self.forwards(&pdu) {
+            return self.forward(pdu);
+        }
+
+        match pdu.proto() {
+            IpProtocol::Icmp => self.process_icmp(&pdu),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers with an administratively prohibited error, unless the packet was not addressed
+    /// to us alone or was itself an ICMP error (RFC 1122).
+    fn reject(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        if !Self::may_report(&pdu) || !self.accepts(&pdu) {
+            return Ok(());
+        }
+
+        let error = icmp::Packet::destination_unreachable(UnreachableCode::AdministrativelyProhibited, 0, &pdu);
+        self.send_icmp(pdu.destination(), pdu.source(), error)
+    }
+
+    /// Reassembles the fragments of datagrams addressed to us. Fragments for other hosts are
+    /// left for the actions, like any other packet.
+    fn reassemble(&mut self, data: Arc<[u8]>) -> Result<Option<Arc<[u8]>>, NetServiceError> {
+        let packet = Ipv4Packet::deserialise(&data)?;
+        if !(packet.more_fragments() || packet.fragment_offset() != 0) || !self.accepts(&packet) {
+            return Ok(Some(data));
+        }
+
+        Ok(self.reassembler.on_fragment(packet, Instant::now()).map(|datagram| serialise(&datagram)))
+    }
+
+    /// Tells the senders of datagrams that could not be reassembled in time.
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        for first in self.reassembler.on_tick(now) {
+            if Self::may_report(&first) {
+                let error = icmp::Packet::time_exceeded(TimeExceededCode::FragmentReassembly, &first);
+                self.send_icmp(first.destination(), first.source(), error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send(&mut self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        let data = self.identify(data)?;
+        let destination = Ipv4Header::deserialise(&data)?.destination();
+
+        let Some(route) = self.routes.read().unwrap().lookup(destination).cloned() else {
+            eprintln!("ipv4: no route to {destination}");
+            return Ok(());
+        };
+
+        let (name, mtu) = {
+            let interface = self.interface.read().unwrap();
+            (interface.name().to_owned(), interface.mtu())
+        };
+        if route.interface != name {
+            return match self.others.get(&route.interface) {
+                Some(other) => Ok(other.send(data)?),
+                None => {
+                    eprintln!("ipv4: {} is routed out of {}, which the stack is not running on", destination, route.interface);
+                    Ok(())
+                },
+            };
+        }
+
+        let next_hop = route.next_hop(destination.into());
+        if data.len() > mtu as usize {
+            return self.send_fragments(Ipv4Packet::deserialise(&data)?, mtu, next_hop);
+        }
+
+        self.send_datagram(data, next_hop)
+    }
+}
+
+#[test]
+fn test_forwarding() {
+    use std::sync::mpsc;
+
+    use crate::interface::DEFAULT_MTU;
+    use crate::netservice::ActionType;
+
+    let mut tun0 = Interface::point_to_point("tun0", DEFAULT_MTU);
+    tun0.add_address("10.0.1.1/30".parse().unwrap());
+    let mut tun1 = Interface::point_to_point("tun1", DEFAULT_MTU);
+    tun1.add_address("10.0.2.1/30".parse().unwrap());
+
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&tun0);
+    routes.write().unwrap().add_connected(&tun1);
+
+    let (send_down, sent) = mpsc::channel();
+    let (send_tun1, routed) = mpsc::channel();
+    let mut ipv4 = Ipv4Service::point_to_point(Arc::new(RwLock::new(tun0)), routes);
+    ipv4.set_send_down(send_down);
+    ipv4.add_interface("tun1", send_tun1);
+    ipv4.add_filter(ActionType::Process, |service, packet| service.forwards(packet), false);
+    ipv4.add_filter(ActionType::Drop, |service, packet| !service.accepts(packet), false);
+
+    let packet = |destination, ttl| {
+        let mut packet = Ipv4Packet::new(Ipv4Address::from([10, 0, 1, 2]), destination, IpProtocol::Udp, vec![0; 8]);
+        packet.set_ttl(ttl);
+        serialise(&packet)
+    };
+    let error = |bytes: Arc<[u8]>| {
+        let packet = Ipv4Packet::deserialise(&bytes).unwrap();
+        assert_eq!((packet.source(), packet.destination()), (Ipv4Address::from([10, 0, 1, 1]), Ipv4Address::from([10, 0, 1, 2])));
+        icmp::Packet::deserialise(packet.data()).unwrap().message().clone()
+    };
+
+    // Nothing is forwarded until forwarding is turned on.
+    ipv4.receive(packet(Ipv4Address::from([10, 0, 2, 2]), 64)).unwrap();
+    assert!(routed.try_recv().is_err() && sent.try_recv().is_err());
+    ipv4.set_forwarding(true);
+
+    // A packet for the network of tun1 goes to its service, one hop older.
+    ipv4.receive(packet(Ipv4Address::from([10, 0, 2, 2]), 64)).unwrap();
+    let forwarded = Ipv4Packet::deserialise(&routed.try_recv().unwrap()).unwrap();
+    assert_eq!(forwarded.ttl(), 63);
+    assert!(forwarded.header().validate_checksum());
+
+    // One that would outlive its time to live, or has nowhere to go, is answered with an error.
+    ipv4.receive(packet(Ipv4Address::from([10, 0, 2, 2]), 1)).unwrap();
+    assert!(matches!(error(sent.try_recv().unwrap()), icmp::Message::TimeExceeded { code: TimeExceededCode::TtlExceeded, .. }));
+
+    ipv4.receive(packet(Ipv4Address::from([192, 168, 0, 1]), 64)).unwrap();
+    assert!(matches!(error(sent.try_recv().unwrap()), icmp::Message::DestinationUnreachable { code: UnreachableCode::Network, .. }));
+
+    // Groups and link-local addresses stay on the link.
+    ipv4.receive(packet(Ipv4Address::from([224, 0, 0, 9]), 64)).unwrap();
+    ipv4.receive(packet(Ipv4Address::from([169, 254, 0, 1]), 64)).unwrap();
+    assert!(routed.try_recv().is_err() && sent.try_recv().is_err());
+}
+
+#[test]
+fn test_forwarding_fragments() {
+    use std::sync::mpsc;
+
+    use crate::netservice::ActionType;
+    use crate::route::{Origin, Route};
+
+    let mut tun0 = Interface::point_to_point("tun0", 100);
+    tun0.add_address("10.0.1.1/30".parse().unwrap());
+
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&tun0);
+    routes.write().unwrap().add(Route {
+        destination: "0.0.0.0/0".parse().unwrap(),
+        gateway: None,
+        interface: "tun0".into(),
+        metric: 0,
+        origin: Origin::Static,
+    });
+
+    let (send_down, sent) = mpsc::channel();
+    let mut ipv4 = Ipv4Service::point_to_point(Arc::new(RwLock::new(tun0)), routes);
+    ipv4.set_send_down(send_down);
+    ipv4.set_forwarding(true);
+    ipv4.add_filter(ActionType::Process, |service, packet| service.forwards(packet), false);
+
+    // The first of two fragments of a datagram, forwarded through a smaller MTU than it came
+    // over, is split again under the identification of the datagram.
+    let mut datagram = Ipv4Packet::new(Ipv4Address::from([10, 0, 1, 2]), Ipv4Address::from([192, 168, 0, 1]), IpProtocol::Udp, (0..=255).collect());
+    datagram.set_identification(0x1234);
+    let fragments = fragment::fragment(&datagram, 180).unwrap();
+    assert_eq!(fragments.len(), 2);
+    fragments.iter().for_each(|fragment| ipv4.receive(serialise(fragment)).unwrap());
+
+    let mut reassembler = Reassembler::new();
+    let mut reassembled = None;
+    let mut pieces = 0;
+    while let Ok(bytes) = sent.try_recv() {
+        let piece = Ipv4Packet::deserialise(&bytes).unwrap();
+        assert!(piece.byte_length() <= 100);
+        assert_eq!(piece.header().identification(), 0x1234);
+        reassembled = reassembler.on_fragment(piece, Instant::now()).or(reassembled);
+        pieces += 1;
+    }
+    assert!(pieces > 2);
+    assert_eq!(reassembled.unwrap().data(), datagram.data());
+
+    // Datagrams of our own each get an identification of their own.
+    let ours = Ipv4Packet::new(Ipv4Address::from([10, 0, 1, 1]), Ipv4Address::from([192, 168, 0, 1]), IpProtocol::Udp, vec![0; 8]);
+    ipv4.send(serialise(&ours)).unwrap();
+    ipv4.send(serialise(&ours)).unwrap();
+    let identification = || Ipv4Packet::deserialise(&sent.try_recv().unwrap()).unwrap().header().identification();
+    assert_ne!(identification(), identification());
+
+    // A datagram too big that may not be fragmented fails back to us if it is ours, and is
+    // answered with an error if it is forwarded.
+    let mut big = Ipv4Packet::new(Ipv4Address::from([10, 0, 1, 1]), Ipv4Address::from([192, 168, 0, 1]), IpProtocol::Udp, vec![0; 200]);
+    big.set_dont_fragment(true);
+    assert!(ipv4.send(serialise(&big)).is_err());
+    assert!(sent.try_recv().is_err());
+
+    let mut big = Ipv4Packet::new(Ipv4Address::from([10, 0, 1, 2]), Ipv4Address::from([192, 168, 0, 1]), IpProtocol::Udp, vec![0; 200]);
+    big.set_dont_fragment(true);
+    ipv4.receive(serialise(&big)).unwrap();
+    let error = Ipv4Packet::deserialise(&sent.try_recv().unwrap()).unwrap();
+    assert_eq!((error.source(), error.destination(), error.proto()), (Ipv4Address::from([10, 0, 1, 1]), big.source(), IpProtocol::Icmp));
+    assert!(sent.try_recv().is_err());
+}
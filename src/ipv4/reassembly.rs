@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use rosi::common::{Layer, Serialise};
+use rosi::common::address::Ipv4Address;
+use rosi::protocols::ipv4::Ipv4Packet;
+
+/// How long the fragments of a datagram are kept waiting for the rest of it.
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Datagrams reassembled at once, and the bytes held for them; the oldest are dropped first.
+const MAX_DATAGRAMS: usize = 64;
+const MAX_BUFFERED: usize = 256 * 1024;
+
+/// Largest payload a datagram with a minimal header can carry. Options in the header of the
+/// first fragment leave room for less.
+const MAX_DATA_LENGTH: usize = u16::MAX as usize - 20;
+
+/// The fields that tell which datagram a fragment belongs to (RFC 791).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    source: Ipv4Address,
+    destination: Ipv4Address,
+    proto: u8,
+    identification: u16,
+}
+
+impl Key {
+    fn of(packet: &Ipv4Packet) -> Self {
+        Self {
+            source: packet.source(),
+            destination: packet.destination(),
+            proto: packet.proto().into(),
+            identification: packet.identification(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Datagram {
+    /// The fragment at offset 0, whose header the whole datagram is given.
+    first: Option<Ipv4Packet>,
+    data: Vec<u8>,
+    /// The parts of `data` received so far, sorted and without overlaps.
+    received: Vec<Range<usize>>,
+    /// The length of the data, known once the last fragment has arrived.
+    length: Option<usize>,
+    expires: Instant,
+}
+
+impl Datagram {
+    fn new(now: Instant) -> Self {
+        Self { first: None, data: vec![], received: vec![], length: None, expires: now + REASSEMBLY_TIMEOUT }
+    }
+
+    /// Largest payload the datagram can carry under the header of its first fragment.
+    fn max_length(&self) -> usize {
+        self.first.as_ref().map_or(MAX_DATA_LENGTH, Ipv4Packet::max_data_length)
+    }
+
+    fn buffered(&self) -> usize {
+        self.data.len() + self.first.as_ref().map_or(0, Serialise::byte_length)
+    }
+
+    /// Copies the bytes of a fragment at `start` into the parts not yet received. Where
+    /// fragments overlap, the bytes that arrived first are kept.
+    fn insert(&mut self, start: usize, bytes: &[u8]) {
+        let end = start + bytes.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+
+        let mut position = start;
+        for range in &self.received {
+            if range.end <= position {
+                continue;
+            }
+            if range.start >= end {
+                break;
+            }
+            if range.start > position {
+                self.data[position..range.start].copy_from_slice(&bytes[position - start..range.start - start]);
+            }
+            position = position.max(range.end);
+        }
+        if position < end {
+            self.data[position..end].copy_from_slice(&bytes[position - start..]);
+        }
+
+        self.received.push(start..end);
+        self.received.sort_by_key(|range| range.start);
+        self.received = self.received.drain(..).fold(vec![], |mut merged: Vec<Range<usize>>, range| {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+            merged
+        });
+    }
+
+    fn is_complete(&self) -> bool {
+        self.length.is_some_and(|length| matches!(self.received.as_slice(), [range] if *range == (0..length)))
+    }
+}
+
+/// Reassembles the datagrams that arrive in fragments.
+///
+/// Fragments may arrive in any order, more than once, or overlapping one another. A datagram
+/// whose fragments contradict each other about its length is dropped.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    datagrams: HashMap<Key, Datagram>,
+    buffered: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a fragment, returning the whole datagram if it was the last piece missing.
+    pub fn on_fragment(&mut self, fragment: Ipv4Packet, now: Instant) -> Option<Ipv4Packet> {
+        let start = fragment.fragment_offset() as usize * 8;
+        let end = start + fragment.data().len();
+        let last = !fragment.more_fragments();
+
+        // Every fragment but the last carries a non-empty multiple of 8 bytes.
+        if end > MAX_DATA_LENGTH || (!last && (fragment.data().is_empty() || !fragment.data().len().is_multiple_of(8))) {
+            return None;
+        }
+
+        let key = Key::of(&fragment);
+        let datagram = self.datagrams.entry(key).or_insert_with(|| Datagram::new(now));
+        let before = datagram.buffered();
+
+        let beyond_end = match datagram.length {
+            Some(length) => end > length || (last && end != length),
+            None => last && datagram.data.len() > end,
+        };
+        if beyond_end {
+            self.remove(&key);
+            return None;
+        }
+
+        if last {
+            datagram.length = Some(end);
+        }
+        datagram.insert(start, fragment.data());
+        if start == 0 && datagram.first.is_none() {
+            datagram.first = Some(fragment);
+        }
+
+        self.buffered = self.buffered - before + datagram.buffered();
+        if datagram.data.len() > datagram.max_length() {
+            self.remove(&key);
+            return None;
+        }
+
+        if datagram.is_complete() {
+            let datagram = self.remove(&key)?;
+            let mut packet = datagram.first?;
+            if datagram.data.len() > packet.max_data_length() {
+                return None;
+            }
+            packet.wrap(&datagram.data.as_slice());
+            packet.set_fragment(false, 0);
+            return Some(packet);
+        }
+
+        self.enforce_limits();
+        None
+    }
+
+    /// Drops the datagrams that have run out of time, returning their first fragments, if
+    /// they arrived, for the sender to be told with a time exceeded message.
+    pub fn on_tick(&mut self, now: Instant) -> Vec<Ipv4Packet> {
+        let expired = self.datagrams.iter().filter(|(_, datagram)| datagram.expires <= now).map(|(key, _)| *key).collect::<Vec<_>>();
+        expired.iter().filter_map(|key| self.remove(key)?.first).collect()
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Datagram> {
+        let datagram = self.datagrams.remove(key)?;
+        self.buffered -= datagram.buffered();
+        Some(datagram)
+    }
+
+    fn enforce_limits(&mut self) {
+        while self.datagrams.len() > MAX_DATAGRAMS || self.buffered > MAX_BUFFERED {
+            let Some(oldest) = self.datagrams.iter().min_by_key(|(_, datagram)| datagram.expires).map(|(key, _)| *key) else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+#[test]
+fn test_reassembly() {
+    use rosi::protocols::ipv4::{IpProtocol, Ipv4Option};
+
+    use super::fragment::fragment;
+
+    let packet = |identification, length| {
+        let mut packet = Ipv4Packet::new(
+            Ipv4Address::from([10, 0, 0, 1]),
+            Ipv4Address::from([10, 0, 0, 2]),
+            IpProtocol::Udp,
+            (0..length).map(|i| i as u8).collect(),
+        );
+        packet.set_identification(identification);
+        packet
+    };
+
+    let now = Instant::now();
+    let mut reassembler = Reassembler::new();
+
+    // Out of order, with a duplicate.
+    let original = packet(1, 100);
+    let fragments = fragment(&original, 60).unwrap();
+    assert_eq!(fragments.len(), 3);
+    assert!(reassembler.on_fragment(fragments[2].clone(), now).is_none());
+    assert!(reassembler.on_fragment(fragments[0].clone(), now).is_none());
+    assert!(reassembler.on_fragment(fragments[2].clone(), now).is_none());
+
+    let whole = reassembler.on_fragment(fragments[1].clone(), now).unwrap();
+    assert_eq!(whole.data(), original.data());
+    assert_eq!(whole.total_length(), original.total_length());
+    assert!(!whole.more_fragments() && whole.fragment_offset() == 0);
+    assert!(whole.header().validate_checksum());
+    assert_eq!(reassembler.datagrams.len(), 0);
+
+    // Overlapping fragments keep the bytes that came first.
+    let original = packet(2, 48);
+    let mut early = fragment(&original, 44).unwrap();
+    let mut late = fragment(&original, 36).unwrap();
+    let mut changed = late[1].clone();
+    changed.wrap(&[0xffu8; 16].as_slice());
+    assert!(reassembler.on_fragment(early.remove(0), now).is_none());
+    assert!(reassembler.on_fragment(changed, now).is_none());
+    let whole = reassembler.on_fragment(late.pop().unwrap(), now).unwrap();
+    assert_eq!(&whole.data()[..24], &original.data()[..24]);
+    assert_eq!(&whole.data()[24..32], &[0xff; 8]);
+
+    // Datagrams that never complete time out, and only those whose first fragment arrived
+    // are reported.
+    let fragments = fragment(&packet(3, 100), 60).unwrap();
+    reassembler.on_fragment(fragments[0].clone(), now);
+    reassembler.on_fragment(fragment(&packet(4, 100), 60).unwrap()[1].clone(), now);
+    assert!(reassembler.on_tick(now + REASSEMBLY_TIMEOUT / 2).is_empty());
+    let expired = reassembler.on_tick(now + REASSEMBLY_TIMEOUT);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].identification(), 3);
+    assert_eq!(reassembler.datagrams.len(), 0);
+
+    // A last fragment that ends before data already received drops the datagram.
+    let fragments = fragment(&packet(5, 100), 60).unwrap();
+    reassembler.on_fragment(fragments[1].clone(), now);
+    let mut short = fragments[0].clone();
+    short.set_fragment(false, 0);
+    assert!(reassembler.on_fragment(short, now).is_none());
+    assert_eq!(reassembler.datagrams.len(), 0);
+
+    // A first fragment with options leaves less room for the data, and a datagram that would
+    // not fit is dropped.
+    let mut first = packet(6, 8);
+    first.set_options(vec![Ipv4Option::RouterAlert(0); 10]).unwrap();
+    first.set_fragment(true, 0);
+    let mut rest = packet(6, 65515 - 8);
+    rest.set_fragment(false, 1);
+    assert_eq!(first.header().byte_length(), 60);
+    assert!(reassembler.on_fragment(rest.clone(), now).is_none());
+    assert!(reassembler.on_fragment(first.clone(), now).is_none());
+    assert_eq!(reassembler.datagrams.len(), 0);
+    assert!(reassembler.on_fragment(first, now).is_none());
+    assert!(reassembler.on_fragment(rest, now).is_none());
+    assert_eq!(reassembler.datagrams.len(), 0);
+
+    // The oldest datagrams make way for new ones.
+    for identification in 0..=MAX_DATAGRAMS as u16 {
+        reassembler.on_fragment(fragment(&packet(identification, 100), 60).unwrap()[0].clone(), now + Duration::from_millis(identification.into()));
+    }
+    assert_eq!(reassembler.datagrams.len(), MAX_DATAGRAMS);
+    assert_eq!(reassembler.on_tick(now + REASSEMBLY_TIMEOUT).len(), 0);
+}
@@ -0,0 +1,434 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use rosi::common::{PseudoHeader, Serialise, Wrapper};
+use rosi::common::address::{Ipv6Address, MacAddress};
+use rosi::protocols::arp::ProtocolAddress;
+use rosi::protocols::ethernet::{EtherType, Frame};
+use rosi::protocols::icmpv6::{self, Message, TimeExceededCode, UnreachableCode};
+use rosi::protocols::ipv4::IpProtocol;
+use rosi::protocols::ipv6::{Ipv6Header, Ipv6Packet};
+
+use super::interface::Interface;
+use super::netservice::{Action, ByteSender, Channels, NetService, NetServiceError};
+use super::route::RoutingTable;
+
+/// Hop limit of every Neighbor Discovery message, which receivers check to know it came from the link (RFC 4861).
+const NDP_HOP_LIMIT: u8 = 255;
+
+const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// Interval between solicitations for an unresolved neighbour, and the most we send before
+/// giving up (RFC 4861 section 10).
+const RETRANS_TIMER: Duration = Duration::from_secs(1);
+const MAX_MULTICAST_SOLICIT: u32 = 3;
+
+/// Packets queued per unresolved neighbour; the oldest are dropped first.
+const MAX_PENDING: usize = 16;
+
+/// A neighbour being solicited, and the packets waiting for its link-layer address.
+struct Unresolved {
+    solicitations: u32,
+    next_solicitation: Instant,
+    pending: VecDeque<Arc<[u8]>>,
+}
+
+/// IPv6 for one interface: answers Neighbor Solicitations for our addresses and pings.
+///
+/// Packets are routed like IPv4 ones, except that those to a group or a link-local address
+/// stay on this link. Neighbours are learned from the link-layer address options of the
+/// Neighbor Discovery messages we receive; packets for a neighbour that is not known yet wait
+/// while it is solicited, and are sent once it advertises itself. Point-to-point links have no
+/// link-layer addresses, so there packets are passed down as they are.
+pub struct Ipv6Service {
+    interface: Arc<RwLock<Interface>>,
+    /// `None` on a point-to-point link.
+    mac: Option<MacAddress>,
+    neighbours: HashMap<Ipv6Address, MacAddress>,
+    unresolved: HashMap<Ipv6Address, Unresolved>,
+    routes: Arc<RwLock<RoutingTable>>,
+    /// The IPv6 services of the other interfaces, by interface name.
+    others: HashMap<String, ByteSender>,
+    forwarding: bool,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl Ipv6Service {
+    pub fn new(interface: Arc<RwLock<Interface>>, routes: Arc<RwLock<RoutingTable>>) -> Self {
+        let mac = {
+            let interface = interface.read().unwrap();
+            (!interface.is_point_to_point()).then(|| interface.mac())
+        };
+
+        Self {
+            interface,
+            mac,
+            neighbours: HashMap::new(),
+            unresolved: HashMap::new(),
+            routes,
+            others: HashMap::new(),
+            forwarding: false,
+            channels: Channels::new(),
+            actions: vec![],
+        }
+    }
+
+    /// Whether `packet` is addressed to one of our addresses, to all nodes, or to the
+    /// solicited-node group of one of our addresses.
+    pub fn accepts(&self, packet: &Ipv6Packet) -> bool {
+        let destination = packet.destination();
+        let interface = self.interface.read().unwrap();
+
+        destination == Ipv6Address::ALL_NODES
+            || interface.owns(destination)
+            || interface.ipv6_addresses().any(|a| a.solicited_node() == destination)
+    }
+
+    /// Sends the packets routed out of interface `name` to `sender`, the IPv6 service on it.
+    pub fn add_interface(&mut self, name: &str, sender: ByteSender) {
+        self.others.insert(name.to_owned(), sender);
+    }
+
+    pub fn set_forwarding(&mut self, forwarding: bool) {
+        self.forwarding = forwarding;
+    }
+
+    /// Whether `packet` is for another host and forwarding is on. Packets to a group, or from
+    /// or to a link-local address, never leave the link they were sent on.
+    pub fn forwards(&self, packet: &Ipv6Packet) -> bool {
+        let (source, destination) = (packet.source(), packet.destination());
+        self.forwarding
+            && !self.accepts(packet)
+            && !destination.is_multicast()
+            && !source.is_unspecified()
+            && !source.is_link_local()
+            && !destination.is_link_local()
+    }
+
+    /// Routes a packet for another host on, taking one off its hop limit, or tells its sender
+    /// why it cannot be. Packets too big for the next link are reported by the service of the
+    /// interface they go out of.
+    fn forward(&mut self, mut packet: Ipv6Packet) -> Result<(), NetServiceError> {
+        if packet.hop_limit() <= 1 {
+            let error = icmpv6::Packet::time_exceeded(TimeExceededCode::HopLimitExceeded, &packet);
+            return self.report(&packet, error);
+        }
+
+        if self.routes.read().unwrap().lookup(packet.destination()).is_none() {
+            let error = icmpv6::Packet::destination_unreachable(UnreachableCode::NoRoute, &packet);
+            return self.report(&packet, error);
+        }
+
+        packet.set_hop_limit(packet.hop_limit() - 1);
+        let mut bytes = vec![0u8; packet.byte_length()];
+        packet.serialise(&mut bytes);
+        self.send(Arc::from(bytes))
+    }
+
+    /// Whether an ICMPv6 error may be sent about `packet`: not if it was sent to a group, from
+    /// the unspecified address, or was an ICMPv6 error itself (RFC 4443).
+    fn may_report(packet: &Ipv6Packet) -> bool {
+        let icmp_error = packet.proto() == IpProtocol::Ipv6Icmp && icmpv6::Packet::deserialise(packet.data()).is_ok_and(|m| m.is_error());
+        !packet.destination().is_multicast() && !packet.source().is_unspecified() && !icmp_error
+    }
+
+    /// Sends an ICMPv6 error about a packet that did not come from us to its sender, from our
+    /// first address on this interface.
+    fn report(&mut self, packet: &Ipv6Packet, error: icmpv6::Packet) -> Result<(), NetServiceError> {
+        let source = self.interface.read().unwrap().ipv6_addresses().next();
+        match source {
+            Some(source) if Self::may_report(packet) => self.send_icmp(source, packet.source(), error, DEFAULT_HOP_LIMIT),
+            _ => Ok(()),
+        }
+    }
+
+    fn process_icmp(&mut self, packet: &Ipv6Packet) -> Result<(), NetServiceError> {
+        let message = icmpv6::Packet::deserialise(packet.data())?;
+        message.verify_checksum(&packet.pseudo_header())?;
+
+        if message.is_ndp() && packet.hop_limit() != NDP_HOP_LIMIT {
+            return Ok(());
+        }
+
+        if let Some(mac) = message.source_link_layer_address() {
+            if !packet.source().is_unspecified() {
+                self.learn(packet.source(), mac)?;
+            }
+        }
+
+        match message.message() {
+            Message::NeighborSolicitation { target, .. } if self.interface.read().unwrap().owns(*target) => {
+                let Some(mac) = self.mac else {
+                    return Ok(());
+                };
+
+                // Solicitations from an unspecified source are duplicate address detection, and are answered to all nodes.
+                let (destination, solicited) = match packet.source() {
+                    source if source.is_unspecified() => (Ipv6Address::ALL_NODES, false),
+                    source => (source, true),
+                };
+
+                let advertisement = icmpv6::Packet::neighbor_advertisement(*target, mac, false, solicited);
+                self.send_icmp(*target, destination, advertisement, NDP_HOP_LIMIT)
+            },
+            Message::NeighborAdvertisement { target, .. } => {
+                match message.target_link_layer_address() {
+                    Some(mac) => self.learn(*target, mac),
+                    None => Ok(()),
+                }
+            },
+            Message::EchoRequest { .. } if !packet.destination().is_multicast() => {
+                let reply = message.reply().unwrap();
+                self.send_icmp(packet.destination(), packet.source(), reply, DEFAULT_HOP_LIMIT)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn send_icmp(&mut self, source: Ipv6Address, destination: Ipv6Address, mut message: icmpv6::Packet, hop_limit: u8) -> Result<(), NetServiceError> {
+        message.fill_checksum(&PseudoHeader::Ipv6 { source, destination });
+
+        let mut bytes = vec![0u8; message.byte_length()];
+        message.serialise(&mut bytes);
+
+        let mut packet = Ipv6Packet::new(source, destination, IpProtocol::Ipv6Icmp, bytes);
+        packet.set_hop_limit(hop_limit);
+
+        let mut bytes = vec![0u8; packet.byte_length()];
+        packet.serialise(&mut bytes);
+        self.send(Arc::from(bytes))
+    }
+
+    /// Records the link-layer address of a neighbour, and sends the packets waiting for it.
+    fn learn(&mut self, address: Ipv6Address, mac: MacAddress) -> Result<(), NetServiceError> {
+        self.neighbours.insert(address, mac);
+        match self.unresolved.remove(&address) {
+            Some(unresolved) => unresolved.pending.into_iter().try_for_each(|data| self.send_frame(mac, data)),
+            None => Ok(()),
+        }
+    }
+
+    /// Queues a packet for a neighbour whose link-layer address is not known, soliciting it
+    /// if that has not started yet.
+    fn queue(&mut self, next_hop: Ipv6Address, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        let now = Instant::now();
+        let unresolved = self.unresolved.entry(next_hop).or_insert_with(|| Unresolved {
+            solicitations: 0,
+            next_solicitation: now,
+            pending: VecDeque::new(),
+        });
+
+        if unresolved.pending.len() == MAX_PENDING {
+            unresolved.pending.pop_front();
+        }
+        unresolved.pending.push_back(data);
+        self.on_tick(now)
+    }
+
+    fn send_frame(&self, destination: MacAddress, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        let Some(source) = self.mac else {
+            return self.send_down(data);
+        };
+
+        let frame = Frame::new(destination, source, EtherType::Ipv6, data.to_vec());
+        let mut bytes = vec![0u8; frame.byte_length()];
+        frame.serialise(&mut bytes);
+        self.send_down(Arc::from(bytes))
+    }
+
+    /// Asks for the link-layer address of `target`, from the first of our addresses.
+    fn solicit(&mut self, target: Ipv6Address) -> Result<(), NetServiceError> {
+        let Some(source) = self.interface.read().unwrap().ipv6_addresses().next() else {
+            return Ok(());
+        };
+
+        let solicitation = icmpv6::Packet::neighbor_solicitation(target, self.mac);
+        self.send_icmp(source, target.solicited_node(), solicitation, NDP_HOP_LIMIT)
+    }
+}
+
+impl NetService for Ipv6Service {
+    type Pdu = Ipv6Packet;
+
+    fn name(&self) -> &'static str {
+        "ipv6"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(pdu: &Self::Pdu) -> Option<Arc<[u8]>> {
+        Some(pdu.unwrap_data())
+    }
+
+    /// Routes packets for other hosts on, and answers Neighbor Discovery and pings for us.
+    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        if self.forwards(&pdu) {
+            return self.forward(pdu);
+        }
+
+        match pdu.proto() {
+            IpProtocol::Ipv6Icmp => self.process_icmp(&pdu),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers with an administratively prohibited error, unless the packet was sent to a
+    /// group or was itself an ICMPv6 error (RFC 4443).
+    fn reject(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        if !Self::may_report(&pdu) || !self.accepts(&pdu) {
+            return Ok(());
+        }
+
+        let error = icmpv6::Packet::destination_unreachable(UnreachableCode::AdministrativelyProhibited, &pdu);
+        self.send_icmp(pdu.destination(), pdu.source(), error, DEFAULT_HOP_LIMIT)
+    }
+
+    /// Solicits unresolved neighbours again, and drops the packets for those that never answer.
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        let mut solicit = vec![];
+        self.unresolved.retain(|address, unresolved| {
+            if now < unresolved.next_solicitation {
+                return true;
+            }
+            if unresolved.solicitations == MAX_MULTICAST_SOLICIT {
+                eprintln!("ipv6: could not resolve {address}, dropped {} packets", unresolved.pending.len());
+                return false;
+            }
+
+            unresolved.solicitations += 1;
+            unresolved.next_solicitation = now + RETRANS_TIMER;
+            solicit.push(*address);
+            true
+        });
+
+        solicit.into_iter().try_for_each(|address| self.solicit(address))
+    }
+
+    fn send(&mut self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        let header = Ipv6Header::deserialise(&data)?;
+        let destination = header.destination();
+
+        let next_hop = if destination.is_multicast() || destination.is_link_local() {
+            destination
+        } else {
+            let Some(route) = self.routes.read().unwrap().lookup(destination).cloned() else {
+                eprintln!("ipv6: no route to {destination}");
+                return Ok(());
+            };
+
+            if route.interface != self.interface.read().unwrap().name() {
+                return match self.others.get(&route.interface) {
+                    Some(other) => Ok(other.send(data)?),
+                    None => {
+                        eprintln!("ipv6: {destination} is routed out of {}, which the stack is not running on", route.interface);
+                        Ok(())
+                    },
+                };
+            }
+
+            match route.next_hop(destination.into()) {
+                ProtocolAddress::Ipv6Address(next_hop) => next_hop,
+                next_hop => {
+                    eprintln!("ipv6: the route to {destination} is through {next_hop}, which is not an IPv6 address");
+                    return Ok(());
+                },
+            }
+        };
+
+        // Routers do not fragment IPv6 packets, but tell their senders to send smaller ones.
+        let mtu = self.interface.read().unwrap().mtu();
+        if data.len() > mtu as usize && !self.interface.read().unwrap().owns(header.source()) {
+            let packet = Ipv6Packet::deserialise(&data)?;
+            let error = icmpv6::Packet::packet_too_big(mtu.into(), &packet);
+            return self.report(&packet, error);
+        }
+
+        if self.mac.is_none() {
+            return self.send_down(data);
+        }
+
+        if destination.is_multicast() {
+            self.send_frame(MacAddress::ipv6_multicast(destination), data)
+        } else if let Some(mac) = self.neighbours.get(&next_hop) {
+            self.send_frame(*mac, data)
+        } else {
+            self.queue(next_hop, data)
+        }
+    }
+}
+
+#[test]
+fn test_neighbour_resolution() {
+    use std::sync::mpsc;
+
+    use crate::netservice::ActionType;
+
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let peer_mac = MacAddress::from([0x02, 0, 0, 0, 0, 2]);
+    let (ours, peer) = (Ipv6Address::from(0x2001_0db8_u128 << 96 | 1), Ipv6Address::from(0x2001_0db8_u128 << 96 | 2));
+
+    let mut interface = Interface::new("tap0", mac, crate::interface::DEFAULT_MTU);
+    interface.add_address("2001:db8::1/64".parse().unwrap());
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+    routes.write().unwrap().add_connected(&interface);
+
+    let mut ipv6 = Ipv6Service::new(Arc::new(RwLock::new(interface)), routes);
+    ipv6.add_filter(ActionType::Process, |_, _| true, false);
+    let (send_down, sent) = mpsc::channel();
+    ipv6.set_send_down(send_down);
+
+    let packet = |source, destination, message: icmpv6::Packet, hop_limit| {
+        let mut message = message;
+        message.fill_checksum(&PseudoHeader::Ipv6 { source, destination });
+        let mut bytes = vec![0u8; message.byte_length()];
+        message.serialise(&mut bytes);
+
+        let mut packet = Ipv6Packet::new(source, destination, IpProtocol::Ipv6Icmp, bytes);
+        packet.set_hop_limit(hop_limit);
+        let mut bytes = vec![0u8; packet.byte_length()];
+        packet.serialise(&mut bytes);
+        Arc::<[u8]>::from(bytes)
+    };
+    let frame = || Frame::deserialise(&sent.try_recv().unwrap()).unwrap();
+
+    // A packet for an unknown neighbour waits while it is solicited.
+    let ping = packet(ours, peer, icmpv6::Packet::echo_request(1, 1, vec![]), DEFAULT_HOP_LIMIT);
+    ipv6.send(ping.clone()).unwrap();
+    let solicitation = frame();
+    assert_eq!(solicitation.destination(), MacAddress::ipv6_multicast(peer.solicited_node()));
+    assert_eq!(Ipv6Packet::deserialise(solicitation.data()).unwrap().destination(), peer.solicited_node());
+    assert!(sent.try_recv().is_err());
+
+    // Solicitations are repeated until the neighbour advertises itself, which sends the packet.
+    ipv6.on_tick(Instant::now() + RETRANS_TIMER).unwrap();
+    frame();
+    let advertisement = icmpv6::Packet::neighbor_advertisement(peer, peer_mac, false, true);
+    ipv6.receive(packet(peer, ours, advertisement, NDP_HOP_LIMIT)).unwrap();
+    let queued = frame();
+    assert_eq!((queued.destination(), queued.source()), (peer_mac, mac));
+    assert_eq!(queued.data(), &ping[..]);
+
+    // Packets for a neighbour that never answers are dropped after the last solicitation.
+    let other = Ipv6Address::from(0x2001_0db8_u128 << 96 | 3);
+    ipv6.send(packet(ours, other, icmpv6::Packet::echo_request(1, 2, vec![]), DEFAULT_HOP_LIMIT)).unwrap();
+    let now = Instant::now();
+    (1..=MAX_MULTICAST_SOLICIT).for_each(|i| ipv6.on_tick(now + RETRANS_TIMER * i).unwrap());
+    assert_eq!(sent.try_iter().count(), MAX_MULTICAST_SOLICIT as usize);
+    assert!(ipv6.unresolved.is_empty());
+}
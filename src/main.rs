@@ -1,69 +1,418 @@
-use std::io;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use rosi::common::{Layer, Serialise};
-use rosi::protocols::{ethernet, arp};
+use rosi::capture::{self, LinkType};
+use rosi::common::Serialise;
+use rosi::common::address::Ipv4Address;
+use rosi::common::log::{self as logging, FileSink, Logger, Stderr};
+use rosi::protocols::ethernet::EtherType;
+use rosi::protocols::icmp;
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+
+use arp::{ArpService, Resolver};
+use cli::{Cli, Command, Verbosity};
+use config::{Config, DhcpServerConfig, InterfaceConfig};
+use device::{Link, PcapDevice};
+use dhcp::{DhcpHandle, DhcpServer, DhcpServerService, DhcpService, LeaseDatabase};
+use ethernet::EthernetService;
+use firewall::{Chain, Firewall};
+use interface::Interface;
+use ipv4::Ipv4Service;
+use ipv6::Ipv6Service;
+use netservice::{ActionType, ByteReceiver, ByteSender, NetService, Stack};
+use route::RoutingTable;
+use tcp::{TcpListener, TcpService};
+use tun_tap::TunTap;
 
 mod netservice;
+mod cli;
+mod device;
+mod qos;
 mod tun_tap;
 mod ethernet;
+mod ipv4;
+mod ipv6;
+mod route;
+mod arp;
+mod config;
+mod dhcp;
+mod firewall;
+mod interface;
+mod tcp;
 
-fn main() -> io::Result<()> {
-    let tap = tun_tap::Iface::new("tap0", tun_tap::Mode::Tap)?;
+/// Port of the echo server run on top of the stack (RFC 862).
+const ECHO_PORT: u16 = 7;
+
+/// Time between echo requests, and how long the last one is waited for.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+const PING_DATA: &[u8] = b"rstack ping";
+
+/// How long the stack waits for the DHCP leases of its interfaces before giving up.
+const LEASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
 
+/// Answers every connection to the echo port until the stack stops.
+fn serve_echo(listener: TcpListener, verbosity: Verbosity) -> io::Result<()> {
     loop {
-        let mut buf = [0u8; 1522];
-        let len = tap.recv(&mut buf)?;
+        let (mut stream, peer) = listener.accept()?;
+        if verbosity > Verbosity::Quiet {
+            println!("echo: connection from {peer}");
+        }
 
-        let frame = match ethernet::Frame::deserialise(&buf[4..len]) {
-            Ok(frame) => frame,
-            Err(e) => {
-                eprint!("ethernet: {e}");
-                continue;
-            },
-        };
+        thread::spawn(move || {
+            let mut buf = [0u8; 1024];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => if stream.write_all(&buf[..len]).is_err() {
+                        break;
+                    },
+                }
+            }
+        });
+    }
+}
 
-        print!("\n{frame}");
-
-        match frame.ethertype() {
-            ethernet::EtherType::Arp => {
-                let arp_packet = match arp::Packet::deserialise(frame.data()) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!("arp: {e}");
-                        continue;
-                    }
-                };
-
-                let resp_packet = arp::Packet::response(
-                    arp_packet.sha(),
-                    arp_packet.tpa(),
-                    arp_packet.sha(),
-                    arp_packet.spa()
-                ).unwrap();
-
-                let mut resp_frame = ethernet::Frame::new(
-                    frame.source(),
-                    frame.source(),
-                    ethernet::EtherType::Arp,
-                    vec![],
-                );
-
-                println!("{arp_packet}");
-                println!("{resp_packet}");
-
-                resp_frame.wrap(&resp_packet);
-
-                println!("{resp_frame}");
-
-                let mut buf = vec![0u8; resp_frame.byte_length()];
-                resp_frame.serialise(&mut buf);
-
-                tap.send(&buf)?;
-            },
-            et => {
-                eprintln!("ignoring frame with ethertype {et}");
+/// Whether `packet` answers one of our echo requests.
+fn is_echo_reply(packet: &Ipv4Packet, identifier: u16) -> bool {
+    packet.proto() == IpProtocol::Icmp && matches!(
+        icmp::Packet::deserialise(packet.data()).as_ref().map(icmp::Packet::message),
+        Ok(icmp::Message::EchoReply { identifier: id, .. }) if *id == identifier
+    )
+}
+
+/// Sends `count` echo requests a second apart through the IPv4 service, and prints the
+/// replies that come back from it.
+fn ping(source: Ipv4Address, destination: Ipv4Address, identifier: u16, count: u16, send: ByteSender, replies: ByteReceiver) -> io::Result<()> {
+    println!("PING {destination} from {source}: {} data bytes", PING_DATA.len());
+
+    let mut sent = vec![];
+    let mut received = 0;
+    for sequence in 1..=count {
+        let request = icmp::Packet::echo_request(identifier, sequence, PING_DATA.to_vec());
+        let mut bytes = vec![0u8; request.byte_length()];
+        request.serialise(&mut bytes);
+
+        let packet = Ipv4Packet::new(source, destination, IpProtocol::Icmp, bytes);
+        let mut bytes = vec![0u8; packet.byte_length()];
+        packet.serialise(&mut bytes);
+
+        sent.push(Instant::now());
+        send.send(Arc::from(bytes)).map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+
+        let deadline = Instant::now() + PING_INTERVAL;
+        while let Ok(reply) = replies.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            let Ok(reply) = Ipv4Packet::deserialise(&reply) else {
                 continue;
+            };
+            let Ok(message) = icmp::Packet::deserialise(reply.data()) else {
+                continue;
+            };
+            let icmp::Message::EchoReply { sequence, data, .. } = message.message() else {
+                continue;
+            };
+            let Some(time) = (*sequence as usize).checked_sub(1).and_then(|i| sent.get(i)).map(Instant::elapsed) else {
+                continue;
+            };
+
+            received += 1;
+            println!(
+                "{} bytes from {}: icmp_seq={sequence} ttl={} time={:.1} ms",
+                data.len(), reply.source(), reply.ttl(), time.as_secs_f64() * 1000.0,
+            );
+        }
+    }
+
+    println!("--- {destination} ping statistics ---");
+    println!("{count} packets transmitted, {received} received");
+    Ok(())
+}
+
+fn print_arp_table(resolver: &Resolver) {
+    for (address, state, hardware) in resolver.entries() {
+        match hardware {
+            Some(hardware) => println!("{address} lladdr {hardware} {state:?}"),
+            None => println!("{address} {state:?}"),
+        }
+    }
+}
+
+/// The IP services of one interface, stacked on its running link but not started.
+struct InterfaceStack {
+    link: thread::JoinHandle<()>,
+    interface: Arc<RwLock<Interface>>,
+    ipv4: Ipv4Service,
+    ipv6: Ipv6Service,
+    /// `None` on a point-to-point link.
+    resolver: Option<Resolver>,
+    /// The DHCP client, if the interface leases its address.
+    dhcp: Option<DhcpHandle>,
+}
+
+/// Opens the device for `interface_config`, or the capture a replay reads, and stacks the IP
+/// services routing with `routes` on it.
+///
+/// A TAP device carries Ethernet, and the IP services go above it through ARP and Ethernet
+/// framing, which are started here along with the link and any DHCP client or server, so that
+/// a lease can be acquired before the IP services start. A TUN device carries bare IP packets,
+/// which go straight to the IP services, and can neither lease nor hand out addresses.
+fn attach(
+    command: &Command,
+    interface_config: &InterfaceConfig,
+    dhcp_server: Option<&DhcpServerConfig>,
+    firewall: &Firewall,
+    routes: &Arc<RwLock<RoutingTable>>,
+    verbosity: Verbosity,
+) -> io::Result<InterfaceStack> {
+    // Replaying a capture needs no kernel device, and so no privileges.
+    let mut link = match command {
+        Command::Replay { input, output } => Link::new(PcapDevice::open(input, output)?),
+        _ => Link::new(TunTap::new(&interface_config.name, interface_config.mode, interface_config.packet_info)?),
+    };
+    let ethernet_link = link.link_type() == LinkType::Ethernet;
+    if (interface_config.dhcp || dhcp_server.is_some()) && !ethernet_link {
+        return Err(invalid_input(format!("{}: DHCP needs a TAP device", link.ifname())));
+    }
+
+    if let Command::Capture { output } = command {
+        link.set_capture(capture::create(output, link.link_type())?);
+    }
+    if let Some(rate) = interface_config.egress_rate {
+        link.set_egress_rate(rate);
+    }
+    link.set_trace(verbosity == Verbosity::Verbose);
+
+    let mut interface = if ethernet_link {
+        Interface::new(link.ifname(), interface_config.mac, interface_config.mtu)
+    } else {
+        Interface::point_to_point(link.ifname(), interface_config.mtu)
+    };
+    interface_config.addresses.iter().for_each(|address| interface.add_address(*address));
+    let interface = Arc::new(RwLock::new(interface));
+
+    let mut ipv6 = Ipv6Service::new(interface.clone(), routes.clone());
+
+    let (ipv4, resolver, dhcp) = if ethernet_link {
+        let mut ethernet = EthernetService::new(interface.read().unwrap().mac());
+        let mut arp = ArpService::new(interface.clone(), routes.clone());
+        let mut ipv4 = Ipv4Service::new(interface.clone(), arp.resolver(), routes.clone());
+
+        ethernet.add_action(Chain::action(firewall.ethernet()));
+        link.stack(&mut ethernet, |_, _| true);
+
+        ethernet.add_filter(ActionType::Drop, |service, frame| !service.accepts(frame), false);
+
+        // The client is sent the whole frames it wants, before ARP and IPv4 see them.
+        let dhcp = interface_config.dhcp.then(|| {
+            let mut dhcp = DhcpService::new(interface.clone(), arp.resolver(), routes.clone(), ipv4.get_send_from_above());
+            let handle = dhcp.handle();
+            let wants = handle.clone();
+            dhcp.set_send_down(ethernet.get_send_from_above());
+            ethernet.add_filter(ActionType::ForwardTo(dhcp.get_send_up()), move |_, frame| wants.wants(frame), false);
+            dhcp.start();
+            handle
+        });
+
+        // So is the server, which answers from the interface's address on the pool's network.
+        if let Some(server_config) = dhcp_server {
+            let leases = match &server_config.leases {
+                Some(path) => LeaseDatabase::load(path)?,
+                None => LeaseDatabase::new(),
+            };
+            let address = interface.read().unwrap().addresses().iter().copied().find(|address| address.contains(server_config.pool.0.into()));
+            let server = address.and_then(|address| DhcpServer::new(address, server_config.clone(), leases))
+                .ok_or_else(|| invalid_input(format!("{}: no address on the network of the DHCP pool", link.ifname())))?;
+            if verbosity > Verbosity::Quiet {
+                println!("{}: serving DHCP from {} for {}-{}", link.ifname(), server.server_address(), server_config.pool.0, server_config.pool.1);
             }
+
+            let mut server = DhcpServerService::new(interface.clone(), server, ipv4.get_send_from_above());
+            server.set_send_down(ethernet.get_send_from_above());
+            ethernet.add_filter(ActionType::ForwardTo(server.get_send_up()), |_, frame| dhcp::to_server(frame), false);
+            server.start();
         }
+
+        ethernet.stack(&mut arp, |_, frame| frame.ethertype() == EtherType::Arp);
+        ethernet.stack(&mut ipv4, |_, frame| frame.ethertype() == EtherType::Ipv4);
+        ethernet.stack(&mut ipv6, |_, frame| frame.ethertype() == EtherType::Ipv6);
+        ethernet.add_filter(ActionType::Drop, |_, _| true, true);
+
+        let resolver = arp.resolver();
+        ethernet.start();
+        arp.start();
+        (ipv4, Some(resolver), dhcp)
+    } else {
+        let mut ipv4 = Ipv4Service::point_to_point(interface.clone(), routes.clone());
+        link.stack(&mut ipv4, |_, packet| device::ip_version(packet) == Some(4));
+        link.stack(&mut ipv6, |_, packet| device::ip_version(packet) == Some(6));
+        (ipv4, None, None)
+    };
+
+    Ok(InterfaceStack { link: link.start(), interface, ipv4, ipv6, resolver, dhcp })
+}
+
+fn main() -> io::Result<()> {
+    let Cli { command, options } = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprint!("rstack: {e}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    if command == Command::Help {
+        print!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    match &options.log_file {
+        Some(path) => logging::set_logger(Logger::new(FileSink::create(path)?, options.log_format)),
+        None => logging::set_logger(Logger::new(Stderr, options.log_format)),
+    }
+
+    let mut config = match &options.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if let Some(path) = &options.rules {
+        config.firewall = Firewall::load(path)?;
+    }
+
+    let verbosity = options.verbosity;
+    let forwarding = options.forwarding(&config);
+    let interface_configs = options.interfaces(&config);
+    let dhcp_server = options.dhcp_server(&config);
+    let firewall = config.firewall;
+
+    if let Some(name) = dhcp_server.as_ref().and_then(|server| server.interface.as_ref()) {
+        if !interface_configs.iter().any(|interface| interface.name == *name) {
+            return Err(invalid_input(format!("the DHCP server is for {name}, which the stack does not run on")));
+        }
+    }
+
+    // The table is shared by the services of every interface, and filled in once they are up.
+    let routes = Arc::new(RwLock::new(RoutingTable::new()));
+
+    // Only the first interface runs on the capture being replayed, or is recorded.
+    let mut stacks = interface_configs.iter().enumerate()
+        .map(|(i, interface_config)| {
+            let command = if i == 0 { &command } else { &Command::Run };
+            let dhcp_server = dhcp_server.as_ref().filter(|server| server.interface.as_ref() == Some(&interface_config.name));
+            attach(command, interface_config, dhcp_server, &firewall, &routes, verbosity)
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Routes and the addresses of TCP and ping may depend on the leases, so they come first.
+    for stack in &stacks {
+        let Some(dhcp) = &stack.dhcp else {
+            continue;
+        };
+        let name = stack.interface.read().unwrap().name().to_owned();
+        if verbosity > Verbosity::Quiet {
+            println!("{name}: waiting for a DHCP lease");
+        }
+        if dhcp.wait_for_lease(LEASE_TIMEOUT).is_none() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("{name}: no DHCP lease after {}s", LEASE_TIMEOUT.as_secs())));
+        }
+    }
+
+    let (local_address, mtu) = {
+        let interface = stacks[0].interface.read().unwrap();
+        let Some(address) = interface.ipv4_addresses().next() else {
+            return Err(invalid_input(format!("{} has no IPv4 address", interface.name())));
+        };
+        (address, interface.mtu())
+    };
+
+    {
+        let mut routes = routes.write().unwrap();
+        stacks.iter().for_each(|stack| routes.add_connected(&stack.interface.read().unwrap()));
+        config.routes.iter().try_for_each(|route| routes.add_static(route)).map_err(invalid_input)?;
+
+        if verbosity > Verbosity::Quiet {
+            stacks.iter().for_each(|stack| println!("{}", stack.interface.read().unwrap()));
+            print!("{routes}");
+            print!("{firewall}");
+        }
+    }
+
+    // Each IP service hands the packets routed out of another interface to the service there.
+    let senders = stacks.iter()
+        .map(|stack| (stack.interface.read().unwrap().name().to_owned(), stack.ipv4.get_send_from_above(), stack.ipv6.get_send_from_above()))
+        .collect::<Vec<_>>();
+    for stack in &mut stacks {
+        let name = stack.interface.read().unwrap().name().to_owned();
+        for (other, send_ipv4, send_ipv6) in senders.iter().filter(|(other, ..)| *other != name) {
+            stack.ipv4.add_interface(other, send_ipv4.clone());
+            stack.ipv6.add_interface(other, send_ipv6.clone());
+        }
+
+        stack.ipv4.set_forwarding(forwarding);
+        stack.ipv6.set_forwarding(forwarding);
+        stack.ipv4.add_action(Chain::action(firewall.ipv4()));
+        stack.ipv6.add_action(Chain::action(firewall.ipv6()));
+        stack.ipv4.add_filter(ActionType::Process, |service, packet| service.forwards(packet), false);
+        stack.ipv6.add_filter(ActionType::Process, |service, packet| service.forwards(packet), false);
+        stack.ipv4.add_filter(ActionType::Drop, |service, packet| !service.accepts(packet), false);
+        stack.ipv6.add_filter(ActionType::Drop, |service, packet| !service.accepts(packet), false);
+    }
+
+    // TCP and ping run over the first interface, and reach the others through its routes.
+    let mut tcp = TcpService::new(local_address, mtu);
+    tcp.set_send_down(stacks[0].ipv4.get_send_from_above());
+
+    // Echo replies for `ping` are taken out before the service would process them.
+    let identifier = std::process::id() as u16;
+    let (send_reply, replies) = mpsc::channel();
+
+    let ipv4 = &mut stacks[0].ipv4;
+    ipv4.add_filter(ActionType::ForwardTo(tcp.get_send_up()), |_, packet| packet.proto() == IpProtocol::Tcp, false);
+    if let Command::Ping { .. } = command {
+        ipv4.add_filter(ActionType::ForwardTo(send_reply), move |_, packet| is_echo_reply(packet, identifier), false);
+    }
+
+    let listener = TcpListener::bind(&tcp.sockets(), ECHO_PORT)?;
+    let send_ipv4 = stacks[0].ipv4.get_send_from_above();
+    let resolver = stacks[0].resolver.clone();
+
+    let mut links = vec![];
+    for mut stack in stacks {
+        stack.ipv4.add_filter(ActionType::Process, |_, packet| packet.proto() == IpProtocol::Icmp, true);
+        stack.ipv6.add_filter(ActionType::Process, |_, packet| packet.proto() == IpProtocol::Ipv6Icmp, true);
+
+        links.push(stack.link);
+        stack.ipv4.start();
+        stack.ipv6.start();
+    }
+    tcp.start();
+
+    match command {
+        Command::Run | Command::Capture { .. } | Command::Replay { .. } => {
+            thread::spawn(move || if let Err(e) = serve_echo(listener, verbosity) {
+                eprintln!("echo: {e}");
+            });
+
+            // Only a replay ever runs out of frames, and it only feeds the first interface.
+            links.swap_remove(0).join().unwrap();
+        },
+        Command::ArpTable { seconds } => {
+            thread::sleep(Duration::from_secs(seconds));
+            match resolver {
+                Some(resolver) => print_arp_table(&resolver),
+                None => eprintln!("rstack: a point-to-point link has no ARP table"),
+            }
+        },
+        Command::Ping { destination, count } => ping(local_address, destination, identifier, count, send_ipv4, replies)?,
+        Command::Help => unreachable!(),
+    }
+
+    if verbosity > Verbosity::Quiet {
+        print!("{firewall}");
     }
+    Ok(())
 }
@@ -1,25 +1,29 @@
 use std::sync::Arc;
-use std::sync::mpsc::{Receiver, RecvError, Sender, SendError};
+use std::sync::mpsc::{self, Receiver, RecvError, RecvTimeoutError, Sender, SendError};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use rosi::common::{DeserialiseError, Pdu};
+use rosi::common::{DeserialiseError, Pdu, Serialise};
 
 pub type ByteReceiver = Receiver<Arc<[u8]>>;
 pub type ByteSender = Sender<Arc<[u8]>>;
 
-// pub type PduFilter<S, T> = &'static dyn Fn(&S, &T) -> bool;
-// pub type Action<S, T> = (PduFilter<S, T>, FilterAction);
+/// How long a service waits for a PDU from below before it services its other queues and timers.
+const TICK: Duration = Duration::from_millis(10);
 
 macro_rules! net_service_error {
     (
         $($ident:ident($t:ty)),*
         $(,)?
     ) => {
-        pub enum NetServiceError<T> {
-            $($ident($t)),*
+        #[derive(Debug)]
+        pub enum NetServiceError {
+            $($ident($t),)*
+            NotConnected,
         }
 
         $(
-            impl<T> From<$t> for NetServiceError<T> {
+            impl From<$t> for NetServiceError {
                 fn from(value: $t) -> Self {
                     Self::$ident(value)
                 }
@@ -29,133 +33,298 @@ macro_rules! net_service_error {
 }
 
 net_service_error! {
-    SendError(SendError<T>),
+    SendError(SendError<Arc<[u8]>>),
     RecvError(RecvError),
     DeserialiseError(DeserialiseError),
     IoError(std::io::Error),
 }
 
-
-struct Send {
-    send_down: ByteSender,
-    receive_from_above: ByteReceiver,
+impl core::fmt::Display for NetServiceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::SendError(e) => write!(f, "{e}"),
+            Self::RecvError(e) => write!(f, "{e}"),
+            Self::DeserialiseError(e) => write!(f, "{e}"),
+            Self::IoError(e) => write!(f, "{e}"),
+            Self::NotConnected => write!(f, "no service below to send to"),
+        }
+    }
 }
 
-struct Recv {
+/// The channels joining a service to its neighbours.
+///
+/// Bytes from the layer below arrive on one queue and bytes from the layers above on
+/// another. Each service hands out senders for its own queues, and is given a sender for
+/// the from-above queue of the service below it.
+pub struct Channels {
     send_up: ByteSender,
     receive_from_below: ByteReceiver,
+    send_from_above: ByteSender,
+    receive_from_above: ByteReceiver,
+    send_down: Option<ByteSender>,
 }
 
+impl Channels {
+    pub fn new() -> Self {
+        let (send_up, receive_from_below) = mpsc::channel();
+        let (send_from_above, receive_from_above) = mpsc::channel();
+
+        Self {
+            send_up,
+            receive_from_below,
+            send_from_above,
+            receive_from_above,
+            send_down: None,
+        }
+    }
+
+    /// A sender for the queue from above, for a service that feeds its own queue.
+    pub fn send_from_above(&self) -> ByteSender {
+        self.send_from_above.clone()
+    }
+}
+
+#[derive(Clone)]
 pub enum ActionType {
     Drop,
     Process,
-    ForwardTo(ByteSender)
+    /// Drops the PDU and tells its sender, if the service knows how.
+    Reject,
+    /// Sends the whole PDU to another service.
+    ForwardTo(ByteSender),
+    /// Sends the payload of the PDU to the service above.
+    Unwrap(ByteSender),
 }
 
 impl From<&ActionType> for &str {
     fn from(value: &ActionType) -> Self {
         match value {
-            ActionType::Allow => "ALLOW",
-            ActionType::Drop => "DROP"
+            ActionType::Drop => "DROP",
+            ActionType::Process => "PROCESS",
+            ActionType::Reject => "REJECT",
+            ActionType::ForwardTo(..) => "FORWARD",
+            ActionType::Unwrap(..) => "UNWRAP",
         }
     }
 }
 
-pub type PduFilter<S: NetService> = &'static dyn Fn(&S, &S::Pdu) -> bool;
-pub type PduProcessor<S: NetService> = &'static dyn FnMut(&mut S, S::Pdu) -> Arc<[u8]>;
+pub type PduClassifier<S> = Box<dyn Fn(&S, &<S as NetService>::Pdu) -> Option<ActionType> + Send>;
 
-struct Action<S: NetService + 'static> {
-    action: ActionType,
-    filter: &'static dyn Fn(&S, &S::Pdu) -> bool,
+/// What a service does with the PDUs it receives.
+pub struct Action<S: NetService> {
+    classify: PduClassifier<S>,
     log: bool,
 }
 
-impl<S: NetService + 'static> Action<S> {
-    pub fn new(action: ActionType, filter: &'static dyn Fn(&S, &S::Pdu) -> bool, log: bool) -> Self {
-        Self { action, filter, log }
+impl<S: NetService> Action<S> {
+    /// Applies `action` to the PDUs matching `filter`.
+    pub fn new(action: ActionType, filter: impl Fn(&S, &S::Pdu) -> bool + Send + 'static, log: bool) -> Self {
+        Self::classifier(move |service, pdu| filter(service, pdu).then(|| action.clone()), log)
     }
 
-    pub fn should_process(&self, service: &S, pdu: S::Pdu) -> bool {
-        if self.filter(service, &pdu) {
-            if self.log {
-                pdu.log(self.action.into())
-            }
-
-            true
-        } else {
-            false
+    /// Chooses the action for each PDU with `classify`. PDUs it returns `None` for are left
+    /// to the actions after this one.
+    pub fn classifier(classify: impl Fn(&S, &S::Pdu) -> Option<ActionType> + Send + 'static, log: bool) -> Self {
+        Self {
+            classify: Box::new(classify),
+            log,
         }
     }
-}
 
-pub enum FilterAction {
-    LogAndBlock,
-    Block,
-    LogAndUnwrap(ByteSender),
-    Unwrap(ByteSender),
-    LogAndProcess,
-    Process,
+    pub fn classify(&self, service: &S, pdu: &S::Pdu) -> Option<ActionType> {
+        (self.classify)(service, pdu)
+    }
 }
 
-impl FilterAction {
-    pub fn unwrap<L: NetService>(layer: L) -> Self {
-        Self::Unwrap(layer.get_send_up())
+/// A protocol layer that runs on its own thread.
+///
+/// PDUs from below are matched against the service's actions in the order they were added,
+/// and the first match decides what happens to the PDU; anything unmatched is dropped.
+/// Bytes from above are handed to `send`.
+pub trait NetService: Sized + Send + 'static {
+    type Pdu: Pdu;
+
+    fn name(&self) -> &'static str;
+
+    fn channels(&self) -> &Channels;
+    fn channels_mut(&mut self) -> &mut Channels;
+
+    fn actions(&self) -> &[Action<Self>];
+    fn add_action(&mut self, action: Action<Self>);
+
+    /// Adds an action for the PDUs matching `filter`, after the existing ones.
+    fn add_filter(&mut self, action: ActionType, filter: impl Fn(&Self, &Self::Pdu) -> bool + Send + 'static, log: bool) {
+        self.add_action(Action::new(action, filter, log));
     }
 
-    pub fn log(self) -> Self {
-        match self {
-            Self::Block => Self::LogAndBlock,
-            Self::Process => Self::LogAndProcess,
-            Self::Unwrap(v) => Self::LogAndUnwrap(v),
-            a => a,
-        }
+    /// The payload carried by `pdu`, for services that encapsulate another layer.
+    fn unwrap_data(pdu: &Self::Pdu) -> Option<Arc<[u8]>>;
+
+    /// Handles a PDU that an action chose to process in this service.
+    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError>;
+
+    /// Handles a PDU that an action rejected. By default it is dropped without telling the sender.
+    fn reject(&mut self, _pdu: Self::Pdu) -> Result<(), NetServiceError> {
+        Ok(())
     }
-}
 
-pub trait NetService {
-    type Pdu: rosi::common::Pdu;
+    /// Handles bytes from a service above. By default they are passed down unchanged.
+    fn send(&mut self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        self.send_down(data)
+    }
 
-    fn actions(&self) -> &[(Option<PduFilter<Self>>, Option<PduProcessor<Self>>)];
-    fn add_action(&mut self, filter: Option<PduFilter<Self>>, processor: Option<PduProcessor<Self>>, action: ActionType);
+    /// Collects the PDUs that arrive from below in pieces, returning a whole one once its last
+    /// piece is in and `None` until then. By default every PDU arrives whole.
+    fn reassemble(&mut self, data: Arc<[u8]>) -> Result<Option<Arc<[u8]>>, NetServiceError> {
+        Ok(Some(data))
+    }
 
-    fn add_encapsulated_service(&mut self, send_up: ByteSender, filter: Option<PduFilter<Self>>, processor: Option<PduProcessor<Self>>) {
-        self.add_action(
-            filter,
-            processor,
-            move |service: &mut Self, packet: Self::Pdu| send_up.send(processor(service, packet))
-        )
+    /// Runs the service's timers. Called at least every `TICK`.
+    fn on_tick(&mut self, _now: Instant) -> Result<(), NetServiceError> {
+        Ok(())
     }
 
-    fn get_send_up(&self) -> ByteSender;
-    fn set_send_down(&mut self, sender: ByteSender);
-    fn get_send_from_above(&self) -> ByteReceiver;
+    /// The sender the service below uses to pass bytes up to this one.
+    fn get_send_up(&self) -> ByteSender {
+        self.channels().send_up.clone()
+    }
 
-    fn process(&mut self) -> Result<(), NetServiceError<Self::Pdu>> {
-        let pdu = self.recv().map_err(|e| e.into())?;
-        for (filter, processor) in self.actions().iter() {
-            if filter.is_some_and(|f| f(self, &pdu)) {
-                processor()
-            }
+    /// The sender services above use to pass bytes down to this one.
+    fn get_send_from_above(&self) -> ByteSender {
+        self.channels().send_from_above.clone()
+    }
+
+    fn set_send_down(&mut self, sender: ByteSender) {
+        self.channels_mut().send_down = Some(sender);
+    }
+
+    fn send_down(&self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        match &self.channels().send_down {
+            Some(sender) => Ok(sender.send(data)?),
+            None => Err(NetServiceError::NotConnected),
+        }
+    }
+
+    /// Parses bytes from below and applies the first matching action.
+    fn receive(&mut self, data: Arc<[u8]>) -> Result<(), NetServiceError> {
+        let Some(data) = self.reassemble(data)? else {
+            return Ok(());
         };
+        let pdu = Self::Pdu::deserialise(&data)?;
 
-        Ok(())
+        let decision = self.actions().iter().find_map(|action| action.classify(self, &pdu).map(|action_type| (action_type, action.log)));
+        let Some((action_type, log)) = decision else {
+            return Ok(());
+        };
+
+        if log {
+            pdu.log((&action_type).into());
+        }
+
+        match action_type {
+            ActionType::Drop => Ok(()),
+            ActionType::Process => self.process_pdu(pdu),
+            ActionType::Reject => self.reject(pdu),
+            ActionType::ForwardTo(sender) => Ok(sender.send(data)?),
+            ActionType::Unwrap(sender) => match Self::unwrap_data(&pdu) {
+                Some(payload) => Ok(sender.send(payload)?),
+                None => Err(NetServiceError::DeserialiseError(format!("{} does not encapsulate another layer", self.name()).into())),
+            },
+        }
     }
 
-    fn process_pdu(&mut self, pdu: Self::Pdu) -> Result<(), NetServiceError<Self::Pdu>>;
+    /// Services both queues and the timers until the process exits.
+    fn run(mut self) {
+        loop {
+            let data = match self.channels().receive_from_below.recv_timeout(TICK) {
+                Ok(data) => Some(data),
+                Err(RecvTimeoutError::Timeout) => None,
+                // The service holds a sender for its own queue, so it is never disconnected.
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            };
+
+            if let Some(Err(e)) = data.map(|data| self.receive(data)) {
+                eprintln!("{}: {e}", self.name());
+            }
 
-    fn recv(&self) -> Result<Self::Pdu, NetServiceError<Self::Pdu>>;
+            while let Ok(data) = self.channels().receive_from_above.try_recv() {
+                if let Err(e) = self.send(data) {
+                    eprintln!("{}: {e}", self.name());
+                }
+            }
+
+            if let Err(e) = self.on_tick(Instant::now()) {
+                eprintln!("{}: {e}", self.name());
+            }
+        }
+    }
+
+    fn start(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
 }
 
+/// Composition of services: `S` may be placed directly above `Self`.
 pub trait Stack<S: NetService>: NetService {
-    fn processor(service: &mut Self, packet: Self::Pdu, send: &'static dyn FnOnce(ByteSender) -> Result<(), RecvError>) -> Result<(), NetServiceError<Self::Pdu>>;
-
-    fn stack(&self, service: &mut S, filter: Option<PduFilter<Self>>) {
+    /// Sends the payload of every PDU matching `filter` up to `service`, and points
+    /// `service` at this one for everything it sends down.
+    fn stack(&mut self, service: &mut S, filter: impl Fn(&Self, &Self::Pdu) -> bool + Send + 'static) {
         service.set_send_down(self.get_send_from_above());
-
-        self.add_encapsulated_service(
-            service.get_send_up(),
-            filter,
-            &Self::processor
-        );
+        self.add_filter(ActionType::Unwrap(service.get_send_up()), filter, false);
     }
 }
+
+#[test]
+fn test_stack_dispatch() {
+    use std::sync::RwLock;
+
+    use rosi::common::Layer;
+    use rosi::common::address::{Ipv4Address, MacAddress};
+    use rosi::protocols::{arp, ethernet};
+
+    use crate::arp::ArpService;
+    use crate::ethernet::EthernetService;
+    use crate::interface::{Interface, DEFAULT_MTU};
+    use crate::route::RoutingTable;
+
+    let mac = MacAddress::from([0x02, 0, 0, 0, 0, 1]);
+    let mut interface = Interface::new("tap0", mac, DEFAULT_MTU);
+    interface.add_address("10.0.0.2/24".parse().unwrap());
+
+    let mut ethernet = EthernetService::new(mac);
+    let mut arp = ArpService::new(Arc::new(RwLock::new(interface)), Arc::new(RwLock::new(RoutingTable::new())));
+    arp.resolver().announce(Ipv4Address::from([10, 0, 0, 2]).into());
+    ethernet.add_filter(ActionType::Drop, |service, frame| !service.accepts(frame), false);
+    ethernet.stack(&mut arp, |_, frame| frame.ethertype() == ethernet::EtherType::Arp);
+
+    let (send_down, receive_down) = mpsc::channel();
+    ethernet.set_send_down(send_down);
+
+    let peer = MacAddress::from([0x02, 0, 0, 0, 0, 2]);
+    let request = arp::Packet::request(peer.into(), Ipv4Address::from([10, 0, 0, 1]).into(), MacAddress::default().into(), Ipv4Address::from([10, 0, 0, 2]).into()).unwrap();
+
+    let frame = |destination| {
+        let mut frame = ethernet::Frame::new(destination, peer, ethernet::EtherType::Arp, vec![]);
+        frame.wrap(&request);
+        let mut buf = vec![0u8; frame.byte_length()];
+        frame.serialise(&mut buf);
+        Arc::<[u8]>::from(buf)
+    };
+
+    // Frames for someone else are dropped, and ours are unwrapped for the service above.
+    ethernet.receive(frame(peer)).unwrap();
+    assert!(arp.channels().receive_from_below.try_recv().is_err());
+
+    ethernet.receive(frame(mac)).unwrap();
+    let payload = arp.channels().receive_from_below.try_recv().unwrap();
+    assert_eq!(arp::Packet::deserialise(&payload).unwrap(), request);
+
+    // Whatever the service above sends down comes out of the service below it.
+    arp.receive(payload).unwrap();
+    let reply = ethernet.channels().receive_from_above.try_recv().unwrap();
+    ethernet.send(reply).unwrap();
+
+    let reply = ethernet::Frame::deserialise(&receive_down.try_recv().unwrap()).unwrap();
+    assert_eq!(reply.destination(), peer);
+    assert_eq!(arp::Packet::deserialise(reply.data()).unwrap().tpa(), Ipv4Address::from([10, 0, 0, 1]).into());
+}
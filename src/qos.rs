@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rosi::capture::LinkType;
+use rosi::common::{Layer, Serialise};
+use rosi::protocols::ethernet::{EtherType, Frame};
+use rosi::protocols::ipv4::{Dscp, Ecn, Ipv4Packet};
+use rosi::protocols::ipv6::Ipv6Packet;
+
+use super::device;
+
+/// Number of priority bands. Band 0 is always served first.
+const BANDS: usize = 6;
+
+/// Frames held in a band before new ones are dropped.
+const LIMIT: usize = 64;
+
+/// Frames in a band from which ECN-capable packets joining it are marked as having met congestion.
+const MARK_THRESHOLD: usize = 16;
+
+/// Bytes a rate-limited queue may send at once after being idle: 10ms at its rate, and at least
+/// one full-sized frame.
+const MIN_BURST: f64 = 1522.0;
+
+/// The band a DSCP is served from, loosely following the service classes of RFC 4594.
+fn band(dscp: Dscp) -> usize {
+    match dscp {
+        Dscp::CS6 | Dscp::CS7 => 0,
+        Dscp::EF | Dscp::VOICE_ADMIT | Dscp::CS5 => 1,
+        Dscp::CS1 | Dscp::LE => 5,
+        dscp if dscp.class() >= 3 => 2,
+        dscp if dscp.class() >= 1 => 3,
+        _ => 4,
+    }
+}
+
+/// The IP packet carried by a frame on a link of `link_type`, with its Ethernet framing if any.
+enum Carried {
+    Ipv4(Option<Frame>, Ipv4Packet),
+    Ipv6(Option<Frame>, Ipv6Packet),
+}
+
+impl Carried {
+    fn parse(link_type: LinkType, frame: &[u8]) -> Option<Self> {
+        let (framing, packet) = match link_type {
+            LinkType::Ethernet => {
+                let frame = Frame::deserialise(frame).ok()?;
+                let version = match frame.ethertype() {
+                    EtherType::Ipv4 => 4,
+                    EtherType::Ipv6 => 6,
+                    _ => return None,
+                };
+                let packet = frame.data().to_vec();
+                (Some(frame), (version, packet))
+            },
+            _ => (None, (device::ip_version(frame)?, frame.to_vec())),
+        };
+
+        match packet {
+            (4, packet) => Some(Self::Ipv4(framing, Ipv4Packet::deserialise(&packet).ok()?)),
+            (6, packet) => Some(Self::Ipv6(framing, Ipv6Packet::deserialise(&packet).ok()?)),
+            _ => None,
+        }
+    }
+
+    fn codepoints(&self) -> (Dscp, Ecn) {
+        match self {
+            Self::Ipv4(_, packet) => (packet.dscp(), packet.ecn()),
+            Self::Ipv6(_, packet) => (packet.dscp(), packet.ecn()),
+        }
+    }
+
+    /// The frame with the packet marked as having met congestion.
+    fn mark(self) -> Arc<[u8]> {
+        match self {
+            Self::Ipv4(framing, mut packet) => {
+                packet.set_ecn(Ecn::Ce);
+                reframe(framing, &packet)
+            },
+            Self::Ipv6(framing, mut packet) => {
+                packet.set_ecn(Ecn::Ce);
+                reframe(framing, &packet)
+            },
+        }
+    }
+}
+
+fn reframe(framing: Option<Frame>, packet: &dyn Serialise) -> Arc<[u8]> {
+    let mut bytes;
+    match framing {
+        Some(mut frame) => {
+            frame.wrap(packet);
+            bytes = vec![0u8; frame.byte_length()];
+            frame.serialise(&mut bytes);
+        },
+        None => {
+            bytes = vec![0u8; packet.byte_length()];
+            packet.serialise(&mut bytes);
+        },
+    }
+    Arc::from(bytes)
+}
+
+/// A priority queue for the frames a link sends, scheduled by the DSCP of the packets they
+/// carry. Frames that carry no IP packet, such as ARP, go in the first band.
+///
+/// A band that grows past a threshold marks the ECN-capable packets joining it with
+/// congestion experienced, and once full drops new frames. The queue only grows when frames
+/// arrive faster than it sends them, so with no rate it is mostly there to put urgent frames
+/// first within a burst.
+pub struct EgressQueue {
+    link_type: LinkType,
+    bands: [VecDeque<Arc<[u8]>>; BANDS],
+    /// Bytes per second the queue sends at most, or `None` for as fast as the device takes them.
+    rate: Option<u64>,
+    /// Bytes that may be sent now, refilled at `rate`. Sending a frame may take it below zero.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl EgressQueue {
+    pub fn new(link_type: LinkType, rate: Option<u64>, now: Instant) -> Self {
+        Self {
+            link_type,
+            bands: Default::default(),
+            rate,
+            tokens: 0.0,
+            refilled: now,
+        }
+    }
+
+    /// Queues a frame, returning false if its band was full and the frame was dropped.
+    pub fn enqueue(&mut self, frame: Arc<[u8]>) -> bool {
+        let carried = Carried::parse(self.link_type, &frame);
+        let (dscp, ecn) = carried.as_ref().map_or((Dscp::CS7, Ecn::NotEct), Carried::codepoints);
+
+        let band = &mut self.bands[band(dscp)];
+        if band.len() >= LIMIT {
+            return false;
+        }
+
+        match carried {
+            Some(carried) if band.len() >= MARK_THRESHOLD && ecn.is_capable() && ecn != Ecn::Ce => band.push_back(carried.mark()),
+            _ => band.push_back(frame),
+        }
+        true
+    }
+
+    /// The next frame to send, if there is one and the rate allows it.
+    pub fn dequeue(&mut self, now: Instant) -> Option<Arc<[u8]>> {
+        if let Some(rate) = self.rate {
+            let burst = (rate as f64 / 100.0).max(MIN_BURST);
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(burst);
+            self.refilled = now;
+
+            if self.tokens <= 0.0 {
+                return None;
+            }
+        }
+
+        let frame = self.bands.iter_mut().find_map(VecDeque::pop_front)?;
+        self.tokens -= frame.len() as f64;
+        Some(frame)
+    }
+}
+
+#[test]
+fn test_egress_queue() {
+    use std::time::Duration;
+
+    use rosi::common::address::Ipv4Address;
+    use rosi::protocols::ipv4::IpProtocol;
+
+    let packet = |dscp, ecn, length| {
+        let mut packet = Ipv4Packet::new(Ipv4Address::from([10, 0, 0, 2]), Ipv4Address::from([10, 0, 0, 1]), IpProtocol::Udp, vec![0; length]);
+        packet.set_dscp(dscp);
+        packet.set_ecn(ecn);
+        reframe(None, &packet)
+    };
+    let codepoints = |frame: &Arc<[u8]>| Carried::parse(LinkType::Raw, frame).unwrap().codepoints();
+
+    let now = Instant::now();
+    let mut queue = EgressQueue::new(LinkType::Raw, None, now);
+
+    // Higher priority classes jump the queue.
+    for dscp in [Dscp::CS1, Dscp::DF, Dscp::AF21, Dscp::AF41, Dscp::EF, Dscp::CS6] {
+        assert!(queue.enqueue(packet(dscp, Ecn::NotEct, 10)));
+    }
+    let order = std::iter::from_fn(|| queue.dequeue(now)).map(|frame| codepoints(&frame).0).collect::<Vec<_>>();
+    assert_eq!(order, [Dscp::CS6, Dscp::EF, Dscp::AF41, Dscp::AF21, Dscp::DF, Dscp::CS1]);
+
+    // A long band marks the ECN-capable packets joining it, and a full one drops them.
+    for _ in 0..LIMIT {
+        assert!(queue.enqueue(packet(Dscp::DF, Ecn::Ect0, 10)));
+    }
+    assert!(!queue.enqueue(packet(Dscp::DF, Ecn::Ect0, 10)));
+
+    let marks = std::iter::from_fn(|| queue.dequeue(now)).map(|frame| codepoints(&frame).1).collect::<Vec<_>>();
+    assert_eq!(marks.len(), LIMIT);
+    assert!(marks[..MARK_THRESHOLD].iter().all(|ecn| *ecn == Ecn::Ect0));
+    assert!(marks[MARK_THRESHOLD..].iter().all(|ecn| *ecn == Ecn::Ce));
+    assert!(queue.bands.iter().all(VecDeque::is_empty));
+
+    // At 100000 bytes per second, the queue sends a burst of at most one frame past 1522 bytes.
+    let mut queue = EgressQueue::new(LinkType::Raw, Some(100_000), now);
+    (0..3).for_each(|_| assert!(queue.enqueue(packet(Dscp::DF, Ecn::NotEct, 980))));
+    let later = now + Duration::from_millis(20);
+    assert!(queue.dequeue(later).is_some());
+    assert!(queue.dequeue(later).is_some());
+    assert!(queue.dequeue(later).is_none());
+    assert!(queue.dequeue(later + Duration::from_millis(20)).is_some());
+}
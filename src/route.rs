@@ -0,0 +1,183 @@
+use std::cmp::Reverse;
+
+use rosi::protocols::arp::ProtocolAddress;
+
+use super::config::RouteConfig;
+use super::interface::{Interface, InterfaceAddress};
+
+/// Where a route came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The network of an address on an interface, which is reached without a gateway.
+    Connected,
+    /// A route from the configuration.
+    Static,
+    /// A route that came with a DHCP lease, which goes when the lease does.
+    Dhcp,
+}
+
+/// A way to reach the addresses in `destination`: out of `interface`, and through `gateway`
+/// if the route has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub destination: InterfaceAddress,
+    pub gateway: Option<ProtocolAddress>,
+    pub interface: String,
+    pub metric: u32,
+    pub origin: Origin,
+}
+
+impl Route {
+    pub fn is_default(&self) -> bool {
+        self.destination.prefix_length() == 0
+    }
+
+    /// The address whose hardware address a packet for `destination` is sent to.
+    pub fn next_hop(&self, destination: ProtocolAddress) -> ProtocolAddress {
+        self.gateway.unwrap_or(destination)
+    }
+}
+
+impl core::fmt::Display for Route {
+    /// Writes the route the way `ip route` does, such as `default via 10.0.0.1 dev tap0 metric 10`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.is_default() {
+            write!(f, "default")?;
+        } else {
+            write!(f, "{}", self.destination)?;
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {gateway}")?;
+        }
+        write!(f, " dev {}", self.interface)?;
+        match self.origin {
+            Origin::Connected => write!(f, " proto connected")?,
+            Origin::Dhcp => write!(f, " proto dhcp")?,
+            Origin::Static => (),
+        }
+        if self.metric != 0 {
+            write!(f, " metric {}", self.metric)?;
+        }
+        Ok(())
+    }
+}
+
+/// The routes of every interface, which the IP services look the next hop of each packet up in.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `route`, replacing any to the same destination through the same gateway and interface.
+    pub fn add(&mut self, route: Route) {
+        self.routes.retain(|r| (&r.destination, r.gateway, &r.interface) != (&route.destination, route.gateway, &route.interface));
+        self.routes.push(route);
+    }
+
+    /// Keeps only the routes for which `keep` returns true.
+    pub fn retain(&mut self, keep: impl FnMut(&Route) -> bool) {
+        self.routes.retain(keep);
+    }
+
+    /// Adds a connected route to the network of every address on `interface`.
+    pub fn add_connected(&mut self, interface: &Interface) {
+        for address in interface.addresses() {
+            self.add(Route {
+                destination: address.network(),
+                gateway: None,
+                interface: interface.name().to_owned(),
+                metric: 0,
+                origin: Origin::Connected,
+            });
+        }
+    }
+
+    /// Adds a route from the configuration, which has checked that it has a gateway of the
+    /// right family or an interface. One without an interface goes out of the interface whose
+    /// connected network its gateway is on, so those have to be added first.
+    pub fn add_static(&mut self, config: &RouteConfig) -> Result<(), String> {
+        let destination = config.destination.network();
+
+        let interface = match (&config.interface, config.gateway) {
+            (Some(interface), _) => interface.clone(),
+            (None, Some(gateway)) => self.routes.iter()
+                .find(|r| r.origin == Origin::Connected && r.destination.contains(gateway))
+                .map(|r| r.interface.clone())
+                .ok_or_else(|| format!("gateway {gateway} is not on a connected network"))?,
+            (None, None) => return Err(format!("route to {destination} has neither a gateway nor an interface")),
+        };
+
+        self.add(Route {
+            destination,
+            gateway: config.gateway,
+            interface,
+            metric: config.metric,
+            origin: Origin::Static,
+        });
+        Ok(())
+    }
+
+    /// The route to `destination`: of those whose network contains it, the one with the longest
+    /// prefix, and of those the one with the lowest metric.
+    pub fn lookup(&self, destination: impl Into<ProtocolAddress>) -> Option<&Route> {
+        let destination = destination.into();
+        self.routes.iter()
+            .filter(|r| r.destination.contains(destination))
+            .min_by_key(|r| (Reverse(r.destination.prefix_length()), r.metric))
+    }
+}
+
+impl core::fmt::Display for RoutingTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.routes.iter().try_for_each(|route| writeln!(f, "{route}"))
+    }
+}
+
+#[test]
+fn test_routing_table() {
+    use rosi::common::address::{Ipv4Address, MacAddress};
+
+    use crate::interface::DEFAULT_MTU;
+
+    let mut tap0 = Interface::new("tap0", MacAddress::from([0x02, 0, 0, 0, 0, 1]), DEFAULT_MTU);
+    tap0.add_address("10.0.0.2/24".parse().unwrap());
+    let mut tap1 = Interface::new("tap1", MacAddress::from([0x02, 0, 0, 0, 0, 2]), DEFAULT_MTU);
+    tap1.add_address("10.0.1.1/24".parse().unwrap());
+
+    let mut table = RoutingTable::new();
+    table.add_connected(&tap0);
+    table.add_connected(&tap1);
+
+    let route = |destination: &str, gateway: [u8; 4], metric| RouteConfig {
+        destination: destination.parse().unwrap(),
+        gateway: Some(Ipv4Address::from(gateway).into()),
+        interface: None,
+        metric,
+    };
+    table.add_static(&route("0.0.0.0/0", [10, 0, 0, 1], 10)).unwrap();
+    table.add_static(&route("0.0.0.0/0", [10, 0, 1, 254], 5)).unwrap();
+    table.add_static(&route("192.168.7.9/16", [10, 0, 0, 3], 0)).unwrap();
+
+    let lookup = |table: &RoutingTable, address: [u8; 4]| table.lookup(Ipv4Address::from(address)).map(ToString::to_string);
+    assert_eq!(lookup(&table, [10, 0, 0, 9]).as_deref(), Some("10.0.0.0/24 dev tap0 proto connected"));
+    assert_eq!(lookup(&table, [10, 0, 1, 9]).as_deref(), Some("10.0.1.0/24 dev tap1 proto connected"));
+    assert_eq!(lookup(&table, [192, 168, 1, 1]).as_deref(), Some("192.168.0.0/16 via 10.0.0.3 dev tap0"));
+    assert_eq!(lookup(&table, [8, 8, 8, 8]).as_deref(), Some("default via 10.0.1.254 dev tap1 metric 5"));
+
+    let default = table.lookup(Ipv4Address::from([8, 8, 8, 8])).unwrap();
+    assert_eq!(default.next_hop(Ipv4Address::from([8, 8, 8, 8]).into()), Ipv4Address::from([10, 0, 1, 254]).into());
+
+    // Adding the same route again replaces it.
+    table.add_static(&route("0.0.0.0/0", [10, 0, 1, 254], 20)).unwrap();
+    assert_eq!(lookup(&table, [8, 8, 8, 8]).as_deref(), Some("default via 10.0.0.1 dev tap0 metric 10"));
+    assert_eq!(table.routes.len(), 5);
+
+    assert!(table.lookup(crate::interface::parse_address("fe80::1").unwrap()).is_none());
+    assert_eq!(table.add_static(&route("10.2.0.0/16", [172, 16, 0, 1], 0)), Err("gateway 172.16.0.1 is not on a connected network".into()));
+    assert!(table.add_static(&RouteConfig { gateway: None, ..route("10.2.0.0/16", [0; 4], 0) }).is_err());
+}
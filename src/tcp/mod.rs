@@ -0,0 +1,5 @@
+mod rto;
+mod socket;
+mod tcb;
+
+pub use socket::{TcpListener, TcpService};
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Clock granularity, which is the interval the TCP service ticks its timers at.
+pub(super) const GRANULARITY: Duration = Duration::from_millis(10);
+
+/// Retransmission timeout estimator (RFC 6298).
+#[derive(Debug, Clone)]
+pub(super) struct RtoEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl Default for RtoEstimator {
+    fn default() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+}
+
+impl RtoEstimator {
+    pub(super) fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    /// Folds in a round-trip time measured from a segment that was not retransmitted.
+    pub(super) fn sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            },
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + delta / 4;
+                srtt * 7 / 8 + rtt / 8
+            },
+        };
+
+        self.srtt = Some(srtt);
+        self.rto = (srtt + GRANULARITY.max(self.rttvar * 4)).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Doubles the timeout after the retransmission timer expires.
+    pub(super) fn backoff(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+}
+
+#[test]
+fn test_rto_estimator() {
+    let mut rto = RtoEstimator::default();
+    assert_eq!(rto.rto(), INITIAL_RTO);
+
+    rto.sample(Duration::from_millis(100));
+    assert_eq!(rto.rto(), MIN_RTO);
+
+    let mut rto = RtoEstimator::default();
+    rto.sample(Duration::from_secs(2));
+    assert_eq!(rto.rto(), Duration::from_secs(6));
+
+    rto.backoff();
+    assert_eq!(rto.rto(), Duration::from_secs(12));
+
+    (0..4).for_each(|_| rto.backoff());
+    assert_eq!(rto.rto(), MAX_RTO);
+}
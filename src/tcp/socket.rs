@@ -0,0 +1,467 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Instant;
+
+use rosi::common::address::Ipv4Address;
+use rosi::common::{Layer, Serialise};
+use rosi::protocols::ipv4::{IpProtocol, Ipv4Packet};
+use rosi::protocols::tcp::{Flags, Segment};
+
+use crate::netservice::{Action, ActionType, ByteSender, Channels, NetService, NetServiceError};
+
+use super::tcb::{Endpoint, Quad, State, Tcb, TcpError};
+
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+struct Connection {
+    tcb: Tcb,
+    /// The listening port a passively opened connection is waiting to be accepted on.
+    listen_port: Option<u16>,
+    /// Set once no `TcpStream` refers to the connection any more.
+    detached: bool,
+}
+
+struct Connections {
+    local_address: Ipv4Address,
+    mss: u16,
+    /// The service's own queue of packets from above, which it sends down from its thread.
+    send_down: ByteSender,
+    listeners: HashMap<u16, VecDeque<Quad>>,
+    connections: HashMap<Quad, Connection>,
+    next_port: u16,
+    started: Instant,
+    secret: u64,
+}
+
+impl Connections {
+    /// Initial sequence numbers follow RFC 6528: a 4µs clock plus a keyed hash of the quad.
+    fn iss(&self, quad: &Quad) -> u32 {
+        let mut hasher = DefaultHasher::new();
+        self.secret.hash(&mut hasher);
+        quad.hash(&mut hasher);
+
+        let clock = (self.started.elapsed().as_micros() / 4) as u32;
+        clock.wrapping_add(hasher.finish() as u32)
+    }
+
+    fn ephemeral_port(&mut self, remote: Endpoint) -> Option<u16> {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+
+            let quad = Quad { local: Endpoint { address: self.local_address, port }, remote };
+            if !self.listeners.contains_key(&port) && !self.connections.contains_key(&quad) {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+
+    fn send_segment(&self, quad: &Quad, mut seg: Segment) {
+        let mut packet = Ipv4Packet::new(quad.local.address, quad.remote.address, IpProtocol::Tcp, vec![]);
+        seg.fill_checksum(&packet.pseudo_header());
+        packet.wrap(&seg);
+
+        let mut buf = vec![0u8; packet.byte_length()];
+        packet.serialise(&mut buf);
+
+        // The service holds the receiving end of its own queue, so this cannot fail.
+        let _ = self.send_down.send(buf.into());
+    }
+
+    /// Transmits a connection's pending segments, queues it for accept once established,
+    /// and forgets it once it is closed and nobody is left to observe that.
+    fn transmit(&mut self, quad: &Quad) {
+        let Some(connection) = self.connections.get_mut(quad) else {
+            return;
+        };
+
+        let segments = connection.tcb.take_output();
+        let state = connection.tcb.state();
+
+        if let Some(port) = connection.listen_port {
+            if !connection.tcb.is_synchronising() {
+                connection.listen_port = None;
+                match self.listeners.get_mut(&port) {
+                    Some(queue) if state != State::Closed => queue.push_back(*quad),
+                    _ => connection.detached = true,
+                }
+            } else if state == State::Closed {
+                connection.detached = true;
+            }
+        }
+
+        let remove = state == State::Closed && connection.detached;
+
+        segments.into_iter().for_each(|seg| self.send_segment(quad, seg));
+
+        if remove {
+            self.connections.remove(quad);
+        }
+    }
+
+    fn on_packet(&mut self, packet: &Ipv4Packet, now: Instant) {
+        if packet.proto() != IpProtocol::Tcp || packet.destination() != self.local_address {
+            return;
+        }
+
+        let Ok(seg) = Segment::deserialise(packet.data()) else {
+            return;
+        };
+
+        if seg.verify_checksum(&packet.pseudo_header()).is_err() {
+            return;
+        }
+
+        let quad = Quad {
+            local: Endpoint { address: packet.destination(), port: seg.destination_port() },
+            remote: Endpoint { address: packet.source(), port: seg.source_port() },
+        };
+
+        let flags = seg.flags();
+        if let Some(connection) = self.connections.get_mut(&quad) {
+            connection.tcb.on_segment(&seg, now);
+        } else if self.listeners.contains_key(&quad.local.port) && flags.contains(Flags::SYN) && !flags.intersects(Flags::ACK | Flags::RST) {
+            let tcb = Tcb::accept(quad, &seg, self.iss(&quad), self.mss, now);
+            self.connections.insert(quad, Connection { tcb, listen_port: Some(quad.local.port), detached: false });
+        } else {
+            if let Some(reset) = Tcb::reset_for(&seg) {
+                self.send_segment(&quad, reset);
+            }
+            return;
+        }
+
+        self.transmit(&quad);
+    }
+
+    fn on_tick(&mut self, now: Instant) {
+        let quads: Vec<Quad> = self.connections.keys().copied().collect();
+        for quad in quads {
+            if let Some(connection) = self.connections.get_mut(&quad) {
+                connection.tcb.on_tick(now);
+            }
+
+            self.transmit(&quad);
+        }
+    }
+}
+
+struct Shared {
+    connections: Mutex<Connections>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Connections> {
+        self.connections.lock().unwrap()
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, Connections>) -> MutexGuard<'a, Connections> {
+        self.changed.wait(guard).unwrap()
+    }
+}
+
+/// A handle to the connections of a `TcpService`, for opening sockets on it once the service
+/// has been started.
+#[derive(Clone)]
+pub struct Sockets {
+    shared: Arc<Shared>,
+}
+
+/// Terminates TCP for a single local IPv4 address.
+///
+/// The service exchanges whole IPv4 packets with the layer below it, and processes every
+/// packet it is sent. Segments are built both on its thread and on those of the streams, so
+/// they all go through its own queue from above, and are sent down from its thread.
+pub struct TcpService {
+    shared: Arc<Shared>,
+    channels: Channels,
+    actions: Vec<Action<Self>>,
+}
+
+impl TcpService {
+    pub fn new(local_address: Ipv4Address, mtu: u16) -> Self {
+        let channels = Channels::new();
+
+        let mut hasher = DefaultHasher::new();
+        Instant::now().hash(&mut hasher);
+        std::process::id().hash(&mut hasher);
+
+        let connections = Connections {
+            local_address,
+            // Room for minimal IPv4 and TCP headers.
+            mss: mtu.saturating_sub(40),
+            send_down: channels.send_from_above(),
+            listeners: HashMap::new(),
+            connections: HashMap::new(),
+            next_port: *EPHEMERAL_PORTS.start(),
+            started: Instant::now(),
+            secret: hasher.finish(),
+        };
+
+        Self {
+            shared: Arc::new(Shared {
+                connections: Mutex::new(connections),
+                changed: Condvar::new(),
+            }),
+            channels,
+            actions: vec![Action::new(ActionType::Process, |_, _| true, false)],
+        }
+    }
+
+    pub fn sockets(&self) -> Sockets {
+        Sockets { shared: self.shared.clone() }
+    }
+}
+
+impl NetService for TcpService {
+    type Pdu = Ipv4Packet;
+
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    fn channels_mut(&mut self) -> &mut Channels {
+        &mut self.channels
+    }
+
+    fn actions(&self) -> &[Action<Self>] {
+        &self.actions
+    }
+
+    fn add_action(&mut self, action: Action<Self>) {
+        self.actions.push(action)
+    }
+
+    fn unwrap_data(_: &Self::Pdu) -> Option<Arc<[u8]>> {
+        None
+    }
+
+    fn process_pdu(&mut self, packet: Self::Pdu) -> Result<(), NetServiceError> {
+        self.shared.lock().on_packet(&packet, Instant::now());
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Runs the timers of every connection, and wakes the streams waiting on them.
+    fn on_tick(&mut self, now: Instant) -> Result<(), NetServiceError> {
+        self.shared.lock().on_tick(now);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+}
+
+pub struct TcpListener {
+    shared: Arc<Shared>,
+    port: u16,
+}
+
+impl TcpListener {
+    pub fn bind(sockets: &Sockets, port: u16) -> io::Result<Self> {
+        let mut connections = sockets.shared.lock();
+        match connections.listeners.entry(port) {
+            Entry::Occupied(_) => Err(io::ErrorKind::AddrInUse.into()),
+            Entry::Vacant(entry) => {
+                entry.insert(VecDeque::new());
+                Ok(Self { shared: sockets.shared.clone(), port })
+            },
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Blocks until a connection has completed the three-way handshake.
+    pub fn accept(&self) -> io::Result<(TcpStream, Endpoint)> {
+        let mut connections = self.shared.lock();
+        loop {
+            if let Some(quad) = connections.listeners.get_mut(&self.port).and_then(|queue| queue.pop_front()) {
+                let stream = TcpStream { shared: self.shared.clone(), quad };
+                return Ok((stream, quad.remote));
+            }
+
+            connections = self.shared.wait(connections);
+        }
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut connections = self.shared.lock();
+        let queued = connections.listeners.remove(&self.port).unwrap_or_default();
+        for quad in queued {
+            if let Some(connection) = connections.connections.get_mut(&quad) {
+                connection.tcb.abort();
+                connection.detached = true;
+            }
+            connections.transmit(&quad);
+        }
+    }
+}
+
+pub struct TcpStream {
+    shared: Arc<Shared>,
+    quad: Quad,
+}
+
+impl TcpStream {
+    /// Opens a connection and blocks until it is established or fails.
+    #[allow(dead_code)]
+    pub fn connect(sockets: &Sockets, remote: Endpoint) -> io::Result<Self> {
+        let shared = sockets.shared.clone();
+        let mut connections = shared.lock();
+
+        let port = connections.ephemeral_port(remote).ok_or(io::ErrorKind::AddrNotAvailable)?;
+        let quad = Quad { local: Endpoint { address: connections.local_address, port }, remote };
+
+        let tcb = Tcb::connect(quad, connections.iss(&quad), connections.mss, Instant::now());
+        connections.connections.insert(quad, Connection { tcb, listen_port: None, detached: false });
+        connections.transmit(&quad);
+
+        loop {
+            let tcb = &connections.connections[&quad].tcb;
+            if let Some(e) = tcb.error() {
+                drop(connections);
+                drop(Self { shared, quad });
+                return Err(e.into());
+            }
+
+            if !tcb.is_synchronising() {
+                break;
+            }
+
+            connections = shared.wait(connections);
+        }
+
+        drop(connections);
+        Ok(Self { shared, quad })
+    }
+
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> Endpoint {
+        self.quad.local
+    }
+
+    #[allow(dead_code)]
+    pub fn peer_addr(&self) -> Endpoint {
+        self.quad.remote
+    }
+
+    /// Sends a FIN once all written data has gone out. Reading continues to work until the peer closes.
+    #[allow(dead_code)]
+    pub fn shutdown(&self) {
+        let mut connections = self.shared.lock();
+        if let Some(connection) = connections.connections.get_mut(&self.quad) {
+            connection.tcb.close(Instant::now());
+        }
+        connections.transmit(&self.quad);
+    }
+
+    /// Runs `op` against the connection's control block, blocking while it would block.
+    fn blocking<T>(&self, mut op: impl FnMut(&mut Tcb, Instant) -> Result<T, TcpError>) -> io::Result<T> {
+        let mut connections = self.shared.lock();
+        loop {
+            let Some(connection) = connections.connections.get_mut(&self.quad) else {
+                return Err(io::ErrorKind::NotConnected.into());
+            };
+
+            let result = op(&mut connection.tcb, Instant::now());
+            connections.transmit(&self.quad);
+
+            match result {
+                Err(TcpError::WouldBlock) => connections = self.shared.wait(connections),
+                result => return result.map_err(|e| e.into()),
+            }
+        }
+    }
+}
+
+impl io::Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.blocking(|tcb, now| tcb.recv(buf, now))
+    }
+}
+
+impl io::Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.blocking(|tcb, now| tcb.send(buf, now))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut connections = self.shared.lock();
+        if let Some(connection) = connections.connections.get_mut(&self.quad) {
+            connection.tcb.close(Instant::now());
+            connection.detached = true;
+        }
+        connections.transmit(&self.quad);
+    }
+}
+
+#[test]
+fn test_sockets() {
+    use std::io::{Read, Write};
+    use std::thread;
+
+    let (client_address, server_address) = (Ipv4Address::from([10, 0, 0, 1]), Ipv4Address::from([10, 0, 0, 2]));
+    let mut client = TcpService::new(client_address, 1500);
+    let mut server = TcpService::new(server_address, 1500);
+    client.set_send_down(server.get_send_up());
+    server.set_send_down(client.get_send_up());
+    let (client_sockets, server_sockets) = (client.sockets(), server.sockets());
+    client.start();
+    server.start();
+
+    let listener = TcpListener::bind(&server_sockets, 7).unwrap();
+    assert_eq!(TcpListener::bind(&server_sockets, 7).err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+
+    // The server waits in accept, and then in read, until the client wakes it.
+    let echo = thread::spawn(move || {
+        let (mut stream, peer) = listener.accept().unwrap();
+        let mut buf = [0u8; 64];
+        loop {
+            match stream.read(&mut buf).unwrap() {
+                0 => return peer,
+                n => stream.write_all(&buf[..n]).unwrap(),
+            }
+        }
+    });
+
+    let mut stream = TcpStream::connect(&client_sockets, Endpoint { address: server_address, port: 7 }).unwrap();
+    assert_eq!(stream.peer_addr(), Endpoint { address: server_address, port: 7 });
+    stream.write_all(b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // Closing our side ends the server's reads, and it closes in turn.
+    stream.shutdown();
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    assert_eq!(echo.join().unwrap(), stream.local_addr());
+
+    let refused = TcpStream::connect(&client_sockets, Endpoint { address: server_address, port: 8 });
+    assert_eq!(refused.err().map(|e| e.kind()), Some(io::ErrorKind::ConnectionRefused));
+}
@@ -0,0 +1,838 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use rosi::common::address::Ipv4Address;
+use rosi::protocols::tcp::{Flags, Segment, TcpOption};
+
+use super::rto::RtoEstimator;
+
+/// MSS assumed for peers that do not send the option (RFC 9293 section 3.7.1).
+pub const DEFAULT_MSS: u16 = 536;
+
+/// Size of each receive and send buffer. We do not negotiate window scaling, so the
+/// receive window can never be larger than this anyway.
+const BUFFER_SIZE: usize = u16::MAX as usize;
+
+const MAX_RETRIES: u32 = 8;
+const MSL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    pub address: Ipv4Address,
+    pub port: u16,
+}
+
+impl core::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:{}", self.address, self.port)
+    }
+}
+
+/// Identifies a connection by both of its endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quad {
+    pub local: Endpoint,
+    pub remote: Endpoint,
+}
+
+/// Connection states (RFC 9293 section 3.3.2). LISTEN is represented by the service's
+/// listener table rather than by a control block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpError {
+    WouldBlock,
+    ConnectionRefused,
+    ConnectionReset,
+    TimedOut,
+    Closing,
+}
+
+impl From<TcpError> for std::io::Error {
+    fn from(value: TcpError) -> Self {
+        use std::io::ErrorKind;
+
+        std::io::Error::from(match value {
+            TcpError::WouldBlock => ErrorKind::WouldBlock,
+            TcpError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            TcpError::ConnectionReset => ErrorKind::ConnectionReset,
+            TcpError::TimedOut => ErrorKind::TimedOut,
+            TcpError::Closing => ErrorKind::BrokenPipe,
+        })
+    }
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+/// Transmission Control Block: the state of a single connection.
+///
+/// The block never touches the network itself. Segments are fed in with `on_segment`, timers
+/// are driven by `on_tick`, and whatever needs transmitting is collected with `take_output`.
+#[derive(Debug)]
+pub struct Tcb {
+    quad: Quad,
+    state: State,
+
+    iss: u32,
+    /// Whether the SYN has been acknowledged, after which every sequence number acknowledged
+    /// is a byte of data or the FIN.
+    syn_acked: bool,
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_max: u32,
+    snd_wnd: u32,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    snd_mss: u16,
+    rcv_mss: u16,
+
+    irs: u32,
+    rcv_nxt: u32,
+
+    /// Unacknowledged and unsent data, starting at `snd_una`.
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+    out_of_order: BTreeMap<u32, Vec<u8>>,
+
+    fin_queued: bool,
+    fin_sent: bool,
+    fin_acked: bool,
+    fin_received: bool,
+
+    rto: RtoEstimator,
+    retransmit_at: Option<Instant>,
+    retries: u32,
+    rtt_probe: Option<(u32, Instant)>,
+    time_wait_until: Option<Instant>,
+
+    ack_pending: bool,
+    error: Option<TcpError>,
+    outbox: Vec<Segment>,
+}
+
+impl Tcb {
+    fn new(quad: Quad, state: State, iss: u32, rcv_mss: u16) -> Self {
+        Self {
+            quad,
+            state,
+            iss,
+            syn_acked: false,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            snd_mss: DEFAULT_MSS,
+            rcv_mss,
+            irs: 0,
+            rcv_nxt: 0,
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            out_of_order: BTreeMap::new(),
+            fin_queued: false,
+            fin_sent: false,
+            fin_acked: false,
+            fin_received: false,
+            rto: RtoEstimator::default(),
+            retransmit_at: None,
+            retries: 0,
+            rtt_probe: None,
+            time_wait_until: None,
+            ack_pending: false,
+            error: None,
+            outbox: vec![],
+        }
+    }
+
+    /// Active open: sends a SYN to the remote endpoint.
+    pub fn connect(quad: Quad, iss: u32, rcv_mss: u16, now: Instant) -> Self {
+        let mut tcb = Self::new(quad, State::SynSent, iss, rcv_mss);
+        tcb.flush(now);
+        tcb
+    }
+
+    /// Passive open: answers a SYN that arrived for a listening port.
+    pub fn accept(quad: Quad, syn: &Segment, iss: u32, rcv_mss: u16, now: Instant) -> Self {
+        let mut tcb = Self::new(quad, State::SynReceived, iss, rcv_mss);
+        tcb.on_syn(syn);
+        tcb.snd_wnd = syn.window() as u32;
+        tcb.snd_wl1 = syn.sequence();
+        tcb.flush(now);
+        tcb
+    }
+
+    /// Builds the reset for a segment that does not belong to any connection (RFC 9293 section 3.10.7.1).
+    pub fn reset_for(seg: &Segment) -> Option<Segment> {
+        let flags = seg.flags();
+        if flags.contains(Flags::RST) {
+            return None;
+        }
+
+        Some(if flags.contains(Flags::ACK) {
+            Segment::new(seg.destination_port(), seg.source_port(), seg.acknowledgement(), 0, Flags::RST, 0, vec![])
+        } else {
+            Segment::new(
+                seg.destination_port(), seg.source_port(),
+                0, seg.sequence().wrapping_add(seg.sequence_length()),
+                Flags::RST | Flags::ACK,
+                0,
+                vec![],
+            )
+        })
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn error(&self) -> Option<TcpError> {
+        self.error
+    }
+
+    pub fn is_synchronising(&self) -> bool {
+        matches!(self.state, State::SynSent | State::SynReceived)
+    }
+
+    /// Segments waiting to be transmitted, without checksums.
+    pub fn take_output(&mut self) -> Vec<Segment> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Queues data for transmission, returning how much of it fit in the send buffer.
+    pub fn send(&mut self, data: &[u8], now: Instant) -> Result<usize, TcpError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        if self.fin_queued || self.state == State::Closed {
+            return Err(TcpError::Closing);
+        }
+
+        let len = data.len().min(BUFFER_SIZE - self.send_buffer.len());
+        if len == 0 && !data.is_empty() {
+            return Err(TcpError::WouldBlock);
+        }
+
+        self.send_buffer.extend(&data[..len]);
+        self.flush(now);
+        Ok(len)
+    }
+
+    /// Reads received data. Returns `Ok(0)` once the peer has closed and everything was read.
+    pub fn recv(&mut self, buf: &mut [u8], now: Instant) -> Result<usize, TcpError> {
+        if self.recv_buffer.is_empty() {
+            return match self.error {
+                Some(e) => Err(e),
+                None if self.fin_received => Ok(0),
+                None => Err(TcpError::WouldBlock),
+            };
+        }
+
+        let window_was_closed = self.rcv_wnd() < self.rcv_mss as u32;
+
+        let len = buf.len().min(self.recv_buffer.len());
+        for (dst, src) in buf.iter_mut().zip(self.recv_buffer.drain(..len)) {
+            *dst = src;
+        }
+
+        // Tell the peer as soon as the window reopens rather than waiting for it to probe.
+        if window_was_closed && self.rcv_wnd() >= self.rcv_mss as u32 {
+            self.ack_pending = true;
+            self.flush(now);
+        }
+
+        Ok(len)
+    }
+
+    /// Closes our half of the connection once all queued data has been sent. In SYN-RECEIVED
+    /// the FIN waits for the handshake to complete, so that the SYN-ACK is still retransmitted.
+    pub fn close(&mut self, now: Instant) {
+        match self.state {
+            State::SynSent => self.state = State::Closed,
+            State::SynReceived => self.fin_queued = true,
+            State::Established => {
+                self.fin_queued = true;
+                self.state = State::FinWait1;
+            },
+            State::CloseWait => {
+                self.fin_queued = true;
+                self.state = State::LastAck;
+            },
+            _ => return,
+        }
+
+        self.flush(now);
+    }
+
+    /// Drops the connection immediately, resetting it if it was synchronised.
+    pub fn abort(&mut self) {
+        if !matches!(self.state, State::SynSent | State::TimeWait | State::Closed) {
+            let seg = self.segment(self.snd_nxt, Flags::RST, vec![]);
+            self.outbox.push(seg);
+        }
+
+        self.state = State::Closed;
+    }
+
+    pub fn on_segment(&mut self, seg: &Segment, now: Instant) {
+        match self.state {
+            State::Closed => return,
+            State::SynSent => self.on_segment_syn_sent(seg, now),
+            _ => self.on_segment_synchronised(seg, now),
+        }
+
+        self.flush(now);
+    }
+
+    /// Runs the retransmission and TIME-WAIT timers.
+    pub fn on_tick(&mut self, now: Instant) {
+        if self.time_wait_until.is_some_and(|t| now >= t) {
+            self.time_wait_until = None;
+            self.state = State::Closed;
+            return;
+        }
+
+        if self.retransmit_at.is_none_or(|t| now < t) {
+            return;
+        }
+
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.fail(TcpError::TimedOut);
+            return;
+        }
+
+        // Go back to the oldest unacknowledged byte and send everything again. Karn's
+        // algorithm: segments sent again are never used for round-trip samples.
+        self.rto.backoff();
+        self.rtt_probe = None;
+        self.retransmit_at = None;
+        self.snd_nxt = if self.is_synchronising() { self.iss } else { self.snd_una };
+        self.fin_sent = self.fin_acked;
+        self.flush(now);
+    }
+
+    fn fail(&mut self, error: TcpError) {
+        self.error = Some(error);
+        self.state = State::Closed;
+        self.retransmit_at = None;
+    }
+
+    fn on_syn(&mut self, syn: &Segment) {
+        self.irs = syn.sequence();
+        self.rcv_nxt = syn.sequence().wrapping_add(1);
+        self.snd_mss = syn.mss().unwrap_or(DEFAULT_MSS).min(self.rcv_mss);
+    }
+
+    fn on_segment_syn_sent(&mut self, seg: &Segment, now: Instant) {
+        let flags = seg.flags();
+        let ack = seg.acknowledgement();
+
+        if flags.contains(Flags::ACK) && (seq_le(ack, self.iss) || seq_lt(self.snd_nxt, ack)) {
+            if !flags.contains(Flags::RST) {
+                let reset = Segment::new(self.quad.local.port, self.quad.remote.port, ack, 0, Flags::RST, 0, vec![]);
+                self.outbox.push(reset);
+            }
+            return;
+        }
+
+        if flags.contains(Flags::RST) {
+            if flags.contains(Flags::ACK) {
+                self.fail(TcpError::ConnectionRefused);
+            }
+            return;
+        }
+
+        if !flags.contains(Flags::SYN) {
+            return;
+        }
+
+        self.on_syn(seg);
+        self.snd_wnd = seg.window() as u32;
+        self.snd_wl1 = seg.sequence();
+        self.snd_wl2 = ack;
+
+        if flags.contains(Flags::ACK) {
+            self.acknowledge(ack, now);
+            self.syn_acked = true;
+            self.state = State::Established;
+            self.ack_pending = true;
+        } else {
+            // Simultaneous open: send a SYN-ACK for our original SYN.
+            self.state = State::SynReceived;
+            self.snd_nxt = self.iss;
+            self.retransmit_at = None;
+        }
+    }
+
+    fn on_segment_synchronised(&mut self, seg: &Segment, now: Instant) {
+        let flags = seg.flags();
+        let seq = seg.sequence();
+        let ack = seg.acknowledgement();
+
+        // A FIN sent again means our ACK of it was lost. It falls before the window, so it is
+        // acknowledged again, and the 2 MSL wait restarted, before the window is checked.
+        if self.state == State::TimeWait && flags.contains(Flags::FIN) && !flags.intersects(Flags::RST | Flags::SYN) {
+            self.receive_fin(seq.wrapping_add(seg.data().len() as u32), now);
+            return;
+        }
+
+        if !self.is_acceptable(seq, seg.sequence_length()) {
+            if !flags.contains(Flags::RST) {
+                self.ack_pending = true;
+            }
+            return;
+        }
+
+        if flags.contains(Flags::RST) {
+            // Only a reset at exactly the expected sequence number is honoured; anything else
+            // in the window gets a challenge ACK (RFC 5961 section 3).
+            if seq == self.rcv_nxt {
+                self.fail(TcpError::ConnectionReset);
+            } else {
+                self.ack_pending = true;
+            }
+            return;
+        }
+
+        if flags.contains(Flags::SYN) {
+            self.ack_pending = true;
+            return;
+        }
+
+        if !flags.contains(Flags::ACK) {
+            return;
+        }
+
+        if self.state == State::SynReceived {
+            if seq_lt(self.snd_una, ack) && seq_le(ack, self.snd_nxt) {
+                // A close while the handshake was in progress takes effect now.
+                self.state = if self.fin_queued { State::FinWait1 } else { State::Established };
+                self.snd_wnd = seg.window() as u32;
+                self.snd_wl1 = seq;
+                self.snd_wl2 = ack;
+            } else {
+                let reset = Segment::new(self.quad.local.port, self.quad.remote.port, ack, 0, Flags::RST, 0, vec![]);
+                self.outbox.push(reset);
+                return;
+            }
+        }
+
+        if seq_lt(self.snd_nxt, ack) {
+            self.ack_pending = true;
+            return;
+        }
+
+        if seq_lt(self.snd_una, ack) {
+            self.acknowledge(ack, now);
+            self.syn_acked = true;
+        }
+
+        if seq_lt(self.snd_wl1, seq) || (self.snd_wl1 == seq && seq_le(self.snd_wl2, ack)) {
+            self.snd_wnd = seg.window() as u32;
+            self.snd_wl1 = seq;
+            self.snd_wl2 = ack;
+        }
+
+        match self.state {
+            State::FinWait1 if self.fin_acked => self.state = State::FinWait2,
+            State::Closing if self.fin_acked => self.enter_time_wait(now),
+            State::LastAck if self.fin_acked => {
+                self.state = State::Closed;
+                return;
+            },
+            _ => (),
+        }
+
+        if matches!(self.state, State::Established | State::FinWait1 | State::FinWait2) && !seg.data().is_empty() {
+            self.receive_data(seq, seg.data());
+            self.ack_pending = true;
+        }
+
+        if flags.contains(Flags::FIN) {
+            self.receive_fin(seq.wrapping_add(seg.data().len() as u32), now);
+        }
+    }
+
+    fn receive_fin(&mut self, fin_seq: u32, now: Instant) {
+        if fin_seq != self.rcv_nxt && !(self.fin_received && fin_seq.wrapping_add(1) == self.rcv_nxt) {
+            // Data before the FIN is missing; the peer will retransmit it.
+            return;
+        }
+
+        self.ack_pending = true;
+        if self.fin_received {
+            if self.state == State::TimeWait {
+                self.enter_time_wait(now);
+            }
+            return;
+        }
+
+        self.fin_received = true;
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+
+        match self.state {
+            State::SynReceived | State::Established => self.state = State::CloseWait,
+            State::FinWait1 if self.fin_acked => self.enter_time_wait(now),
+            State::FinWait1 => self.state = State::Closing,
+            State::FinWait2 => self.enter_time_wait(now),
+            _ => (),
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.time_wait_until = Some(now + MSL * 2);
+    }
+
+    fn rcv_wnd(&self) -> u32 {
+        (BUFFER_SIZE - self.recv_buffer.len()) as u32
+    }
+
+    /// Segment acceptability test (RFC 9293 section 3.10.7.4).
+    fn is_acceptable(&self, seq: u32, len: u32) -> bool {
+        let wnd = self.rcv_wnd();
+        let in_window = |s: u32| seq_le(self.rcv_nxt, s) && seq_lt(s, self.rcv_nxt.wrapping_add(wnd));
+
+        match (len, wnd) {
+            (0, 0) => seq == self.rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            (_, _) => in_window(seq) || in_window(seq.wrapping_add(len - 1)),
+        }
+    }
+
+    fn acknowledge(&mut self, ack: u32, now: Instant) {
+        let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+        if !self.syn_acked {
+            // The first sequence number is the SYN.
+            acked -= 1;
+        }
+
+        let data = acked.min(self.send_buffer.len());
+        self.send_buffer.drain(..data);
+        if acked > data && self.fin_sent {
+            self.fin_acked = true;
+        }
+
+        self.snd_una = ack;
+
+        if let Some((probe, sent)) = self.rtt_probe {
+            if seq_le(probe, ack) {
+                self.rto.sample(now - sent);
+                self.rtt_probe = None;
+            }
+        }
+
+        self.retries = 0;
+        self.retransmit_at = if self.snd_una == self.snd_nxt {
+            None
+        } else {
+            Some(now + self.rto.rto())
+        };
+    }
+
+    fn receive_data(&mut self, seq: u32, data: &[u8]) {
+        // Trim anything already received from the front, and anything beyond the window from the back.
+        let (seq, data) = if seq_lt(seq, self.rcv_nxt) {
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            (self.rcv_nxt, &data[skip.min(data.len())..])
+        } else {
+            (seq, data)
+        };
+
+        let offset = seq.wrapping_sub(self.rcv_nxt) as usize;
+        let wnd = self.rcv_wnd() as usize;
+        let data = &data[..data.len().min(wnd.saturating_sub(offset))];
+        if data.is_empty() {
+            return;
+        }
+
+        if offset > 0 {
+            self.out_of_order.insert(seq, data.to_vec());
+            return;
+        }
+
+        self.recv_buffer.extend(data);
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u32);
+
+        while let Some(entry) = self.out_of_order.first_entry() {
+            let seq = *entry.key();
+            if seq_lt(self.rcv_nxt, seq) {
+                break;
+            }
+
+            let data = entry.remove();
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                self.recv_buffer.extend(&data[skip..]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add((data.len() - skip) as u32);
+            }
+        }
+    }
+
+    fn segment(&self, seq: u32, flags: Flags, data: Vec<u8>) -> Segment {
+        let ack = if flags.contains(Flags::ACK) { self.rcv_nxt } else { 0 };
+        let mut seg = Segment::new(
+            self.quad.local.port, self.quad.remote.port,
+            seq, ack,
+            flags,
+            self.rcv_wnd() as u16,
+            data,
+        );
+
+        if flags.contains(Flags::SYN) {
+            // A single MSS option always fits.
+            let _ = seg.set_options(vec![TcpOption::MaximumSegmentSize(self.rcv_mss)]);
+        }
+
+        seg
+    }
+
+    fn transmit(&mut self, seg: Segment, now: Instant) {
+        let end = seg.sequence().wrapping_add(seg.sequence_length());
+        if seq_lt(self.snd_max, end) {
+            if self.rtt_probe.is_none() && seq_le(self.snd_max, seg.sequence()) {
+                self.rtt_probe = Some((end, now));
+            }
+            self.snd_max = end;
+        }
+
+        self.snd_nxt = end;
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto.rto());
+        }
+
+        self.outbox.push(seg);
+    }
+
+    /// Sends whatever the state, window and buffers allow, then a bare ACK if one is owed.
+    fn flush(&mut self, now: Instant) {
+        let before = self.outbox.len();
+
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = match self.state {
+                        State::SynSent => Flags::SYN,
+                        _ => Flags::SYN | Flags::ACK,
+                    };
+
+                    let seg = self.segment(self.iss, flags, vec![]);
+                    self.transmit(seg, now);
+                }
+            },
+            State::Established | State::CloseWait | State::FinWait1 | State::Closing | State::LastAck => {
+                self.send_data(now);
+            },
+            State::FinWait2 | State::TimeWait | State::Closed => (),
+        }
+
+        if self.ack_pending && self.outbox.len() == before && self.state != State::Closed {
+            let seg = self.segment(self.snd_nxt, Flags::ACK, vec![]);
+            self.outbox.push(seg);
+        }
+
+        self.ack_pending = false;
+    }
+
+    fn send_data(&mut self, now: Instant) {
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let sent = in_flight.min(self.send_buffer.len());
+            let unsent = self.send_buffer.len() - sent;
+
+            let window = self.snd_wnd as usize;
+            let usable = if window == 0 && in_flight == 0 {
+                // Zero window probe; the retransmission timer repeats it until the window opens.
+                1
+            } else {
+                window.saturating_sub(in_flight)
+            };
+
+            let len = unsent.min(usable).min(self.snd_mss as usize);
+            let send_fin = self.fin_queued && !self.fin_sent && len == unsent;
+            if len == 0 && !send_fin {
+                break;
+            }
+
+            let mut flags = Flags::ACK;
+            if len > 0 && len == unsent {
+                flags.insert(Flags::PSH);
+            }
+
+            if send_fin {
+                flags.insert(Flags::FIN);
+                self.fin_sent = true;
+            }
+
+            let data = self.send_buffer.range(sent..sent + len).copied().collect();
+            let seg = self.segment(self.snd_nxt, flags, data);
+            self.transmit(seg, now);
+
+            if send_fin {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tcb_handshake_transfer_and_close() {
+    fn deliver(from: &mut Tcb, to: &mut Tcb, now: Instant) -> usize {
+        let segments = from.take_output();
+        segments.iter().for_each(|seg| to.on_segment(seg, now));
+        segments.len()
+    }
+
+    let client_end = Endpoint { address: Ipv4Address::from([10, 0, 0, 1]), port: 49152 };
+    let server_end = Endpoint { address: Ipv4Address::from([10, 0, 0, 2]), port: 80 };
+    let now = Instant::now();
+
+    let mut client = Tcb::connect(Quad { local: client_end, remote: server_end }, 1000, 1460, now);
+    let syn = client.take_output().remove(0);
+    assert_eq!(syn.flags(), Flags::SYN);
+    assert_eq!(syn.mss(), Some(1460));
+
+    let mut server = Tcb::accept(Quad { local: server_end, remote: client_end }, &syn, u32::MAX - 1, 1460, now);
+    assert_eq!(server.state(), State::SynReceived);
+
+    deliver(&mut server, &mut client, now);
+    assert_eq!(client.state(), State::Established);
+    deliver(&mut client, &mut server, now);
+    assert_eq!(server.state(), State::Established);
+
+    // Enough data to need several segments, crossing the server's sequence number wrap.
+    let payload: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+    assert_eq!(server.send(&payload, now), Ok(4000));
+    assert_eq!(deliver(&mut server, &mut client, now), 3);
+    deliver(&mut client, &mut server, now);
+
+    let mut received = vec![0u8; 8000];
+    assert_eq!(client.recv(&mut received, now), Ok(4000));
+    assert_eq!(&received[..4000], payload.as_slice());
+    assert_eq!(client.recv(&mut received, now), Err(TcpError::WouldBlock));
+
+    // After 2^32 bytes the oldest unacknowledged byte is at the initial sequence number again,
+    // and is not taken for the SYN.
+    server.iss = server.snd_una;
+    assert_eq!(server.send(&payload[..100], now), Ok(100));
+    deliver(&mut server, &mut client, now);
+    deliver(&mut client, &mut server, now);
+    assert!(server.send_buffer.is_empty());
+    assert_eq!(client.recv(&mut received, now), Ok(100));
+
+    client.close(now);
+    assert_eq!(client.state(), State::FinWait1);
+    deliver(&mut client, &mut server, now);
+    assert_eq!(server.state(), State::CloseWait);
+    assert_eq!(server.recv(&mut received, now), Ok(0));
+
+    deliver(&mut server, &mut client, now);
+    assert_eq!(client.state(), State::FinWait2);
+
+    server.close(now);
+    deliver(&mut server, &mut client, now);
+    assert_eq!(client.state(), State::TimeWait);
+    deliver(&mut client, &mut server, now);
+    assert_eq!(server.state(), State::Closed);
+
+    client.on_tick(now + MSL * 2);
+    assert_eq!(client.state(), State::Closed);
+}
+
+#[test]
+fn test_tcb_retransmission_and_reset() {
+    let local = Endpoint { address: Ipv4Address::from([10, 0, 0, 1]), port: 49153 };
+    let remote = Endpoint { address: Ipv4Address::from([10, 0, 0, 2]), port: 7 };
+    let now = Instant::now();
+
+    let mut client = Tcb::connect(Quad { local, remote }, 5, 1460, now);
+    let syn = client.take_output().remove(0);
+
+    client.on_tick(now + Duration::from_millis(999));
+    assert!(client.take_output().is_empty());
+
+    client.on_tick(now + Duration::from_secs(1));
+    let retransmitted = client.take_output();
+    assert_eq!(retransmitted.len(), 1);
+    assert_eq!(retransmitted[0].sequence(), syn.sequence());
+    assert_eq!(retransmitted[0].flags(), Flags::SYN);
+
+    // A closed port answers with RST+ACK, which refuses the connection.
+    let reset = Tcb::reset_for(&syn).unwrap();
+    assert_eq!(reset.flags(), Flags::RST | Flags::ACK);
+    assert_eq!(reset.acknowledgement(), 6);
+
+    client.on_segment(&reset, now);
+    assert_eq!(client.state(), State::Closed);
+    assert_eq!(client.error(), Some(TcpError::ConnectionRefused));
+}
+
+#[test]
+fn test_tcb_close_during_handshake_and_time_wait() {
+    let client_end = Endpoint { address: Ipv4Address::from([10, 0, 0, 1]), port: 49154 };
+    let server_end = Endpoint { address: Ipv4Address::from([10, 0, 0, 2]), port: 7 };
+    let now = Instant::now();
+
+    let mut client = Tcb::connect(Quad { local: client_end, remote: server_end }, 1000, 1460, now);
+    let syn = client.take_output().remove(0);
+    let mut server = Tcb::accept(Quad { local: server_end, remote: client_end }, &syn, 5000, 1460, now);
+    let syn_ack = server.take_output().remove(0);
+
+    // Closed in SYN-RECEIVED, the server keeps retransmitting its SYN-ACK, and only sends its
+    // FIN once the handshake completes.
+    server.close(now);
+    assert_eq!(server.state(), State::SynReceived);
+    assert!(server.take_output().is_empty());
+    server.on_tick(now + Duration::from_secs(1));
+    let retransmitted = server.take_output();
+    assert_eq!(retransmitted.len(), 1);
+    assert_eq!(retransmitted[0].flags(), syn_ack.flags());
+
+    client.on_segment(&retransmitted[0], now);
+    let ack = client.take_output().remove(0);
+    server.on_segment(&ack, now);
+    assert_eq!(server.state(), State::FinWait1);
+    let fin = server.take_output().remove(0);
+    assert!(fin.flags().contains(Flags::FIN));
+
+    // The client closes too, and the server ends up in TIME-WAIT.
+    client.on_segment(&fin, now);
+    client.close(now);
+    client.take_output().iter().for_each(|seg| server.on_segment(seg, now));
+    assert_eq!(server.state(), State::TimeWait);
+    server.take_output();
+
+    // A FIN sent again is acknowledged again, and the wait starts over.
+    let later = now + MSL;
+    let fin = Segment::new(49154, 7, 1001, 5002, Flags::FIN | Flags::ACK, 1000, vec![]);
+    server.on_segment(&fin, later);
+    let ack = server.take_output();
+    assert_eq!(ack.len(), 1);
+    assert_eq!((ack[0].flags(), ack[0].acknowledgement()), (Flags::ACK, 1002));
+    server.on_tick(now + MSL * 2);
+    assert_eq!(server.state(), State::TimeWait);
+    server.on_tick(later + MSL * 2);
+    assert_eq!(server.state(), State::Closed);
+}
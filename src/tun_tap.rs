@@ -1,43 +1,186 @@
-use std::sync::Arc;
+use std::io;
+use std::str::FromStr;
 
-use crate::netservice::ByteReceiver;
+use rosi::capture::LinkType;
+use rosi::common::{DeserialiseError, Serialise};
+use rosi::protocols::ethernet::EtherType;
 
-use super::netservice::{ActionType, NetService, NetServiceError, ByteSender, Stack, PduFilter, PduProcessor};
-use super::ethernet::EthernetService;
+use super::device::{self, Device};
 
-struct Tap {
-    tap: tun_tap::Iface,
-    actions: Vec<[(Option<PduFilter<Self>>, Option<PduProcessor<Self>>)]>,
-    send_from_above: ByteSender
+/// Largest packet read from the device, whatever its MTU: the largest IP packet in a
+/// VLAN-tagged Ethernet frame, plus the packet information header.
+const BUFFER_SIZE: usize = 65535 + 18 + PI_LENGTH;
+
+/// Length of the packet information header the kernel puts in front of every packet.
+const PI_LENGTH: usize = 4;
+
+/// Set by the kernel when a packet did not fit in the buffer it was read into.
+const TUN_PKT_STRIP: u16 = 0x0001;
+
+/// Whether a kernel device carries Ethernet frames or bare IP packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Tap,
+    Tun,
 }
 
-impl Tap {
-    pub fn new(name: &str) -> std::io::Result<Self> {
-        Ok(
-            Self {
-                tap: tun_tap::Iface::new(name, tun_tap::Mode::Tap)?,
-                send_up: None,
-            }
-        )
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tap" => Ok(Self::Tap),
+            "tun" => Ok(Self::Tun),
+            _ => Err(format!("invalid mode {s}, expected tap or tun")),
+        }
+    }
+}
+
+impl core::fmt::Display for Mode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Tap => write!(f, "tap"),
+            Self::Tun => write!(f, "tun"),
+        }
+    }
+}
+
+/// The header in front of every packet on a device opened without `IFF_NO_PI`: flags, and
+/// the EtherType of the packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketInfo {
+    pub flags: u16,
+    pub proto: EtherType,
+}
+
+impl PacketInfo {
+    /// Whether the packet was cut short to fit the buffer it was read into.
+    pub fn truncated(&self) -> bool {
+        self.flags & TUN_PKT_STRIP != 0
+    }
+}
+
+impl Serialise for PacketInfo {
+    fn byte_length(&self) -> usize {
+        PI_LENGTH
+    }
+
+    fn serialise(&self, buf: &mut [u8]) -> usize {
+        self.flags.serialise(buf);
+        self.proto.serialise(&mut buf[2..]);
+        PI_LENGTH
+    }
+
+    fn deserialise(buf: &[u8]) -> Result<Self, DeserialiseError> {
+        if buf.len() < PI_LENGTH {
+            return Err(DeserialiseError::BufferTooSmall(file!(), line!(), column!(), PI_LENGTH, buf.len()));
+        }
+
+        Ok(Self { flags: u16::deserialise(buf)?, proto: EtherType::deserialise(&buf[2..])? })
     }
+}
+
+/// A kernel TUN or TAP device. Opening one needs `CAP_NET_ADMIN`.
+///
+/// Unless the device was opened without packet information, packets are read without their
+/// header and written with one added.
+pub struct TunTap {
+    iface: ::tun_tap::Iface,
+    mode: Mode,
+    packet_info: bool,
+}
 
-    pub fn wrap<S: NetService>(&mut self) {
-        self.
+impl TunTap {
+    pub fn new(name: &str, mode: Mode, packet_info: bool) -> io::Result<Self> {
+        let kernel_mode = match mode {
+            Mode::Tap => ::tun_tap::Mode::Tap,
+            Mode::Tun => ::tun_tap::Mode::Tun,
+        };
+
+        let iface = if packet_info {
+            ::tun_tap::Iface::new(name, kernel_mode)?
+        } else {
+            ::tun_tap::Iface::without_packet_info(name, kernel_mode)?
+        };
+
+        Ok(Self { iface, mode, packet_info })
+    }
+
+    /// The EtherType to put in the packet information header of `packet`.
+    fn proto(&self, packet: &[u8]) -> EtherType {
+        match self.mode {
+            Mode::Tap => packet.get(12..14).and_then(|ethertype| EtherType::deserialise(ethertype).ok()).unwrap_or(EtherType::from(0)),
+            Mode::Tun => match device::ip_version(packet) {
+                Some(6) => EtherType::Ipv6,
+                _ => EtherType::Ipv4,
+            },
+        }
     }
 }
 
-impl NetService for Tap {
-    type Pdu = Arc<[u8]>;
+impl Device for TunTap {
+    fn name(&self) -> &str {
+        self.iface.name()
+    }
 
-    fn actions(&self) -> &[(Option<PduFilter<Self>>, ActionType, Option<PduProcessor<Self>>)] {
-        self.actions
+    fn link_type(&self) -> LinkType {
+        match self.mode {
+            Mode::Tap => LinkType::Ethernet,
+            Mode::Tun => LinkType::Raw,
+        }
     }
 
-    fn add_action(&mut self, filter: Option<PduFilter<Self>>, processor: Option<PduProcessor<Self>>, action: ActionType) {
-        self.actions.push((filter, action, processor))
+    fn recv(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        loop {
+            let len = self.iface.recv(&mut buf)?;
+            if !self.packet_info {
+                return Ok(Some(buf[..len].to_vec()));
+            }
+
+            let Ok(info) = PacketInfo::deserialise(&buf[..len]) else {
+                continue;
+            };
+            if info.truncated() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("dropped a {} packet too long for the buffer", info.proto)));
+            }
+
+            // A TUN device may also carry protocols the stack has no service for.
+            if self.mode == Mode::Tun && !matches!(info.proto, EtherType::Ipv4 | EtherType::Ipv6) {
+                continue;
+            }
+
+            if len > PI_LENGTH {
+                return Ok(Some(buf[PI_LENGTH..len].to_vec()));
+            }
+        }
     }
 
-    fn get_send_from_above(&self) -> crate::netservice::ByteReceiver {
-        self.
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        if !self.packet_info {
+            self.iface.send(packet)?;
+            return Ok(());
+        }
+
+        let info = PacketInfo { flags: 0, proto: self.proto(packet) };
+        let mut buf = vec![0u8; PI_LENGTH + packet.len()];
+        info.serialise(&mut buf);
+        buf[PI_LENGTH..].copy_from_slice(packet);
+
+        self.iface.send(&buf)?;
+        Ok(())
     }
 }
+
+#[test]
+fn test_packet_info() {
+    let info = PacketInfo::deserialise(&[0x00, 0x01, 0x86, 0xdd, 0x60]).unwrap();
+    assert_eq!(info, PacketInfo { flags: TUN_PKT_STRIP, proto: EtherType::Ipv6 });
+    assert!(info.truncated());
+
+    let mut buf = [0u8; PI_LENGTH];
+    assert_eq!(PacketInfo { flags: 0, proto: EtherType::Ipv4 }.serialise(&mut buf), PI_LENGTH);
+    assert_eq!(buf, [0x00, 0x00, 0x08, 0x00]);
+
+    assert!(PacketInfo::deserialise(&[0x00, 0x00]).is_err());
+}